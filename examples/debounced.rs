@@ -8,9 +8,9 @@ fn main() {
     // emit some events by changing a file
     std::thread::spawn(|| {
         let path = Path::new("test.txt");
-        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(path);
         loop {
-            std::fs::write(&path, b"Lorem ipsum").unwrap();
+            std::fs::write(path, b"Lorem ipsum").unwrap();
             std::thread::sleep(Duration::from_millis(250));
         }
     });
@@ -27,9 +27,10 @@ fn main() {
         .unwrap();
 
     // print all events, non returning
-    for events in rx {
-        for e in events {
-            println!("{:?}", e);
+    for result in rx {
+        match result {
+            Ok(events) => events.iter().for_each(|event| println!("{:?}", event)),
+            Err(errors) => errors.iter().for_each(|error| println!("{:?}", error)),
         }
     }
 }
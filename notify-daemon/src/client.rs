@@ -0,0 +1,156 @@
+//! The connection of a process to the daemon
+
+use crate::{default_socket_path, Request, Response};
+use notify::serialization::{self, read_json_lines, write_json_line};
+use notify::{Error, EventHandler, RecursiveMode, Result};
+use std::{
+    collections::HashMap,
+    io::BufReader,
+    net::Shutdown,
+    os::unix::net::UnixStream,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, Mutex,
+    },
+};
+
+type Replies = Arc<Mutex<HashMap<u64, mpsc::Sender<std::result::Result<(), String>>>>>;
+
+/// Subscribes to paths watched by the daemon, passing their events to an event handler.
+///
+/// The events arrive on a thread of the client. Errors of the daemon's watcher are passed to the
+/// event handler too, and a final error when the daemon disconnects.
+pub struct Client {
+    stream: UnixStream,
+    next_id: u64,
+    watches: HashMap<PathBuf, u64>,
+    replies: Replies,
+    closed: Arc<AtomicBool>,
+}
+
+impl Client {
+    /// Connect to the daemon listening on `socket`.
+    pub fn connect<P: AsRef<Path>, F: EventHandler>(
+        socket: P,
+        mut event_handler: F,
+    ) -> Result<Self> {
+        let stream = UnixStream::connect(socket).map_err(Error::io)?;
+        let reader = stream.try_clone().map_err(Error::io)?;
+        let replies = Replies::default();
+        let replies_c = replies.clone();
+        let closed = Arc::new(AtomicBool::new(false));
+        let closed_c = closed.clone();
+        std::thread::Builder::new()
+            .name("notify-daemon client".to_string())
+            .spawn(move || {
+                for response in read_json_lines::<_, Response>(BufReader::new(reader)) {
+                    let reply = match response {
+                        Ok(Response::Event { event, .. }) => {
+                            event_handler.handle_event(Ok(event));
+                            continue;
+                        }
+                        Ok(Response::Error { error }) => {
+                            event_handler.handle_event(Err(Error::generic(&error)));
+                            continue;
+                        }
+                        Ok(Response::Done { id }) => (id, Ok(())),
+                        Ok(Response::Failed { id, error }) => (id, Err(error)),
+                        Err(serialization::Error::Io(_)) => break,
+                        Err(e) => {
+                            event_handler.handle_event(Err(Error::generic(&e.to_string())));
+                            break;
+                        }
+                    };
+                    if let Ok(mut replies) = replies_c.lock() {
+                        if let Some(tx) = replies.remove(&reply.0) {
+                            let _ = tx.send(reply.1);
+                        }
+                    }
+                }
+                // waiting requests fail as their senders are dropped
+                if let Ok(mut replies) = replies_c.lock() {
+                    replies.clear();
+                }
+                if !closed_c.load(Ordering::Acquire) {
+                    let error = Error::generic("the notify daemon disconnected");
+                    event_handler.handle_event(Err(error));
+                }
+            })
+            .map_err(Error::io)?;
+
+        Ok(Self {
+            stream,
+            next_id: 0,
+            watches: HashMap::new(),
+            replies,
+            closed,
+        })
+    }
+
+    /// Connect to the daemon listening on [`default_socket_path`].
+    pub fn connect_default<F: EventHandler>(event_handler: F) -> Result<Self> {
+        Self::connect(default_socket_path(), event_handler)
+    }
+
+    /// Subscribe to the events of `path`, replacing an earlier subscription to it.
+    ///
+    /// Relative paths are resolved against the current directory, as the daemon's differs.
+    pub fn watch(&mut self, path: &Path, recursive_mode: RecursiveMode) -> Result<()> {
+        let path = if path.is_absolute() {
+            path.to_path_buf()
+        } else {
+            std::env::current_dir().map_err(Error::io)?.join(path)
+        };
+        let id = self.watches.get(&path).copied().unwrap_or_else(|| {
+            self.next_id += 1;
+            self.next_id
+        });
+        self.request(
+            id,
+            Request::Watch {
+                id,
+                path: path.clone(),
+                recursive: recursive_mode == RecursiveMode::Recursive,
+            },
+        )
+        .map_err(|e| e.add_path(path.clone()))?;
+        self.watches.insert(path, id);
+        Ok(())
+    }
+
+    /// End the subscription to `path`.
+    pub fn unwatch(&mut self, path: &Path) -> Result<()> {
+        let path = if path.is_absolute() {
+            path.to_path_buf()
+        } else {
+            std::env::current_dir().map_err(Error::io)?.join(path)
+        };
+        let id = self
+            .watches
+            .remove(&path)
+            .ok_or_else(|| Error::watch_not_found().add_path(path.clone()))?;
+        self.request(id, Request::Unwatch { id })
+    }
+
+    /// Send `request` and wait for the daemon's answer for subscription `id`
+    fn request(&mut self, id: u64, request: Request) -> Result<()> {
+        let (tx, rx) = mpsc::channel();
+        if let Ok(mut replies) = self.replies.lock() {
+            replies.insert(id, tx);
+        }
+        write_json_line(&mut self.stream, &request).map_err(|e| Error::generic(&e.to_string()))?;
+        match rx.recv() {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(error)) => Err(Error::generic(&error)),
+            Err(_) => Err(Error::generic("the notify daemon disconnected")),
+        }
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        self.closed.store(true, Ordering::Release);
+        let _ = self.stream.shutdown(Shutdown::Both);
+    }
+}
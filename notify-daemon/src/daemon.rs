@@ -0,0 +1,351 @@
+//! The daemon serving the clients
+
+use crate::{Request, Response};
+use notify::serialization::{read_json_lines, write_json_line};
+use notify::{Config, Event, RecommendedWatcher, RecursiveMode, Result, WatchOverlap, Watcher};
+use std::{
+    collections::HashMap,
+    io::{self, BufReader},
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc, Arc, Mutex,
+    },
+};
+
+/// Watches paths for the clients connected to its socket, see the [crate] docs.
+///
+/// ```rust,no_run
+/// use notify::Config;
+/// use notify_daemon::{default_socket_path, Daemon};
+///
+/// # fn main() -> std::io::Result<()> {
+/// let daemon = Daemon::bind(default_socket_path(), Config::default())?;
+/// daemon.run();
+/// # Ok(())
+/// # }
+/// ```
+pub struct Daemon {
+    listener: UnixListener,
+    path: PathBuf,
+    watches: Arc<Mutex<Watches>>,
+    clients: Arc<Mutex<HashMap<u64, Connection>>>,
+    next_client: AtomicU64,
+}
+
+/// The watcher shared by all clients, with the subscriptions to each path it watches
+struct Watches {
+    watcher: RecommendedWatcher,
+    paths: HashMap<PathBuf, Subscribers>,
+}
+
+#[derive(Clone, Copy, Default)]
+struct Subscribers {
+    recursive: usize,
+    non_recursive: usize,
+}
+
+impl Subscribers {
+    /// The mode the path is watched with for these subscribers, `None` if there are none
+    fn mode(self) -> Option<RecursiveMode> {
+        if self.recursive > 0 {
+            Some(RecursiveMode::Recursive)
+        } else if self.non_recursive > 0 {
+            Some(RecursiveMode::NonRecursive)
+        } else {
+            None
+        }
+    }
+
+    fn count(&mut self, mode: RecursiveMode) -> &mut usize {
+        match mode {
+            RecursiveMode::Recursive => &mut self.recursive,
+            RecursiveMode::NonRecursive => &mut self.non_recursive,
+        }
+    }
+}
+
+impl Watches {
+    /// Add a subscription to `path`, watching it if it isn't already
+    fn acquire(&mut self, path: &Path, mode: RecursiveMode) -> Result<()> {
+        let before = self.paths.get(path).copied().unwrap_or_default();
+        let mut after = before;
+        *after.count(mode) += 1;
+        self.rewatch(path, before.mode(), after.mode())?;
+        self.paths.insert(path.to_path_buf(), after);
+        Ok(())
+    }
+
+    /// Remove a subscription to `path`, unwatching it if it was the last
+    fn release(&mut self, path: &Path, mode: RecursiveMode) {
+        let before = match self.paths.get(path) {
+            Some(subscribers) => *subscribers,
+            None => return,
+        };
+        let mut after = before;
+        let count = after.count(mode);
+        *count = count.saturating_sub(1);
+        let _ = self.rewatch(path, before.mode(), after.mode());
+        match after.mode() {
+            Some(_) => self.paths.insert(path.to_path_buf(), after),
+            None => self.paths.remove(path),
+        };
+    }
+
+    /// Change the mode `path` is watched with, restoring the old watch on failure
+    fn rewatch(
+        &mut self,
+        path: &Path,
+        before: Option<RecursiveMode>,
+        after: Option<RecursiveMode>,
+    ) -> Result<()> {
+        if before == after {
+            return Ok(());
+        }
+        if before.is_some() {
+            self.watcher.unwatch(path)?;
+        }
+        if let Some(mode) = after {
+            if let Err(e) = self.watcher.watch(path, mode) {
+                if let Some(mode) = before {
+                    let _ = self.watcher.watch(path, mode);
+                }
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A connected client
+struct Connection {
+    tx: mpsc::Sender<Response>,
+    subscriptions: HashMap<u64, (PathBuf, RecursiveMode)>,
+}
+
+impl Connection {
+    /// The ids of the subscriptions containing a path of `event`, all without paths
+    fn subscribed(&self, event: &Event) -> Vec<u64> {
+        let mut ids: Vec<u64> = self
+            .subscriptions
+            .iter()
+            .filter(|(_, (root, mode))| {
+                event.paths.is_empty()
+                    || event.paths.iter().any(|path| match mode {
+                        RecursiveMode::Recursive => path.starts_with(root),
+                        RecursiveMode::NonRecursive => {
+                            path == root || path.parent() == Some(root.as_path())
+                        }
+                    })
+            })
+            .map(|(id, _)| *id)
+            .collect();
+        ids.sort_unstable();
+        ids
+    }
+}
+
+impl Daemon {
+    /// Listen on the socket at `path`, watching with `config`.
+    ///
+    /// A socket left behind by a daemon which exited is replaced, a socket another daemon is
+    /// listening on fails with [`io::ErrorKind::AddrInUse`]. Overlapping watches of clients are
+    /// always merged, see [`WatchOverlap::Merge`].
+    pub fn bind<P: AsRef<Path>>(path: P, config: Config) -> io::Result<Self> {
+        let path = path.as_ref();
+        if path.exists() {
+            if UnixStream::connect(path).is_ok() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    format!("a daemon is listening on {}", path.display()),
+                ));
+            }
+            std::fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path)?;
+
+        let clients: Arc<Mutex<HashMap<u64, Connection>>> = Arc::default();
+        let clients_c = clients.clone();
+        let watcher = RecommendedWatcher::new(
+            move |event: Result<Event>| {
+                let clients = match clients_c.lock() {
+                    Ok(clients) => clients,
+                    Err(_) => return,
+                };
+                match event {
+                    Ok(event) => {
+                        for connection in clients.values() {
+                            let ids = connection.subscribed(&event);
+                            if !ids.is_empty() {
+                                let event = event.clone();
+                                let _ = connection.tx.send(Response::Event { ids, event });
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        for connection in clients.values() {
+                            let error = e.to_string();
+                            let _ = connection.tx.send(Response::Error { error });
+                        }
+                    }
+                }
+            },
+            config.with_watch_overlap(WatchOverlap::Merge),
+        )
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+
+        Ok(Self {
+            listener,
+            path: path.to_path_buf(),
+            watches: Arc::new(Mutex::new(Watches {
+                watcher,
+                paths: HashMap::new(),
+            })),
+            clients,
+            next_client: AtomicU64::new(0),
+        })
+    }
+
+    /// The socket the daemon listens on
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The paths watched with the OS for all clients, with their mode
+    pub fn watches(&self) -> Vec<(PathBuf, RecursiveMode)> {
+        let watches = self.watches.lock().expect("Can't lock daemon watches!");
+        let mut paths: Vec<_> = watches
+            .paths
+            .iter()
+            .filter_map(|(path, subscribers)| Some((path.clone(), subscribers.mode()?)))
+            .collect();
+        paths.sort();
+        paths
+    }
+
+    /// Serve clients, each on threads of its own, until the process exits.
+    ///
+    /// Failures to accept a connection are skipped.
+    pub fn run(&self) {
+        for stream in self.listener.incoming().flatten() {
+            let _ = self.serve(stream);
+        }
+    }
+
+    fn serve(&self, stream: UnixStream) -> io::Result<()> {
+        let client = self.next_client.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = mpsc::channel::<Response>();
+        let mut writer = stream.try_clone()?;
+        std::thread::Builder::new()
+            .name("notify-daemon writer".to_string())
+            .spawn(move || {
+                for response in rx {
+                    if write_json_line(&mut writer, &response).is_err() {
+                        let _ = writer.shutdown(std::net::Shutdown::Both);
+                        break;
+                    }
+                }
+            })?;
+
+        let connection = Connection {
+            tx: tx.clone(),
+            subscriptions: HashMap::new(),
+        };
+        if let Ok(mut clients) = self.clients.lock() {
+            clients.insert(client, connection);
+        }
+
+        let watches = self.watches.clone();
+        let clients = self.clients.clone();
+        std::thread::Builder::new()
+            .name("notify-daemon reader".to_string())
+            .spawn(move || {
+                for request in read_json_lines::<_, Request>(BufReader::new(stream)) {
+                    let response = match request {
+                        Ok(request) => handle(request, client, &watches, &clients),
+                        Err(_) => break,
+                    };
+                    let _ = tx.send(response);
+                }
+
+                // the client disconnected
+                let connection = clients.lock().ok().and_then(|mut c| c.remove(&client));
+                if let (Some(connection), Ok(mut watches)) = (connection, watches.lock()) {
+                    for (path, mode) in connection.subscriptions.values() {
+                        watches.release(path, *mode);
+                    }
+                }
+            })?;
+        Ok(())
+    }
+}
+
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Apply the request of `client`.
+///
+/// The clients are never locked while changing watches, which waits for the watcher and would
+/// deadlock with its event handler.
+fn handle(
+    request: Request,
+    client: u64,
+    watches: &Mutex<Watches>,
+    clients: &Mutex<HashMap<u64, Connection>>,
+) -> Response {
+    let failed = |id, error: &dyn std::fmt::Display| Response::Failed {
+        id,
+        error: error.to_string(),
+    };
+    let unsubscribe = |id| {
+        clients
+            .lock()
+            .ok()
+            .and_then(|mut clients| clients.get_mut(&client)?.subscriptions.remove(&id))
+    };
+
+    match request {
+        Request::Watch {
+            id,
+            path,
+            recursive,
+        } => {
+            let mode = if recursive {
+                RecursiveMode::Recursive
+            } else {
+                RecursiveMode::NonRecursive
+            };
+            let mut watches = match watches.lock() {
+                Ok(watches) => watches,
+                Err(e) => return failed(id, &e),
+            };
+            // a subscription with the same id is replaced
+            if let Some((path, mode)) = unsubscribe(id) {
+                watches.release(&path, mode);
+            }
+            if let Err(e) = watches.acquire(&path, mode) {
+                return failed(id, &e);
+            }
+            drop(watches);
+            if let Ok(mut clients) = clients.lock() {
+                if let Some(connection) = clients.get_mut(&client) {
+                    connection.subscriptions.insert(id, (path, mode));
+                }
+            }
+            Response::Done { id }
+        }
+        Request::Unwatch { id } => match unsubscribe(id) {
+            Some((path, mode)) => {
+                if let Ok(mut watches) = watches.lock() {
+                    watches.release(&path, mode);
+                }
+                Response::Done { id }
+            }
+            None => failed(id, &notify::Error::watch_not_found()),
+        },
+    }
+}
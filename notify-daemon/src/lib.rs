@@ -0,0 +1,172 @@
+//! Sharing the watches of notify between the processes of a machine
+//!
+//! Every process watching files with notify installs its own watches with the OS, and on Linux
+//! all processes of a user share one limit of inotify watches. The `notify-daemon` binary watches
+//! for all of them instead: processes connect with a [`Client`], subscribe to paths, and receive
+//! the events below them over a Unix socket. Paths watched by several clients are watched once.
+//!
+//! ```rust,no_run
+//! # use std::path::Path;
+//! use notify::RecursiveMode;
+//! use notify_daemon::Client;
+//!
+//! # fn main() -> notify::Result<()> {
+//! let mut client = Client::connect_default(|event| println!("{:?}", event))?;
+//! client.watch(Path::new("src"), RecursiveMode::Recursive)?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The daemon listens on [`default_socket_path`] unless given another path as its argument, and
+//! can be embedded in a program of its own with [`Daemon`]. Messages are the versioned JSON Lines
+//! of `notify::serialization`, see [`Request`] and [`Response`].
+//!
+//! Only Unix sockets are supported for now, there is no named pipe transport for Windows yet.
+
+#[cfg(unix)]
+mod client;
+#[cfg(unix)]
+mod daemon;
+
+#[cfg(unix)]
+pub use client::Client;
+#[cfg(unix)]
+pub use daemon::Daemon;
+
+use notify::serialization::Versioned;
+use notify::Event;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// A message from a client to the daemon
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum Request {
+    /// Subscribe to the events of `path`, answered with [`Response::Done`] or [`Response::Failed`]
+    Watch {
+        /// Id of the subscription, chosen by the client
+        id: u64,
+        /// Absolute path to watch
+        path: PathBuf,
+        /// Whether to watch the directories below `path` too
+        recursive: bool,
+    },
+    /// End the subscription `id`, answered with [`Response::Done`] or [`Response::Failed`]
+    Unwatch {
+        /// Id of the subscription
+        id: u64,
+    },
+}
+
+impl Versioned for Request {
+    const TYPE: &'static str = "daemon-request";
+    const VERSION: u32 = 1;
+}
+
+/// A message from the daemon to a client
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum Response {
+    /// The request for subscription `id` succeeded
+    Done {
+        /// Id of the subscription
+        id: u64,
+    },
+    /// The request for subscription `id` failed
+    Failed {
+        /// Id of the subscription
+        id: u64,
+        /// Description of the error
+        error: String,
+    },
+    /// An event of the subscriptions `ids`
+    Event {
+        /// Ids of the subscriptions containing a path of the event
+        ids: Vec<u64>,
+        /// The event
+        event: Event,
+    },
+    /// An error of the daemon's watcher, sent to all clients
+    Error {
+        /// Description of the error
+        error: String,
+    },
+}
+
+impl Versioned for Response {
+    const TYPE: &'static str = "daemon-response";
+    const VERSION: u32 = 1;
+}
+
+/// The socket the daemon listens on by default
+///
+/// This is `notify-rs.sock` in `$XDG_RUNTIME_DIR`, or `notify-rs-$USER.sock` in the temporary
+/// directory if it isn't set.
+pub fn default_socket_path() -> PathBuf {
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => PathBuf::from(dir).join("notify-rs.sock"),
+        None => {
+            let user = std::env::var("USER").unwrap_or_else(|_| "default".to_owned());
+            std::env::temp_dir().join(format!("notify-rs-{}.sock", user))
+        }
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn clients_share_the_watches_of_the_daemon() {
+    use notify::event::EventKind;
+    use notify::{Config, RecursiveMode};
+    use std::{fs, sync::mpsc, time::Duration};
+
+    let dir = std::env::temp_dir().join(format!("notify-daemon-{}", std::process::id()));
+    let watched = dir.join("watched");
+    fs::create_dir_all(watched.join("nested")).unwrap();
+    let socket = dir.join("daemon.sock");
+    let daemon = std::sync::Arc::new(Daemon::bind(&socket, Config::default()).unwrap());
+    let daemon_c = daemon.clone();
+    std::thread::spawn(move || daemon_c.run());
+    let watches = || daemon.watches();
+
+    let (tx_a, rx_a) = mpsc::channel();
+    let (tx_b, rx_b) = mpsc::channel();
+    let mut a = Client::connect(&socket, tx_a).unwrap();
+    let mut b = Client::connect(&socket, tx_b).unwrap();
+    a.watch(&watched, RecursiveMode::Recursive).unwrap();
+    b.watch(&watched, RecursiveMode::NonRecursive).unwrap();
+    assert_eq!(watches(), [(watched.clone(), RecursiveMode::Recursive)]);
+    assert!(b
+        .watch(&dir.join("missing"), RecursiveMode::Recursive)
+        .is_err());
+
+    fs::write(watched.join("nested").join("file"), "").unwrap();
+    fs::write(watched.join("file"), "").unwrap();
+    let created = |rx: &mpsc::Receiver<notify::Result<Event>>| {
+        let mut paths = Vec::new();
+        while let Ok(event) = rx.recv_timeout(Duration::from_millis(500)) {
+            let event = event.unwrap();
+            if let EventKind::Create(_) = event.kind {
+                paths.extend(event.paths);
+            }
+        }
+        paths
+    };
+    assert_eq!(
+        created(&rx_a),
+        [watched.join("nested").join("file"), watched.join("file")]
+    );
+    assert_eq!(created(&rx_b), [watched.join("file")]);
+
+    drop(a);
+    b.unwatch(&watched).unwrap();
+    for _ in 0..50 {
+        if watches().is_empty() {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    assert!(watches().is_empty());
+    fs::remove_dir_all(dir).unwrap();
+}
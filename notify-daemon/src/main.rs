@@ -0,0 +1,28 @@
+//! The daemon, listening on the socket given as its argument or the default one
+
+#[cfg(unix)]
+fn main() {
+    let path = std::env::args_os()
+        .nth(1)
+        .map_or_else(notify_daemon::default_socket_path, Into::into);
+    let config = match notify::Config::from_env() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("notify-daemon: {}", e);
+            std::process::exit(2);
+        }
+    };
+    match notify_daemon::Daemon::bind(&path, config) {
+        Ok(daemon) => daemon.run(),
+        Err(e) => {
+            eprintln!("notify-daemon: can't listen on {}: {}", path.display(), e);
+            std::process::exit(1);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn main() {
+    eprintln!("notify-daemon: only Unix sockets are supported for now");
+    std::process::exit(1);
+}
@@ -0,0 +1,595 @@
+//! At-least-once delivery of debounced batches
+
+use crate::{DebounceEventHandler, DebounceEventResult, DebouncedEvent};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    io,
+    sync::{Arc, Condvar, Mutex},
+    time::{Duration, Instant},
+};
+#[cfg(feature = "serialization")]
+use std::{
+    fs,
+    io::{BufRead, Write},
+    path::PathBuf,
+};
+
+/// A batch of debounced events, with the id to acknowledge it with
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Batch {
+    /// Id of the batch, unique for an [`AckDelivery`] and its state file
+    pub id: u64,
+    /// The events
+    pub events: Vec<DebouncedEvent>,
+}
+
+#[cfg(feature = "serialization")]
+impl notify::serialization::Versioned for Batch {
+    const TYPE: &'static str = "debounced-batch";
+    const VERSION: u32 = 1;
+}
+
+/// A [`DebounceEventHandler`] delivering each batch until it is acknowledged.
+///
+/// Batches are kept until the consumer acknowledges them with [`Acks::ack`], and delivered again
+/// once they weren't acknowledged within the redelivery timeout. With [`AckDelivery::persistent`]
+/// they are kept in a state file, so the batches a process didn't acknowledge are delivered again
+/// by the next one. Consumers may see a batch more than once, but not lose one.
+///
+/// ```rust,no_run
+/// # use std::path::Path;
+/// # use std::time::Duration;
+/// use notify_debouncer_mini::{notify::*, new_debouncer, AckDelivery, Batch};
+///
+/// # fn sync(batch: &Batch) -> bool { true }
+/// # fn main() {
+/// let (tx, rx) = std::sync::mpsc::channel::<Batch>();
+/// let delivery = AckDelivery::new(tx, Duration::from_secs(30)).unwrap();
+/// let acks = delivery.acks();
+/// let mut debouncer = new_debouncer(Duration::from_secs(1), None, delivery).unwrap();
+/// debouncer.watcher().watch(Path::new("."), RecursiveMode::Recursive).unwrap();
+///
+/// for batch in rx {
+///     if sync(&batch) {
+///         acks.ack(batch.id).unwrap();
+///     }
+/// }
+/// # }
+/// ```
+///
+/// Batches are passed to the handler on the debouncer's thread when they are new, and on a thread
+/// of the delivery when they are delivered again. Errors are not passed to the handler.
+pub struct AckDelivery {
+    shared: Arc<Shared>,
+    handler: Handler,
+}
+
+type Handler = Arc<Mutex<Box<dyn AckHandler>>>;
+
+/// The consumer of an [`AckDelivery`], implemented for closures and channel senders taking
+/// [`Batch`]es.
+pub trait AckHandler: Send + 'static {
+    /// Handles a batch, which is delivered again until it is acknowledged.
+    fn handle_batch(&mut self, batch: Batch);
+}
+
+impl<F> AckHandler for F
+where
+    F: FnMut(Batch) + Send + 'static,
+{
+    fn handle_batch(&mut self, batch: Batch) {
+        (self)(batch);
+    }
+}
+
+#[cfg(feature = "crossbeam")]
+impl AckHandler for crossbeam_channel::Sender<Batch> {
+    fn handle_batch(&mut self, batch: Batch) {
+        let _ = self.send(batch);
+    }
+}
+
+impl AckHandler for std::sync::mpsc::Sender<Batch> {
+    fn handle_batch(&mut self, batch: Batch) {
+        let _ = self.send(batch);
+    }
+}
+
+/// Acknowledges the batches of an [`AckDelivery`], shared with the consumer.
+#[derive(Clone)]
+pub struct Acks(Arc<Shared>);
+
+impl Acks {
+    /// Acknowledge the batch `id`, so it isn't delivered again, returns whether it was pending.
+    ///
+    /// Fails if the state file can't be written, the batch is acknowledged in memory regardless.
+    pub fn ack(&self, id: u64) -> io::Result<bool> {
+        let mut state = self.0.lock();
+        let pending = state.pending.remove(&id).is_some();
+        if pending {
+            state.record(Change::Acked(id))?;
+        }
+        Ok(pending)
+    }
+
+    /// The ids of the batches not acknowledged yet, oldest first
+    pub fn pending(&self) -> Vec<u64> {
+        self.0.lock().pending.keys().copied().collect()
+    }
+}
+
+struct Shared {
+    state: Mutex<State>,
+    wake: Condvar,
+}
+
+impl Shared {
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().expect("Can't lock ack delivery!")
+    }
+}
+
+struct State {
+    pending: BTreeMap<u64, Pending>,
+    next_id: u64,
+    redeliver_after: Duration,
+    stopped: bool,
+    #[cfg(feature = "serialization")]
+    file: Option<StateFile>,
+}
+
+/// The state file of a persistent delivery
+#[cfg(feature = "serialization")]
+struct StateFile {
+    path: PathBuf,
+    /// The file opened for appending changes, until it is rewritten
+    journal: Option<fs::File>,
+    /// The number of changes appended since the file was rewritten
+    changes: usize,
+    /// Whether appending failed, so that the file has to be rewritten
+    broken: bool,
+}
+
+/// The file is rewritten once this many more changes were appended than batches are pending.
+#[cfg(feature = "serialization")]
+const COMPACT_AFTER: usize = 64;
+
+/// A change of the pending batches
+#[derive(Clone, Copy)]
+#[cfg_attr(not(feature = "serialization"), allow(dead_code))]
+enum Change {
+    Added(u64),
+    Acked(u64),
+}
+
+struct Pending {
+    events: Vec<DebouncedEvent>,
+    /// Time of the last delivery, `None` for batches loaded from the state file
+    delivered: Option<Instant>,
+}
+
+/// The first line of a state file
+#[cfg(feature = "serialization")]
+#[derive(Serialize, Deserialize)]
+struct SavedState {
+    next_id: u64,
+    pending: Vec<Batch>,
+}
+
+#[cfg(feature = "serialization")]
+impl notify::serialization::Versioned for SavedState {
+    const TYPE: &'static str = "ack-delivery-state";
+    const VERSION: u32 = 1;
+}
+
+/// The lines of a state file after the first one
+#[cfg(feature = "serialization")]
+#[derive(Serialize, Deserialize)]
+enum SavedChange {
+    Added(Batch),
+    Acked(u64),
+}
+
+#[cfg(feature = "serialization")]
+impl notify::serialization::Versioned for SavedChange {
+    const TYPE: &'static str = "ack-delivery-change";
+    const VERSION: u32 = 1;
+}
+
+impl State {
+    /// The batches due at `now`, marked as delivered, and the time the next one is due
+    fn take_due(&mut self, now: Instant) -> (Vec<Batch>, Option<Instant>) {
+        let redeliver_after = self.redeliver_after;
+        let mut due = Vec::new();
+        let mut next = None;
+        for (id, pending) in self.pending.iter_mut() {
+            let at = match pending.delivered {
+                Some(delivered) if now.saturating_duration_since(delivered) < redeliver_after => {
+                    delivered + redeliver_after
+                }
+                _ => {
+                    pending.delivered = Some(now);
+                    due.push(Batch {
+                        id: *id,
+                        events: pending.events.clone(),
+                    });
+                    now + redeliver_after
+                }
+            };
+            next = Some(next.map_or(at, |next: Instant| next.min(at)));
+        }
+        (due, next)
+    }
+
+    /// Record `change` in the state file, appending it or rewriting the file.
+    #[cfg(feature = "serialization")]
+    fn record(&mut self, change: Change) -> io::Result<()> {
+        let pending = self.pending.len();
+        let file = match &mut self.file {
+            Some(file) => file,
+            None => return Ok(()),
+        };
+        if file.broken || file.changes >= pending + COMPACT_AFTER {
+            return self.rewrite();
+        }
+        let change = match change {
+            Change::Added(id) => SavedChange::Added(Batch {
+                id,
+                events: self.pending[&id].events.clone(),
+            }),
+            Change::Acked(id) => SavedChange::Acked(id),
+        };
+        let line = notify::serialization::to_json_line(&change)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let appended = match &mut file.journal {
+            Some(journal) => journal.write_all((line + "\n").as_bytes()),
+            None => fs::OpenOptions::new()
+                .append(true)
+                .open(&file.path)
+                .and_then(|mut journal| {
+                    journal.write_all((line + "\n").as_bytes())?;
+                    file.journal = Some(journal);
+                    Ok(())
+                }),
+        };
+        match appended {
+            Ok(()) => file.changes += 1,
+            // a partly written line would end up in the middle of the file
+            Err(_) => file.broken = true,
+        }
+        appended
+    }
+
+    #[cfg(not(feature = "serialization"))]
+    fn record(&mut self, _change: Change) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Write the pending batches to a new state file, replacing the old one at once
+    #[cfg(feature = "serialization")]
+    fn rewrite(&mut self) -> io::Result<()> {
+        let file = match &mut self.file {
+            Some(file) => file,
+            None => return Ok(()),
+        };
+        let saved = SavedState {
+            next_id: self.next_id,
+            pending: self
+                .pending
+                .iter()
+                .map(|(id, pending)| Batch {
+                    id: *id,
+                    events: pending.events.clone(),
+                })
+                .collect(),
+        };
+        let line = notify::serialization::to_json_line(&saved)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let mut tmp = file.path.clone().into_os_string();
+        tmp.push(".tmp");
+        file.journal = None;
+        file.broken = true;
+        fs::write(&tmp, line + "\n")?;
+        fs::rename(&tmp, &file.path)?;
+        file.changes = 0;
+        file.broken = false;
+        Ok(())
+    }
+}
+
+impl AckDelivery {
+    /// Deliver batches to `handler` until they are acknowledged, again every `redeliver_after`.
+    pub fn new<H: AckHandler>(handler: H, redeliver_after: Duration) -> io::Result<Self> {
+        Self::start(
+            handler,
+            State {
+                pending: BTreeMap::new(),
+                next_id: 1,
+                redeliver_after,
+                stopped: false,
+                #[cfg(feature = "serialization")]
+                file: None,
+            },
+        )
+    }
+
+    /// Like [`AckDelivery::new`], keeping the pending batches in the state file at `path`.
+    ///
+    /// The batches left in the file by an earlier delivery are delivered right away. The file holds
+    /// the pending batches followed by the batches added and acknowledged since, a line of the
+    /// versioned JSON of `notify::serialization` each. Once the changes outnumber the pending
+    /// batches, the file is rewritten through a temporary file.
+    #[cfg(feature = "serialization")]
+    pub fn persistent<H: AckHandler, P: Into<PathBuf>>(
+        handler: H,
+        redeliver_after: Duration,
+        path: P,
+    ) -> io::Result<Self> {
+        let invalid = |e| io::Error::new(io::ErrorKind::InvalidData, e);
+        let path = path.into();
+        let mut state = State {
+            pending: BTreeMap::new(),
+            next_id: 1,
+            redeliver_after,
+            stopped: false,
+            file: None,
+        };
+        let mut changes = 0;
+        match fs::File::open(&path) {
+            Ok(file) => {
+                let mut lines = io::BufReader::new(file).lines().peekable();
+                if let Some(line) = lines.next() {
+                    let saved: SavedState =
+                        notify::serialization::from_json_line(line?.trim()).map_err(invalid)?;
+                    state.next_id = saved.next_id;
+                    for batch in saved.pending {
+                        state.pending.insert(
+                            batch.id,
+                            Pending {
+                                events: batch.events,
+                                delivered: None,
+                            },
+                        );
+                    }
+                }
+                while let Some(line) = lines.next() {
+                    let change = match notify::serialization::from_json_line(line?.trim()) {
+                        Ok(change) => change,
+                        // the last line may be cut short by a crash while appending it
+                        Err(_) if lines.peek().is_none() => break,
+                        Err(e) => return Err(invalid(e)),
+                    };
+                    match change {
+                        SavedChange::Added(batch) => {
+                            state.next_id = state.next_id.max(batch.id + 1);
+                            state.pending.insert(
+                                batch.id,
+                                Pending {
+                                    events: batch.events,
+                                    delivered: None,
+                                },
+                            );
+                        }
+                        SavedChange::Acked(id) => {
+                            state.pending.remove(&id);
+                        }
+                    }
+                    changes += 1;
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        state.file = Some(StateFile {
+            path,
+            journal: None,
+            changes,
+            broken: false,
+        });
+        // starts a file which the changes can be appended to
+        if changes == 0 {
+            state.rewrite()?;
+        }
+        Self::start(handler, state)
+    }
+
+    fn start<H: AckHandler>(handler: H, state: State) -> io::Result<Self> {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(state),
+            wake: Condvar::new(),
+        });
+        let handler: Handler = Arc::new(Mutex::new(Box::new(handler)));
+        let shared_c = shared.clone();
+        let handler_c = handler.clone();
+        std::thread::Builder::new()
+            .name("notify-rs debouncer redelivery".to_string())
+            .spawn(move || loop {
+                let due = {
+                    let mut state = shared_c.lock();
+                    if state.stopped {
+                        break;
+                    }
+                    let (due, next) = state.take_due(Instant::now());
+                    if due.is_empty() {
+                        let wait = next.map_or(state.redeliver_after, |next| {
+                            next.saturating_duration_since(Instant::now())
+                        });
+                        let _ = shared_c.wake.wait_timeout(state, wait);
+                        continue;
+                    }
+                    due
+                };
+                let mut handler = handler_c.lock().expect("Can't lock ack handler!");
+                for batch in due {
+                    handler.handle_batch(batch);
+                }
+            })?;
+
+        Ok(Self { shared, handler })
+    }
+
+    /// The handle to acknowledge batches with
+    pub fn acks(&self) -> Acks {
+        Acks(self.shared.clone())
+    }
+}
+
+impl DebounceEventHandler for AckDelivery {
+    fn handle_event(&mut self, event: DebounceEventResult) {
+        let events = match event {
+            Ok(events) => events,
+            Err(_) => return,
+        };
+
+        let batch = {
+            let mut state = self.shared.lock();
+            let id = state.next_id;
+            state.next_id += 1;
+            state.pending.insert(
+                id,
+                Pending {
+                    events: events.clone(),
+                    delivered: Some(Instant::now()),
+                },
+            );
+            // the batch is delivered again from memory until the file can be written
+            let _ = state.record(Change::Added(id));
+            Batch { id, events }
+        };
+        self.shared.wake.notify_one();
+        let mut handler = self.handler.lock().expect("Can't lock ack handler!");
+        handler.handle_batch(batch);
+    }
+}
+
+impl Drop for AckDelivery {
+    fn drop(&mut self) {
+        self.shared.lock().stopped = true;
+        self.shared.wake.notify_one();
+    }
+}
+
+#[test]
+fn unacknowledged_batches_are_delivered_again() {
+    use crate::DebouncedEventKind;
+    use std::{path::PathBuf, sync::mpsc};
+
+    let batch = |path: &str| {
+        Ok(vec![DebouncedEvent::new(
+            PathBuf::from(path),
+            DebouncedEventKind::Any,
+        )])
+    };
+    let (tx, rx) = mpsc::channel();
+    let mut delivery = AckDelivery::new(tx, Duration::from_millis(100)).unwrap();
+    let acks = delivery.acks();
+
+    delivery.handle_event(batch("a"));
+    delivery.handle_event(batch("b"));
+    let ids: Vec<u64> = rx.try_iter().map(|batch| batch.id).collect();
+    assert_eq!(ids, [1, 2]);
+    assert!(acks.ack(1).unwrap());
+    assert!(!acks.ack(1).unwrap());
+    assert_eq!(acks.pending(), [2]);
+
+    let again = rx.recv_timeout(Duration::from_secs(1)).unwrap();
+    assert_eq!(again.id, 2);
+    assert_eq!(again.events[0].path, PathBuf::from("b"));
+    assert!(acks.ack(2).unwrap());
+    assert!(rx.recv_timeout(Duration::from_millis(300)).is_err());
+}
+
+#[cfg(feature = "serialization")]
+#[test]
+fn pending_batches_survive_a_restart() {
+    use crate::DebouncedEventKind;
+    use std::sync::mpsc;
+
+    let path = std::env::temp_dir().join(format!("notify-ack-{}", std::process::id()));
+    let _ = fs::remove_file(&path);
+    let batch = |path: &str| {
+        Ok(vec![DebouncedEvent::new(
+            PathBuf::from(path),
+            DebouncedEventKind::Any,
+        )])
+    };
+
+    let (tx, rx) = mpsc::channel();
+    let mut delivery = AckDelivery::persistent(tx, Duration::from_secs(60), &path).unwrap();
+    delivery.handle_event(batch("a"));
+    delivery.handle_event(batch("b"));
+    delivery.acks().ack(1).unwrap();
+    assert_eq!(rx.try_iter().count(), 2);
+    drop(delivery);
+
+    let (tx, rx) = mpsc::channel();
+    let mut delivery = AckDelivery::persistent(tx, Duration::from_secs(60), &path).unwrap();
+    let again = rx.recv_timeout(Duration::from_secs(1)).unwrap();
+    assert_eq!(
+        (again.id, again.events[0].path.clone()),
+        (2, PathBuf::from("b"))
+    );
+    delivery.handle_event(batch("c"));
+    assert_eq!(rx.recv_timeout(Duration::from_secs(1)).unwrap().id, 3);
+    drop(delivery);
+    fs::remove_file(&path).unwrap();
+}
+
+#[cfg(feature = "serialization")]
+#[test]
+fn state_files_are_appended_to_and_rewritten() {
+    use crate::DebouncedEventKind;
+    use std::sync::mpsc;
+
+    let path = std::env::temp_dir().join(format!("notify-ack-journal-{}", std::process::id()));
+    let _ = fs::remove_file(&path);
+    let batch = |path: &str| {
+        Ok(vec![DebouncedEvent::new(
+            PathBuf::from(path),
+            DebouncedEventKind::Any,
+        )])
+    };
+    let lines = || fs::read_to_string(&path).unwrap().lines().count();
+
+    let (tx, rx) = mpsc::channel();
+    let mut delivery = AckDelivery::persistent(tx, Duration::from_secs(60), &path).unwrap();
+    delivery.handle_event(batch("a"));
+    delivery.handle_event(batch("b"));
+    delivery.handle_event(batch("c"));
+    delivery.acks().ack(1).unwrap();
+    assert_eq!(lines(), 5);
+    drop(delivery);
+    drop(rx);
+
+    // a line cut short by a crash is ignored
+    let mut file = fs::OpenOptions::new().append(true).open(&path).unwrap();
+    file.write_all(b"{\"type\":\"ack-del").unwrap();
+    drop(file);
+    let (tx, rx) = mpsc::channel();
+    let mut delivery = AckDelivery::persistent(tx, Duration::from_secs(60), &path).unwrap();
+    let mut again: Vec<u64> = (0..2)
+        .map(|_| rx.recv_timeout(Duration::from_secs(1)).unwrap().id)
+        .collect();
+    again.sort_unstable();
+    assert_eq!(again, [2, 3]);
+
+    // the file shrinks once the changes outnumber the pending batches
+    for _ in 0..COMPACT_AFTER * 2 {
+        delivery.handle_event(batch("d"));
+        let id = rx.recv_timeout(Duration::from_secs(1)).unwrap().id;
+        delivery.acks().ack(id).unwrap();
+    }
+    assert!(lines() <= 1 + 2 + COMPACT_AFTER + 1);
+    assert_eq!(delivery.acks().pending(), [2, 3]);
+    drop(delivery);
+
+    let (tx, rx) = mpsc::channel();
+    let delivery = AckDelivery::persistent(tx, Duration::from_secs(60), &path).unwrap();
+    assert_eq!(delivery.acks().pending(), [2, 3]);
+    assert_eq!(rx.recv_timeout(Duration::from_secs(1)).unwrap().id, 2);
+    drop(delivery);
+    fs::remove_file(&path).unwrap();
+}
@@ -40,6 +40,32 @@
 //! # }
 //! ```
 //!
+//! # Throttling
+//!
+//! By default an event is only emitted once a path had no further events for the timeout, so a long
+//! running write is reported once at its end, or as continuous events every timeout.
+//! With [`DebounceMode::Throttle`] the first event of a path is emitted right away instead, and
+//! further events of the path are summarized in one event per timeout while they keep coming:
+//!
+//! ```rust,no_run
+//! # use std::path::Path;
+//! # use std::time::Duration;
+//! use notify_debouncer_mini::{notify::*,new_debouncer_with_config,DebounceMode,DebouncerConfig,DebounceEventResult};
+//!
+//! # fn main() {
+//!     let config = DebouncerConfig::default()
+//!         .with_timeout(Duration::from_millis(250))
+//!         .with_mode(DebounceMode::Throttle);
+//!     let mut debouncer = new_debouncer_with_config::<_, RecommendedWatcher>(config, |res: DebounceEventResult| {
+//!         if let Ok(events) = res {
+//!             events.iter().for_each(|e|println!("Event {:?} for {:?}",e.kind,e.path));
+//!         }
+//!     }).unwrap();
+//!
+//!     debouncer.watcher().watch(Path::new("."), RecursiveMode::Recursive).unwrap();
+//! # }
+//! ```
+//!
 //! # Features
 //!
 //! The following crate features can be turned on or off in your cargo dependency config:
@@ -47,10 +73,22 @@
 //! - `crossbeam` enabled by default, adds [`DebounceEventHandler`](DebounceEventHandler) support for crossbeam channels.
 //!   Also enables crossbeam-channel in the re-exported notify. You may want to disable this when using the tokio async runtime.
 //! - `serde` enables serde support for events.
+//! - `serialization` adds [`DebouncedEvent`] to the versioned wire formats of `notify::serialization`, allows
+//!   deserializing [`DebouncerConfig`], and keeps the pending batches of [`AckDelivery::persistent`] in a file.
+//!
+//! # Language servers
+//!
+//! The [`lsp`] module turns debounced batches into the file events of the LSP `workspace/didChangeWatchedFiles`
+//! notification, for the glob patterns registered by a language server.
+mod ack;
+pub mod lsp;
+mod runner;
+
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 use std::{
     collections::HashMap,
+    fmt,
     path::PathBuf,
     sync::{
         atomic::{AtomicBool, Ordering},
@@ -59,8 +97,11 @@ use std::{
     time::{Duration, Instant},
 };
 
+pub use ack::{AckDelivery, AckHandler, Acks, Batch};
 pub use notify;
-use notify::{Error, ErrorKind, Event, RecommendedWatcher, Watcher};
+pub use runner::{Cancellation, OverlapPolicy, Runner};
+use notify::filter::{EditorNoise, EventFilter};
+use notify::{Error, ErrorKind, Event, EventMap, RecommendedWatcher, Watcher};
 
 /// The set of requirements for watcher debounce event handling functions.
 ///
@@ -90,6 +131,47 @@ use notify::{Error, ErrorKind, Event, RecommendedWatcher, Watcher};
 pub trait DebounceEventHandler: Send + 'static {
     /// Handles an event.
     fn handle_event(&mut self, event: DebounceEventResult);
+
+    /// Handles an event, returning the events of the batch which couldn't be processed.
+    ///
+    /// The debouncer delivers the returned events again after the [retry backoff](DebouncerConfig::with_retry_backoff),
+    /// unless newer events of their paths were delivered in between. Burst markers aren't delivered again.
+    ///
+    /// Calls [`handle_event`](Self::handle_event) and accepts all events by default. Closures can reject events
+    /// when wrapped in [`Retrying`].
+    fn try_handle_event(&mut self, event: DebounceEventResult) -> Result<(), Vec<DebouncedEvent>> {
+        self.handle_event(event);
+        Ok(())
+    }
+
+    /// Handles a batch of events in a buffer lent by the debouncer, which keeps it for the next batch.
+    ///
+    /// Draining the buffer instead of taking the events avoids allocating a vec per tick for high-frequency
+    /// emitters. The events left in the buffer are delivered again like the ones rejected by
+    /// [`try_handle_event`](Self::try_handle_event), which this calls by default. Errors are handled by
+    /// [`handle_event`](Self::handle_event).
+    ///
+    /// ```rust,no_run
+    /// # use notify_debouncer_mini::{DebounceEventHandler, DebounceEventResult, DebouncedEvent};
+    /// struct PathCounter(usize);
+    ///
+    /// impl DebounceEventHandler for PathCounter {
+    ///     fn handle_event(&mut self, event: DebounceEventResult) {
+    ///         if let Ok(events) = event {
+    ///             self.0 += events.len();
+    ///         }
+    ///     }
+    ///
+    ///     fn handle_batch(&mut self, events: &mut Vec<DebouncedEvent>) {
+    ///         self.0 += events.drain(..).count();
+    ///     }
+    /// }
+    /// ```
+    fn handle_batch(&mut self, events: &mut Vec<DebouncedEvent>) {
+        if let Err(rejected) = self.try_handle_event(Ok(std::mem::take(events))) {
+            *events = rejected;
+        }
+    }
 }
 
 impl<F> DebounceEventHandler for F
@@ -101,6 +183,42 @@ where
     }
 }
 
+/// An event handler returning the events it couldn't process, see [`DebounceEventHandler::try_handle_event`].
+///
+/// ```rust,no_run
+/// # use std::path::Path;
+/// # use std::time::Duration;
+/// use notify_debouncer_mini::{new_debouncer, DebounceEventResult, Retrying};
+///
+/// # fn upload(path: &Path) -> std::io::Result<()> { Ok(()) }
+/// # fn main() {
+/// let mut debouncer = new_debouncer(Duration::from_secs(1), None, Retrying(|res: DebounceEventResult| {
+///     match res {
+///         // the failed uploads are tried again later
+///         Ok(events) => {
+///             let failed: Vec<_> = events.into_iter().filter(|e| upload(&e.path).is_err()).collect();
+///             if failed.is_empty() { Ok(()) } else { Err(failed) }
+///         }
+///         Err(_) => Ok(()),
+///     }
+/// })).unwrap();
+/// # }
+/// ```
+pub struct Retrying<F>(pub F);
+
+impl<F> DebounceEventHandler for Retrying<F>
+where
+    F: FnMut(DebounceEventResult) -> Result<(), Vec<DebouncedEvent>> + Send + 'static,
+{
+    fn handle_event(&mut self, event: DebounceEventResult) {
+        let _ = (self.0)(event);
+    }
+
+    fn try_handle_event(&mut self, event: DebounceEventResult) -> Result<(), Vec<DebouncedEvent>> {
+        (self.0)(event)
+    }
+}
+
 #[cfg(feature = "crossbeam")]
 impl DebounceEventHandler for crossbeam_channel::Sender<DebounceEventResult> {
     fn handle_event(&mut self, event: DebounceEventResult) {
@@ -120,16 +238,29 @@ struct EventData {
     insert: Instant,
     /// Last Update
     update: Instant,
+    /// Whether events were held back since the insertion, when throttling
+    pending: bool,
+    /// Whether the path was created by the first event and nothing was emitted for it since
+    created: bool,
+    /// Whether the path was removed by the last event
+    removed: bool,
 }
 
 impl EventData {
-    fn new_any() -> Self {
-        let time = Instant::now();
+    fn new_any(time: Instant) -> Self {
         Self {
-            insert: time.clone(),
+            insert: time,
             update: time,
+            pending: false,
+            created: false,
+            removed: false,
         }
     }
+
+    /// Whether the path only existed while its events were debounced
+    fn is_ephemeral(&self) -> bool {
+        self.created && self.removed
+    }
 }
 
 /// A result of debounced events.
@@ -145,6 +276,265 @@ pub enum DebouncedEventKind {
     Any,
     /// Event but debounce timed out (for example continuous writes)
     AnyContinuous,
+    /// Summary of the events held back by [`DebounceMode::Throttle`] since the last event for the path
+    AnyThrottled,
+    /// First event after the watched paths were idle, with the path of that event
+    ///
+    /// Only emitted if enabled with [`DebouncerConfig::with_burst_markers`], before any other event of the burst.
+    BurstStart,
+    /// The watched paths became idle, with the path of the last event
+    ///
+    /// Only emitted if enabled with [`DebouncerConfig::with_burst_markers`], after all other events of the burst.
+    BurstEnd,
+}
+
+/// How the debouncer limits the events of a path.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Deserialize))]
+#[cfg_attr(feature = "serialization", serde(rename_all = "snake_case"))]
+#[non_exhaustive]
+pub enum DebounceMode {
+    /// Wait until a path had no events for the timeout, emitting [`DebouncedEventKind::AnyContinuous`] events in between
+    /// if they don't stop.
+    Debounce,
+    /// Emit the first event of a path immediately, then at most one [`DebouncedEventKind::AnyThrottled`] event per timeout
+    /// while events keep coming.
+    Throttle,
+}
+
+impl fmt::Display for DebouncedEventKind {
+    /// Formats the kind like [`notify::EventKind`], for example `ANY(continuous)`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Any => "ANY",
+            Self::AnyContinuous => "ANY(continuous)",
+            Self::AnyThrottled => "ANY(throttled)",
+            Self::BurstStart => "BURST_START",
+            Self::BurstEnd => "BURST_END",
+        })
+    }
+}
+
+impl Default for DebounceMode {
+    fn default() -> Self {
+        Self::Debounce
+    }
+}
+
+/// Debouncer configuration
+///
+/// With the `serialization` feature, the config can be deserialized like [`notify::Config`], with the
+/// notify config in `notify_config`. Missing fields keep their default, and durations are parsed as described in
+/// [`notify::units`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serialization", derive(Deserialize))]
+#[cfg_attr(feature = "serialization", serde(default, deny_unknown_fields))]
+pub struct DebouncerConfig {
+    #[cfg_attr(
+        feature = "serialization",
+        serde(deserialize_with = "notify::units::deserialize_duration")
+    )]
+    timeout: Duration,
+    #[cfg_attr(
+        feature = "serialization",
+        serde(deserialize_with = "notify::units::deserialize_option_duration")
+    )]
+    tick_rate: Option<Duration>,
+    mode: DebounceMode,
+    #[cfg_attr(
+        feature = "serialization",
+        serde(deserialize_with = "notify::units::deserialize_option_duration")
+    )]
+    burst_markers: Option<Duration>,
+    suppress_ephemeral: bool,
+    editor_noise: Vec<EditorNoise>,
+    #[cfg_attr(
+        feature = "serialization",
+        serde(deserialize_with = "notify::units::deserialize_duration")
+    )]
+    retry_backoff: Duration,
+    coarse_clock: bool,
+    #[cfg_attr(feature = "serialization", serde(skip))]
+    priority_paths: Option<EventFilter>,
+    #[cfg_attr(feature = "serialization", serde(skip))]
+    event_map: Option<EventMap>,
+    notify_config: notify::Config,
+}
+
+impl DebouncerConfig {
+    /// Amount of time after which a debounced event is emitted, or in [`DebounceMode::Throttle`] the minimum
+    /// time between two events of a path.
+    ///
+    /// 500ms by default.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Returns current setting
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Interval at which pending events are checked, must not be greater than the timeout.
+    ///
+    /// If None, a tick rate less than the timeout is selected. None by default.
+    pub fn with_tick_rate(mut self, tick_rate: Option<Duration>) -> Self {
+        self.tick_rate = tick_rate;
+        self
+    }
+
+    /// Returns current setting
+    pub fn tick_rate(&self) -> Option<Duration> {
+        self.tick_rate
+    }
+
+    /// How events of a path are limited.
+    ///
+    /// [`DebounceMode::Debounce`] by default.
+    pub fn with_mode(mut self, mode: DebounceMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Returns current setting
+    pub fn mode(&self) -> DebounceMode {
+        self.mode
+    }
+
+    /// Emit [`DebouncedEventKind::BurstStart`] and [`DebouncedEventKind::BurstEnd`] events when events start coming
+    /// in after the watched paths were idle, and when they were idle again for the given threshold.
+    ///
+    /// The end of a burst is only emitted once all of its events are, so at least the timeout after its last event.
+    /// None by default.
+    pub fn with_burst_markers(mut self, idle_threshold: Option<Duration>) -> Self {
+        self.burst_markers = idle_threshold;
+        self
+    }
+
+    /// Returns current setting
+    pub fn burst_markers(&self) -> Option<Duration> {
+        self.burst_markers
+    }
+
+    /// Emit nothing for paths which were created and removed again before their event was due, like
+    /// the temporary files of compilers and lock files.
+    ///
+    /// Only applies to [`DebounceMode::Debounce`], paths reported as [`DebouncedEventKind::AnyContinuous`]
+    /// already are reported as usual. Off by default.
+    pub fn with_suppress_ephemeral(mut self, suppress_ephemeral: bool) -> Self {
+        self.suppress_ephemeral = suppress_ephemeral;
+        self
+    }
+
+    /// Returns current setting
+    pub fn suppress_ephemeral(&self) -> bool {
+        self.suppress_ephemeral
+    }
+
+    /// Emit nothing for the temporary, swap and backup files of these editors, see [`EditorNoise`].
+    ///
+    /// Unlike [`notify::Config::with_editor_noise`] this also applies to events from other sources
+    /// inserted into a [`DebounceBuffer`]. None by default.
+    pub fn with_editor_noise(mut self, editors: Vec<EditorNoise>) -> Self {
+        self.editor_noise = editors;
+        self
+    }
+
+    /// Returns current setting
+    pub fn editor_noise(&self) -> &[EditorNoise] {
+        &self.editor_noise
+    }
+
+    /// Time after which the events rejected by [`DebounceEventHandler::try_handle_event`] are delivered again.
+    ///
+    /// The backoff doubles with every further rejection of a path in a row, up to 32 times this and
+    /// at most a day. 1s by default.
+    pub fn with_retry_backoff(mut self, backoff: Duration) -> Self {
+        self.retry_backoff = backoff;
+        self
+    }
+
+    /// Returns current setting
+    pub fn retry_backoff(&self) -> Duration {
+        self.retry_backoff
+    }
+
+    /// Timestamp the events of the watcher with the time of the last tick instead of reading the clock for each.
+    ///
+    /// This lowers the cost of every event under extreme event rates, at the price of events being emitted up to one
+    /// tick rate early. Not used by a [`DebounceBuffer`], which is given the times. Off by default.
+    pub fn with_coarse_clock(mut self, coarse_clock: bool) -> Self {
+        self.coarse_clock = coarse_clock;
+        self
+    }
+
+    /// Returns current setting
+    pub fn coarse_clock(&self) -> bool {
+        self.coarse_clock
+    }
+
+    /// Emit the events matching this filter immediately as [`DebouncedEventKind::Any`], without debouncing them.
+    ///
+    /// Only the paths of an event matching the path conditions of the filter bypass the debouncer, its other paths
+    /// are debounced as usual. Meant for the few paths which need an instant reaction in an otherwise noisy tree,
+    /// like config files and control sockets. Can't be deserialized, None by default.
+    pub fn with_priority_paths(mut self, filter: Option<EventFilter>) -> Self {
+        self.priority_paths = filter;
+        self
+    }
+
+    /// Returns current setting
+    pub fn priority_paths(&self) -> Option<&EventFilter> {
+        self.priority_paths.as_ref()
+    }
+
+    /// Rewrite every event with `map` before debouncing it, so the debounced events have the paths it reports.
+    ///
+    /// Unlike the event map of the notify config, this also rewrites the events inserted into a [`DebounceBuffer`].
+    /// Can't be deserialized, None by default.
+    pub fn with_event_map(mut self, map: Option<EventMap>) -> Self {
+        self.event_map = map;
+        self
+    }
+
+    /// Returns current setting
+    pub fn event_map(&self) -> Option<&EventMap> {
+        self.event_map.as_ref()
+    }
+
+    /// Configuration of the watcher backend.
+    ///
+    /// Its thread priority and affinity apply to the thread of the debouncer as well.
+    /// [`new_debouncer_with_watcher`] leaves creating the watcher to its builder, only the thread of the
+    /// debouncer uses this then.
+    pub fn with_notify_config(mut self, notify_config: notify::Config) -> Self {
+        self.notify_config = notify_config;
+        self
+    }
+
+    /// Returns current setting
+    pub fn notify_config(&self) -> &notify::Config {
+        &self.notify_config
+    }
+}
+
+impl Default for DebouncerConfig {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_millis(500),
+            tick_rate: None,
+            mode: DebounceMode::default(),
+            burst_markers: None,
+            suppress_ephemeral: false,
+            editor_noise: Vec::new(),
+            retry_backoff: Duration::from_secs(1),
+            coarse_clock: false,
+            priority_paths: None,
+            event_map: None,
+            notify_config: notify::Config::default(),
+        }
+    }
 }
 
 /// A debounced event.
@@ -165,33 +555,209 @@ impl DebouncedEvent {
     }
 }
 
+impl fmt::Display for DebouncedEvent {
+    /// Formats the event on one line like [`notify::Event`], for example `ANY(continuous) src/lib.rs`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.kind, self.path.display())
+    }
+}
+
+#[cfg(feature = "serialization")]
+impl notify::serialization::Versioned for DebouncedEvent {
+    const TYPE: &'static str = "debounced-event";
+    const VERSION: u32 = 1;
+}
+
 type DebounceData = Arc<Mutex<DebounceDataInner>>;
 
+/// Longest time until rejected events are delivered again
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(24 * 60 * 60);
+
 #[derive(Default)]
 struct DebounceDataInner {
     d: HashMap<PathBuf, EventData>,
     timeout: Duration,
+    mode: DebounceMode,
+    burst_threshold: Option<Duration>,
+    suppress_ephemeral: bool,
+    editor_noise: Vec<EditorNoise>,
+    retry_backoff: Duration,
+    /// Rejected events to deliver again
+    retries: HashMap<PathBuf, Retry>,
+    /// The rejections in a row of the paths delivered again in the last batch
+    retried: HashMap<PathBuf, u32>,
+    /// Events the debouncer thread emits right away, without waiting for the next tick
+    immediate: Vec<DebouncedEvent>,
+    coarse_clock: bool,
+    priority_paths: Option<EventFilter>,
+    /// Time of the last tick, the time of new events with the coarse clock
+    tick: Option<Instant>,
+    burst: Option<Burst>,
     e: Vec<crate::Error>,
 }
 
+/// An event rejected by the event handler
+struct Retry {
+    kind: DebouncedEventKind,
+    /// Time the event is delivered again
+    due: Instant,
+    /// Rejections in a row of the path
+    rejections: u32,
+}
+
+/// The currently active burst of events
+struct Burst {
+    /// Time of the last event
+    update: Instant,
+    /// Path of the last event
+    path: PathBuf,
+}
+
 impl DebounceDataInner {
-    /// Retrieve a vec of debounced events, removing them if not continuous
+    /// Retrieve a vec of debounced events, followed by the end of the burst if it is over
+    #[cfg(test)]
     pub fn debounced_events(&mut self) -> Vec<DebouncedEvent> {
-        let mut events_expired = Vec::with_capacity(self.d.len());
+        let mut events = Vec::new();
+        self.debounced_events_into(&mut events);
+        events
+    }
+
+    /// Append the debounced events to `events`, followed by the end of the burst if it is over
+    fn debounced_events_into(&mut self, events: &mut Vec<DebouncedEvent>) {
+        let now = Instant::now();
+        self.tick = Some(now);
+        self.extend_debounced_events(now, events);
+    }
+
+    /// Retrieve a vec of the events debounced at `now`, followed by the end of the burst if it is over
+    fn debounced_events_at(&mut self, now: Instant) -> Vec<DebouncedEvent> {
+        let mut events = Vec::new();
+        self.extend_debounced_events(now, &mut events);
+        events
+    }
+
+    /// Append the events debounced at `now` to `events`, followed by the end of the burst if it is over
+    fn extend_debounced_events(&mut self, now: Instant, events: &mut Vec<DebouncedEvent>) {
+        let start = events.len();
+        if self.mode == DebounceMode::Throttle {
+            self.throttled_events(now, events);
+        } else {
+            self.expired_events(now, events);
+        }
+        self.retried.clear();
+        if !self.retries.is_empty() {
+            // newer events replace the rejected ones of their paths
+            for event in &events[start..] {
+                self.retries.remove(&event.path);
+            }
+            let retried = &mut self.retried;
+            self.retries.retain(|path, retry| {
+                if retry.due > now {
+                    return true;
+                }
+                retried.insert(path.clone(), retry.rejections);
+                events.push(DebouncedEvent::new(path.clone(), retry.kind));
+                false
+            });
+        }
+        if let (Some(threshold), Some(burst)) = (self.burst_threshold, &self.burst) {
+            if self.d.is_empty() && self.retries.is_empty() && now.saturating_duration_since(burst.update) >= threshold {
+                if let Some(burst) = self.burst.take() {
+                    events.push(DebouncedEvent::new(burst.path, DebouncedEventKind::BurstEnd));
+                }
+            }
+        }
+    }
+
+    /// Append the debounced events to `events_expired`, removing them if not continuous
+    fn expired_events(&mut self, now: Instant, events_expired: &mut Vec<DebouncedEvent>) {
         let mut data_back = HashMap::with_capacity(self.d.len());
         // TODO: perfect fit for drain_filter https://github.com/rust-lang/rust/issues/59618
         for (k, v) in self.d.drain() {
-            if v.update.elapsed() >= self.timeout {
-                events_expired.push(DebouncedEvent::new(k, DebouncedEventKind::Any));
-            } else if v.insert.elapsed() >= self.timeout {
-                data_back.insert(k.clone(), v);
+            if now.saturating_duration_since(v.update) >= self.timeout {
+                if !(self.suppress_ephemeral && v.is_ephemeral()) {
+                    events_expired.push(DebouncedEvent::new(k, DebouncedEventKind::Any));
+                }
+            } else if now.saturating_duration_since(v.insert) >= self.timeout {
+                // the path is reported now, so its removal has to be as well
+                data_back.insert(k.clone(), EventData { created: false, ..v });
                 events_expired.push(DebouncedEvent::new(k, DebouncedEventKind::AnyContinuous));
             } else {
                 data_back.insert(k, v);
             }
         }
         self.d = data_back;
-        events_expired
+    }
+
+    /// Append summaries for paths whose throttle interval ended to `events_expired`, removing paths without new events
+    fn throttled_events(&mut self, now: Instant, events_expired: &mut Vec<DebouncedEvent>) {
+        let timeout = self.timeout;
+        self.d.retain(|k, v| {
+            if now.saturating_duration_since(v.insert) < timeout {
+                return true;
+            }
+            if !v.pending {
+                return false;
+            }
+            events_expired.push(DebouncedEvent::new(k.clone(), DebouncedEventKind::AnyThrottled));
+            *v = EventData::new_any(now);
+            true
+        });
+    }
+
+    /// Deliver `events` rejected at `now` again after the backoff
+    fn retry_at(&mut self, events: impl IntoIterator<Item = DebouncedEvent>, now: Instant) {
+        for event in events {
+            if matches!(event.kind, DebouncedEventKind::BurstStart | DebouncedEventKind::BurstEnd) {
+                continue;
+            }
+            let rejections = self.retried.get(&event.path).map_or(1, |n| n.saturating_add(1));
+            let delay = self
+                .retry_backoff
+                .checked_mul(2u32.pow((rejections - 1).min(5)))
+                .map_or(MAX_RETRY_DELAY, |delay| delay.min(MAX_RETRY_DELAY));
+            let due = now.checked_add(delay).unwrap_or(now);
+            self.retries.insert(
+                event.path,
+                Retry {
+                    kind: event.kind,
+                    due,
+                    rejections,
+                },
+            );
+        }
+    }
+
+    /// Remove the pending events without emitting them, as they would have been emitted
+    ///
+    /// The current burst isn't affected, it still ends once the paths are idle.
+    fn take_pending(&mut self) -> Vec<DebouncedEvent> {
+        let mut events = Vec::with_capacity(self.d.len() + self.retries.len());
+        for (path, v) in self.d.drain() {
+            let kind = match self.mode {
+                DebounceMode::Throttle if v.pending => DebouncedEventKind::AnyThrottled,
+                // the events were emitted already
+                DebounceMode::Throttle => continue,
+                _ if self.suppress_ephemeral && v.is_ephemeral() => continue,
+                _ => DebouncedEventKind::Any,
+            };
+            events.push(DebouncedEvent::new(path, kind));
+        }
+        for (path, retry) in self.retries.drain() {
+            if !events.iter().any(|event| event.path == path) {
+                events.push(DebouncedEvent::new(path, retry.kind));
+            }
+        }
+        // the debouncer thread still emits the burst markers it hasn't emitted yet
+        for event in std::mem::take(&mut self.immediate) {
+            if matches!(event.kind, DebouncedEventKind::BurstStart | DebouncedEventKind::BurstEnd) {
+                self.immediate.push(event);
+            } else if !events.iter().any(|pending| pending.path == event.path) {
+                events.push(event);
+            }
+        }
+        events.sort_by(|a, b| a.path.cmp(&b.path));
+        events
     }
 
     /// Returns all currently stored errors
@@ -206,15 +772,149 @@ impl DebounceDataInner {
         self.e.push(e);
     }
 
-    /// Add new event to debouncer cache
-    pub fn add_event(&mut self, e: Event) {
+    /// Add new event to debouncer cache, returns the events to emit immediately
+    pub fn add_event(&mut self, e: Event) -> Vec<DebouncedEvent> {
+        let now = match self.tick {
+            Some(tick) if self.coarse_clock => tick,
+            _ => Instant::now(),
+        };
+        self.add_event_at(e, now)
+    }
+
+    /// Add an event which happened at `now`, returns the events to emit immediately
+    fn add_event_at(&mut self, mut e: Event, now: Instant) -> Vec<DebouncedEvent> {
+        if !self.editor_noise.is_empty() && !e.paths.is_empty() {
+            let editors = &self.editor_noise;
+            e.paths
+                .retain(|path| !editors.iter().any(|editor| editor.matches(path)));
+            if e.paths.is_empty() {
+                return Vec::new();
+            }
+        }
+        let mut immediate = Vec::new();
+        if self.burst_threshold.is_some() {
+            let path = e.paths.last().cloned().unwrap_or_default();
+            if self.burst.is_none() {
+                let first = e.paths.first().cloned().unwrap_or_default();
+                immediate.push(DebouncedEvent::new(first, DebouncedEventKind::BurstStart));
+            }
+            self.burst = Some(Burst { update: now, path });
+        }
+        let priority = self.priority_paths.as_ref().filter(|filter| filter.matches(&e));
+        let (is_create, is_remove) = (e.kind.is_create(), e.kind.is_remove());
         for path in e.paths.into_iter() {
+            if priority.map_or(false, |filter| filter.matches_path(&path)) {
+                immediate.push(DebouncedEvent::new(path, DebouncedEventKind::Any));
+                continue;
+            }
             if let Some(v) = self.d.get_mut(&path) {
-                v.update = Instant::now();
+                v.update = now;
+                v.pending = true;
+                v.removed = is_remove;
             } else {
-                self.d.insert(path, EventData::new_any());
+                if self.mode == DebounceMode::Throttle {
+                    immediate.push(DebouncedEvent::new(path.clone(), DebouncedEventKind::Any));
+                }
+                self.d.insert(
+                    path,
+                    EventData {
+                        created: is_create,
+                        removed: is_remove,
+                        ..EventData::new_any(now)
+                    },
+                );
             }
         }
+        immediate
+    }
+}
+
+/// The debouncing algorithm of the [`Debouncer`], without a watcher or thread.
+///
+/// Events are inserted as they happen and the debounced events taken out when they are due, all at
+/// times given by the caller. This applies the exact semantics of the debouncer to events from other
+/// sources, or in a loop of the application's own:
+///
+/// ```rust
+/// # use std::path::PathBuf;
+/// # use std::time::{Duration, Instant};
+/// use notify_debouncer_mini::notify::event::{Event, EventKind};
+/// use notify_debouncer_mini::{DebounceBuffer, DebouncedEventKind, DebouncerConfig};
+///
+/// let config = DebouncerConfig::default().with_timeout(Duration::from_millis(100));
+/// let mut buffer = DebounceBuffer::new(&config);
+///
+/// let start = Instant::now();
+/// let event = Event::new(EventKind::Any).add_path(PathBuf::from("a"));
+/// buffer.insert(event.clone(), start);
+/// buffer.insert(event, start + Duration::from_millis(50));
+///
+/// assert!(buffer.expire(start + Duration::from_millis(90)).is_empty());
+/// let events = buffer.expire(start + Duration::from_millis(150));
+/// assert_eq!(events[0].kind, DebouncedEventKind::Any);
+/// assert!(buffer.is_empty());
+/// ```
+///
+/// Only the timeout, mode, burst markers, ephemeral suppression, editor noise, retry backoff, priority paths and event
+/// map of the config are used.
+pub struct DebounceBuffer {
+    data: DebounceDataInner,
+    event_map: Option<EventMap>,
+}
+
+impl DebounceBuffer {
+    /// Create an empty buffer debouncing like `config`.
+    pub fn new(config: &DebouncerConfig) -> Self {
+        Self {
+            data: DebounceDataInner {
+                timeout: config.timeout,
+                mode: config.mode,
+                burst_threshold: config.burst_markers,
+                suppress_ephemeral: config.suppress_ephemeral,
+                editor_noise: config.editor_noise.clone(),
+                retry_backoff: config.retry_backoff,
+                priority_paths: config.priority_paths.clone(),
+                ..Default::default()
+            },
+            event_map: config.event_map.clone(),
+        }
+    }
+
+    /// Insert an event which happened at `now`, returns the events to emit immediately
+    ///
+    /// These are the [`DebouncedEventKind::BurstStart`] events, the events of priority paths, and the first events of
+    /// paths when throttling.
+    pub fn insert(&mut self, event: Event, now: Instant) -> Vec<DebouncedEvent> {
+        let event = match &self.event_map {
+            Some(map) => map.apply(event),
+            None => event,
+        };
+        self.data.add_event_at(event, now)
+    }
+
+    /// Take out the events due at `now`
+    ///
+    /// Like the ticks of the [`Debouncer`], this should be called regularly: paths with events continuing
+    /// past the timeout are reported as [`DebouncedEventKind::AnyContinuous`] on every call.
+    pub fn expire(&mut self, now: Instant) -> Vec<DebouncedEvent> {
+        self.data.debounced_events_at(now)
+    }
+
+    /// Deliver `events` of the batch last taken out with [`expire`](Self::expire) again after the retry backoff
+    ///
+    /// These are the events the handler rejected, see [`DebounceEventHandler::try_handle_event`].
+    pub fn retry(&mut self, events: Vec<DebouncedEvent>, now: Instant) {
+        self.data.retry_at(events, now)
+    }
+
+    /// Remove and return the pending events without emitting them, see [`Debouncer::take_pending`]
+    pub fn take_pending(&mut self) -> Vec<DebouncedEvent> {
+        self.data.take_pending()
+    }
+
+    /// Whether no events are pending
+    pub fn is_empty(&self) -> bool {
+        self.data.d.is_empty() && self.data.retries.is_empty() && self.data.burst.is_none()
     }
 }
 
@@ -223,6 +923,7 @@ pub struct Debouncer<T: Watcher> {
     stop: Arc<AtomicBool>,
     watcher: T,
     debouncer_thread: Option<std::thread::JoinHandle<()>>,
+    data: DebounceData,
 }
 
 impl<T: Watcher> Debouncer<T> {
@@ -244,6 +945,15 @@ impl<T: Watcher> Debouncer<T> {
         self.stop.store(true, Ordering::Relaxed);
     }
 
+    /// Remove and return the events waiting for their timeout, without passing them to the event handler.
+    ///
+    /// This is atomic with the debouncing: every event is either taken here or emitted by the debouncer.
+    /// Useful on shutdown, or to discard stale events before a full rescan. Events waiting to be delivered
+    /// again after a rejection are taken too, sorted by path with the kinds they would have been emitted with.
+    pub fn take_pending(&self) -> Vec<DebouncedEvent> {
+        self.data.lock().expect("Can't lock debouncer data!").take_pending()
+    }
+
     /// Access to the internally used notify Watcher backend
     pub fn watcher(&mut self) -> &mut dyn Watcher {
         &mut self.watcher
@@ -265,15 +975,117 @@ impl<T: Watcher> Drop for Debouncer<T> {
 pub fn new_debouncer_opt<F: DebounceEventHandler, T: Watcher>(
     timeout: Duration,
     tick_rate: Option<Duration>,
-    mut event_handler: F,
+    event_handler: F,
     config: notify::Config
 ) -> Result<Debouncer<T>, Error> {
+    let config = DebouncerConfig::default()
+        .with_timeout(timeout)
+        .with_tick_rate(tick_rate)
+        .with_notify_config(config);
+    new_debouncer_with_config(config, event_handler)
+}
+
+/// Creates a new debounced watcher from a [`DebouncerConfig`].
+pub fn new_debouncer_with_config<F: DebounceEventHandler, T: Watcher>(
+    config: DebouncerConfig,
+    event_handler: F,
+) -> Result<Debouncer<T>, Error> {
+    let notify_config = config.notify_config.clone();
+    new_debouncer_with_watcher(|input| T::new(input, notify_config), config, event_handler)
+}
+
+/// Feeds the events of a watcher to its debouncer, see [`new_debouncer_with_watcher`].
+///
+/// Sources which aren't notify watchers pass their events to [`handle_event`](notify::EventHandler::handle_event)
+/// themselves.
+pub struct DebounceInput {
+    data: DebounceData,
+    /// The debouncer thread, woken up to emit immediate events
+    thread: std::thread::Thread,
+    event_map: Option<EventMap>,
+}
+
+impl notify::EventHandler for DebounceInput {
+    fn handle_event(&mut self, e: Result<Event, Error>) {
+        // mapped before locking, the map may take its time
+        let e = match &self.event_map {
+            Some(map) => e.map(|e| map.apply(e)),
+            None => e,
+        };
+        let immediate = {
+            let mut lock = self.data.lock().expect("Can't lock debouncer data!");
+
+            match e {
+                Ok(e) => {
+                    let immediate = lock.add_event(e);
+                    let any = !immediate.is_empty();
+                    lock.immediate.extend(immediate);
+                    any
+                }
+                // can't have multiple TX, so we need to pipe that through our debouncer
+                Err(e) => {
+                    lock.add_error(e);
+                    false
+                }
+            }
+        };
+        // emitted by the debouncer thread, so the watcher isn't held up by the event handler
+        if immediate {
+            self.thread.unpark();
+        }
+    }
+}
+
+/// Creates a new debouncer around the watcher returned by `watcher_builder`.
+///
+/// The builder gets the [`DebounceInput`] to use as the event handler of the watcher, so the watcher can be
+/// created with any backend and [`notify::Config`], which the [notify config](DebouncerConfig::with_notify_config)
+/// of `config` isn't used for then. Other sources of events run with a [`NullWatcher`](notify::null::NullWatcher)
+/// and feed the input themselves:
+///
+/// ```rust,no_run
+/// # use std::path::PathBuf;
+/// # use std::time::Duration;
+/// use notify_debouncer_mini::notify::{null::NullWatcher, Event, EventHandler, EventKind};
+/// use notify_debouncer_mini::{new_debouncer_with_watcher, DebounceEventResult, DebouncerConfig};
+///
+/// # fn main() -> Result<(), notify::Error> {
+/// let config = DebouncerConfig::default().with_timeout(Duration::from_millis(200));
+/// let debouncer = new_debouncer_with_watcher(
+///     |mut input| {
+///         std::thread::spawn(move || loop {
+///             // e.g. paths reported by a remote file system
+///             let event = Event::new(EventKind::Any).add_path(PathBuf::from("remote/file"));
+///             input.handle_event(Ok(event));
+///             std::thread::sleep(Duration::from_millis(50));
+///         });
+///         Ok(NullWatcher)
+///     },
+///     config,
+///     |res: DebounceEventResult| println!("{:?}", res),
+/// )?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// The debouncer stops if the builder fails, and its error is returned.
+pub fn new_debouncer_with_watcher<B, F, T>(
+    watcher_builder: B,
+    config: DebouncerConfig,
+    mut event_handler: F,
+) -> Result<Debouncer<T>, Error>
+where
+    B: FnOnce(DebounceInput) -> Result<T, Error>,
+    F: DebounceEventHandler,
+    T: Watcher,
+{
+    let timeout = config.timeout;
     let data = DebounceData::default();
 
     let stop = Arc::new(AtomicBool::new(false));
 
     let tick_div = 4;
-    let tick = match tick_rate {
+    let tick = match config.tick_rate {
         Some(v) => {
             if v > timeout {
                 return Err(Error::new(ErrorKind::Generic(format!(
@@ -294,46 +1106,78 @@ pub fn new_debouncer_opt<F: DebounceEventHandler, T: Watcher>(
     {
         let mut data_w = data.lock().unwrap();
         data_w.timeout = timeout;
+        data_w.mode = config.mode;
+        data_w.burst_threshold = config.burst_markers;
+        data_w.suppress_ephemeral = config.suppress_ephemeral;
+        data_w.editor_noise = config.editor_noise.clone();
+        data_w.retry_backoff = config.retry_backoff;
+        data_w.coarse_clock = config.coarse_clock;
+        data_w.priority_paths = config.priority_paths.clone();
     }
 
     let data_c = data.clone();
     let stop_c = stop.clone();
+    let notify_config = config.notify_config.clone();
     let thread = std::thread::Builder::new()
         .name("notify-rs debouncer loop".to_string())
-        .spawn(move || loop {
-            if stop_c.load(Ordering::Acquire) {
-                break;
-            }
-            std::thread::sleep(tick);
-            let send_data;
-            let errors: Vec<crate::Error>;
-            {
-                let mut lock = data_c.lock().expect("Can't lock debouncer data!");
-                send_data = lock.debounced_events();
-                errors = lock.errors();
-            }
-            if send_data.len() > 0 {
-                event_handler.handle_event(Ok(send_data));
-            }
-            if errors.len() > 0 {
-                event_handler.handle_event(Err(errors));
+        .spawn(move || {
+            let _ = notify_config.apply_thread_scheduling();
+            // lent to the event handler on every tick
+            let mut send_data = Vec::new();
+            let mut next_tick = Instant::now() + tick;
+            loop {
+                if stop_c.load(Ordering::Acquire) {
+                    break;
+                }
+                // woken up early for immediate events
+                let now = Instant::now();
+                if now < next_tick {
+                    std::thread::park_timeout(next_tick - now);
+                }
+                let ticked = Instant::now() >= next_tick;
+                let mut errors = Vec::new();
+                {
+                    let mut lock = data_c.lock().expect("Can't lock debouncer data!");
+                    send_data.append(&mut lock.immediate);
+                    if ticked {
+                        lock.debounced_events_into(&mut send_data);
+                        errors = lock.errors();
+                    }
+                }
+                if !send_data.is_empty() {
+                    event_handler.handle_batch(&mut send_data);
+                    if !send_data.is_empty() {
+                        let mut lock = data_c.lock().expect("Can't lock debouncer data!");
+                        lock.retry_at(send_data.drain(..), Instant::now());
+                    }
+                }
+                if !errors.is_empty() {
+                    event_handler.handle_event(Err(errors));
+                }
+                if ticked {
+                    next_tick = Instant::now() + tick;
+                }
             }
         })?;
 
-    let watcher = T::new(move |e: Result<Event, Error>| {
-        let mut lock = data.lock().expect("Can't lock debouncer data!");
-
-        match e {
-            Ok(e) => lock.add_event(e),
-            // can't have multiple TX, so we need to pipe that through our debouncer
-            Err(e) => lock.add_error(e),
+    let input = DebounceInput {
+        data: data.clone(),
+        thread: thread.thread().clone(),
+        event_map: config.event_map.clone(),
+    };
+    let watcher = match watcher_builder(input) {
+        Ok(watcher) => watcher,
+        Err(e) => {
+            stop.store(true, Ordering::Relaxed);
+            return Err(e);
         }
-    }, config)?;
+    };
 
     let guard = Debouncer {
         watcher,
         debouncer_thread: Some(thread),
         stop,
+        data,
     };
 
     Ok(guard)
@@ -351,3 +1195,339 @@ pub fn new_debouncer<F: DebounceEventHandler>(
 ) -> Result<Debouncer<RecommendedWatcher>, Error> {
     new_debouncer_opt::<F, RecommendedWatcher>(timeout, tick_rate, event_handler, notify::Config::default())
 }
+
+#[test]
+fn throttle_emits_first_event_and_one_summary_per_timeout() {
+    use notify::event::{EventKind, ModifyKind};
+
+    let modify = || Event::new(EventKind::Modify(ModifyKind::Any)).add_path(PathBuf::from("a"));
+    let mut data = DebounceDataInner {
+        timeout: Duration::from_millis(50),
+        mode: DebounceMode::Throttle,
+        ..Default::default()
+    };
+
+    let immediate = data.add_event(modify());
+    assert_eq!(immediate, [DebouncedEvent::new(PathBuf::from("a"), DebouncedEventKind::Any)]);
+    assert!(data.add_event(modify()).is_empty());
+    assert!(data.add_event(modify()).is_empty());
+    assert!(data.debounced_events().is_empty());
+
+    std::thread::sleep(Duration::from_millis(60));
+    let summary = data.debounced_events();
+    assert_eq!(summary, [DebouncedEvent::new(PathBuf::from("a"), DebouncedEventKind::AnyThrottled)]);
+
+    // no events during the next interval: the path goes idle and its next event is emitted right away
+    std::thread::sleep(Duration::from_millis(60));
+    assert!(data.debounced_events().is_empty());
+    assert_eq!(data.add_event(modify()).len(), 1);
+}
+
+#[test]
+fn bursts_are_marked_around_their_events() {
+    use notify::event::{EventKind, ModifyKind};
+
+    let modify = |path: &str| Event::new(EventKind::Modify(ModifyKind::Any)).add_path(PathBuf::from(path));
+    let mut data = DebounceDataInner {
+        timeout: Duration::from_millis(20),
+        burst_threshold: Some(Duration::from_millis(200)),
+        ..Default::default()
+    };
+
+    let start = data.add_event(modify("a"));
+    assert_eq!(start, [DebouncedEvent::new(PathBuf::from("a"), DebouncedEventKind::BurstStart)]);
+    assert!(data.add_event(modify("b")).is_empty());
+
+    std::thread::sleep(Duration::from_millis(30));
+    let mut events = data.debounced_events();
+    events.sort_by(|a, b| a.path.cmp(&b.path));
+    assert_eq!(events, [
+        DebouncedEvent::new(PathBuf::from("a"), DebouncedEventKind::Any),
+        DebouncedEvent::new(PathBuf::from("b"), DebouncedEventKind::Any),
+    ]);
+
+    std::thread::sleep(Duration::from_millis(200));
+    let end = data.debounced_events();
+    assert_eq!(end, [DebouncedEvent::new(PathBuf::from("b"), DebouncedEventKind::BurstEnd)]);
+    assert!(data.debounced_events().is_empty());
+    assert_eq!(data.add_event(modify("c")).len(), 1);
+}
+
+#[test]
+fn debounced_events_are_described_on_one_line() {
+    let event = DebouncedEvent::new(PathBuf::from("src/lib.rs"), DebouncedEventKind::AnyContinuous);
+    assert_eq!(event.to_string(), "ANY(continuous) src/lib.rs");
+    let event = DebouncedEvent::new(PathBuf::from("src"), DebouncedEventKind::BurstStart);
+    assert_eq!(event.to_string(), "BURST_START src");
+}
+
+#[test]
+#[cfg(feature = "serialization")]
+fn debounced_event_wire_format_is_stable() {
+    use notify::serialization;
+
+    let event = DebouncedEvent::new(PathBuf::from("/a"), DebouncedEventKind::AnyContinuous);
+    let line = serialization::to_json_line(&event).unwrap();
+    assert_eq!(line, r#"{"type":"debounced-event","version":1,"data":{"path":"/a","kind":"AnyContinuous"}}"#);
+    assert_eq!(serialization::from_json_line::<DebouncedEvent>(&line).unwrap(), event);
+}
+
+#[test]
+fn debounce_buffer_runs_at_the_given_times() {
+    use notify::event::{EventKind, ModifyKind};
+
+    let modify = |path: &str| Event::new(EventKind::Modify(ModifyKind::Any)).add_path(PathBuf::from(path));
+    let config = DebouncerConfig::default()
+        .with_timeout(Duration::from_secs(10))
+        .with_burst_markers(Some(Duration::from_secs(60)));
+    let mut buffer = DebounceBuffer::new(&config);
+    let start = Instant::now();
+    let at = |secs| start + Duration::from_secs(secs);
+
+    let immediate = buffer.insert(modify("a"), start);
+    assert_eq!(immediate, [DebouncedEvent::new(PathBuf::from("a"), DebouncedEventKind::BurstStart)]);
+    assert!(buffer.insert(modify("a"), at(5)).is_empty());
+    assert!(buffer.insert(modify("b"), at(8)).is_empty());
+    assert!(buffer.expire(at(9)).is_empty());
+    assert_eq!(buffer.expire(at(12)), [DebouncedEvent::new(PathBuf::from("a"), DebouncedEventKind::AnyContinuous)]);
+
+    let mut events = buffer.expire(at(18));
+    events.sort_by(|a, b| a.path.cmp(&b.path));
+    assert_eq!(events, [
+        DebouncedEvent::new(PathBuf::from("a"), DebouncedEventKind::Any),
+        DebouncedEvent::new(PathBuf::from("b"), DebouncedEventKind::Any),
+    ]);
+    assert!(!buffer.is_empty());
+    assert_eq!(buffer.expire(at(68)), [DebouncedEvent::new(PathBuf::from("b"), DebouncedEventKind::BurstEnd)]);
+    assert!(buffer.is_empty());
+}
+
+#[test]
+fn ephemeral_paths_can_be_suppressed() {
+    use notify::event::{CreateKind, EventKind, RemoveKind};
+
+    let event = |kind, path: &str| Event::new(kind).add_path(PathBuf::from(path));
+    let config = DebouncerConfig::default()
+        .with_timeout(Duration::from_secs(10))
+        .with_suppress_ephemeral(true);
+    let mut buffer = DebounceBuffer::new(&config);
+    let start = Instant::now();
+    let at = |secs| start + Duration::from_secs(secs);
+
+    buffer.insert(event(EventKind::Create(CreateKind::File), "tmp"), start);
+    buffer.insert(event(EventKind::Remove(RemoveKind::File), "tmp"), at(2));
+    buffer.insert(event(EventKind::Create(CreateKind::File), "kept"), at(2));
+    assert_eq!(buffer.expire(at(13)), [DebouncedEvent::new(PathBuf::from("kept"), DebouncedEventKind::Any)]);
+    assert!(buffer.is_empty());
+}
+
+#[test]
+fn editor_noise_is_dropped() {
+    let event = |path: &str| Event::new(notify::EventKind::Any).add_path(PathBuf::from(path));
+    let config = DebouncerConfig::default()
+        .with_timeout(Duration::from_secs(10))
+        .with_editor_noise(vec![EditorNoise::Vim]);
+    let mut buffer = DebounceBuffer::new(&config);
+    let start = Instant::now();
+
+    buffer.insert(event(".main.rs.swp"), start);
+    buffer.insert(event("4913"), start);
+    buffer.insert(event("main.rs"), start);
+    assert_eq!(
+        buffer.expire(start + Duration::from_secs(11)),
+        [DebouncedEvent::new(PathBuf::from("main.rs"), DebouncedEventKind::Any)]
+    );
+    assert!(buffer.is_empty());
+}
+
+#[test]
+fn rejected_events_are_delivered_again() {
+    let event = |path: &str| Event::new(notify::EventKind::Any).add_path(PathBuf::from(path));
+    let any = |path: &str| DebouncedEvent::new(PathBuf::from(path), DebouncedEventKind::Any);
+    let config = DebouncerConfig::default()
+        .with_timeout(Duration::from_secs(1))
+        .with_retry_backoff(Duration::from_secs(10));
+    let mut buffer = DebounceBuffer::new(&config);
+    let start = Instant::now();
+    let at = |secs| start + Duration::from_secs(secs);
+
+    buffer.insert(event("a"), start);
+    buffer.insert(event("b"), start);
+    let mut events = buffer.expire(at(1));
+    events.sort_by(|a, b| a.path.cmp(&b.path));
+    assert_eq!(events, [any("a"), any("b")]);
+    buffer.retry(vec![any("a"), any("b")], at(1));
+    assert!(!buffer.is_empty());
+    assert!(buffer.expire(at(10)).is_empty());
+
+    // a newer event of b replaces the retry
+    buffer.insert(event("b"), at(10));
+    let mut events = buffer.expire(at(11));
+    events.sort_by(|a, b| a.path.cmp(&b.path));
+    assert_eq!(events, [any("a"), any("b")]);
+
+    // the backoff doubles for a rejected again
+    buffer.retry(vec![any("a")], at(11));
+    assert!(buffer.expire(at(30)).is_empty());
+    assert_eq!(buffer.expire(at(31)), [any("a")]);
+    assert!(buffer.is_empty());
+
+    // huge backoffs are capped instead of overflowing
+    let config = config.with_retry_backoff(Duration::MAX);
+    let mut buffer = DebounceBuffer::new(&config);
+    buffer.retry(vec![any("a")], start);
+    assert!(buffer.expire(start + MAX_RETRY_DELAY / 2).is_empty());
+    assert_eq!(buffer.expire(start + MAX_RETRY_DELAY), [any("a")]);
+}
+
+#[test]
+fn pending_events_are_taken_without_emitting_them() {
+    let event = |path: &str| Event::new(notify::EventKind::Any).add_path(PathBuf::from(path));
+    let any = |path: &str| DebouncedEvent::new(PathBuf::from(path), DebouncedEventKind::Any);
+    let config = DebouncerConfig::default().with_timeout(Duration::from_secs(1));
+    let mut buffer = DebounceBuffer::new(&config);
+    let start = Instant::now();
+
+    buffer.insert(event("a"), start);
+    assert_eq!(buffer.expire(start + Duration::from_secs(1)), [any("a")]);
+    buffer.retry(vec![any("a")], start + Duration::from_secs(1));
+    buffer.insert(event("c"), start + Duration::from_secs(1));
+    buffer.insert(event("b"), start + Duration::from_secs(1));
+
+    assert_eq!(buffer.take_pending(), [any("a"), any("b"), any("c")]);
+    assert!(buffer.is_empty());
+    assert!(buffer.expire(start + Duration::from_secs(60)).is_empty());
+}
+
+#[test]
+fn priority_paths_bypass_debouncing() {
+    let modify = |paths: &[&str]| {
+        paths.iter().fold(Event::new(notify::EventKind::Any), |event, path| event.add_path(PathBuf::from(path)))
+    };
+    let any = |path: &str| DebouncedEvent::new(PathBuf::from(path), DebouncedEventKind::Any);
+    let config = DebouncerConfig::default()
+        .with_timeout(Duration::from_secs(1))
+        .with_priority_paths(Some(EventFilter::new().glob("/etc/app/*.toml")));
+    let mut buffer = DebounceBuffer::new(&config);
+    let start = Instant::now();
+
+    assert_eq!(buffer.insert(modify(&["/etc/app/app.toml", "/srv/a"]), start), [any("/etc/app/app.toml")]);
+    assert_eq!(buffer.insert(modify(&["/etc/app/app.toml"]), start), [any("/etc/app/app.toml")]);
+    assert_eq!(buffer.expire(start + Duration::from_secs(1)), [any("/srv/a")]);
+    assert!(buffer.is_empty());
+}
+
+#[test]
+fn events_left_in_a_batch_are_rejected() {
+    let any = |path: &str| DebouncedEvent::new(PathBuf::from(path), DebouncedEventKind::Any);
+    let mut handler = Retrying(|res: DebounceEventResult| match res {
+        Ok(events) => Err(events.into_iter().filter(|e| e.path.ends_with("b")).collect::<Vec<_>>()),
+        Err(_) => Ok(()),
+    });
+
+    let mut events = vec![any("a"), any("b")];
+    handler.handle_batch(&mut events);
+    assert_eq!(events, [any("b")]);
+}
+
+#[test]
+fn coarse_clock_timestamps_events_with_the_last_tick() {
+    let event = || Event::new(notify::EventKind::Any).add_path(PathBuf::from("a"));
+    let mut data = DebounceDataInner {
+        timeout: Duration::from_millis(20),
+        coarse_clock: true,
+        ..Default::default()
+    };
+
+    assert!(data.debounced_events().is_empty());
+    let tick = data.tick.unwrap();
+    std::thread::sleep(Duration::from_millis(30));
+    data.add_event(event());
+    assert_eq!(data.d[&PathBuf::from("a")].update, tick);
+    // the event is due at the next tick, as its time was taken a timeout earlier
+    assert_eq!(data.debounced_events(), [DebouncedEvent::new(PathBuf::from("a"), DebouncedEventKind::Any)]);
+}
+
+#[cfg(feature = "serialization")]
+#[test]
+fn debouncer_config_is_deserialized_with_units() {
+    let config: DebouncerConfig = serde_json::from_str(
+        r#"{ "timeout": "500ms", "mode": "throttle", "notify_config": { "poll_interval": 2000 } }"#,
+    )
+    .unwrap();
+    assert_eq!(config.timeout(), Duration::from_millis(500));
+    assert_eq!(config.mode(), DebounceMode::Throttle);
+    assert_eq!(config.notify_config().poll_interval(), Duration::from_secs(2));
+    assert_eq!(config.tick_rate(), None);
+}
+
+#[test]
+fn event_map_rewrites_events_before_debouncing() {
+    let event = |path: &str| Event::new(notify::EventKind::Any).add_path(PathBuf::from(path));
+    let any = |path: &str| DebouncedEvent::new(PathBuf::from(path), DebouncedEventKind::Any);
+    let map = EventMap::new(|mut event: Event| {
+        event.paths = event.paths.iter().map(|path| PathBuf::from("/host").join(path)).collect();
+        event
+    });
+    let config = DebouncerConfig::default()
+        .with_timeout(Duration::from_secs(1))
+        .with_event_map(Some(map));
+    let mut buffer = DebounceBuffer::new(&config);
+    let start = Instant::now();
+
+    buffer.insert(event("a"), start);
+    buffer.insert(event("a"), start);
+    assert_eq!(buffer.expire(start + Duration::from_secs(1)), [any("/host/a")]);
+}
+
+#[test]
+fn debouncer_wraps_the_watcher_of_its_builder() {
+    use notify::{null::NullWatcher, EventHandler};
+
+    let (tx, rx) = std::sync::mpsc::channel();
+    let config = DebouncerConfig::default().with_timeout(Duration::from_millis(50));
+    let _debouncer = new_debouncer_with_watcher(
+        |mut input| {
+            input.handle_event(Ok(Event::new(notify::EventKind::Any).add_path(PathBuf::from("a"))));
+            Ok(NullWatcher)
+        },
+        config.clone(),
+        tx,
+    )
+    .unwrap();
+    let events = rx.recv_timeout(Duration::from_secs(1)).unwrap().unwrap();
+    assert_eq!(events, [DebouncedEvent::new(PathBuf::from("a"), DebouncedEventKind::Any)]);
+
+    let failed = new_debouncer_with_watcher::<_, _, NullWatcher>(
+        |_| Err(Error::generic("no source")),
+        config,
+        |_: DebounceEventResult| {},
+    );
+    assert!(failed.is_err());
+}
+
+#[test]
+fn immediate_events_are_emitted_by_the_debouncer_thread() {
+    use notify::{null::NullWatcher, EventHandler};
+
+    let (tx, rx) = std::sync::mpsc::channel();
+    let config = DebouncerConfig::default()
+        .with_timeout(Duration::from_secs(10))
+        .with_mode(DebounceMode::Throttle);
+    let _debouncer = new_debouncer_with_watcher(
+        |mut input| {
+            input.handle_event(Ok(Event::new(notify::EventKind::Any).add_path(PathBuf::from("a"))));
+            Ok(NullWatcher)
+        },
+        config,
+        move |res: DebounceEventResult| {
+            let thread = std::thread::current().name().map(String::from);
+            tx.send((res.unwrap(), thread)).unwrap();
+        },
+    )
+    .unwrap();
+    // long before the first tick
+    let (events, thread) = rx.recv_timeout(Duration::from_secs(1)).unwrap();
+    assert_eq!(events, [DebouncedEvent::new(PathBuf::from("a"), DebouncedEventKind::Any)]);
+    assert_eq!(thread.as_deref(), Some("notify-rs debouncer loop"));
+}
@@ -0,0 +1,528 @@
+//! Translating debounced batches into LSP `workspace/didChangeWatchedFiles` notifications
+//!
+//! Language servers register [`FileSystemWatcher`]s with the client, or watch the workspace
+//! themselves from the same patterns. [`FileWatchers`] takes these registrations, tells which
+//! roots to watch, and turns each debounced batch into the [`FileEvent`]s of the notification:
+//!
+//! ```rust,no_run
+//! # use std::path::Path;
+//! # use std::time::Duration;
+//! use notify_debouncer_mini::lsp::{FileSystemWatcher, FileWatchers, GlobPattern};
+//! use notify_debouncer_mini::{new_debouncer, DebounceEventResult};
+//!
+//! # fn main() -> notify_debouncer_mini::notify::Result<()> {
+//! let watchers = FileWatchers::new(
+//!     &[FileSystemWatcher::new(GlobPattern::from("**/*.rs"))],
+//!     Path::new("/project"),
+//! )?;
+//! let file_watchers = watchers.clone();
+//! let mut debouncer = new_debouncer(Duration::from_millis(200), None, move |res: DebounceEventResult| {
+//!     if let Ok(events) = res {
+//!         let changes = file_watchers.file_events(&events);
+//!         // send the notification with `changes` to the client
+//!     }
+//! })?;
+//! watchers.watch(debouncer.watcher())?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Debounced events don't tell created files apart from changed ones, so files which exist are
+//! reported as [`FileChangeType::Changed`] and those which don't as [`FileChangeType::Deleted`].
+//! Glob patterns support `*`, `?`, `**` and `{a,b}` alternatives, but no `[...]` character ranges.
+//! With the `serde` feature the types serialize to the JSON of the protocol.
+
+use crate::{DebouncedEvent, DebouncedEventKind};
+use notify::filter::EventFilter;
+use notify::{Error, RecursiveMode, Result, Watcher};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::path::{Component, Path, PathBuf};
+
+/// The type of a file event, serialized as its number in the protocol.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum FileChangeType {
+    /// The file got created
+    Created = 1,
+    /// The file got changed
+    Changed = 2,
+    /// The file got deleted
+    Deleted = 3,
+}
+
+impl FileChangeType {
+    /// The [`WatchKind`] watching for changes of this type
+    pub fn watch_kind(self) -> WatchKind {
+        match self {
+            FileChangeType::Created => WatchKind::CREATE,
+            FileChangeType::Changed => WatchKind::CHANGE,
+            FileChangeType::Deleted => WatchKind::DELETE,
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl Serialize for FileChangeType {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_u8(*self as u8)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for FileChangeType {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        match u8::deserialize(deserializer)? {
+            1 => Ok(FileChangeType::Created),
+            2 => Ok(FileChangeType::Changed),
+            3 => Ok(FileChangeType::Deleted),
+            n => Err(serde::de::Error::custom(format!(
+                "invalid file change type {}",
+                n
+            ))),
+        }
+    }
+}
+
+/// An event describing a file change
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct FileEvent {
+    /// The file's `file://` URI
+    pub uri: String,
+    /// The change type
+    #[cfg_attr(feature = "serde", serde(rename = "type"))]
+    pub typ: FileChangeType,
+}
+
+/// The changes a [`FileSystemWatcher`] is interested in, serialized as the bitmask of the protocol.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
+pub struct WatchKind(u8);
+
+impl WatchKind {
+    /// Interested in create events
+    pub const CREATE: WatchKind = WatchKind(1);
+    /// Interested in change events
+    pub const CHANGE: WatchKind = WatchKind(2);
+    /// Interested in delete events
+    pub const DELETE: WatchKind = WatchKind(4);
+    /// Interested in all events, the default of the protocol
+    pub const ALL: WatchKind = WatchKind(7);
+
+    /// The kind with the bits of the protocol
+    pub fn from_bits(bits: u8) -> Self {
+        WatchKind(bits)
+    }
+
+    /// The bits of the protocol
+    pub fn bits(self) -> u8 {
+        self.0
+    }
+
+    /// Whether all changes of `other` are included
+    pub fn contains(self, other: WatchKind) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl Default for WatchKind {
+    fn default() -> Self {
+        WatchKind::ALL
+    }
+}
+
+impl std::ops::BitOr for WatchKind {
+    type Output = WatchKind;
+
+    fn bitor(self, other: WatchKind) -> WatchKind {
+        WatchKind(self.0 | other.0)
+    }
+}
+
+/// The glob pattern of a [`FileSystemWatcher`]
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(untagged))]
+pub enum GlobPattern {
+    /// A pattern matched against absolute paths, or anywhere in the workspace if relative
+    Pattern(String),
+    /// A pattern matched against the paths relative to a base directory
+    #[cfg_attr(feature = "serde", serde(rename_all = "camelCase"))]
+    Relative {
+        /// The `file://` URI of the base directory
+        ///
+        /// Deserialized from a URI or from a workspace folder with its URI.
+        #[cfg_attr(feature = "serde", serde(deserialize_with = "deserialize_base_uri"))]
+        base_uri: String,
+        /// The pattern
+        pattern: String,
+    },
+}
+
+impl From<&str> for GlobPattern {
+    fn from(pattern: &str) -> Self {
+        GlobPattern::Pattern(pattern.to_owned())
+    }
+}
+
+#[cfg(feature = "serde")]
+fn deserialize_base_uri<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<String, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum BaseUri {
+        Uri(String),
+        WorkspaceFolder { uri: String },
+    }
+
+    Ok(match BaseUri::deserialize(deserializer)? {
+        BaseUri::Uri(uri) | BaseUri::WorkspaceFolder { uri } => uri,
+    })
+}
+
+/// A registration of the `workspace/didChangeWatchedFiles` capability
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "camelCase"))]
+pub struct FileSystemWatcher {
+    /// The files to watch
+    pub glob_pattern: GlobPattern,
+    /// The changes to report, all if `None`
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub kind: Option<WatchKind>,
+}
+
+impl FileSystemWatcher {
+    /// Watch all changes of the files matching `glob_pattern`
+    pub fn new(glob_pattern: GlobPattern) -> Self {
+        Self {
+            glob_pattern,
+            kind: None,
+        }
+    }
+}
+
+/// The registered [`FileSystemWatcher`]s, matching debounced events against them.
+#[derive(Clone, Debug)]
+pub struct FileWatchers {
+    watchers: Vec<(EventFilter, WatchKind)>,
+    roots: Vec<PathBuf>,
+}
+
+impl FileWatchers {
+    /// Compile the `watchers`, with relative plain patterns applying to the `workspace` directory.
+    ///
+    /// Fails if the base URI of a relative pattern isn't a `file://` URI.
+    pub fn new(watchers: &[FileSystemWatcher], workspace: &Path) -> Result<Self> {
+        let mut compiled = Vec::new();
+        let mut roots = Vec::new();
+        for watcher in watchers {
+            let (root, pattern) = match &watcher.glob_pattern {
+                GlobPattern::Pattern(pattern) if pattern.starts_with('/') => {
+                    (literal_prefix(pattern), pattern.clone())
+                }
+                GlobPattern::Pattern(pattern) => (workspace.to_path_buf(), pattern.clone()),
+                GlobPattern::Relative { base_uri, pattern } => {
+                    let base = uri_to_path(base_uri).ok_or_else(|| {
+                        Error::generic(&format!("base URI {:?} is not a file URI", base_uri))
+                    })?;
+                    let pattern = format!("{}/{}", glob_path(&base), pattern);
+                    (base, pattern)
+                }
+            };
+
+            let filter = expand_alternatives(&pattern)
+                .iter()
+                .fold(EventFilter::new().under(&root), |filter, pattern| {
+                    filter.glob(pattern)
+                });
+            compiled.push((filter, watcher.kind.unwrap_or_default()));
+            roots.push(root);
+        }
+
+        // parents sort before their children, which their recursive watches already cover
+        roots.sort();
+        let mut outer: Vec<PathBuf> = Vec::new();
+        for root in roots {
+            if !outer.iter().any(|parent| root.starts_with(parent)) {
+                outer.push(root);
+            }
+        }
+
+        Ok(Self {
+            watchers: compiled,
+            roots: outer,
+        })
+    }
+
+    /// The directories to watch recursively for the registered patterns
+    pub fn roots(&self) -> &[PathBuf] {
+        &self.roots
+    }
+
+    /// Watch the [roots](FileWatchers::roots) with `watcher`, usually the one of a debouncer.
+    pub fn watch(&self, watcher: &mut dyn Watcher) -> Result<()> {
+        for root in &self.roots {
+            watcher.watch(root, RecursiveMode::Recursive)?;
+        }
+        Ok(())
+    }
+
+    /// The file events of a debounced batch which a registered watcher is interested in
+    ///
+    /// Burst markers are skipped, and each path is reported once.
+    pub fn file_events(&self, events: &[DebouncedEvent]) -> Vec<FileEvent> {
+        let mut file_events: Vec<FileEvent> = Vec::new();
+        for event in events {
+            if matches!(
+                event.kind,
+                DebouncedEventKind::BurstStart | DebouncedEventKind::BurstEnd
+            ) {
+                continue;
+            }
+
+            let typ = if event.path.exists() {
+                FileChangeType::Changed
+            } else {
+                FileChangeType::Deleted
+            };
+            if !self.is_watched(&event.path, typ) {
+                continue;
+            }
+            let uri = path_to_uri(&event.path);
+            if !file_events.iter().any(|file_event| file_event.uri == uri) {
+                file_events.push(FileEvent { uri, typ });
+            }
+        }
+        file_events
+    }
+
+    /// Whether a registered watcher is interested in changes of type `typ` to `path`
+    pub fn is_watched(&self, path: &Path, typ: FileChangeType) -> bool {
+        self.watchers
+            .iter()
+            .any(|(filter, kind)| kind.contains(typ.watch_kind()) && filter.matches_path(path))
+    }
+}
+
+/// The leading components of an absolute pattern without wildcards
+fn literal_prefix(pattern: &str) -> PathBuf {
+    let mut prefix = PathBuf::from("/");
+    let components: Vec<&str> = pattern.split('/').filter(|c| !c.is_empty()).collect();
+    for (i, component) in components.iter().enumerate() {
+        // a pattern naming a file watches its directory
+        if i + 1 == components.len() || component.contains(|c| "*?{[".contains(c)) {
+            break;
+        }
+        prefix.push(component);
+    }
+    prefix
+}
+
+/// An absolute path as the start of an absolute pattern, see [`EventFilter::glob`]
+fn glob_path(path: &Path) -> String {
+    let mut glob = String::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => {
+                glob.push('/');
+                glob.push_str(&name.to_string_lossy());
+            }
+            Component::Prefix(prefix) => {
+                glob.push('/');
+                glob.push_str(&prefix.as_os_str().to_string_lossy());
+            }
+            _ => {}
+        }
+    }
+    glob
+}
+
+/// The patterns of all combinations of the `{a,b}` alternatives in `pattern`
+fn expand_alternatives(pattern: &str) -> Vec<String> {
+    let open = match pattern.find('{') {
+        Some(open) => open,
+        None => return vec![pattern.to_owned()],
+    };
+    let mut depth = 0;
+    let mut alternatives = Vec::new();
+    let mut start = open + 1;
+    for (i, c) in pattern[open..].char_indices().map(|(i, c)| (open + i, c)) {
+        match c {
+            '{' => depth += 1,
+            ',' if depth == 1 => {
+                alternatives.push(&pattern[start..i]);
+                start = i + 1;
+            }
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    alternatives.push(&pattern[start..i]);
+                    let (head, tail) = (&pattern[..open], &pattern[i + 1..]);
+                    return alternatives
+                        .iter()
+                        .flat_map(|alternative| {
+                            expand_alternatives(&format!("{}{}{}", head, alternative, tail))
+                        })
+                        .collect();
+                }
+            }
+            _ => {}
+        }
+    }
+    // an unclosed brace is matched literally
+    vec![pattern.to_owned()]
+}
+
+/// The `file://` URI of an absolute path
+pub fn path_to_uri(path: &Path) -> String {
+    let mut uri = String::from("file://");
+    for component in path.components() {
+        match component {
+            Component::Prefix(prefix) => {
+                uri.push('/');
+                uri.push_str(&prefix.as_os_str().to_string_lossy());
+            }
+            Component::Normal(name) => {
+                uri.push('/');
+                for byte in name.to_string_lossy().bytes() {
+                    match byte {
+                        b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                            uri.push(byte as char)
+                        }
+                        _ => uri.push_str(&format!("%{:02X}", byte)),
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    if !uri["file://".len()..].contains('/') {
+        uri.push('/');
+    }
+    uri
+}
+
+/// The path of a `file://` URI, `None` if it has another scheme, a host or invalid escapes
+pub fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    let rest = uri.strip_prefix("file://")?;
+    let path = rest.strip_prefix("localhost").unwrap_or(rest);
+    if !path.starts_with('/') {
+        return None;
+    }
+
+    let mut bytes = Vec::new();
+    let mut input = path.bytes();
+    while let Some(byte) = input.next() {
+        if byte == b'%' {
+            let hex = [input.next()?, input.next()?];
+            let hex = std::str::from_utf8(&hex).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+        } else {
+            bytes.push(byte);
+        }
+    }
+    let path = String::from_utf8(bytes).ok()?;
+
+    // `file:///C:/dir` names `C:/dir` on Windows
+    if cfg!(windows) && path.get(2..3) == Some(":") {
+        return Some(PathBuf::from(&path[1..]));
+    }
+    Some(PathBuf::from(path))
+}
+
+#[cfg(unix)]
+#[test]
+fn debounced_events_are_converted_into_file_events() {
+    let dir = std::env::temp_dir().join(format!("notify-lsp-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("src")).unwrap();
+    std::fs::write(dir.join("src/main.rs"), "").unwrap();
+    std::fs::write(dir.join("Cargo.toml"), "").unwrap();
+
+    let base_uri = path_to_uri(&dir.join("src"));
+    let watchers = FileWatchers::new(
+        &[
+            FileSystemWatcher::new(GlobPattern::from("**/*.{toml,lock}")),
+            FileSystemWatcher {
+                glob_pattern: GlobPattern::Relative {
+                    base_uri: base_uri.clone(),
+                    pattern: "*.rs".to_owned(),
+                },
+                kind: Some(WatchKind::DELETE),
+            },
+        ],
+        &dir,
+    )
+    .unwrap();
+    assert_eq!(watchers.roots(), std::slice::from_ref(&dir));
+
+    let event = |path: &str| DebouncedEvent::new(dir.join(path), DebouncedEventKind::Any);
+    let events = [
+        event("Cargo.toml"),
+        event("Cargo.lock"),
+        event("src/main.rs"),
+        event("src/lib.rs"),
+        event("src/nested/mod.rs"),
+        event("Cargo.toml"),
+    ];
+    assert_eq!(
+        watchers.file_events(&events),
+        [
+            FileEvent {
+                uri: path_to_uri(&dir.join("Cargo.toml")),
+                typ: FileChangeType::Changed,
+            },
+            FileEvent {
+                uri: path_to_uri(&dir.join("Cargo.lock")),
+                typ: FileChangeType::Deleted,
+            },
+            FileEvent {
+                uri: format!("{}/lib.rs", base_uri),
+                typ: FileChangeType::Deleted,
+            },
+        ]
+    );
+
+    assert_eq!(
+        path_to_uri(Path::new("/a b/ü#.rs")),
+        "file:///a%20b/%C3%BC%23.rs"
+    );
+    assert_eq!(
+        uri_to_path("file:///a%20b/%C3%BC%23.rs"),
+        Some(PathBuf::from("/a b/ü#.rs"))
+    );
+    assert_eq!(uri_to_path("https://example.com/a"), None);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn lsp_types_use_the_json_of_the_protocol() {
+    let watcher: FileSystemWatcher = serde_json::from_str(
+        r#"{ "globPattern": { "baseUri": { "uri": "file:///project", "name": "project" }, "pattern": "*.rs" }, "kind": 4 }"#,
+    )
+    .unwrap();
+    assert_eq!(
+        watcher,
+        FileSystemWatcher {
+            glob_pattern: GlobPattern::Relative {
+                base_uri: "file:///project".to_owned(),
+                pattern: "*.rs".to_owned(),
+            },
+            kind: Some(WatchKind::DELETE),
+        }
+    );
+
+    let event = FileEvent {
+        uri: "file:///project/main.rs".to_owned(),
+        typ: FileChangeType::Deleted,
+    };
+    let json = serde_json::to_string(&event).unwrap();
+    assert_eq!(json, r#"{"uri":"file:///project/main.rs","type":3}"#);
+    assert_eq!(serde_json::from_str::<FileEvent>(&json).unwrap(), event);
+}
@@ -0,0 +1,258 @@
+//! Running a closure or command for each debounced batch
+
+use crate::{DebounceEventHandler, DebounceEventResult, DebouncedEvent};
+use std::{
+    process::Command,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    time::Duration,
+};
+
+/// What to do with a batch arriving while the job is still running for an earlier one.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[non_exhaustive]
+pub enum OverlapPolicy {
+    /// Run the job for the new batch once the current run is done.
+    Queue,
+    /// Cancel the current run and run the job for the new batch.
+    ///
+    /// Commands are killed, closures are asked to stop through their [`Cancellation`].
+    Cancel,
+    /// Drop the new batch.
+    Skip,
+}
+
+/// Tells a running job whether it was cancelled by [`OverlapPolicy::Cancel`].
+#[derive(Clone, Debug, Default)]
+pub struct Cancellation(Arc<AtomicBool>);
+
+impl Cancellation {
+    /// Whether the job should stop
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Acquire)
+    }
+
+    fn cancel(&self) {
+        self.0.store(true, Ordering::Release);
+    }
+}
+
+/// A [`DebounceEventHandler`] running a job for each batch of debounced events, on its own thread.
+///
+/// ```rust,no_run
+/// # use std::path::Path;
+/// # use std::process::Command;
+/// # use std::time::Duration;
+/// use notify_debouncer_mini::{notify::*,new_debouncer,OverlapPolicy,Runner};
+///
+/// # fn main() {
+///     let mut cargo = Command::new("cargo");
+///     cargo.arg("build");
+///     let runner = Runner::command(cargo, OverlapPolicy::Cancel).unwrap();
+///     let mut debouncer = new_debouncer(Duration::from_secs(1), None, runner).unwrap();
+///     debouncer.watcher().watch(Path::new("src"), RecursiveMode::Recursive).unwrap();
+/// # }
+/// ```
+///
+/// Errors are not passed to the job. A run still in progress when the runner is dropped is finished,
+/// queued batches are dropped.
+#[derive(Debug)]
+pub struct Runner {
+    policy: OverlapPolicy,
+    tx: mpsc::Sender<(Vec<DebouncedEvent>, Cancellation)>,
+    running: Arc<AtomicBool>,
+    current: Arc<Mutex<Cancellation>>,
+    stop: Arc<AtomicBool>,
+}
+
+impl Runner {
+    /// Call `job` for each batch.
+    ///
+    /// The job runs on the runner's thread, one batch at a time.
+    pub fn new<F>(mut job: F, policy: OverlapPolicy) -> std::io::Result<Self>
+    where
+        F: FnMut(Vec<DebouncedEvent>, &Cancellation) + Send + 'static,
+    {
+        let (tx, rx) = mpsc::channel::<(Vec<DebouncedEvent>, Cancellation)>();
+        let running = Arc::new(AtomicBool::new(false));
+        let stop = Arc::new(AtomicBool::new(false));
+        let running_c = running.clone();
+        let stop_c = stop.clone();
+        std::thread::Builder::new()
+            .name("notify-rs debouncer runner".to_string())
+            .spawn(move || {
+                while let Ok((events, cancellation)) = rx.recv() {
+                    if stop_c.load(Ordering::Acquire) {
+                        break;
+                    }
+                    if cancellation.is_cancelled() {
+                        continue;
+                    }
+                    running_c.store(true, Ordering::Release);
+                    job(events, &cancellation);
+                    running_c.store(false, Ordering::Release);
+                }
+            })?;
+
+        Ok(Self {
+            policy,
+            tx,
+            running,
+            current: Arc::default(),
+            stop,
+        })
+    }
+
+    /// Spawn `command` for each batch and wait for it to exit.
+    ///
+    /// Failing to spawn the command is ignored.
+    pub fn command(mut command: Command, policy: OverlapPolicy) -> std::io::Result<Self> {
+        Self::new(
+            move |_events, cancellation| {
+                let mut child = match command.spawn() {
+                    Ok(child) => child,
+                    Err(_) => return,
+                };
+                loop {
+                    match child.try_wait() {
+                        Ok(None) if cancellation.is_cancelled() => {
+                            let _ = child.kill();
+                            let _ = child.wait();
+                            return;
+                        }
+                        Ok(None) => std::thread::sleep(Duration::from_millis(10)),
+                        _ => return,
+                    }
+                }
+            },
+            policy,
+        )
+    }
+
+    /// Whether the job is currently running
+    ///
+    /// With [`OverlapPolicy::Skip`] this includes a batch passed to the runner whose run didn't start
+    /// yet.
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::Acquire)
+    }
+}
+
+impl DebounceEventHandler for Runner {
+    fn handle_event(&mut self, event: DebounceEventResult) {
+        let events = match event {
+            Ok(events) => events,
+            Err(_) => return,
+        };
+
+        let cancellation = Cancellation::default();
+        match self.policy {
+            OverlapPolicy::Queue => {}
+            // claimed before sending, a batch arriving before the job started is skipped as well
+            OverlapPolicy::Skip => {
+                if self
+                    .running
+                    .compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire)
+                    .is_err()
+                {
+                    return;
+                }
+            }
+            OverlapPolicy::Cancel => {
+                // cancels both the current run and any batch still waiting for it
+                let mut current = self.current.lock().expect("Can't lock runner!");
+                current.cancel();
+                *current = cancellation.clone();
+            }
+        }
+        let _ = self.tx.send((events, cancellation));
+    }
+}
+
+impl Drop for Runner {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+    }
+}
+
+#[test]
+fn runner_applies_overlap_policy() {
+    use crate::DebouncedEventKind;
+    use std::path::{Path, PathBuf};
+
+    let batch = |path: &str| {
+        Ok(vec![DebouncedEvent::new(
+            PathBuf::from(path),
+            DebouncedEventKind::Any,
+        )])
+    };
+    let run = |policy| {
+        let (started_tx, started_rx) = mpsc::channel();
+        let (done_tx, done_rx) = mpsc::channel();
+        let mut runner = Runner::new(
+            move |events: Vec<DebouncedEvent>, cancellation: &Cancellation| {
+                let _ = started_tx.send(());
+                // the first run lasts until it is cancelled, or at most 300ms
+                if events[0].path == Path::new("first") {
+                    for _ in 0..30 {
+                        if cancellation.is_cancelled() {
+                            break;
+                        }
+                        std::thread::sleep(Duration::from_millis(10));
+                    }
+                }
+                done_tx
+                    .send((events[0].path.clone(), cancellation.is_cancelled()))
+                    .unwrap();
+            },
+            policy,
+        )
+        .unwrap();
+        runner.handle_event(batch("first"));
+        started_rx.recv().unwrap();
+        runner.handle_event(batch("second"));
+        runner.handle_event(batch("third"));
+        drop(started_rx);
+        let mut runs = Vec::new();
+        while let Ok(run) = done_rx.recv_timeout(Duration::from_secs(1)) {
+            runs.push(run);
+        }
+        runs
+    };
+    let path = PathBuf::from;
+
+    assert_eq!(
+        run(OverlapPolicy::Queue),
+        [
+            (path("first"), false),
+            (path("second"), false),
+            (path("third"), false)
+        ]
+    );
+    assert_eq!(run(OverlapPolicy::Skip), [(path("first"), false)]);
+    assert_eq!(
+        run(OverlapPolicy::Cancel),
+        [(path("first"), true), (path("third"), false)]
+    );
+
+    // the job of a skipping runner doesn't have to be started yet to skip further batches
+    let (tx, rx) = mpsc::channel();
+    let mut runner = Runner::new(
+        move |events: Vec<DebouncedEvent>, _: &Cancellation| {
+            tx.send(events[0].path.clone()).unwrap();
+        },
+        OverlapPolicy::Skip,
+    )
+    .unwrap();
+    runner.handle_event(batch("first"));
+    runner.handle_event(batch("second"));
+    assert_eq!(rx.recv_timeout(Duration::from_secs(1)), Ok(path("first")));
+    assert!(rx.recv_timeout(Duration::from_millis(100)).is_err());
+    while runner.is_running() {
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    runner.handle_event(batch("third"));
+    assert_eq!(rx.recv_timeout(Duration::from_secs(1)), Ok(path("third")));
+}
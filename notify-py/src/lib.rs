@@ -0,0 +1,517 @@
+//! Python bindings for notify and its mini debouncer
+//!
+//! Builds the `notify_rs` Python module with [maturin](https://www.maturin.rs):
+//!
+//! ```python
+//! import notify_rs
+//!
+//! watcher = notify_rs.Watcher()
+//! watcher.watch("src", recursive=True)
+//! for event in watcher:
+//!     print(event.kind, event.paths)
+//! ```
+//!
+//! Both the `Watcher` and the `Debouncer` either pass their events to a callback given to them,
+//! or are iterated over. Iterating blocks until the next event without holding the GIL, and
+//! raises `KeyboardInterrupt` and friends as they arrive.
+
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender};
+use notify::format::{self, Verbosity};
+use notify::{Config, PollWatcher, RecommendedWatcher, RecursiveMode, Watcher};
+use notify_debouncer_mini::{
+    new_debouncer_opt, DebounceEventResult, DebouncedEventKind, Debouncer,
+};
+use pyo3::create_exception;
+use pyo3::exceptions::{PyException, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyList;
+use std::{
+    collections::VecDeque,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+create_exception!(
+    notify_rs,
+    NotifyError,
+    PyException,
+    "An error reported by a watcher, other than an IO error."
+);
+
+fn to_py_err(error: notify::Error) -> PyErr {
+    let message = error.to_string();
+    match error.kind {
+        notify::ErrorKind::Io(e) => e.into(),
+        _ => NotifyError::new_err(message),
+    }
+}
+
+/// The exception raised for `error`, to pass it to a callback.
+fn exception(py: Python<'_>, error: notify::Error) -> Bound<'_, PyAny> {
+    to_py_err(error).into_value(py).into_bound(py).into_any()
+}
+
+/// Call `callback` with `arg`, printing the exception it raises.
+fn call(py: Python<'_>, callback: &PyObject, arg: PyResult<Bound<'_, PyAny>>) {
+    if let Err(e) = arg.and_then(|arg| callback.call1(py, (arg,))) {
+        e.print(py);
+    }
+}
+
+fn duration(seconds: f64) -> PyResult<Duration> {
+    if !(seconds.is_finite() && seconds >= 0.0) {
+        return Err(PyValueError::new_err(
+            "durations must be positive numbers of seconds",
+        ));
+    }
+    Ok(Duration::from_secs_f64(seconds))
+}
+
+fn recursive_mode(recursive: bool) -> RecursiveMode {
+    match recursive {
+        true => RecursiveMode::Recursive,
+        false => RecursiveMode::NonRecursive,
+    }
+}
+
+/// Wait for a value from `rx` without the GIL, checking for signals in between.
+///
+/// Returns `None` if `timeout` passed or all senders are gone.
+fn recv<T: Send>(
+    py: Python<'_>,
+    rx: &Receiver<T>,
+    timeout: Option<Duration>,
+) -> PyResult<Option<T>> {
+    const SIGNAL_CHECK: Duration = Duration::from_millis(100);
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    loop {
+        let wait = match deadline {
+            Some(deadline) => deadline
+                .saturating_duration_since(Instant::now())
+                .min(SIGNAL_CHECK),
+            None => SIGNAL_CHECK,
+        };
+        match py.allow_threads(|| rx.recv_timeout(wait)) {
+            Ok(value) => return Ok(Some(value)),
+            Err(RecvTimeoutError::Disconnected) => return Ok(None),
+            Err(RecvTimeoutError::Timeout) => {
+                py.check_signals()?;
+                if deadline.map_or(false, |deadline| Instant::now() >= deadline) {
+                    return Ok(None);
+                }
+            }
+        }
+    }
+}
+
+/// A filesystem event.
+///
+/// `kind` is the top-level kind in lower case, like `"modify"`, and `details` the full kind,
+/// like `"MODIFY(data:content)"`.
+#[pyclass(module = "notify_rs", name = "Event", frozen)]
+struct PyEvent {
+    event: notify::Event,
+}
+
+#[pymethods]
+impl PyEvent {
+    #[getter]
+    fn kind(&self) -> String {
+        format::kind(&self.event.kind, Verbosity::Brief)
+            .to_string()
+            .to_lowercase()
+    }
+
+    #[getter]
+    fn details(&self) -> String {
+        format::kind(&self.event.kind, Verbosity::Verbose).to_string()
+    }
+
+    #[getter]
+    fn paths(&self) -> Vec<PathBuf> {
+        self.event.paths.clone()
+    }
+
+    #[getter]
+    fn tracker(&self) -> Option<usize> {
+        self.event.tracker()
+    }
+
+    #[getter]
+    fn info(&self) -> Option<&str> {
+        self.event.info()
+    }
+
+    #[getter]
+    fn source(&self) -> Option<&str> {
+        self.event.source()
+    }
+
+    #[getter]
+    fn watch_token(&self) -> Option<u64> {
+        self.event.watch_token()
+    }
+
+    #[getter]
+    fn need_rescan(&self) -> bool {
+        self.event.need_rescan()
+    }
+
+    fn __str__(&self) -> String {
+        self.event.to_string()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("<Event {}>", format::event(&self.event, Verbosity::Verbose))
+    }
+}
+
+/// Passes the events of a [PyWatcher] on.
+enum Handler {
+    Callback(PyObject),
+    Channel(Sender<notify::Result<notify::Event>>),
+}
+
+impl notify::EventHandler for Handler {
+    fn handle_event(&mut self, event: notify::Result<notify::Event>) {
+        match self {
+            Self::Callback(callback) => Python::with_gil(|py| {
+                let arg = match event {
+                    Ok(event) => Bound::new(py, PyEvent { event }).map(Bound::into_any),
+                    Err(e) => Ok(exception(py, e)),
+                };
+                call(py, callback, arg);
+            }),
+            Self::Channel(tx) => {
+                let _ = tx.send(event);
+            }
+        }
+    }
+}
+
+/// Watches paths with the recommended backend of the platform, or by polling.
+///
+/// With a `callback`, it is called with each `Event`, or the exception of an error, from the
+/// watcher's thread. Without one, the watcher is iterated over.
+#[pyclass(module = "notify_rs", name = "Watcher")]
+struct PyWatcher {
+    watcher: Box<dyn Watcher + Send + Sync>,
+    rx: Option<Receiver<notify::Result<notify::Event>>>,
+}
+
+#[pymethods]
+impl PyWatcher {
+    /// Pass `poll_interval` in seconds to poll instead of using the recommended backend.
+    #[new]
+    #[pyo3(signature = (callback=None, *, poll_interval=None))]
+    fn new(callback: Option<PyObject>, poll_interval: Option<f64>) -> PyResult<Self> {
+        let mut config = Config::default();
+        if let Some(poll_interval) = poll_interval {
+            config = config.with_poll_interval(duration(poll_interval)?);
+        }
+
+        let (handler, rx) = match callback {
+            Some(callback) => (Handler::Callback(callback), None),
+            None => {
+                let (tx, rx) = crossbeam_channel::unbounded();
+                (Handler::Channel(tx), Some(rx))
+            }
+        };
+
+        let watcher: Box<dyn Watcher + Send + Sync> = match poll_interval {
+            Some(_) => Box::new(PollWatcher::new(handler, config).map_err(to_py_err)?),
+            None => Box::new(RecommendedWatcher::new(handler, config).map_err(to_py_err)?),
+        };
+        Ok(Self { watcher, rx })
+    }
+
+    #[pyo3(signature = (path, recursive=true))]
+    fn watch(&mut self, py: Python<'_>, path: PathBuf, recursive: bool) -> PyResult<()> {
+        let watcher = &mut self.watcher;
+        py.allow_threads(|| watcher.watch(&path, recursive_mode(recursive)))
+            .map_err(to_py_err)
+    }
+
+    fn unwatch(&mut self, py: Python<'_>, path: PathBuf) -> PyResult<()> {
+        let watcher = &mut self.watcher;
+        py.allow_threads(|| watcher.unwatch(&path))
+            .map_err(to_py_err)
+    }
+
+    /// Wait at most `timeout` seconds for the next event, forever without one.
+    ///
+    /// Returns `None` if none arrived in time.
+    #[pyo3(signature = (timeout=None))]
+    fn next_event(&self, py: Python<'_>, timeout: Option<f64>) -> PyResult<Option<PyEvent>> {
+        let rx = self.receiver()?;
+        let timeout = timeout.map(duration).transpose()?;
+        match recv(py, rx, timeout)? {
+            Some(event) => Ok(Some(PyEvent {
+                event: event.map_err(to_py_err)?,
+            })),
+            None => Ok(None),
+        }
+    }
+
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&self, py: Python<'_>) -> PyResult<Option<PyEvent>> {
+        self.next_event(py, None)
+    }
+}
+
+impl PyWatcher {
+    fn receiver(&self) -> PyResult<&Receiver<notify::Result<notify::Event>>> {
+        self.rx
+            .as_ref()
+            .ok_or_else(|| NotifyError::new_err("events are passed to the callback"))
+    }
+}
+
+/// A debounced event: one per path and timeout, see notify-debouncer-mini.
+///
+/// `kind` is `"any"`, `"any_continuous"`, `"any_throttled"`, `"burst_start"` or `"burst_end"`.
+#[pyclass(module = "notify_rs", name = "DebouncedEvent", frozen)]
+struct PyDebouncedEvent {
+    #[pyo3(get)]
+    path: PathBuf,
+    kind: DebouncedEventKind,
+}
+
+#[pymethods]
+impl PyDebouncedEvent {
+    #[getter]
+    fn kind(&self) -> &'static str {
+        match self.kind {
+            DebouncedEventKind::Any => "any",
+            DebouncedEventKind::AnyContinuous => "any_continuous",
+            DebouncedEventKind::AnyThrottled => "any_throttled",
+            DebouncedEventKind::BurstStart => "burst_start",
+            DebouncedEventKind::BurstEnd => "burst_end",
+            _ => "other",
+        }
+    }
+
+    fn __repr__(&self) -> String {
+        format!("<DebouncedEvent {} {}>", self.kind, self.path.display())
+    }
+}
+
+fn debounced_events(events: Vec<notify_debouncer_mini::DebouncedEvent>) -> Vec<PyDebouncedEvent> {
+    events
+        .into_iter()
+        .map(|event| PyDebouncedEvent {
+            path: event.path,
+            kind: event.kind,
+        })
+        .collect()
+}
+
+/// Debounces the events of the recommended watcher, emitting one event per path and `timeout`
+/// seconds.
+///
+/// With a `callback`, it is called with each batch, a list of `DebouncedEvent`s, or the
+/// exception of the first error. Without one, the debouncer is iterated over.
+#[pyclass(module = "notify_rs", name = "Debouncer")]
+struct PyDebouncer {
+    debouncer: Debouncer<RecommendedWatcher>,
+    rx: Option<Receiver<DebounceEventResult>>,
+    pending: VecDeque<PyDebouncedEvent>,
+}
+
+#[pymethods]
+impl PyDebouncer {
+    #[new]
+    #[pyo3(signature = (timeout, callback=None))]
+    fn new(timeout: f64, callback: Option<PyObject>) -> PyResult<Self> {
+        let timeout = duration(timeout)?;
+        let (debouncer, rx) = match callback {
+            Some(callback) => {
+                let handler = move |result: DebounceEventResult| {
+                    Python::with_gil(|py| {
+                        let arg = match result {
+                            Ok(events) => {
+                                PyList::new(py, debounced_events(events)).map(Bound::into_any)
+                            }
+                            Err(errors) => match errors.into_iter().next() {
+                                Some(e) => Ok(exception(py, e)),
+                                None => return,
+                            },
+                        };
+                        call(py, &callback, arg);
+                    })
+                };
+                let debouncer = new_debouncer_opt::<_, RecommendedWatcher>(
+                    timeout,
+                    None,
+                    handler,
+                    Config::default(),
+                );
+                (debouncer.map_err(to_py_err)?, None)
+            }
+            None => {
+                let (tx, rx) = crossbeam_channel::unbounded();
+                let debouncer = new_debouncer_opt::<_, RecommendedWatcher>(
+                    timeout,
+                    None,
+                    tx,
+                    Config::default(),
+                );
+                (debouncer.map_err(to_py_err)?, Some(rx))
+            }
+        };
+        Ok(Self {
+            debouncer,
+            rx,
+            pending: VecDeque::new(),
+        })
+    }
+
+    #[pyo3(signature = (path, recursive=true))]
+    fn watch(&mut self, py: Python<'_>, path: PathBuf, recursive: bool) -> PyResult<()> {
+        let debouncer = &mut self.debouncer;
+        py.allow_threads(|| debouncer.watcher().watch(&path, recursive_mode(recursive)))
+            .map_err(to_py_err)
+    }
+
+    fn unwatch(&mut self, py: Python<'_>, path: PathBuf) -> PyResult<()> {
+        let debouncer = &mut self.debouncer;
+        py.allow_threads(|| debouncer.watcher().unwatch(&path))
+            .map_err(to_py_err)
+    }
+
+    /// Wait at most `timeout` seconds for the next event, forever without one.
+    ///
+    /// Returns `None` if none arrived in time.
+    #[pyo3(signature = (timeout=None))]
+    fn next_event(
+        &mut self,
+        py: Python<'_>,
+        timeout: Option<f64>,
+    ) -> PyResult<Option<PyDebouncedEvent>> {
+        let timeout = timeout.map(duration).transpose()?;
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        while self.pending.is_empty() {
+            let rx = self
+                .rx
+                .as_ref()
+                .ok_or_else(|| NotifyError::new_err("events are passed to the callback"))?;
+            let timeout =
+                deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+            match recv(py, rx, timeout)? {
+                Some(Ok(events)) => self.pending.extend(debounced_events(events)),
+                Some(Err(errors)) => {
+                    if let Some(e) = errors.into_iter().next() {
+                        return Err(to_py_err(e));
+                    }
+                }
+                None => return Ok(None),
+            }
+        }
+        Ok(self.pending.pop_front())
+    }
+
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&mut self, py: Python<'_>) -> PyResult<Option<PyDebouncedEvent>> {
+        self.next_event(py, None)
+    }
+}
+
+#[pymodule]
+fn notify_rs(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add("NotifyError", m.py().get_type::<NotifyError>())?;
+    m.add_class::<PyEvent>()?;
+    m.add_class::<PyWatcher>()?;
+    m.add_class::<PyDebouncedEvent>()?;
+    m.add_class::<PyDebouncer>()?;
+    Ok(())
+}
+
+#[cfg(test)]
+fn with_module<F: FnOnce(Python<'_>, &Bound<'_, PyModule>)>(f: F) {
+    pyo3::prepare_freethreaded_python();
+    Python::with_gil(|py| {
+        let module = PyModule::new(py, "notify_rs").unwrap();
+        notify_rs(&module).unwrap();
+        f(py, &module);
+    });
+}
+
+#[test]
+fn events_are_converted_to_python_objects() {
+    use notify::event::{DataChange, EventKind, ModifyKind};
+
+    with_module(|py, _| {
+        let event = notify::Event::new(EventKind::Modify(ModifyKind::Data(DataChange::Content)))
+            .add_path(PathBuf::from("/a"))
+            .set_tracker(3)
+            .set_watch_token(9);
+        let event = Bound::new(py, PyEvent { event }).unwrap();
+        let get = |name| event.getattr(name).unwrap();
+
+        assert_eq!(get("kind").extract::<String>().unwrap(), "modify");
+        assert_eq!(
+            get("details").extract::<String>().unwrap(),
+            "MODIFY(data:content)"
+        );
+        assert_eq!(
+            get("paths").extract::<Vec<PathBuf>>().unwrap(),
+            [PathBuf::from("/a")]
+        );
+        assert_eq!(get("tracker").extract::<Option<usize>>().unwrap(), Some(3));
+        assert_eq!(
+            get("watch_token").extract::<Option<u64>>().unwrap(),
+            Some(9)
+        );
+        assert!(get("info").is_none());
+        assert!(!get("need_rescan").extract::<bool>().unwrap());
+        assert!(event.repr().unwrap().to_string().starts_with("<Event "));
+    });
+}
+
+#[test]
+fn debounced_events_are_converted_to_python_objects() {
+    with_module(|py, _| {
+        let events = debounced_events(vec![notify_debouncer_mini::DebouncedEvent {
+            path: PathBuf::from("/a"),
+            kind: DebouncedEventKind::AnyContinuous,
+        }]);
+        let list = PyList::new(py, events).unwrap();
+        let event = list.get_item(0).unwrap();
+
+        assert_eq!(
+            event.getattr("path").unwrap().extract::<PathBuf>().unwrap(),
+            PathBuf::from("/a")
+        );
+        assert_eq!(
+            event.getattr("kind").unwrap().extract::<String>().unwrap(),
+            "any_continuous"
+        );
+    });
+}
+
+#[test]
+fn errors_are_raised_as_python_exceptions() {
+    use pyo3::exceptions::PyFileNotFoundError;
+
+    with_module(|py, module| {
+        let not_found = std::io::Error::from(std::io::ErrorKind::NotFound);
+        let error = to_py_err(notify::Error::io(not_found));
+        assert!(error.is_instance_of::<PyFileNotFoundError>(py));
+
+        let error = to_py_err(notify::Error::watch_not_found());
+        assert!(error.is_instance(py, &module.getattr("NotifyError").unwrap()));
+        assert!(error.is_instance_of::<PyException>(py));
+
+        assert!(duration(-1.0)
+            .unwrap_err()
+            .is_instance_of::<PyValueError>(py));
+        assert!(duration(f64::NAN).is_err());
+        assert_eq!(duration(0.5).unwrap(), Duration::from_millis(500));
+    });
+}
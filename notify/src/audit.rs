@@ -0,0 +1,308 @@
+#[cfg(feature = "debug-audit")]
+use crate::Config;
+#[cfg(feature = "debug-audit")]
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap, HashSet},
+    fmt,
+    hash::{Hash, Hasher},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+/// A kind of OS resource allocated by the watchers
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(not(feature = "debug-audit"), allow(dead_code))]
+#[non_exhaustive]
+pub enum Resource {
+    /// An inotify watch descriptor, or a file descriptor watched by kqueue
+    WatchDescriptor,
+    /// A Windows handle or an FSEvents stream
+    Handle,
+    /// A thread spawned by the watcher
+    Thread,
+}
+
+#[cfg(feature = "debug-audit")]
+impl Resource {
+    const ALL: [Resource; 3] = [
+        Resource::WatchDescriptor,
+        Resource::Handle,
+        Resource::Thread,
+    ];
+
+    fn index(self) -> usize {
+        match self {
+            Resource::WatchDescriptor => 0,
+            Resource::Handle => 1,
+            Resource::Thread => 2,
+        }
+    }
+}
+
+/// A problem found by the audit
+#[cfg(feature = "debug-audit")]
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum Violation {
+    /// Resources were still held when the watcher was dropped
+    Leak {
+        /// The backend of the watcher
+        backend: &'static str,
+        /// The kind of the resources
+        resource: Resource,
+        /// How many were held
+        count: usize,
+    },
+    /// A resource was released which wasn't held, or was released twice
+    UnknownRelease {
+        /// The backend of the watcher
+        backend: &'static str,
+        /// The kind of the resource
+        resource: Resource,
+    },
+    /// The internal state of the watcher is inconsistent
+    Invariant {
+        /// The backend of the watcher
+        backend: &'static str,
+        /// What is wrong
+        message: String,
+    },
+}
+
+#[cfg(feature = "debug-audit")]
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Violation::Leak {
+                backend,
+                resource,
+                count,
+            } => write!(f, "{}: {} {:?} resources leaked", backend, count, resource),
+            Violation::UnknownRelease { backend, resource } => {
+                write!(
+                    f,
+                    "{}: released a {:?} which wasn't held",
+                    backend, resource
+                )
+            }
+            Violation::Invariant { backend, message } => write!(f, "{}: {}", backend, message),
+        }
+    }
+}
+
+/// The function called with every [Violation], see [Config::with_audit_hook]
+#[cfg(feature = "debug-audit")]
+#[derive(Clone, Copy)]
+pub struct AuditHook(pub fn(&Violation));
+
+#[cfg(feature = "debug-audit")]
+impl PartialEq for AuditHook {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 as usize == other.0 as usize
+    }
+}
+
+#[cfg(feature = "debug-audit")]
+impl Eq for AuditHook {}
+
+#[cfg(feature = "debug-audit")]
+impl Hash for AuditHook {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (self.0 as usize).hash(state);
+    }
+}
+
+#[cfg(feature = "debug-audit")]
+impl fmt::Debug for AuditHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("AuditHook")
+            .field(&(self.0 as *const ()))
+            .finish()
+    }
+}
+
+#[cfg(feature = "debug-audit")]
+static LIVE: [AtomicUsize; 3] = [
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+];
+
+/// Returns how many resources of this kind all watchers of the process hold
+#[cfg(feature = "debug-audit")]
+pub fn live(resource: Resource) -> usize {
+    LIVE[resource.index()].load(Ordering::SeqCst)
+}
+
+/// The resources of one watcher, shared by its threads
+///
+/// Resources are told apart by a key, acquiring a held resource again is a no-op.
+#[cfg(feature = "debug-audit")]
+#[derive(Clone, Debug)]
+pub(crate) struct Audit {
+    inner: Arc<Inner>,
+}
+
+#[cfg(feature = "debug-audit")]
+#[derive(Debug)]
+struct Inner {
+    backend: &'static str,
+    hook: Option<AuditHook>,
+    held: Mutex<HashMap<Resource, HashSet<u64>>>,
+}
+
+// not every backend checks invariants
+#[cfg(feature = "debug-audit")]
+#[allow(dead_code)]
+impl Audit {
+    pub(crate) fn new(backend: &'static str, config: &Config) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                backend,
+                hook: config.audit_hook(),
+                held: Mutex::new(HashMap::new()),
+            }),
+        }
+    }
+
+    pub(crate) fn acquire<K: Hash>(&self, resource: Resource, key: K) {
+        if self
+            .inner
+            .lock()
+            .entry(resource)
+            .or_default()
+            .insert(hash(key))
+        {
+            LIVE[resource.index()].fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    pub(crate) fn release<K: Hash>(&self, resource: Resource, key: K) {
+        let released = self
+            .inner
+            .lock()
+            .entry(resource)
+            .or_default()
+            .remove(&hash(key));
+        if released {
+            LIVE[resource.index()].fetch_sub(1, Ordering::SeqCst);
+        } else {
+            self.inner.report(Violation::UnknownRelease {
+                backend: self.inner.backend,
+                resource,
+            });
+        }
+    }
+
+    /// Release all resources of this kind, like the watch descriptors of a closed inotify instance
+    pub(crate) fn release_all(&self, resource: Resource) {
+        let count = self.held(resource);
+        self.inner.lock().remove(&resource);
+        LIVE[resource.index()].fetch_sub(count, Ordering::SeqCst);
+    }
+
+    pub(crate) fn held(&self, resource: Resource) -> usize {
+        self.inner.lock().get(&resource).map_or(0, HashSet::len)
+    }
+
+    /// Check an invariant of the watcher's state, `check` returns what is wrong
+    pub(crate) fn check<F: FnOnce() -> Option<String>>(&self, check: F) {
+        if let Some(message) = check() {
+            self.inner.report(Violation::Invariant {
+                backend: self.inner.backend,
+                message,
+            });
+        }
+    }
+}
+
+#[cfg(feature = "debug-audit")]
+impl Inner {
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<Resource, HashSet<u64>>> {
+        // a hook panicking in another thread doesn't make the counts wrong
+        self.held.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn report(&self, violation: Violation) {
+        match self.hook {
+            Some(AuditHook(hook)) => hook(&violation),
+            None => panic!("notify audit: {}", violation),
+        }
+    }
+}
+
+#[cfg(feature = "debug-audit")]
+impl Drop for Inner {
+    fn drop(&mut self) {
+        let held = std::mem::take(&mut *self.lock());
+        for resource in Resource::ALL {
+            let count = held.get(&resource).map_or(0, HashSet::len);
+            if count > 0 {
+                LIVE[resource.index()].fetch_sub(count, Ordering::SeqCst);
+                self.report(Violation::Leak {
+                    backend: self.backend,
+                    resource,
+                    count,
+                });
+            }
+        }
+    }
+}
+
+#[cfg(feature = "debug-audit")]
+fn hash<K: Hash>(key: K) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Without the `debug-audit` feature, nothing is tracked
+#[cfg(not(feature = "debug-audit"))]
+#[derive(Clone, Debug)]
+pub(crate) struct Audit;
+
+#[cfg(not(feature = "debug-audit"))]
+#[allow(dead_code)]
+impl Audit {
+    pub(crate) fn new(_backend: &'static str, _config: &crate::Config) -> Self {
+        Audit
+    }
+
+    pub(crate) fn acquire<K>(&self, _resource: Resource, _key: K) {}
+
+    pub(crate) fn release<K>(&self, _resource: Resource, _key: K) {}
+
+    pub(crate) fn release_all(&self, _resource: Resource) {}
+
+    pub(crate) fn held(&self, _resource: Resource) -> usize {
+        0
+    }
+
+    pub(crate) fn check<F: FnOnce() -> Option<String>>(&self, _check: F) {}
+}
+
+#[cfg(feature = "debug-audit")]
+#[test]
+fn audit_reports_leaks_and_unknown_releases() {
+    static VIOLATIONS: AtomicUsize = AtomicUsize::new(0);
+    fn count(_: &Violation) {
+        VIOLATIONS.fetch_add(1, Ordering::SeqCst);
+    }
+
+    let config = Config::default().with_audit_hook(Some(AuditHook(count)));
+    let audit = Audit::new("test", &config);
+    audit.acquire(Resource::Handle, 1);
+    audit.acquire(Resource::Handle, 1);
+    audit.acquire(Resource::Handle, 2);
+    assert_eq!(audit.held(Resource::Handle), 2);
+    audit.release(Resource::Handle, 1);
+    audit.release(Resource::Handle, 1);
+    audit.check(|| None);
+    audit.check(|| Some("broken".to_string()));
+    assert_eq!(VIOLATIONS.load(Ordering::SeqCst), 2);
+
+    drop(audit);
+    assert_eq!(VIOLATIONS.load(Ordering::SeqCst), 3);
+}
@@ -0,0 +1,331 @@
+//! Recursive watches made of one non-recursive watch per directory
+
+use crate::delivery::SharedHandler;
+use crate::event::{CreateKind, EventKind, ModifyKind, Precision};
+use crate::scheduling::Scheduling;
+use crate::{registry, unbounded, Config, Event, EventHandler, Result, Sender};
+use crate::{RecursiveMode, SuppressGuard, Watcher, WatcherKind};
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard, Weak},
+};
+use walkdir::WalkDir;
+
+/// A watcher emulating recursive watches by watching every directory below them non-recursively,
+/// and watching new directories as they are created.
+///
+/// This gives the same recursive semantics on every backend, whether it supports recursive
+/// watches natively or not:
+///
+/// ```no_run
+/// # use std::path::Path;
+/// use notify::{AutoWatcher, Config, PollWatcher, RecursiveMode, Watcher};
+///
+/// # fn main() -> notify::Result<()> {
+/// let mut watcher =
+///     AutoWatcher::<PollWatcher>::new(|event| println!("{:?}", event), Config::default())?;
+/// watcher.watch(Path::new("."), RecursiveMode::Recursive)?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Directories are watched from a separate thread once their creation is reported, so entries
+/// created inside them in the meantime are reported as created when the directory is watched.
+/// Directories are unwatched once they are reported as removed or renamed.
+#[derive(Debug)]
+pub struct AutoWatcher<W> {
+    watcher: Arc<Mutex<W>>,
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// Recursively watched paths
+    roots: Vec<PathBuf>,
+    /// Directories below the roots, watched non-recursively on their behalf
+    watched: HashSet<PathBuf>,
+}
+
+impl State {
+    fn root_of(&self, path: &Path) -> Option<&Path> {
+        self.roots
+            .iter()
+            .map(PathBuf::as_path)
+            .find(|root| path != *root && path.starts_with(root))
+    }
+
+    /// Watch the directories in the tree of `dir`, reporting their entries as created if
+    /// `event_handler` is given.
+    fn add_tree<W: Watcher>(
+        &mut self,
+        watcher: &mut W,
+        dir: &Path,
+        mut event_handler: Option<&mut SharedHandler>,
+    ) {
+        for entry in WalkDir::new(dir)
+            .min_depth(1)
+            .follow_links(true)
+            .into_iter()
+            .filter_map(|entry| entry.ok())
+        {
+            let is_dir = entry.file_type().is_dir();
+            if is_dir
+                && !self.watched.contains(entry.path())
+                && watcher
+                    .watch(entry.path(), RecursiveMode::NonRecursive)
+                    .is_ok()
+            {
+                self.watched.insert(entry.path().to_owned());
+            }
+
+            if let Some(ref mut event_handler) = event_handler {
+                let kind = match is_dir {
+                    true => CreateKind::Folder,
+                    false => CreateKind::File,
+                };
+                event_handler.handle_event(Ok(Event::new(EventKind::Create(kind))
+                    .add_path(entry.into_path())
+                    .set_precision(Precision::Inferred)));
+            }
+        }
+    }
+
+    /// Stop watching `root` recursively, unwatching the directories no other root covers.
+    fn remove_root<W: Watcher>(&mut self, watcher: &mut W, root: &Path) {
+        self.roots.retain(|other| other != root);
+        let covered = |dir: &Path| self.roots.iter().any(|other| dir.starts_with(other));
+        let removed: Vec<PathBuf> = self
+            .watched
+            .iter()
+            .filter(|dir| dir.starts_with(root) && !covered(dir))
+            .cloned()
+            .collect();
+        for dir in removed {
+            let _ = watcher.unwatch(&dir);
+            self.watched.remove(&dir);
+        }
+    }
+
+    /// Unwatch the directories in the tree of the removed `path`.
+    fn remove_tree<W: Watcher>(&mut self, watcher: &mut W, path: &Path) {
+        let removed: Vec<PathBuf> = self
+            .watched
+            .iter()
+            .filter(|dir| dir.starts_with(path))
+            .cloned()
+            .collect();
+        for dir in removed {
+            let _ = watcher.unwatch(&dir);
+            self.watched.remove(&dir);
+        }
+    }
+
+    /// Follow the creation, removal or renaming of `path`.
+    fn update<W: Watcher>(
+        &mut self,
+        watcher: &mut W,
+        path: &Path,
+        event_handler: &mut SharedHandler,
+    ) {
+        if self.root_of(path).is_none() {
+            return;
+        }
+
+        if path.is_dir() {
+            if self.watched.contains(path)
+                || watcher.watch(path, RecursiveMode::NonRecursive).is_err()
+            {
+                return;
+            }
+            self.watched.insert(path.to_owned());
+            self.add_tree(watcher, path, Some(event_handler));
+        } else if !path.exists() {
+            self.remove_tree(watcher, path);
+        }
+    }
+}
+
+impl<W: Watcher> AutoWatcher<W> {
+    fn lock(&self) -> (MutexGuard<'_, W>, MutexGuard<'_, State>) {
+        // a panicking event handler leaves the watches intact
+        let watcher = self
+            .watcher
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let state = self
+            .state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        (watcher, state)
+    }
+
+    fn watch_inner(
+        &mut self,
+        path: &Path,
+        recursive_mode: RecursiveMode,
+        token: Option<u64>,
+    ) -> Result<()> {
+        let path = registry::absolute(path);
+        let (mut watcher, mut state) = self.lock();
+        match token {
+            Some(token) => watcher.watch_with_token(&path, RecursiveMode::NonRecursive, token)?,
+            None => watcher.watch(&path, RecursiveMode::NonRecursive)?,
+        }
+
+        if state.roots.contains(&path) {
+            state.remove_root(&mut *watcher, &path);
+        }
+        if recursive_mode.is_recursive() {
+            state.watched.remove(&path);
+            state.roots.push(path.clone());
+            if path.is_dir() {
+                state.add_tree(&mut *watcher, &path, None);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<W: Watcher + Send + 'static> Watcher for AutoWatcher<W> {
+    fn new<F: EventHandler>(event_handler: F, config: Config) -> Result<Self> {
+        let event_handler = SharedHandler::new(event_handler);
+        let (tx, rx) = unbounded::<Vec<PathBuf>>();
+        let scheduling = Scheduling::new(&config);
+        let watcher = W::new(Forward::new(event_handler.clone(), tx), config)?;
+
+        let watcher = Arc::new(Mutex::new(watcher));
+        let state = Arc::new(Mutex::new(State::default()));
+        // the thread only holds on to the watcher while busy, so dropping this stops it
+        let weak: Weak<Mutex<W>> = Arc::downgrade(&watcher);
+        let state_c = state.clone();
+        let mut event_handler_c = event_handler;
+        std::thread::Builder::new()
+            .name("notify-rs auto watch loop".to_string())
+            .spawn(move || {
+                let _ = scheduling.apply();
+                while let Ok(paths) = rx.recv() {
+                    let watcher = match weak.upgrade() {
+                        Some(watcher) => watcher,
+                        None => break,
+                    };
+                    let mut watcher = watcher.lock().unwrap_or_else(|p| p.into_inner());
+                    let mut state = state_c.lock().unwrap_or_else(|p| p.into_inner());
+                    for path in paths {
+                        state.update(&mut *watcher, &path, &mut event_handler_c);
+                    }
+                }
+            })?;
+
+        Ok(Self { watcher, state })
+    }
+
+    fn watch(&mut self, path: &Path, recursive_mode: RecursiveMode) -> Result<()> {
+        self.watch_inner(path, recursive_mode, None)
+    }
+
+    fn watch_with_token(
+        &mut self,
+        path: &Path,
+        recursive_mode: RecursiveMode,
+        token: u64,
+    ) -> Result<()> {
+        // the token of the root is found for the directories below it as well
+        self.watch_inner(path, recursive_mode, Some(token))
+    }
+
+    fn unwatch(&mut self, path: &Path) -> Result<()> {
+        let path = registry::absolute(path);
+        let (mut watcher, mut state) = self.lock();
+        if state.roots.contains(&path) {
+            state.remove_root(&mut *watcher, &path);
+        }
+        state.watched.remove(&path);
+        watcher.unwatch(&path)
+    }
+
+    fn suppress(&self, path: &Path) -> Result<SuppressGuard> {
+        self.lock().0.suppress(path)
+    }
+
+    fn is_watched(&self, path: &Path) -> bool {
+        let (watcher, state) = self.lock();
+        let absolute = registry::absolute(path);
+        watcher.is_watched(path) || state.roots.iter().any(|root| absolute.starts_with(root))
+    }
+
+    fn configure(&mut self, option: Config) -> Result<bool> {
+        self.lock().0.configure(option)
+    }
+
+    fn kind() -> WatcherKind {
+        W::kind()
+    }
+}
+
+/// Passes events on to the user's handler, and the paths which may be directories to the
+/// auto watch thread.
+struct Forward {
+    event_handler: SharedHandler,
+    tx: Sender<Vec<PathBuf>>,
+}
+
+impl Forward {
+    fn new(event_handler: SharedHandler, tx: Sender<Vec<PathBuf>>) -> Self {
+        Self { event_handler, tx }
+    }
+}
+
+impl EventHandler for Forward {
+    fn handle_event(&mut self, event: Result<Event>) {
+        if let Ok(ref event) = event {
+            if matches!(
+                event.kind,
+                EventKind::Create(_)
+                    | EventKind::Remove(_)
+                    | EventKind::Modify(ModifyKind::Name(_))
+            ) {
+                let paths = event.paths.iter().map(|path| registry::absolute(path));
+                let _ = self.tx.send(paths.collect());
+            }
+        }
+        self.event_handler.handle_event(event);
+    }
+}
+
+#[test]
+#[cfg(all(any(target_os = "linux", target_os = "android"), feature = "linux_inotify"))]
+fn auto_watcher_watches_new_directories() {
+    use crate::INotifyWatcher;
+    use std::{fs, time::Duration};
+
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir(dir.path().join("existing")).unwrap();
+    let (tx, rx) = std::sync::mpsc::channel();
+    let mut watcher = AutoWatcher::<INotifyWatcher>::new(tx, Config::default()).unwrap();
+    watcher.watch(dir.path(), RecursiveMode::Recursive).unwrap();
+
+    let received = |rx: &std::sync::mpsc::Receiver<Result<Event>>, path: &Path| loop {
+        match rx.recv_timeout(Duration::from_secs(1)) {
+            Ok(Ok(event)) if event.paths.iter().any(|p| p == path) => break true,
+            Ok(_) => continue,
+            Err(_) => break false,
+        }
+    };
+
+    let file = dir.path().join("existing").join("file");
+    fs::write(&file, b"a").unwrap();
+    assert!(received(&rx, &file));
+
+    fs::create_dir(dir.path().join("new")).unwrap();
+    assert!(received(&rx, &dir.path().join("new")));
+    // give the auto watch thread a moment to add the watch
+    std::thread::sleep(Duration::from_millis(100));
+    let file = dir.path().join("new").join("file");
+    fs::write(&file, b"a").unwrap();
+    assert!(received(&rx, &file));
+    assert!(watcher.is_watched(&file));
+
+    watcher.unwatch(dir.path()).unwrap();
+    assert!(!watcher.is_watched(&file));
+}
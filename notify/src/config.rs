@@ -1,6 +1,19 @@
 //! Configuration types
 
-use std::time::Duration;
+#[cfg(feature = "debug-audit")]
+use crate::audit::AuditHook;
+use crate::units::{parse_duration, parse_size};
+use crate::filter::EditorNoise;
+use crate::stats::LossStats;
+use crate::{Error, Event, EventFilter, Result};
+#[cfg(feature = "serde")]
+use serde::Deserialize;
+use std::{
+    fmt,
+    hash::{Hash, Hasher},
+    sync::Arc,
+    time::Duration,
+};
 
 /// Indicates whether only the provided directory or its sub-directories as well should be watched
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
@@ -21,6 +34,204 @@ impl RecursiveMode {
     }
 }
 
+/// Whether a watched symbolic link or the file it points to is watched
+///
+/// See [crate::Watcher::watch_with_symlink_mode]
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+pub enum SymlinkMode {
+    /// Watch the file or directory the link points to, reported with the paths below the link
+    Target,
+
+    /// Watch the link itself, reporting its removal, renames and changes of its metadata, but
+    /// not the changes of its target
+    Link,
+}
+
+impl Default for SymlinkMode {
+    fn default() -> Self {
+        SymlinkMode::Target
+    }
+}
+
+/// How the [crate::PollWatcher] compares files that exceed the content size limit
+///
+/// See [Config::with_content_size_limit]
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum LargeFileComparison {
+    /// Don't read the file at all, only its metadata is compared
+    MetadataOnly,
+
+    /// Hash only the first and the last `n` bytes of the file, and its length
+    HeadAndTail(u64),
+}
+
+/// What a backend does when waiting for or reading events fails with a transient error
+///
+/// Transient errors are interrupted system calls (`EINTR`), which long running processes handling
+/// signals see regularly, and reads which would block (`EAGAIN`) after a spurious wakeup. Other
+/// errors are reported to the event handler and stop the backend's event loop.
+///
+/// See [Config::with_retry_policy]
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum RetryPolicy {
+    /// Retry the call until it succeeds, without reporting the failures
+    Forever,
+
+    /// Retry the call up to `n` times in a row, then report the error to the event handler and
+    /// stop the event loop
+    Bounded(u32),
+
+    /// Report every failure to the event handler as an error, then retry the call
+    Escalate,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy::Forever
+    }
+}
+
+/// What a watcher does when a new watch overlaps an existing one
+///
+/// Watches overlap when one of them is below a recursively watched directory of the other, like a
+/// recursive watch of `/a` and a watch of `/a/b`.
+///
+/// See [Config::with_watch_overlap]
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum WatchOverlap {
+    /// Add the watch as it is, most backends deliver the events below both watches twice
+    Allow,
+
+    /// Only install the outermost watches with the OS, so every event is delivered once
+    ///
+    /// The inner watches are still registered: they can be unwatched as usual, and are installed
+    /// again when the watch enclosing them is removed. Initial scans of inner watches are skipped
+    /// while they are enclosed, their entries were reported for the enclosing watch.
+    Merge,
+
+    /// Fail with [crate::ErrorKind::WatchOverlap], naming the existing watch
+    Reject,
+}
+
+impl Default for WatchOverlap {
+    fn default() -> Self {
+        WatchOverlap::Allow
+    }
+}
+
+/// What to do with new events while a bounded event queue is full
+///
+/// See [Config::with_event_queue], and `stream::EventStream::with_buffer` with the `stream`
+/// feature
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum OverflowStrategy {
+    /// Wait until the event handler took an event, holding up the backend
+    ///
+    /// No event is lost here, but the OS may drop events while the backend waits, which most
+    /// backends report as a rescan.
+    Block,
+
+    /// Drop the oldest queued event to make room for the new one
+    DropOldest,
+
+    /// Drop the new event
+    DropNewest,
+
+    /// Replace the queued event with the same paths as the new one, so only the latest event of a
+    /// path is kept, or drop the oldest queued event if there is none
+    CoalescePerPath,
+}
+
+impl Default for OverflowStrategy {
+    fn default() -> Self {
+        OverflowStrategy::Block
+    }
+}
+
+/// The scheduling priority of the threads a watcher starts, see [Config::with_thread_priority]
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum ThreadPriority {
+    /// Only run when nothing else wants to, a nice value of 19 on Linux
+    Lowest,
+
+    /// Below other threads, a nice value of 10 on Linux
+    Low,
+
+    /// The default priority of threads
+    Normal,
+
+    /// Above other threads, a nice value of -10 on Linux
+    High,
+
+    /// The highest priority short of real-time scheduling, a nice value of -20 on Linux
+    Highest,
+}
+
+/// The function rewriting every event before it is delivered, see [Config::with_event_map]
+///
+/// ```rust
+/// # use std::path::{Path, PathBuf};
+/// use notify::EventMap;
+///
+/// // report the paths of a container volume as the paths on the host
+/// let map = EventMap::new(|mut event| {
+///     for path in &mut event.paths {
+///         if let Ok(rest) = path.strip_prefix("/data") {
+///             *path = Path::new("/srv/volumes/app").join(rest);
+///         }
+///     }
+///     event
+/// });
+/// ```
+///
+/// Maps are equal if they are clones of each other.
+#[derive(Clone)]
+pub struct EventMap(Arc<dyn Fn(Event) -> Event + Send + Sync>);
+
+impl EventMap {
+    /// Rewrite events with `map`.
+    pub fn new<F: Fn(Event) -> Event + Send + Sync + 'static>(map: F) -> Self {
+        Self(Arc::new(map))
+    }
+
+    /// Returns `event` rewritten
+    pub fn apply(&self, event: Event) -> Event {
+        (self.0)(event)
+    }
+}
+
+impl PartialEq for EventMap {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for EventMap {}
+
+impl Hash for EventMap {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (Arc::as_ptr(&self.0) as *const () as usize).hash(state);
+    }
+}
+
+impl fmt::Debug for EventMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("EventMap")
+            .field(&(Arc::as_ptr(&self.0) as *const ()))
+            .finish()
+    }
+}
+
 /// Watcher Backend configuration
 /// 
 /// This contains multiple settings that may relate to only one specific backend,
@@ -34,14 +245,142 @@ impl RecursiveMode {
 ///     .with_compare_contents(true);
 /// ```
 /// 
-/// Some options can be changed during runtime, others have to be set when creating the watcher backend.
-#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+/// Some options can be changed during runtime by passing a new config to
+/// [Watcher::configure](crate::Watcher::configure), others have to be set when creating the watcher backend.
+///
+/// With the `serde` feature, the config can be deserialized from the application's own config
+/// files. Missing fields keep their default, durations and sizes are parsed as described in
+/// [crate::units]. The event filter, the event map, the loss statistics and the audit hook can't be
+/// deserialized.
+///
+/// ```rust
+/// # #[cfg(feature = "serde")]
+/// # fn main() -> serde_json::Result<()> {
+/// # use std::time::Duration;
+/// # use notify::Config;
+/// let config: Config = serde_json::from_str(r#"{
+///     "poll_interval": "2s",
+///     "content_size_limit": "64KiB",
+///     "retry_policy": { "bounded": 3 }
+/// }"#)?;
+/// assert_eq!(config.poll_interval(), Duration::from_secs(2));
+/// # Ok(())
+/// # }
+/// # #[cfg(not(feature = "serde"))]
+/// # fn main() {}
+/// ```
+///
+/// See [Config::from_env] to read it from environment variables instead.
+#[derive(Clone, PartialEq, Eq, Debug, Hash)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
 pub struct Config {
     /// See [BackendConfig::with_poll_interval]
+    #[cfg_attr(
+        feature = "serde",
+        serde(deserialize_with = "crate::units::deserialize_duration")
+    )]
     poll_interval: Duration,
 
     /// See [BackendConfig::with_compare_contents]
     compare_contents: bool,
+
+    /// See [Config::with_content_size_limit]
+    #[cfg_attr(
+        feature = "serde",
+        serde(deserialize_with = "crate::units::deserialize_option_size")
+    )]
+    content_size_limit: Option<u64>,
+
+    /// See [Config::with_large_file_comparison]
+    large_file_comparison: LargeFileComparison,
+
+    /// See [Config::with_initial_scan]
+    initial_scan: bool,
+
+    /// See [Config::with_anchored_watches]
+    anchored_watches: bool,
+
+    /// See [Config::with_shared_event_loop]
+    shared_event_loop: bool,
+
+    /// See [Config::with_watch_overlap]
+    watch_overlap: WatchOverlap,
+
+    /// See [Config::with_coalesce_window]
+    #[cfg_attr(
+        feature = "serde",
+        serde(deserialize_with = "crate::units::deserialize_option_duration")
+    )]
+    coalesce_window: Option<Duration>,
+
+    /// See [Config::with_hardlink_dedup]
+    #[cfg_attr(
+        feature = "serde",
+        serde(deserialize_with = "crate::units::deserialize_option_duration")
+    )]
+    hardlink_dedup: Option<Duration>,
+
+    /// See [Config::with_poll_fallback]
+    poll_fallback: bool,
+
+    /// See [Config::with_overflow_recovery]
+    overflow_recovery: bool,
+
+    /// See [Config::with_security_changes]
+    security_changes: bool,
+
+    /// See [Config::with_ignore_own_events]
+    ignore_own_events: bool,
+
+    /// See [Config::with_mount_marks]
+    mount_marks: bool,
+
+    /// See [Config::with_change_regions]
+    change_regions: Option<u64>,
+
+    /// See [Config::with_event_filter]
+    #[cfg_attr(feature = "serde", serde(skip))]
+    event_filter: Option<EventFilter>,
+
+    /// See [Config::with_editor_noise]
+    editor_noise: Vec<EditorNoise>,
+
+    /// See [Config::with_event_queue]
+    event_queue: Option<usize>,
+
+    /// See [Config::with_overflow_strategy]
+    overflow_strategy: OverflowStrategy,
+
+    /// See [Config::with_content_hash]
+    #[cfg(feature = "blake3")]
+    #[cfg_attr(
+        feature = "serde",
+        serde(deserialize_with = "crate::units::deserialize_option_size")
+    )]
+    content_hash: Option<u64>,
+
+    /// See [Config::with_retry_policy]
+    retry_policy: RetryPolicy,
+
+    /// See [Config::with_event_map]
+    #[cfg_attr(feature = "serde", serde(skip))]
+    event_map: Option<EventMap>,
+
+    /// See [Config::with_thread_priority]
+    thread_priority: Option<ThreadPriority>,
+
+    /// See [Config::with_thread_affinity]
+    thread_affinity: Option<Vec<usize>>,
+
+    /// See [Config::with_loss_stats]
+    #[cfg_attr(feature = "serde", serde(skip))]
+    loss_stats: Option<LossStats>,
+
+    /// See [Config::with_audit_hook]
+    #[cfg(feature = "debug-audit")]
+    #[cfg_attr(feature = "serde", serde(skip))]
+    audit_hook: Option<AuditHook>,
 }
 
 impl Config {
@@ -50,7 +389,9 @@ impl Config {
     /// Interval between each rescan attempt. This can be extremely expensive for large
     /// file trees so it is recommended to measure and tune accordingly.
     /// 
-    /// The default poll frequency is 30 seconds.
+    /// This can be changed during runtime with [crate::Watcher::configure], taking effect after
+    /// the current interval. The default poll frequency is 30 seconds. Single watches can be
+    /// scanned with an interval of their own, see [crate::PollWatcher::set_poll_interval].
     pub fn with_poll_interval(mut self, dur: Duration) -> Self {
         self.poll_interval = dur;
         self
@@ -70,7 +411,7 @@ impl Config {
     /// By enabling this feature, performance will be significantly impacted as all files will
     /// need to be read and hashed at each `poll_interval`.
     /// 
-    /// This can't be changed during runtime. Off by default.
+    /// This can be changed during runtime with [crate::Watcher::configure]. Off by default.
     pub fn with_compare_contents(mut self, compare_contents: bool) -> Self {
         self.compare_contents = compare_contents;
         self
@@ -80,13 +421,640 @@ impl Config {
     pub fn compare_contents(&self) -> bool {
         self.compare_contents
     }
+
+    /// For [crate::PollWatcher]
+    ///
+    /// Maximum file size in bytes for which contents are fully hashed when
+    /// [Config::with_compare_contents] is enabled. Larger files are compared according to
+    /// [Config::with_large_file_comparison] instead, so that media directories with
+    /// multi-gigabyte files don't have to be read completely on every `poll_interval`.
+    ///
+    /// This can be changed during runtime with [crate::Watcher::configure]. No limit by default.
+    pub fn with_content_size_limit(mut self, limit: Option<u64>) -> Self {
+        self.content_size_limit = limit;
+        self
+    }
+
+    /// Returns current setting
+    pub fn content_size_limit(&self) -> Option<u64> {
+        self.content_size_limit
+    }
+
+    /// For [crate::PollWatcher]
+    ///
+    /// How files above the [Config::with_content_size_limit] are compared.
+    ///
+    /// This can be changed during runtime with [crate::Watcher::configure]. Defaults to
+    /// [LargeFileComparison::MetadataOnly].
+    pub fn with_large_file_comparison(mut self, comparison: LargeFileComparison) -> Self {
+        self.large_file_comparison = comparison;
+        self
+    }
+
+    /// Returns current setting
+    pub fn large_file_comparison(&self) -> LargeFileComparison {
+        self.large_file_comparison
+    }
+
+    /// For all watchers
+    ///
+    /// Emit a `Create` event flagged as [crate::event::Flag::InitialScan] for every entry that
+    /// already exists when a path is watched, including the path itself. These events are
+    /// delivered before `watch()` returns, after the watch has been established, so no entry
+    /// is missed but some may be reported twice.
+    ///
+    /// This can be changed during runtime with [crate::Watcher::configure]. Off by default.
+    pub fn with_initial_scan(mut self, initial_scan: bool) -> Self {
+        self.initial_scan = initial_scan;
+        self
+    }
+
+    /// Returns current setting
+    pub fn initial_scan(&self) -> bool {
+        self.initial_scan
+    }
+
+    /// For the inotify backend on Linux
+    ///
+    /// Anchor every watched path to an open file descriptor, so that renaming the watched path
+    /// or one of its ancestors doesn't leave the watcher reporting stale paths. When a move is
+    /// detected, a `Modify(Name(Both))` event from the old to the new location is emitted and all
+    /// further events use the new location. Moving an ancestor doesn't cause any events by
+    /// itself, so such a move is noticed with the next events read from the watch.
+    ///
+    /// This can't be changed during runtime. Off by default.
+    pub fn with_anchored_watches(mut self, anchored_watches: bool) -> Self {
+        self.anchored_watches = anchored_watches;
+        self
+    }
+
+    /// Returns current setting
+    pub fn anchored_watches(&self) -> bool {
+        self.anchored_watches
+    }
+
+    /// For the inotify backend on Linux
+    ///
+    /// Run the event loop of the watcher on a thread shared by all watchers with this setting,
+    /// instead of a thread of its own. The thread waits for the inotify instances of all of them
+    /// with one epoll instance and handles the events of each watcher in turn, so applications
+    /// with many watchers save threads and wakeups. A slow event handler delays the events of
+    /// the other watchers sharing the thread, and [Config::with_thread_priority] and
+    /// [Config::with_thread_affinity] don't apply to it. An event handler on the thread can't
+    /// change the watches of the watchers sharing it, which fails instead of waiting for the
+    /// thread itself. The thread is started again if it failed, and stops with the last watcher.
+    ///
+    /// This can't be changed during runtime. Off by default.
+    pub fn with_shared_event_loop(mut self, shared_event_loop: bool) -> Self {
+        self.shared_event_loop = shared_event_loop;
+        self
+    }
+
+    /// Returns current setting
+    pub fn shared_event_loop(&self) -> bool {
+        self.shared_event_loop
+    }
+
+    /// For all watchers
+    ///
+    /// How a watch overlapping an existing one is added, see [WatchOverlap]. Watching the same path
+    /// again replaces its watch as before, and never counts as an overlap.
+    ///
+    /// This can't be changed during runtime. Overlapping watches are allowed by default.
+    pub fn with_watch_overlap(mut self, overlap: WatchOverlap) -> Self {
+        self.watch_overlap = overlap;
+        self
+    }
+
+    /// Returns current setting
+    pub fn watch_overlap(&self) -> WatchOverlap {
+        self.watch_overlap
+    }
+
+    /// For all watchers
+    ///
+    /// Drop events which repeat the previously delivered event, with the same kind and paths,
+    /// within this window. At most one such duplicate is delivered per window, which tames the
+    /// bursts of identical `Modify` events some backends produce while a file is written. This
+    /// is not a replacement for debouncing, a few milliseconds are usually enough.
+    ///
+    /// This can be changed during runtime with [crate::Watcher::configure]. Off by default.
+    pub fn with_coalesce_window(mut self, window: Option<Duration>) -> Self {
+        self.coalesce_window = window;
+        self
+    }
+
+    /// Returns current setting
+    pub fn coalesce_window(&self) -> Option<Duration> {
+        self.coalesce_window
+    }
+
+    /// For all watchers, on Unix
+    ///
+    /// Merge events of the same kind for a file with several hardlinks within this window,
+    /// whichever of its paths they were reported for, so a file linked into several watched
+    /// directories is only reported once. Events through the path last reported are always
+    /// delivered, so repeated writes aren't lost. Files are recognized by their device and inode,
+    /// which are looked up once for each path until it is created, renamed or removed again.
+    /// Once several paths were seen for a file, the events delivered list them in their
+    /// [hardlinks](crate::event::EventAttributes::hardlinks) attribute, including those of the
+    /// events merged into an earlier one. A file's paths are forgotten once no event was reported
+    /// for it for a window. Removed files can't be recognized and are always reported.
+    ///
+    /// This can be changed during runtime with [crate::Watcher::configure]. Off by default.
+    pub fn with_hardlink_dedup(mut self, window: Option<Duration>) -> Self {
+        self.hardlink_dedup = window;
+        self
+    }
+
+    /// Returns current setting
+    pub fn hardlink_dedup(&self) -> Option<Duration> {
+        self.hardlink_dedup
+    }
+
+    /// For the inotify backend on Linux
+    ///
+    /// Watch paths on filesystems which inotify doesn't report changes for, like `/proc`, `/sys`
+    /// and FUSE mounts, with a [crate::PollWatcher] instead, using the poll options of this
+    /// config. The filesystem is detected when the path is watched, and an `Other` event with
+    /// the info `"poll fallback"` and the path is emitted for every such watch.
+    ///
+    /// This can't be changed during runtime. Off by default.
+    pub fn with_poll_fallback(mut self, poll_fallback: bool) -> Self {
+        self.poll_fallback = poll_fallback;
+        self
+    }
+
+    /// Returns current setting
+    pub fn poll_fallback(&self) -> bool {
+        self.poll_fallback
+    }
+
+    /// For the inotify backend on Linux
+    ///
+    /// Keep a snapshot of every watched path, and rescan the watched paths when the kernel's
+    /// event queue overflows. The changes found are then emitted as regular events after the
+    /// `Rescan` event, and directories created meanwhile are watched as well. Changes of paths
+    /// which events were read for since the previous snapshot aren't reported again. The
+    /// snapshots use the content comparison options of this config.
+    ///
+    /// This costs memory for every watched file and every path events were read for, and the
+    /// rescan may take a while on large trees.
+    ///
+    /// This can't be changed during runtime. Off by default.
+    pub fn with_overflow_recovery(mut self, overflow_recovery: bool) -> Self {
+        self.overflow_recovery = overflow_recovery;
+        self
+    }
+
+    /// Returns current setting
+    pub fn overflow_recovery(&self) -> bool {
+        self.overflow_recovery
+    }
+
+    /// For the Windows backend
+    ///
+    /// Report changes of the security descriptors of files, their owner and ACLs, as
+    /// `Modify(Metadata(Permissions))` events. Windows doesn't tell which part of a descriptor
+    /// changed, so ownership changes are reported the same way. This reads the changes of every
+    /// watched directory through a second handle. Without it, security changes are reported as
+    /// `Modify(Any)`, like the other modifications.
+    ///
+    /// This can't be changed during runtime. Off by default.
+    pub fn with_security_changes(mut self, security_changes: bool) -> Self {
+        self.security_changes = security_changes;
+        self
+    }
+
+    /// Returns current setting
+    pub fn security_changes(&self) -> bool {
+        self.security_changes
+    }
+
+    /// For the FSEvents and Endpoint Security backends on macOS, the ETW backend on Windows and
+    /// the audit and fanotify backends on Linux
+    ///
+    /// Drop the events of changes made by the watcher's own process, from any of its threads.
+    /// This tells the application's own writes apart reliably, where
+    /// [`Watcher::suppress`](crate::Watcher::suppress) has to guess by path and time. Other
+    /// backends aren't told which process made a change, and deliver these events.
+    ///
+    /// This can't be changed during runtime. Off by default.
+    pub fn with_ignore_own_events(mut self, ignore_own_events: bool) -> Self {
+        self.ignore_own_events = ignore_own_events;
+        self
+    }
+
+    /// Returns current setting
+    pub fn ignore_own_events(&self) -> bool {
+        self.ignore_own_events
+    }
+
+    /// For the fanotify backend
+    ///
+    /// Mark the mount of a recursively watched directory instead of its whole filesystem, to
+    /// leave out the changes made through other mounts of the filesystem, e.g. of a container.
+    /// The kernel only reports the writes to files for mounts, not creations, removals and
+    /// renames.
+    ///
+    /// This can't be changed during runtime. Off by default.
+    pub fn with_mount_marks(mut self, mount_marks: bool) -> Self {
+        self.mount_marks = mount_marks;
+        self
+    }
+
+    /// Returns current setting
+    pub fn mount_marks(&self) -> bool {
+        self.mount_marks
+    }
+
+    /// For [crate::PollWatcher]
+    ///
+    /// Hash the first and the last `n` bytes of every file separately, to report which end of a
+    /// modified file changed as the [region](crate::event::MetadataDelta::region) of its
+    /// metadata delta. Log followers can tell appends from rewrites this way.
+    ///
+    /// This reads up to `2 * n` bytes of every file on every scan.
+    ///
+    /// This can be changed during runtime with [crate::Watcher::configure]. Off by default.
+    pub fn with_change_regions(mut self, n: Option<u64>) -> Self {
+        self.change_regions = n;
+        self
+    }
+
+    /// Returns current setting
+    pub fn change_regions(&self) -> Option<u64> {
+        self.change_regions
+    }
+
+    /// For all watchers
+    ///
+    /// Only deliver events matching this [EventFilter], errors are always delivered. The events
+    /// are still produced by the backend, this saves the event handler from seeing them.
+    ///
+    /// This can be changed during runtime with [crate::Watcher::configure]. Off by default.
+    pub fn with_event_filter(mut self, filter: Option<EventFilter>) -> Self {
+        self.event_filter = filter;
+        self
+    }
+
+    /// Returns current setting
+    pub fn event_filter(&self) -> Option<&EventFilter> {
+        self.event_filter.as_ref()
+    }
+
+    /// For all watchers
+    ///
+    /// Drop the events of the temporary, swap and backup files of these editors, see
+    /// [EditorNoise]. Events with several paths, like a temporary file renamed over the edited
+    /// one, are still delivered if one of their paths isn't such a file.
+    ///
+    /// This can be changed during runtime with [crate::Watcher::configure]. Off by default.
+    pub fn with_editor_noise(mut self, editors: Vec<EditorNoise>) -> Self {
+        self.editor_noise = editors;
+        self
+    }
+
+    /// Returns current setting
+    pub fn editor_noise(&self) -> &[EditorNoise] {
+        &self.editor_noise
+    }
+
+    /// For all watchers
+    ///
+    /// Pass events to the event handler through a queue of up to this many events, drained on a
+    /// thread of its own, so a slow event handler doesn't hold up the backend. What happens to new
+    /// events while the queue is full is set with [Config::with_overflow_strategy]. If events were
+    /// dropped, the handler receives an event with [Flag::Rescan](crate::event::Flag::Rescan)
+    /// after the queued ones.
+    ///
+    /// Without a queue the backend calls the event handler itself, waiting for each event. This
+    /// also applies to the debouncers, which take their notify config. This can't be changed
+    /// during runtime. Off by default.
+    pub fn with_event_queue(mut self, capacity: Option<usize>) -> Self {
+        self.event_queue = capacity;
+        self
+    }
+
+    /// Returns current setting
+    pub fn event_queue(&self) -> Option<usize> {
+        self.event_queue
+    }
+
+    /// For all watchers with an event queue
+    ///
+    /// What happens to new events while the queue set with [Config::with_event_queue] is full, see
+    /// [OverflowStrategy]. The default is [OverflowStrategy::Block].
+    ///
+    /// This can't be changed during runtime.
+    pub fn with_overflow_strategy(mut self, strategy: OverflowStrategy) -> Self {
+        self.overflow_strategy = strategy;
+        self
+    }
+
+    /// Returns current setting
+    pub fn overflow_strategy(&self) -> OverflowStrategy {
+        self.overflow_strategy
+    }
+
+    /// For all watchers, with the `blake3` feature
+    ///
+    /// Attach the blake3 hash of created and written files up to the given size in bytes to
+    /// their events, read with [Event::content_hash](crate::Event::content_hash). Files are
+    /// hashed on a separate thread, which also calls the event handler, so events still arrive
+    /// in order.
+    ///
+    /// This can't be changed during runtime. Off by default.
+    #[cfg(feature = "blake3")]
+    pub fn with_content_hash(mut self, size_limit: Option<u64>) -> Self {
+        self.content_hash = size_limit;
+        self
+    }
+
+    /// Returns current setting
+    #[cfg(feature = "blake3")]
+    pub fn content_hash(&self) -> Option<u64> {
+        self.content_hash
+    }
+
+    /// For the inotify and kqueue backends
+    ///
+    /// How transient errors of the event loop are handled, see [RetryPolicy]. The default is
+    /// [RetryPolicy::Forever].
+    ///
+    /// This can't be changed during runtime.
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = policy;
+        self
+    }
+
+    /// Returns current setting
+    pub fn retry_policy(&self) -> RetryPolicy {
+        self.retry_policy
+    }
+
+    /// For all watchers
+    ///
+    /// Rewrite every event with `map` before passing it to the event handler, e.g. to translate
+    /// the paths of a container into paths on the host or to attach attributes computed by the
+    /// application. Events are matched against the watches and the event filter before, with the
+    /// paths reported by the backend.
+    ///
+    /// This can be changed during runtime. Off by default.
+    pub fn with_event_map(mut self, map: Option<EventMap>) -> Self {
+        self.event_map = map;
+        self
+    }
+
+    /// Returns current setting
+    pub fn event_map(&self) -> Option<&EventMap> {
+        self.event_map.as_ref()
+    }
+
+    /// For all watchers
+    ///
+    /// Run the threads the watcher starts, like the loop reading the events of the backend and
+    /// the thread of the event queue, with `priority`. Monitoring daemons lower it to stay out of
+    /// the way of the work they watch.
+    ///
+    /// Linux and Android set the nice value of the threads, other Unix systems the priority
+    /// within their scheduling policy, and Windows the thread priority. Raising it usually needs
+    /// privileges, a thread keeps its priority if it can't be set.
+    ///
+    /// This can't be changed during runtime. None by default, the threads keep the priority of
+    /// the thread creating the watcher.
+    pub fn with_thread_priority(mut self, priority: Option<ThreadPriority>) -> Self {
+        self.thread_priority = priority;
+        self
+    }
+
+    /// Returns current setting
+    pub fn thread_priority(&self) -> Option<ThreadPriority> {
+        self.thread_priority
+    }
+
+    /// For all watchers, on Linux, Android and Windows
+    ///
+    /// Run the threads the watcher starts only on the CPUs with the indices in `cpus`, keeping
+    /// them off the cores of latency-sensitive work. On Windows these are the CPUs of the
+    /// processor group of the process. Threads run on any CPU if the affinity can't be set.
+    ///
+    /// This can't be changed during runtime. None by default.
+    pub fn with_thread_affinity(mut self, cpus: Option<Vec<usize>>) -> Self {
+        self.thread_affinity = cpus;
+        self
+    }
+
+    /// Returns current setting
+    pub fn thread_affinity(&self) -> Option<&[usize]> {
+        self.thread_affinity.as_deref()
+    }
+
+    /// Apply the [thread priority](Config::with_thread_priority) and the
+    /// [thread affinity](Config::with_thread_affinity) to the calling thread, like the watchers
+    /// do for the threads they start.
+    ///
+    /// Meant for threads processing events on behalf of a watcher, like those of a debouncer.
+    /// Both settings are tried, the first error is returned.
+    pub fn apply_thread_scheduling(&self) -> std::io::Result<()> {
+        crate::scheduling::Scheduling::new(self).apply()
+    }
+
+    /// For all watchers
+    ///
+    /// Count the rescan events delivered, and the events dropped or coalesced by the event queue,
+    /// in `stats`. The application keeps a clone of it to read the totals, see [LossStats].
+    ///
+    /// This can't be changed during runtime. Off by default.
+    pub fn with_loss_stats(mut self, stats: Option<LossStats>) -> Self {
+        self.loss_stats = stats;
+        self
+    }
+
+    /// Returns current setting
+    pub fn loss_stats(&self) -> Option<&LossStats> {
+        self.loss_stats.as_ref()
+    }
+
+    /// For all watchers, with the `debug-audit` feature
+    ///
+    /// Call this hook with the leaked resources and broken invariants found by the
+    /// [audit](crate::audit), instead of panicking.
+    ///
+    /// This can't be changed during runtime.
+    #[cfg(feature = "debug-audit")]
+    pub fn with_audit_hook(mut self, hook: Option<AuditHook>) -> Self {
+        self.audit_hook = hook;
+        self
+    }
+
+    /// Returns current setting
+    #[cfg(feature = "debug-audit")]
+    pub fn audit_hook(&self) -> Option<AuditHook> {
+        self.audit_hook
+    }
+
+    /// Reads the config from `NOTIFY_*` environment variables, keeping the default of every option
+    /// whose variable isn't set.
+    ///
+    /// The variables are named after the options, like `NOTIFY_POLL_INTERVAL=2s`,
+    /// `NOTIFY_COMPARE_CONTENTS=true` or `NOTIFY_CONTENT_SIZE_LIMIT=64KiB`. Durations and sizes are
+    /// parsed as described in [crate::units], and optional settings are disabled with `none`. The
+    /// enums are written as `metadata_only`, `head_and_tail:<size>`, `allow`, `merge`, `reject`,
+    /// `forever`, `bounded:<n>`, `escalate`, `block`, `drop_oldest`, `drop_newest` and
+    /// `coalesce_per_path`, `lowest` to `highest` for the thread priority, the editor noise as a
+    /// list like `vim,emacs`, or `all`, and the thread affinity as a list like `0,1`. The event
+    /// filter, the event map, the loss statistics and the audit hook can't be set this way.
+    ///
+    /// Returns an error naming the variable if a value can't be parsed.
+    pub fn from_env() -> Result<Self> {
+        let mut config = Self::default();
+        set_from_env(&mut config.poll_interval, "POLL_INTERVAL", parse_duration)?;
+        set_from_env(&mut config.compare_contents, "COMPARE_CONTENTS", parse_bool)?;
+        set_from_env(&mut config.content_size_limit, "CONTENT_SIZE_LIMIT", |s| {
+            parse_option(s, parse_size)
+        })?;
+        set_from_env(
+            &mut config.large_file_comparison,
+            "LARGE_FILE_COMPARISON",
+            |s| match s.split_once(':') {
+                None if s == "metadata_only" => Some(LargeFileComparison::MetadataOnly),
+                Some(("head_and_tail", n)) => parse_size(n).map(LargeFileComparison::HeadAndTail),
+                _ => None,
+            },
+        )?;
+        set_from_env(&mut config.initial_scan, "INITIAL_SCAN", parse_bool)?;
+        set_from_env(&mut config.anchored_watches, "ANCHORED_WATCHES", parse_bool)?;
+        set_from_env(&mut config.shared_event_loop, "SHARED_EVENT_LOOP", parse_bool)?;
+        set_from_env(&mut config.watch_overlap, "WATCH_OVERLAP", |s| match s {
+            "allow" => Some(WatchOverlap::Allow),
+            "merge" => Some(WatchOverlap::Merge),
+            "reject" => Some(WatchOverlap::Reject),
+            _ => None,
+        })?;
+        set_from_env(&mut config.coalesce_window, "COALESCE_WINDOW", |s| {
+            parse_option(s, parse_duration)
+        })?;
+        set_from_env(&mut config.hardlink_dedup, "HARDLINK_DEDUP", |s| {
+            parse_option(s, parse_duration)
+        })?;
+        set_from_env(&mut config.poll_fallback, "POLL_FALLBACK", parse_bool)?;
+        set_from_env(
+            &mut config.overflow_recovery,
+            "OVERFLOW_RECOVERY",
+            parse_bool,
+        )?;
+        set_from_env(&mut config.security_changes, "SECURITY_CHANGES", parse_bool)?;
+        set_from_env(
+            &mut config.ignore_own_events,
+            "IGNORE_OWN_EVENTS",
+            parse_bool,
+        )?;
+        set_from_env(&mut config.mount_marks, "MOUNT_MARKS", parse_bool)?;
+        set_from_env(&mut config.change_regions, "CHANGE_REGIONS", |s| {
+            parse_option(s, |n| n.parse().ok())
+        })?;
+        set_from_env(&mut config.editor_noise, "EDITOR_NOISE", |s| match s {
+            "none" => Some(Vec::new()),
+            "all" => Some(EditorNoise::ALL.to_vec()),
+            _ => s.split(',').map(|editor| editor.trim().parse().ok()).collect(),
+        })?;
+        set_from_env(&mut config.event_queue, "EVENT_QUEUE", |s| {
+            parse_option(s, |n| n.parse().ok())
+        })?;
+        set_from_env(&mut config.overflow_strategy, "OVERFLOW_STRATEGY", |s| match s {
+            "block" => Some(OverflowStrategy::Block),
+            "drop_oldest" => Some(OverflowStrategy::DropOldest),
+            "drop_newest" => Some(OverflowStrategy::DropNewest),
+            "coalesce_per_path" => Some(OverflowStrategy::CoalescePerPath),
+            _ => None,
+        })?;
+        #[cfg(feature = "blake3")]
+        set_from_env(&mut config.content_hash, "CONTENT_HASH", |s| {
+            parse_option(s, parse_size)
+        })?;
+        set_from_env(&mut config.retry_policy, "RETRY_POLICY", |s| {
+            match s.split_once(':') {
+                None if s == "forever" => Some(RetryPolicy::Forever),
+                None if s == "escalate" => Some(RetryPolicy::Escalate),
+                Some(("bounded", n)) => n.parse().ok().map(RetryPolicy::Bounded),
+                _ => None,
+            }
+        })?;
+        set_from_env(&mut config.thread_priority, "THREAD_PRIORITY", |s| {
+            parse_option(s, |s| match s {
+                "lowest" => Some(ThreadPriority::Lowest),
+                "low" => Some(ThreadPriority::Low),
+                "normal" => Some(ThreadPriority::Normal),
+                "high" => Some(ThreadPriority::High),
+                "highest" => Some(ThreadPriority::Highest),
+                _ => None,
+            })
+        })?;
+        set_from_env(&mut config.thread_affinity, "THREAD_AFFINITY", |s| {
+            parse_option(s, |s| s.split(',').map(|cpu| cpu.trim().parse().ok()).collect())
+        })?;
+        Ok(config)
+    }
+}
+
+/// Sets `option` from the `NOTIFY_<name>` environment variable if it is set
+fn set_from_env<T>(option: &mut T, name: &str, parse: impl Fn(&str) -> Option<T>) -> Result<()> {
+    let name = format!("NOTIFY_{}", name);
+    if let Ok(value) = std::env::var(&name) {
+        *option = parse(value.trim())
+            .ok_or_else(|| Error::generic(&format!("invalid value {:?} for {}", value, name)))?;
+    }
+    Ok(())
+}
+
+fn parse_bool(s: &str) -> Option<bool> {
+    match s.to_ascii_lowercase().as_str() {
+        "true" | "yes" | "on" | "1" => Some(true),
+        "false" | "no" | "off" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+fn parse_option<T>(s: &str, parse: impl Fn(&str) -> Option<T>) -> Option<Option<T>> {
+    match s {
+        "none" => Some(None),
+        _ => parse(s).map(Some),
+    }
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self { 
             poll_interval: Duration::from_secs(30),
-            compare_contents: false
+            compare_contents: false,
+            content_size_limit: None,
+            large_file_comparison: LargeFileComparison::MetadataOnly,
+            initial_scan: false,
+            anchored_watches: false,
+            shared_event_loop: false,
+            watch_overlap: WatchOverlap::default(),
+            coalesce_window: None,
+            hardlink_dedup: None,
+            poll_fallback: false,
+            overflow_recovery: false,
+            security_changes: false,
+            ignore_own_events: false,
+            mount_marks: false,
+            change_regions: None,
+            event_filter: None,
+            editor_noise: Vec::new(),
+            event_queue: None,
+            overflow_strategy: OverflowStrategy::default(),
+            #[cfg(feature = "blake3")]
+            content_hash: None,
+            retry_policy: RetryPolicy::default(),
+            thread_priority: None,
+            thread_affinity: None,
+            event_map: None,
+            loss_stats: None,
+            #[cfg(feature = "debug-audit")]
+            audit_hook: None,
         }
     }
 }
\ No newline at end of file
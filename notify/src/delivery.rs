@@ -0,0 +1,954 @@
+//! Processing shared by all watchers between a backend and the user's event handler
+
+use crate::filter::EditorNoise;
+use crate::scheduling::Scheduling;
+use crate::stats::LossStats;
+use crate::suppress::Suppressions;
+use crate::{
+    event::{EventKind, Flag, ModifyKind},
+    registry, Config, Event, EventFilter, EventHandler, EventMap, OverflowStrategy, Result,
+};
+use instant::Instant;
+use std::{
+    collections::{HashMap, VecDeque},
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Condvar, Mutex, RwLock},
+    time::Duration,
+};
+
+/// Wraps the event handler given to a watcher, applying the steps enabled in its [Config].
+pub(crate) struct Delivery {
+    event_handler: Box<dyn EventHandler>,
+    options: DeliveryOptions,
+    tokens: WatchTokens,
+    suppressions: Suppressions,
+    ignore_own_events: bool,
+    losses: Option<LossStats>,
+}
+
+impl Delivery {
+    pub(crate) fn new<F: EventHandler>(event_handler: F, config: &Config) -> Self {
+        #[cfg(feature = "blake3")]
+        let event_handler: Box<dyn EventHandler> =
+            match (config.content_hash(), config.event_queue()) {
+                // the thread of the event queue hashes the files then
+                (Some(size_limit), Some(_)) => Box::new(ContentHasher::Inline(
+                    SharedHandler::new(event_handler),
+                    size_limit,
+                )),
+                (Some(size_limit), None) => Box::new(ContentHasher::spawn(
+                    event_handler,
+                    size_limit,
+                    Scheduling::new(config),
+                )),
+                (None, _) => Box::new(event_handler),
+            };
+        #[cfg(not(feature = "blake3"))]
+        let event_handler: Box<dyn EventHandler> = Box::new(event_handler);
+        let event_handler: Box<dyn EventHandler> = match config.event_queue() {
+            Some(capacity) => Box::new(EventQueue::spawn(
+                event_handler,
+                capacity,
+                config.overflow_strategy(),
+                config.loss_stats().cloned(),
+                Scheduling::new(config),
+            )),
+            None => event_handler,
+        };
+        Self {
+            event_handler,
+            options: DeliveryOptions(Arc::new(Mutex::new(Options {
+                filter: config.event_filter().cloned(),
+                editor_noise: config.editor_noise().to_vec(),
+                coalesce: config.coalesce_window().map(Coalesce::new),
+                hardlinks: config.hardlink_dedup().map(HardlinkDedup::new),
+                map: config.event_map().cloned(),
+            }))),
+            tokens: WatchTokens::default(),
+            suppressions: Suppressions::default(),
+            ignore_own_events: config.ignore_own_events(),
+            losses: config.loss_stats().cloned(),
+        }
+    }
+
+    /// The tokens attached to the events delivered, for the watcher to register them.
+    pub(crate) fn tokens(&self) -> WatchTokens {
+        self.tokens.clone()
+    }
+
+    /// The steps applied to the events delivered, for the watcher to reconfigure them.
+    pub(crate) fn options(&self) -> DeliveryOptions {
+        self.options.clone()
+    }
+
+    /// The paths whose events are suppressed, for the watcher to add guards.
+    pub(crate) fn suppressions(&self) -> Suppressions {
+        self.suppressions.clone()
+    }
+}
+
+impl EventHandler for Delivery {
+    fn handle_event(&mut self, mut event: Result<Event>) {
+        if let Ok(ref mut event) = event {
+            if self.ignore_own_events && event.attrs.process_id() == Some(std::process::id()) {
+                return;
+            }
+            if let Some(token) = event.paths.first().and_then(|path| self.tokens.find(path)) {
+                event.attrs.set_watch_token(token);
+            }
+            if !self.suppressions.should_deliver(event) {
+                return;
+            }
+        }
+
+        if !self.options.should_deliver(&mut event) {
+            return;
+        }
+
+        if let (Some(losses), Ok(event)) = (&self.losses, &event) {
+            if event.need_rescan() {
+                losses.record_rescan(event.watch_token(), event.dropped().unwrap_or(0));
+            }
+        }
+        let event = event.map(|event| self.options.map(event));
+
+        self.event_handler.handle_event(event);
+    }
+}
+
+/// The steps of a [Delivery] which can be changed while the watcher runs, shared between the
+/// watcher and its [Delivery].
+#[derive(Clone, Debug, Default)]
+pub(crate) struct DeliveryOptions(Arc<Mutex<Options>>);
+
+#[derive(Debug, Default)]
+struct Options {
+    filter: Option<EventFilter>,
+    editor_noise: Vec<EditorNoise>,
+    coalesce: Option<Coalesce>,
+    hardlinks: Option<HardlinkDedup>,
+    map: Option<EventMap>,
+}
+
+impl DeliveryOptions {
+    /// Apply the event filter, the editor noise, the coalesce window, the hardlink dedup window and
+    /// the event map of `config` to the events delivered from now on.
+    pub(crate) fn configure(&self, config: &Config) {
+        if let Ok(mut options) = self.0.lock() {
+            options.filter = config.event_filter().cloned();
+            options.editor_noise = config.editor_noise().to_vec();
+            let window = config.coalesce_window();
+            if options.coalesce.as_ref().map(|coalesce| coalesce.window) != window {
+                options.coalesce = window.map(Coalesce::new);
+            }
+            let window = config.hardlink_dedup();
+            if options.hardlinks.as_ref().map(|dedup| dedup.window) != window {
+                options.hardlinks = window.map(HardlinkDedup::new);
+            }
+            options.map = config.event_map().cloned();
+        }
+    }
+
+    /// Rewrite `event` with the event map, which is called without holding the lock.
+    fn map(&self, event: Event) -> Event {
+        let map = self.0.lock().ok().and_then(|options| options.map.clone());
+        match map {
+            Some(map) => map.apply(event),
+            None => event,
+        }
+    }
+
+    fn should_deliver(&self, event: &mut Result<Event>) -> bool {
+        let mut options = match self.0.lock() {
+            Ok(options) => options,
+            Err(_) => return true,
+        };
+
+        if let (Some(filter), Ok(event)) = (&options.filter, &*event) {
+            if !filter.matches(event) {
+                return false;
+            }
+        }
+
+        if let Ok(event) = &*event {
+            if is_editor_noise(&options.editor_noise, event) {
+                return false;
+            }
+        }
+
+        if let Some(ref mut coalesce) = options.coalesce {
+            if !coalesce.should_deliver(event) {
+                return false;
+            }
+        }
+
+        match (&mut options.hardlinks, event) {
+            (Some(dedup), Ok(event)) => dedup.should_deliver(event),
+            _ => true,
+        }
+    }
+}
+
+/// Whether all paths of `event` are files of the `editors`.
+fn is_editor_noise(editors: &[EditorNoise], event: &Event) -> bool {
+    !editors.is_empty()
+        && !event.paths.is_empty()
+        && event
+            .paths
+            .iter()
+            .all(|path| editors.iter().any(|editor| editor.matches(path)))
+}
+
+/// The tokens of watches added with [Watcher::watch_with_token](crate::Watcher::watch_with_token),
+/// shared between a watcher and its [Delivery].
+#[derive(Clone, Debug, Default)]
+pub(crate) struct WatchTokens(Arc<RwLock<HashMap<PathBuf, u64>>>);
+
+impl WatchTokens {
+    /// Attach `token` to the events below `path`, replacing an existing token of it.
+    pub(crate) fn insert(&self, path: &Path, token: u64) {
+        if let Ok(mut tokens) = self.0.write() {
+            // some backends report the canonical paths of their watches.
+            if let Ok(canonical) = fs::canonicalize(path) {
+                tokens.insert(canonical, token);
+            }
+            tokens.insert(registry::absolute(path), token);
+        }
+    }
+
+    pub(crate) fn remove(&self, path: &Path) {
+        if let Ok(mut tokens) = self.0.write() {
+            if let Ok(canonical) = fs::canonicalize(path) {
+                tokens.remove(&canonical);
+            }
+            tokens.remove(&registry::absolute(path));
+        }
+    }
+
+    /// The token of the innermost watch with a token containing `path`.
+    fn find(&self, path: &Path) -> Option<u64> {
+        let tokens = self.0.read().ok()?;
+        if tokens.is_empty() {
+            return None;
+        }
+        registry::absolute(path)
+            .ancestors()
+            .find_map(|ancestor| tokens.get(ancestor).copied())
+    }
+}
+
+/// An event handler shared by several backends serving one watcher.
+#[derive(Clone)]
+pub(crate) struct SharedHandler(Arc<Mutex<Box<dyn EventHandler>>>);
+
+impl SharedHandler {
+    pub(crate) fn new<F: EventHandler>(event_handler: F) -> Self {
+        Self(Arc::new(Mutex::new(Box::new(event_handler))))
+    }
+}
+
+impl EventHandler for SharedHandler {
+    fn handle_event(&mut self, event: Result<Event>) {
+        if let Ok(mut event_handler) = self.0.lock() {
+            event_handler.handle_event(event);
+        }
+    }
+}
+
+/// Passes events to the event handler on its own thread through a bounded queue, see
+/// [Config::with_event_queue].
+enum EventQueue {
+    Thread(Arc<QueueShared>),
+    /// The thread couldn't be started, call the event handler on the backend's thread instead
+    Inline(SharedHandler),
+}
+
+impl EventQueue {
+    fn spawn(
+        event_handler: Box<dyn EventHandler>,
+        capacity: usize,
+        strategy: OverflowStrategy,
+        losses: Option<LossStats>,
+        scheduling: Scheduling,
+    ) -> Self {
+        let event_handler = SharedHandler(Arc::new(Mutex::new(event_handler)));
+        let mut event_handler_c = event_handler.clone();
+        let shared = Arc::new(QueueShared::new(capacity, strategy, losses));
+        let shared_c = shared.clone();
+        let thread = std::thread::Builder::new()
+            .name("notify-rs event queue".to_string())
+            .spawn(move || {
+                let _ = scheduling.apply();
+                while let Some(event) = shared_c.pop() {
+                    event_handler_c.handle_event(event);
+                }
+            });
+        match thread {
+            Ok(_) => Self::Thread(shared),
+            Err(_) => Self::Inline(event_handler),
+        }
+    }
+}
+
+impl EventHandler for EventQueue {
+    fn handle_event(&mut self, event: Result<Event>) {
+        match self {
+            Self::Thread(shared) => shared.push(event),
+            Self::Inline(event_handler) => event_handler.handle_event(event),
+        }
+    }
+}
+
+impl Drop for EventQueue {
+    fn drop(&mut self) {
+        // the thread delivers the queued events, then stops
+        if let Self::Thread(shared) = self {
+            shared.close();
+        }
+    }
+}
+
+struct QueueShared {
+    queue: Mutex<Queue>,
+    not_empty: Condvar,
+    not_full: Condvar,
+}
+
+struct Queue {
+    events: VecDeque<Result<Event>>,
+    capacity: usize,
+    strategy: OverflowStrategy,
+    /// The number of events dropped since the last rescan event
+    lost: u64,
+    losses: Option<LossStats>,
+    closed: bool,
+}
+
+impl Queue {
+    /// Count `event` as lost, to be replaced by a rescan event.
+    fn drop_event(&mut self, event: Option<Result<Event>>) {
+        self.lost += 1;
+        if let Some(losses) = &self.losses {
+            losses.record_dropped(watch_token(&event));
+        }
+    }
+
+    /// Count `event` as replaced by a later event of its paths.
+    fn coalesce_event(&mut self, event: Option<Result<Event>>) {
+        if let Some(losses) = &self.losses {
+            losses.record_coalesced(watch_token(&event));
+        }
+    }
+}
+
+fn watch_token(event: &Option<Result<Event>>) -> Option<u64> {
+    match event {
+        Some(Ok(event)) => event.watch_token(),
+        _ => None,
+    }
+}
+
+impl QueueShared {
+    fn new(capacity: usize, strategy: OverflowStrategy, losses: Option<LossStats>) -> Self {
+        Self {
+            queue: Mutex::new(Queue {
+                events: VecDeque::new(),
+                capacity: capacity.max(1),
+                strategy,
+                lost: 0,
+                losses,
+                closed: false,
+            }),
+            not_empty: Condvar::new(),
+            not_full: Condvar::new(),
+        }
+    }
+
+    fn push(&self, event: Result<Event>) {
+        let mut queue = match self.queue.lock() {
+            Ok(queue) => queue,
+            Err(_) => return,
+        };
+        if queue.events.len() >= queue.capacity {
+            match queue.strategy {
+                OverflowStrategy::Block => {
+                    while queue.events.len() >= queue.capacity && !queue.closed {
+                        queue = match self.not_full.wait(queue) {
+                            Ok(queue) => queue,
+                            Err(_) => return,
+                        };
+                    }
+                }
+                OverflowStrategy::DropOldest => {
+                    let oldest = queue.events.pop_front();
+                    queue.drop_event(oldest);
+                }
+                OverflowStrategy::DropNewest => {
+                    queue.drop_event(Some(event));
+                    return;
+                }
+                OverflowStrategy::CoalescePerPath => {
+                    let same_paths = match &event {
+                        Ok(event) => queue.events.iter().position(
+                            |queued| matches!(queued, Ok(queued) if queued.paths == event.paths),
+                        ),
+                        Err(_) => None,
+                    };
+                    match same_paths {
+                        Some(i) => {
+                            let replaced = queue.events.remove(i);
+                            queue.coalesce_event(replaced);
+                        }
+                        None => {
+                            let oldest = queue.events.pop_front();
+                            queue.drop_event(oldest);
+                        }
+                    }
+                }
+            }
+        }
+        queue.events.push_back(event);
+        self.not_empty.notify_one();
+    }
+
+    /// The next event, waiting for one, or `None` once the queue was closed and drained
+    fn pop(&self) -> Option<Result<Event>> {
+        let mut queue = self.queue.lock().ok()?;
+        loop {
+            if let Some(event) = queue.events.pop_front() {
+                self.not_full.notify_one();
+                return Some(event);
+            }
+            if queue.lost > 0 {
+                let dropped = std::mem::replace(&mut queue.lost, 0);
+                if let Some(losses) = &queue.losses {
+                    losses.record_rescan(None, 0);
+                }
+                let rescan = Event::new(EventKind::Other).set_flag(Flag::Rescan);
+                return Some(Ok(rescan.set_dropped(dropped)));
+            }
+            if queue.closed {
+                return None;
+            }
+            queue = self.not_empty.wait(queue).ok()?;
+        }
+    }
+
+    fn close(&self) {
+        if let Ok(mut queue) = self.queue.lock() {
+            queue.closed = true;
+        }
+        self.not_empty.notify_all();
+        self.not_full.notify_all();
+    }
+}
+
+/// Attaches content hashes to events on its own thread, passing them on from there.
+#[cfg(feature = "blake3")]
+enum ContentHasher {
+    Thread(crate::Sender<Result<Event>>),
+    /// The thread couldn't be started, hash on the backend's thread instead
+    Inline(SharedHandler, u64),
+}
+
+#[cfg(feature = "blake3")]
+impl ContentHasher {
+    fn spawn<F: EventHandler>(event_handler: F, size_limit: u64, scheduling: Scheduling) -> Self {
+        let event_handler = SharedHandler::new(event_handler);
+        let mut event_handler_c = event_handler.clone();
+        let (tx, rx) = crate::unbounded::<Result<Event>>();
+        let thread = std::thread::Builder::new()
+            .name("notify-rs content hasher".to_string())
+            .spawn(move || {
+                let _ = scheduling.apply();
+                while let Ok(mut event) = rx.recv() {
+                    Self::hash(&mut event, size_limit);
+                    event_handler_c.handle_event(event);
+                }
+            });
+        match thread {
+            Ok(_) => Self::Thread(tx),
+            Err(_) => Self::Inline(event_handler, size_limit),
+        }
+    }
+
+    fn hash(event: &mut Result<Event>, size_limit: u64) {
+        use crate::event::{AccessKind, AccessMode, EventKind, ModifyKind};
+
+        let event = match event {
+            Ok(event) => event,
+            Err(_) => return,
+        };
+        let written = matches!(
+            event.kind,
+            EventKind::Create(_)
+                | EventKind::Modify(ModifyKind::Any)
+                | EventKind::Modify(ModifyKind::Data(_))
+                | EventKind::Access(AccessKind::Close(AccessMode::Write))
+        );
+        let path = match event.paths.first() {
+            Some(path) if written => path,
+            _ => return,
+        };
+        match fs::metadata(path) {
+            Ok(metadata) if metadata.is_file() && metadata.len() <= size_limit => {}
+            _ => return,
+        }
+        if let Ok(contents) = fs::read(path) {
+            event
+                .attrs
+                .set_content_hash(*blake3::hash(&contents).as_bytes());
+        }
+    }
+}
+
+#[cfg(feature = "blake3")]
+impl EventHandler for ContentHasher {
+    fn handle_event(&mut self, mut event: Result<Event>) {
+        match self {
+            Self::Thread(tx) => {
+                let _ = tx.send(event);
+            }
+            Self::Inline(event_handler, size_limit) => {
+                Self::hash(&mut event, *size_limit);
+                event_handler.handle_event(event);
+            }
+        }
+    }
+}
+
+/// Drops events repeating the last delivered one within a short window.
+///
+/// Only the last delivered event is remembered, so this is bounded and cheap, but only catches
+/// consecutive duplicates.
+#[derive(Debug)]
+struct Coalesce {
+    window: Duration,
+    last: Option<(Event, Instant)>,
+}
+
+impl Coalesce {
+    fn new(window: Duration) -> Self {
+        Self { window, last: None }
+    }
+
+    fn should_deliver(&mut self, event: &Result<Event>) -> bool {
+        let event = match event {
+            Ok(event) => event,
+            Err(_) => {
+                self.last = None;
+                return true;
+            }
+        };
+
+        let now = Instant::now();
+        if let Some((ref last, delivered)) = self.last {
+            if last.kind == event.kind
+                && last.paths == event.paths
+                && now.duration_since(delivered) < self.window
+            {
+                return false;
+            }
+        }
+
+        self.last = Some((event.clone(), now));
+        true
+    }
+}
+
+/// Merges events of the same kind for one file reported through several of its hardlinks.
+#[derive(Debug)]
+struct HardlinkDedup {
+    window: Duration,
+    /// The file of each path seen, which is only looked up for paths not seen before
+    ids: HashMap<PathBuf, Option<(u64, u64)>>,
+    files: HashMap<(u64, u64), Hardlinks>,
+    /// Expired files are forgotten once there are this many
+    limit: usize,
+}
+
+#[derive(Debug)]
+struct Hardlinks {
+    paths: Vec<PathBuf>,
+    last: Option<(PathBuf, EventKind, Instant)>,
+}
+
+impl HardlinkDedup {
+    const MIN_LIMIT: usize = 1024;
+
+    fn new(window: Duration) -> Self {
+        Self {
+            window,
+            ids: HashMap::new(),
+            files: HashMap::new(),
+            limit: Self::MIN_LIMIT,
+        }
+    }
+
+    fn should_deliver(&mut self, event: &mut Event) -> bool {
+        // renames name several files
+        let path = match event.paths.as_slice() {
+            [path] => path,
+            _ => return true,
+        };
+
+        // the path may name another file after these, and removed files can't be recognized
+        if matches!(
+            event.kind,
+            EventKind::Create(_) | EventKind::Remove(_) | EventKind::Modify(ModifyKind::Name(_))
+        ) {
+            self.forget(path);
+        }
+        if event.kind.is_remove() {
+            return true;
+        }
+
+        let id = match self.ids.get(path) {
+            Some(id) => *id,
+            None => match file_id(path) {
+                Some(id) => *self.ids.entry(path.clone()).or_insert(id),
+                None => return true,
+            },
+        };
+        let id = match id {
+            Some(id) => id,
+            None => return true,
+        };
+
+        let now = Instant::now();
+        let window = self.window;
+        let links = self.files.entry(id).or_insert_with(|| Hardlinks {
+            paths: Vec::new(),
+            last: None,
+        });
+        if let Some((ref last_path, ref kind, seen)) = links.last {
+            if now.duration_since(seen) >= window {
+                links.paths.retain(|known| known == path);
+            } else if last_path != path && *kind == event.kind {
+                // the event already delivered stands for this path too
+                if !links.paths.contains(path) {
+                    links.paths.push(path.clone());
+                }
+                return false;
+            }
+        }
+        if !links.paths.contains(path) {
+            links.paths.push(path.clone());
+        }
+        links.last = Some((path.clone(), event.kind.clone(), now));
+        if links.paths.len() > 1 {
+            event.attrs.set_hardlinks(links.paths.clone());
+        }
+
+        if self.files.len() >= self.limit {
+            self.prune(now);
+        }
+        true
+    }
+
+    /// Forget which file `path` is.
+    fn forget(&mut self, path: &Path) {
+        if let Some(Some(id)) = self.ids.remove(path) {
+            if let Some(links) = self.files.get_mut(&id) {
+                links.paths.retain(|known| known != path);
+            }
+        }
+    }
+
+    /// Forget the files without an event for a window and the paths which were seen for them.
+    fn prune(&mut self, now: Instant) {
+        let window = self.window;
+        self.files.retain(|_, links| {
+            links
+                .last
+                .as_ref()
+                .map_or(false, |(_, _, seen)| now.duration_since(*seen) < window)
+        });
+        let files = &self.files;
+        self.ids
+            .retain(|_, id| id.map_or(false, |id| files.contains_key(&id)));
+        self.limit = Self::MIN_LIMIT.max(self.files.len() * 2);
+    }
+}
+
+/// The device and inode of a file, or none for a directory.
+#[cfg(unix)]
+fn file_id(path: &Path) -> Option<Option<(u64, u64)>> {
+    use std::os::unix::fs::MetadataExt;
+
+    let metadata = fs::symlink_metadata(path).ok()?;
+    Some((!metadata.is_dir()).then(|| (metadata.dev(), metadata.ino())))
+}
+
+#[cfg(not(unix))]
+fn file_id(_path: &Path) -> Option<Option<(u64, u64)>> {
+    None
+}
+
+#[test]
+fn coalesce_drops_consecutive_duplicates_only() {
+    use crate::event::{EventKind, ModifyKind};
+    use std::path::PathBuf;
+
+    let modify = |path: &str| {
+        Ok(Event::new(EventKind::Modify(ModifyKind::Any)).add_path(PathBuf::from(path)))
+    };
+    let mut coalesce = Coalesce::new(Duration::from_secs(3600));
+
+    assert!(coalesce.should_deliver(&modify("a")));
+    assert!(!coalesce.should_deliver(&modify("a")));
+    assert!(!coalesce.should_deliver(&modify("a")));
+    assert!(coalesce.should_deliver(&modify("b")));
+    assert!(coalesce.should_deliver(&modify("a")));
+
+    let mut coalesce = Coalesce::new(Duration::from_secs(0));
+    assert!(coalesce.should_deliver(&modify("a")));
+    assert!(coalesce.should_deliver(&modify("a")));
+}
+
+#[test]
+fn watch_tokens_are_attached_by_innermost_watch() {
+    use crate::event::{EventKind, ModifyKind};
+    use std::sync::mpsc;
+
+    let (tx, rx) = mpsc::channel();
+    let mut delivery = Delivery::new(tx, &Config::default());
+    let root = std::env::temp_dir().join("notify-watch-tokens");
+    delivery.tokens().insert(&root, 1);
+    delivery.tokens().insert(&root.join("inner"), 2);
+
+    for path in [
+        root.join("a"),
+        root.join("inner").join("b"),
+        PathBuf::from("/elsewhere"),
+    ] {
+        delivery.handle_event(Ok(
+            Event::new(EventKind::Modify(ModifyKind::Any)).add_path(path)
+        ));
+    }
+    let tokens: Vec<_> = rx
+        .try_iter()
+        .map(|event| event.unwrap().watch_token())
+        .collect();
+    assert_eq!(tokens, [Some(1), Some(2), None]);
+
+    delivery.tokens().remove(&root.join("inner"));
+    assert_eq!(delivery.tokens.find(&root.join("inner").join("b")), Some(1));
+}
+
+#[test]
+fn own_events_are_dropped_if_ignored() {
+    use crate::event::{EventKind, ModifyKind};
+    use std::sync::mpsc;
+
+    let (tx, rx) = mpsc::channel();
+    let config = Config::default().with_ignore_own_events(true);
+    let mut delivery = Delivery::new(tx, &config);
+    for process_id in [std::process::id(), std::process::id().wrapping_add(1)] {
+        let event = Event::new(EventKind::Modify(ModifyKind::Any)).set_process_id(process_id);
+        delivery.handle_event(Ok(event));
+    }
+    delivery.handle_event(Ok(Event::new(EventKind::Modify(ModifyKind::Any))));
+    assert_eq!(rx.try_iter().count(), 2);
+}
+
+#[test]
+#[cfg(unix)]
+fn hardlink_dedup_merges_events_of_the_same_file() {
+    use crate::event::ModifyKind;
+
+    let dir = tempfile::tempdir().unwrap();
+    let (a, b, other) = (
+        dir.path().join("a"),
+        dir.path().join("b"),
+        dir.path().join("other"),
+    );
+    fs::write(&a, b"contents").unwrap();
+    fs::hard_link(&a, &b).unwrap();
+    fs::write(&other, b"contents").unwrap();
+
+    let modify =
+        |path: &PathBuf| Event::new(EventKind::Modify(ModifyKind::Any)).add_path(path.clone());
+    let mut dedup = HardlinkDedup::new(Duration::from_secs(3600));
+
+    let mut event = modify(&a);
+    assert!(dedup.should_deliver(&mut event));
+    assert_eq!(event.hardlinks(), None);
+    assert!(!dedup.should_deliver(&mut modify(&b)));
+
+    // repeated writes through the same path are all reported
+    let mut event = modify(&a);
+    assert!(dedup.should_deliver(&mut event));
+    assert_eq!(event.hardlinks(), Some(&[a.clone(), b.clone()][..]));
+    assert!(!dedup.should_deliver(&mut modify(&b)));
+    assert!(dedup.should_deliver(&mut modify(&a)));
+
+    let mut other_event = modify(&other);
+    assert!(dedup.should_deliver(&mut other_event));
+    assert_eq!(other_event.hardlinks(), None);
+
+    let mut event =
+        Event::new(EventKind::Access(crate::event::AccessKind::Any)).add_path(b.clone());
+    assert!(dedup.should_deliver(&mut event));
+    assert_eq!(event.hardlinks(), Some(&[a.clone(), b.clone()][..]));
+
+    // a path replaced by another file is looked up again
+    fs::remove_file(&b).unwrap();
+    fs::write(&b, b"contents").unwrap();
+    let create = Event::new(EventKind::Create(crate::event::CreateKind::File)).add_path(b.clone());
+    assert!(dedup.should_deliver(&mut create.clone()));
+    assert!(dedup.should_deliver(&mut modify(&a)));
+    let mut event = modify(&b);
+    assert!(dedup.should_deliver(&mut event));
+    assert_eq!(event.hardlinks(), None);
+}
+
+#[test]
+#[cfg(feature = "blake3")]
+fn content_hash_is_attached_to_written_files() {
+    use crate::event::{CreateKind, EventKind};
+    use std::sync::mpsc;
+
+    let dir = tempfile::tempdir().unwrap();
+    let (small, large) = (dir.path().join("small"), dir.path().join("large"));
+    fs::write(&small, b"contents").unwrap();
+    fs::write(&large, [0; 100]).unwrap();
+
+    let (tx, rx) = mpsc::channel();
+    let mut delivery = Delivery::new(tx, &Config::default().with_content_hash(Some(10)));
+    for path in [&small, &large] {
+        delivery.handle_event(Ok(
+            Event::new(EventKind::Create(CreateKind::File)).add_path(path.clone())
+        ));
+    }
+    let hashes: Vec<_> = (0..2)
+        .map(|_| {
+            let event = rx.recv_timeout(Duration::from_secs(1)).unwrap().unwrap();
+            event.content_hash().copied()
+        })
+        .collect();
+    assert_eq!(hashes, [Some(*blake3::hash(b"contents").as_bytes()), None]);
+}
+
+#[test]
+fn full_event_queues_apply_the_overflow_strategy() {
+    use crate::event::ModifyKind;
+    use std::sync::mpsc;
+
+    let modify = |path: &str| {
+        Ok(Event::new(EventKind::Modify(ModifyKind::Any)).add_path(PathBuf::from(path)))
+    };
+    let drain = |strategy, paths: &[&str]| {
+        let shared = QueueShared::new(2, strategy, None);
+        for path in paths {
+            shared.push(modify(path));
+        }
+        shared.close();
+        std::iter::from_fn(|| shared.pop())
+            .map(|event| match event.unwrap() {
+                event if event.need_rescan() => format!("rescan {}", event.dropped().unwrap()),
+                event => event.paths[0].display().to_string(),
+            })
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        drain(OverflowStrategy::DropOldest, &["a", "b", "c", "d"]),
+        ["c", "d", "rescan 2"]
+    );
+    assert_eq!(
+        drain(OverflowStrategy::DropNewest, &["a", "b", "c"]),
+        ["a", "b", "rescan 1"]
+    );
+    assert_eq!(
+        drain(OverflowStrategy::CoalescePerPath, &["a", "b", "a"]),
+        ["b", "a"]
+    );
+    assert_eq!(
+        drain(OverflowStrategy::CoalescePerPath, &["a", "b", "c"]),
+        ["b", "c", "rescan 1"]
+    );
+
+    // blocking loses nothing
+    let (tx, rx) = mpsc::channel();
+    let mut delivery = Delivery::new(tx, &Config::default().with_event_queue(Some(1)));
+    for path in ["a", "b", "c"] {
+        delivery.handle_event(modify(path));
+    }
+    drop(delivery);
+    let paths: Vec<_> = rx.iter().map(|event| event.unwrap().paths).collect();
+    assert_eq!(
+        paths,
+        [
+            [PathBuf::from("a")],
+            [PathBuf::from("b")],
+            [PathBuf::from("c")]
+        ]
+    );
+}
+
+#[test]
+fn losses_are_counted_per_watch_token() {
+    use crate::event::ModifyKind;
+    use crate::stats::{Loss, LossStats};
+
+    let losses = LossStats::new();
+    let config = Config::default().with_loss_stats(Some(losses.clone()));
+    let mut delivery = Delivery::new(|_| {}, &config);
+    delivery.tokens().insert(Path::new("/watched"), 7);
+    let rescan = Event::new(EventKind::Other).set_flag(Flag::Rescan);
+    delivery.handle_event(Ok(rescan.clone().add_path(PathBuf::from("/watched/dir"))));
+    delivery.handle_event(Ok(rescan));
+
+    let shared = QueueShared::new(1, OverflowStrategy::CoalescePerPath, Some(losses.clone()));
+    let modify = Event::new(EventKind::Modify(ModifyKind::Any)).set_watch_token(7);
+    for path in ["/watched/a", "/watched/a", "/watched/b"] {
+        shared.push(Ok(modify.clone().add_path(PathBuf::from(path))));
+    }
+    shared.close();
+    assert_eq!(std::iter::from_fn(|| shared.pop()).count(), 2);
+
+    let expected = |rescans, dropped, coalesced| Loss {
+        rescans,
+        dropped,
+        coalesced,
+    };
+    assert_eq!(losses.total(), expected(3, 1, 1));
+    assert_eq!(losses.watch(7), expected(1, 1, 1));
+}
+
+#[test]
+fn events_are_rewritten_by_the_event_map() {
+    use crate::event::ModifyKind;
+    use std::sync::mpsc;
+
+    let (tx, rx) = mpsc::channel();
+    let map = EventMap::new(|mut event: Event| {
+        for path in &mut event.paths {
+            if let Ok(rest) = path.strip_prefix("/data") {
+                *path = Path::new("/host").join(rest);
+            }
+        }
+        event.set_info("mapped")
+    });
+    let config = Config::default()
+        .with_event_filter(Some(EventFilter::new().glob("/data/*.txt")))
+        .with_event_map(Some(map));
+    let mut delivery = Delivery::new(tx, &config);
+    let modify = |path: &str| {
+        Ok(Event::new(EventKind::Modify(ModifyKind::Any)).add_path(PathBuf::from(path)))
+    };
+    delivery.handle_event(modify("/data/a.txt"));
+    delivery.handle_event(modify("/data/b.log"));
+    delivery
+        .options()
+        .configure(&config.clone().with_event_map(None));
+    delivery.handle_event(modify("/data/c.txt"));
+    drop(delivery);
+
+    let events: Vec<Event> = rx.iter().map(|event| event.unwrap()).collect();
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0].paths, [PathBuf::from("/host/a.txt")]);
+    assert_eq!(events[0].info(), Some("mapped"));
+    assert_eq!(events[1].paths, [PathBuf::from("/data/c.txt")]);
+}
@@ -0,0 +1,508 @@
+//! Watcher implementation for macOS' Endpoint Security framework
+//!
+//! Subscribes to the file notifications of an Endpoint Security client, see the
+//! [Endpoint Security reference][ref]. The client is told about every change of every process,
+//! one event per change and without coalescing, and the watches only select the events
+//! delivered. Each event carries the process id of the process making the change and the time
+//! of the change as its source time.
+//!
+//! Creating a client requires the `com.apple.developer.endpoint-security.client` entitlement,
+//! running as root and the approval of the user for full disk access.
+//!
+//! [ref]: https://developer.apple.com/documentation/endpointsecurity
+
+use crate::delivery::{Delivery, DeliveryOptions, WatchTokens};
+use crate::event::*;
+use crate::registry::{self, WatchRegistry};
+use crate::suppress::{SuppressGuard, Suppressions};
+use crate::{snapshot, Config, Error, EventHandler, RecursiveMode, Result, Watcher, WatcherKind};
+use std::ffi::{c_void, OsStr};
+use std::fmt;
+use std::os::raw::{c_char, c_int, c_ulong};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::slice;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, UNIX_EPOCH};
+
+// es_event_type_t
+const ES_EVENT_TYPE_NOTIFY_CREATE: u32 = 13;
+const ES_EVENT_TYPE_NOTIFY_LINK: u32 = 19;
+const ES_EVENT_TYPE_NOTIFY_RENAME: u32 = 25;
+const ES_EVENT_TYPE_NOTIFY_SETEXTATTR: u32 = 27;
+const ES_EVENT_TYPE_NOTIFY_SETMODE: u32 = 29;
+const ES_EVENT_TYPE_NOTIFY_SETOWNER: u32 = 30;
+const ES_EVENT_TYPE_NOTIFY_UNLINK: u32 = 32;
+const ES_EVENT_TYPE_NOTIFY_WRITE: u32 = 33;
+
+const SUBSCRIPTIONS: [u32; 8] = [
+    ES_EVENT_TYPE_NOTIFY_CREATE,
+    ES_EVENT_TYPE_NOTIFY_LINK,
+    ES_EVENT_TYPE_NOTIFY_RENAME,
+    ES_EVENT_TYPE_NOTIFY_SETEXTATTR,
+    ES_EVENT_TYPE_NOTIFY_SETMODE,
+    ES_EVENT_TYPE_NOTIFY_SETOWNER,
+    ES_EVENT_TYPE_NOTIFY_UNLINK,
+    ES_EVENT_TYPE_NOTIFY_WRITE,
+];
+
+// es_destination_type_t
+const ES_DESTINATION_TYPE_EXISTING_FILE: u32 = 0;
+
+// es_new_client_result_t
+const ES_NEW_CLIENT_RESULT_SUCCESS: u32 = 0;
+const ES_NEW_CLIENT_RESULT_ERR_NOT_ENTITLED: u32 = 3;
+const ES_NEW_CLIENT_RESULT_ERR_NOT_PERMITTED: u32 = 4;
+const ES_NEW_CLIENT_RESULT_ERR_NOT_PRIVILEGED: u32 = 5;
+
+// es_return_t
+const ES_RETURN_SUCCESS: u32 = 0;
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct EsStringToken {
+    length: usize,
+    data: *const c_char,
+}
+
+impl EsStringToken {
+    unsafe fn to_path(self) -> PathBuf {
+        if self.data.is_null() {
+            return PathBuf::new();
+        }
+        let bytes = slice::from_raw_parts(self.data as *const u8, self.length);
+        PathBuf::from(OsStr::from_bytes(bytes))
+    }
+}
+
+/// The start of `es_file_t`, followed by the file's `stat`
+#[repr(C)]
+struct EsFile {
+    path: EsStringToken,
+}
+
+/// The start of `es_process_t`
+#[repr(C)]
+struct EsProcess {
+    audit_token: [u32; 8],
+}
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct EsNewPath {
+    dir: *const EsFile,
+    filename: EsStringToken,
+}
+
+#[repr(C)]
+union EsDestination {
+    existing_file: *const EsFile,
+    new_path: EsNewPath,
+}
+
+impl EsDestination {
+    unsafe fn to_path(&self, destination_type: u32) -> PathBuf {
+        if destination_type == ES_DESTINATION_TYPE_EXISTING_FILE {
+            (*self.existing_file).path.to_path()
+        } else {
+            let new_path = &self.new_path;
+            (*new_path.dir)
+                .path
+                .to_path()
+                .join(new_path.filename.to_path())
+        }
+    }
+}
+
+#[repr(C)]
+struct EsEventCreate {
+    destination_type: u32,
+    destination: EsDestination,
+}
+
+#[repr(C)]
+struct EsEventRename {
+    source: *const EsFile,
+    destination_type: u32,
+    destination: EsDestination,
+}
+
+#[repr(C)]
+struct EsEventLink {
+    source: *const EsFile,
+    target_dir: *const EsFile,
+    target_filename: EsStringToken,
+}
+
+/// The start of the events which target one file: `unlink`, `write` and `setextattr`
+#[repr(C)]
+struct EsEventTarget {
+    target: *const EsFile,
+}
+
+/// The start of `es_event_setmode_t` and `es_event_setowner_t`, the target after 8 bytes
+#[repr(C)]
+struct EsEventSetAttribute {
+    attribute: u64,
+    target: *const EsFile,
+}
+
+/// The start of `es_message_t`, with its `event` union
+#[repr(C)]
+struct EsMessage {
+    version: u32,
+    time: libc::timespec,
+    mach_time: u64,
+    deadline: u64,
+    process: *const EsProcess,
+    seq_num: u64,
+    action_type: u32,
+    /// `es_event_id_t` or `es_result_t`
+    action: [u32; 9],
+    event_type: u32,
+    event: [u64; 0],
+}
+
+/// The layout of a block literal, the Objective-C closures the framework takes its handler as.
+#[repr(C)]
+struct HandlerBlock {
+    isa: *const c_void,
+    flags: c_int,
+    reserved: c_int,
+    invoke: unsafe extern "C" fn(*const HandlerBlock, *mut c_void, *const EsMessage),
+    descriptor: *const BlockDescriptor,
+    /// The only capture, the [Shared] state of the watcher
+    shared: *const c_void,
+}
+
+#[repr(C)]
+struct BlockDescriptor {
+    reserved: c_ulong,
+    size: c_ulong,
+}
+
+static DESCRIPTOR: BlockDescriptor = BlockDescriptor {
+    reserved: 0,
+    size: std::mem::size_of::<HandlerBlock>() as c_ulong,
+};
+
+/// Copying a global block returns the block itself, so it stays where the watcher keeps it.
+const BLOCK_IS_GLOBAL: c_int = 1 << 28;
+
+#[link(name = "EndpointSecurity")]
+extern "C" {
+    static _NSConcreteGlobalBlock: [*const c_void; 32];
+
+    fn es_new_client(client: *mut *mut c_void, handler: *const HandlerBlock) -> u32;
+    fn es_subscribe(client: *mut c_void, events: *const u32, event_count: u32) -> u32;
+    fn es_delete_client(client: *mut c_void) -> u32;
+}
+
+unsafe extern "C" fn handle_message(
+    block: *const HandlerBlock,
+    _client: *mut c_void,
+    message: *const EsMessage,
+) {
+    let shared = &*((*block).shared as *const Shared);
+    if let Some(event) = translate(&*message) {
+        shared.handle(event);
+    }
+}
+
+/// The [Event] of a message.
+unsafe fn translate(message: &EsMessage) -> Option<Event> {
+    let event = message.event.as_ptr() as *const u8;
+    let (kind, paths) = match message.event_type {
+        ES_EVENT_TYPE_NOTIFY_CREATE => {
+            let create = &*(event as *const EsEventCreate);
+            let path = create.destination.to_path(create.destination_type);
+            (EventKind::Create(CreateKind::Any), vec![path])
+        }
+        ES_EVENT_TYPE_NOTIFY_LINK => {
+            let link = &*(event as *const EsEventLink);
+            let path = (*link.target_dir)
+                .path
+                .to_path()
+                .join(link.target_filename.to_path());
+            (EventKind::Create(CreateKind::File), vec![path])
+        }
+        ES_EVENT_TYPE_NOTIFY_RENAME => {
+            let rename = &*(event as *const EsEventRename);
+            let from = (*rename.source).path.to_path();
+            let to = rename.destination.to_path(rename.destination_type);
+            (
+                EventKind::Modify(ModifyKind::Name(RenameMode::Both)),
+                vec![from, to],
+            )
+        }
+        ES_EVENT_TYPE_NOTIFY_SETEXTATTR => (
+            EventKind::Modify(ModifyKind::Metadata(MetadataKind::Extended)),
+            vec![(*(*(event as *const EsEventTarget)).target).path.to_path()],
+        ),
+        ES_EVENT_TYPE_NOTIFY_SETMODE => (
+            EventKind::Modify(ModifyKind::Metadata(MetadataKind::Permissions)),
+            vec![(*(*(event as *const EsEventSetAttribute)).target)
+                .path
+                .to_path()],
+        ),
+        ES_EVENT_TYPE_NOTIFY_SETOWNER => (
+            EventKind::Modify(ModifyKind::Metadata(MetadataKind::Ownership)),
+            vec![(*(*(event as *const EsEventSetAttribute)).target)
+                .path
+                .to_path()],
+        ),
+        ES_EVENT_TYPE_NOTIFY_UNLINK => (
+            EventKind::Remove(RemoveKind::Any),
+            vec![(*(*(event as *const EsEventTarget)).target).path.to_path()],
+        ),
+        ES_EVENT_TYPE_NOTIFY_WRITE => (
+            EventKind::Modify(ModifyKind::Data(DataChange::Any)),
+            vec![(*(*(event as *const EsEventTarget)).target).path.to_path()],
+        ),
+        _ => return None,
+    };
+
+    let mut event = Event::new(kind);
+    event.paths = paths;
+    if !message.process.is_null() {
+        // `audit_token_to_pid`
+        event = event.set_process_id((*message.process).audit_token[5]);
+    }
+    if let (Ok(secs), Ok(nanos)) = (
+        u64::try_from(message.time.tv_sec),
+        u32::try_from(message.time.tv_nsec),
+    ) {
+        event = event.set_source_time(UNIX_EPOCH + Duration::new(secs, nanos));
+    }
+    Some(event)
+}
+
+/// The parts of a watcher shared with the handler of its client.
+struct Shared {
+    registry: Mutex<WatchRegistry>,
+    delivery: Mutex<Delivery>,
+}
+
+impl Shared {
+    fn registry(&self) -> MutexGuard<'_, WatchRegistry> {
+        self.registry.lock().unwrap_or_else(|p| p.into_inner())
+    }
+
+    fn delivery(&self) -> MutexGuard<'_, Delivery> {
+        self.delivery.lock().unwrap_or_else(|p| p.into_inner())
+    }
+
+    fn handle(&self, event: Event) {
+        // a rename is delivered if either end is watched.
+        let registry = self.registry();
+        if !event.paths.iter().any(|path| registry.is_watched(path)) {
+            return;
+        }
+        drop(registry);
+        self.delivery().handle_event(Ok(event));
+    }
+}
+
+/// Watcher implementation based on Endpoint Security, see the [module](self) docs
+pub struct EndpointSecurityWatcher {
+    client: *mut c_void,
+    /// The handler of the client, which has to outlive it
+    _block: Box<HandlerBlock>,
+    shared: Arc<Shared>,
+    tokens: WatchTokens,
+    options: DeliveryOptions,
+    suppressions: Suppressions,
+    initial_scan: bool,
+}
+
+impl fmt::Debug for EndpointSecurityWatcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EndpointSecurityWatcher")
+            .field("client", &self.client)
+            .field("registry", &*self.shared.registry())
+            .field("initial_scan", &self.initial_scan)
+            .finish_non_exhaustive()
+    }
+}
+
+// The client is only used to delete it, which is safe from any thread.
+unsafe impl Send for EndpointSecurityWatcher {}
+
+impl EndpointSecurityWatcher {
+    fn watch_inner(&mut self, path: &Path, recursive_mode: RecursiveMode) -> Result<()> {
+        let path = registry::absolute(path);
+        if !path.exists() {
+            return Err(Error::path_not_found().add_path(path));
+        }
+        {
+            let mut registry = self.shared.registry();
+            registry.plan_watch(&path, recursive_mode)?;
+            registry.insert(&path, recursive_mode);
+        }
+        if self.initial_scan {
+            let mut delivery = self.shared.delivery();
+            for event in snapshot::initial_scan_events(&path, recursive_mode) {
+                delivery.handle_event(Ok(event));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Watcher for EndpointSecurityWatcher {
+    /// Create a new watcher, creating an Endpoint Security client.
+    fn new<F: EventHandler>(event_handler: F, config: Config) -> Result<Self> {
+        let delivery = Delivery::new(event_handler, &config);
+        let tokens = delivery.tokens();
+        let options = delivery.options();
+        let suppressions = delivery.suppressions();
+        let shared = Arc::new(Shared {
+            registry: Mutex::new(WatchRegistry::new(config.watch_overlap())),
+            delivery: Mutex::new(delivery),
+        });
+
+        let block = Box::new(HandlerBlock {
+            isa: unsafe { _NSConcreteGlobalBlock.as_ptr() as *const c_void },
+            flags: BLOCK_IS_GLOBAL,
+            reserved: 0,
+            invoke: handle_message,
+            descriptor: &DESCRIPTOR,
+            shared: Arc::as_ptr(&shared) as *const c_void,
+        });
+        let mut client = ptr::null_mut();
+        match unsafe { es_new_client(&mut client, &*block) } {
+            ES_NEW_CLIENT_RESULT_SUCCESS => {}
+            ES_NEW_CLIENT_RESULT_ERR_NOT_ENTITLED => {
+                return Err(Error::generic(
+                    "Endpoint Security requires the com.apple.developer.endpoint-security.client \
+                     entitlement.",
+                ))
+            }
+            ES_NEW_CLIENT_RESULT_ERR_NOT_PERMITTED => {
+                return Err(Error::generic(
+                    "Endpoint Security requires the approval for full disk access.",
+                ))
+            }
+            ES_NEW_CLIENT_RESULT_ERR_NOT_PRIVILEGED => {
+                return Err(Error::generic(
+                    "Endpoint Security requires running as root.",
+                ))
+            }
+            code => {
+                return Err(Error::generic(&format!(
+                    "Failed to create the Endpoint Security client: {}",
+                    code
+                )))
+            }
+        }
+        let watcher = EndpointSecurityWatcher {
+            client,
+            _block: block,
+            shared,
+            tokens,
+            options,
+            suppressions,
+            initial_scan: config.initial_scan(),
+        };
+
+        let count = SUBSCRIPTIONS.len() as u32;
+        if unsafe { es_subscribe(watcher.client, SUBSCRIPTIONS.as_ptr(), count) }
+            != ES_RETURN_SUCCESS
+        {
+            return Err(Error::generic(
+                "Failed to subscribe to the Endpoint Security events.",
+            ));
+        }
+        Ok(watcher)
+    }
+
+    fn watch(&mut self, path: &Path, recursive_mode: RecursiveMode) -> Result<()> {
+        self.watch_inner(path, recursive_mode)
+    }
+
+    fn watch_with_token(
+        &mut self,
+        path: &Path,
+        recursive_mode: RecursiveMode,
+        token: u64,
+    ) -> Result<()> {
+        // registered first, so that the initial scan carries the token already.
+        self.tokens.insert(path, token);
+        if let Err(e) = self.watch_inner(path, recursive_mode) {
+            self.tokens.remove(path);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn unwatch(&mut self, path: &Path) -> Result<()> {
+        let mut registry = self.shared.registry();
+        if registry.recursive_mode(path).is_none() {
+            return Err(Error::watch_not_found().add_path(path.to_owned()));
+        }
+        registry.remove(path);
+        self.tokens.remove(path);
+        Ok(())
+    }
+
+    fn set_recursive_mode(&mut self, path: &Path, recursive_mode: RecursiveMode) -> Result<()> {
+        // the watches only select the events, so there is nothing to install.
+        let mut registry = self.shared.registry();
+        registry.plan_set_recursive_mode(path, recursive_mode)?;
+        registry.insert(path, recursive_mode);
+        Ok(())
+    }
+
+    fn suppress(&self, path: &Path) -> Result<SuppressGuard> {
+        Ok(self.suppressions.guard(path))
+    }
+
+    fn is_watched(&self, path: &Path) -> bool {
+        self.shared.registry().is_watched(path)
+    }
+
+    fn configure(&mut self, config: Config) -> Result<bool> {
+        self.options.configure(&config);
+        Ok(true)
+    }
+
+    fn kind() -> WatcherKind {
+        WatcherKind::EndpointSecurity
+    }
+}
+
+impl Drop for EndpointSecurityWatcher {
+    fn drop(&mut self) {
+        // the handler isn't called anymore once the client is deleted, the block and the shared
+        // state it points to are freed after this.
+        unsafe { es_delete_client(self.client) };
+    }
+}
+
+#[test]
+fn layouts_match_the_sdk_headers() {
+    use std::mem::{size_of, MaybeUninit};
+
+    macro_rules! offset_of {
+        ($ty:ty, $field:ident) => {{
+            let value = MaybeUninit::<$ty>::uninit();
+            let base = value.as_ptr();
+            unsafe { ptr::addr_of!((*base).$field) as usize - base as usize }
+        }};
+    }
+
+    // the offsets of the fields read in the structs of `EndpointSecurity/ESMessage.h`
+    assert_eq!(size_of::<EsStringToken>(), 16);
+    assert_eq!(offset_of!(EsNewPath, filename), 8);
+    assert_eq!(offset_of!(EsEventCreate, destination), 8);
+    assert_eq!(offset_of!(EsEventRename, destination_type), 8);
+    assert_eq!(offset_of!(EsEventRename, destination), 16);
+    assert_eq!(offset_of!(EsEventLink, target_dir), 8);
+    assert_eq!(offset_of!(EsEventLink, target_filename), 16);
+    assert_eq!(offset_of!(EsEventSetAttribute, target), 8);
+    assert_eq!(offset_of!(EsMessage, time), 8);
+    assert_eq!(offset_of!(EsMessage, process), 40);
+    assert_eq!(offset_of!(EsMessage, event_type), 96);
+    assert_eq!(offset_of!(EsMessage, event), 104);
+}
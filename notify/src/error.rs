@@ -28,10 +28,13 @@ pub enum ErrorKind {
     WatchNotFound,
 
     /// An invalid value was passed as runtime configuration.
-    InvalidConfig(Config),
+    InvalidConfig(Box<Config>),
 
     /// Can't watch (more) files, limit on the total number of inotify watches reached
     MaxFilesWatch,
+
+    /// The watch would overlap the existing watch at this path, see [`Config::with_watch_overlap`].
+    WatchOverlap(PathBuf),
 }
 
 /// Notify error type.
@@ -93,7 +96,7 @@ impl Error {
 
     /// Creates a new "invalid config" error from the given `Config`.
     pub fn invalid_config(config: &Config) -> Self {
-        Self::new(ErrorKind::InvalidConfig(config.clone()))
+        Self::new(ErrorKind::InvalidConfig(Box::new(config.clone())))
     }
 }
 
@@ -106,6 +109,9 @@ impl fmt::Display for Error {
             ErrorKind::Generic(ref err) => err.clone(),
             ErrorKind::Io(ref err) => err.to_string(),
             ErrorKind::MaxFilesWatch => "OS file watch limit reached.".into(),
+            ErrorKind::WatchOverlap(ref existing) => {
+                format!("Overlaps the watch of {:?}.", existing)
+            }
         };
 
         if self.paths.is_empty() {
@@ -156,6 +162,19 @@ impl From<std::sync::mpsc::RecvError> for Error {
     }
 }
 
+#[cfg(feature = "flume")]
+impl<T> From<flume::SendError<T>> for Error {
+    fn from(err: flume::SendError<T>) -> Self {
+        Error::generic(&format!("internal channel disconnect: {:?}", err))
+    }
+}
+#[cfg(feature = "flume")]
+impl From<flume::RecvError> for Error {
+    fn from(err: flume::RecvError) -> Self {
+        Error::generic(&format!("internal channel disconnect: {:?}", err))
+    }
+}
+
 impl<T> From<std::sync::PoisonError<T>> for Error {
     fn from(err: std::sync::PoisonError<T>) -> Self {
         Error::generic(&format!("internal mutex poisoned: {:?}", err))
@@ -0,0 +1,660 @@
+//! Watcher implementation for Event Tracing for Windows
+//!
+//! Consumes the file events of the `Microsoft-Windows-Kernel-File` provider in a real-time trace
+//! session, see the [ETW reference][ref]. The provider reports the changes of all volumes, with
+//! the process making them, and the watches only select the events delivered. Starting a trace
+//! session requires administrator rights or membership in the "Performance Log Users" group.
+//!
+//! Events are reported once ETW flushes its buffers, which takes up to a second. The kernel
+//! reports a rename with one path only, as `Modify(Name(Any))`, and writes as
+//! `Modify(Data(Any))` for every write call. Events carry the process making the change and the
+//! time the kernel reported it as their source time.
+//!
+//! [ref]: https://learn.microsoft.com/en-us/windows/win32/etw/about-event-tracing
+
+use crate::audit::{Audit, Resource};
+use crate::delivery::{Delivery, DeliveryOptions, WatchTokens};
+use crate::event::*;
+use crate::registry::{self, WatchRegistry};
+use crate::scheduling::Scheduling;
+use crate::suppress::{SuppressGuard, Suppressions};
+use crate::{snapshot, Config, Error, EventHandler, RecursiveMode, Result, Watcher, WatcherKind};
+use std::collections::HashMap;
+use std::ffi::{c_void, OsStr, OsString};
+use std::fmt;
+use std::io;
+use std::mem;
+use std::os::windows::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::ptr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use windows_sys::core::GUID;
+use windows_sys::Win32::Foundation::{ERROR_ACCESS_DENIED, ERROR_ALREADY_EXISTS, ERROR_SUCCESS};
+use windows_sys::Win32::Storage::FileSystem::{GetLogicalDriveStringsW, QueryDosDeviceW};
+use windows_sys::Win32::System::Diagnostics::Etw::{
+    CloseTrace, ControlTraceW, EnableTraceEx2, OpenTraceW, ProcessTrace, StartTraceW,
+    TdhGetProperty, TdhGetPropertySize, CONTROLTRACE_HANDLE, EVENT_CONTROL_CODE_ENABLE_PROVIDER,
+    EVENT_RECORD, EVENT_TRACE_CONTROL_STOP, EVENT_TRACE_LOGFILEW, EVENT_TRACE_PROPERTIES,
+    EVENT_TRACE_REAL_TIME_MODE, PROCESSTRACE_HANDLE, PROCESS_TRACE_MODE_EVENT_RECORD,
+    PROCESS_TRACE_MODE_REAL_TIME, PROPERTY_DATA_DESCRIPTOR, TRACE_LEVEL_VERBOSE,
+    WNODE_FLAG_TRACED_GUID,
+};
+
+/// The `Microsoft-Windows-Kernel-File` provider
+const KERNEL_FILE: GUID = GUID::from_u128(0xedd08927_9cc4_4e65_b970_c2560fb5c289);
+
+const KEYWORD_FILEIO: u64 = 0x20;
+const KEYWORD_CREATE: u64 = 0x80;
+const KEYWORD_WRITE: u64 = 0x200;
+const KEYWORD_DELETE_PATH: u64 = 0x400;
+const KEYWORD_RENAME_SETLINK_PATH: u64 = 0x800;
+const KEYWORD_CREATE_NEW_FILE: u64 = 0x1000;
+
+const EVENT_CREATE: u16 = 12;
+const EVENT_CLOSE: u16 = 14;
+const EVENT_WRITE: u16 = 16;
+const EVENT_DELETE_PATH: u16 = 26;
+const EVENT_RENAME_PATH: u16 = 27;
+const EVENT_CREATE_NEW_FILE: u16 = 30;
+
+/// The `CreateOptions` bit of files opened as directories
+const FILE_DIRECTORY_FILE: u32 = 0x1;
+
+const INVALID_PROCESSTRACE_HANDLE: PROCESSTRACE_HANDLE = if cfg!(target_pointer_width = "64") {
+    u64::MAX
+} else {
+    u32::MAX as u64
+};
+
+/// Numbers the sessions of this process, whose names have to be unique system-wide.
+static SESSIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// The properties of a trace session, followed by the space for its name.
+#[repr(C)]
+struct Properties {
+    properties: EVENT_TRACE_PROPERTIES,
+    name: [u16; 256],
+}
+
+impl Properties {
+    fn new() -> Box<Self> {
+        let mut p: Box<Properties> = Box::new(unsafe { mem::zeroed() });
+        p.properties.Wnode.BufferSize = mem::size_of::<Properties>() as u32;
+        p.properties.Wnode.Flags = WNODE_FLAG_TRACED_GUID;
+        // system time timestamps, which convert to the source time of the events
+        p.properties.Wnode.ClientContext = 2;
+        p.properties.LogFileMode = EVENT_TRACE_REAL_TIME_MODE;
+        p.properties.FlushTimer = 1;
+        p.properties.LoggerNameOffset = mem::size_of::<EVENT_TRACE_PROPERTIES>() as u32;
+        p
+    }
+}
+
+/// A real-time trace session with the kernel file provider enabled, stopped when dropped.
+struct Session {
+    handle: CONTROLTRACE_HANDLE,
+    name: Vec<u16>,
+}
+
+impl Session {
+    fn start() -> Result<Self> {
+        let name = format!(
+            "notify-rs etw {} {}",
+            process::id(),
+            SESSIONS.fetch_add(1, Ordering::Relaxed)
+        );
+        let name: Vec<u16> = OsStr::new(&name).encode_wide().chain(Some(0)).collect();
+
+        let mut handle = 0;
+        let mut code = unsafe {
+            StartTraceW(
+                &mut handle,
+                name.as_ptr(),
+                &mut Properties::new().properties,
+            )
+        };
+        if code == ERROR_ALREADY_EXISTS {
+            // left behind by an earlier process with the same id, sessions outlive their process
+            unsafe {
+                ControlTraceW(
+                    0,
+                    name.as_ptr(),
+                    &mut Properties::new().properties,
+                    EVENT_TRACE_CONTROL_STOP,
+                )
+            };
+            code = unsafe {
+                StartTraceW(
+                    &mut handle,
+                    name.as_ptr(),
+                    &mut Properties::new().properties,
+                )
+            };
+        }
+        match code {
+            ERROR_SUCCESS => {}
+            ERROR_ACCESS_DENIED => {
+                return Err(Error::generic(
+                    "Starting an ETW session requires administrator rights or membership in the \
+                     Performance Log Users group.",
+                ))
+            }
+            code => return Err(Error::io(io::Error::from_raw_os_error(code as i32))),
+        }
+        let session = Session { handle, name };
+
+        let keywords = KEYWORD_FILEIO
+            | KEYWORD_CREATE
+            | KEYWORD_WRITE
+            | KEYWORD_DELETE_PATH
+            | KEYWORD_RENAME_SETLINK_PATH
+            | KEYWORD_CREATE_NEW_FILE;
+        let code = unsafe {
+            EnableTraceEx2(
+                session.handle,
+                &KERNEL_FILE,
+                EVENT_CONTROL_CODE_ENABLE_PROVIDER,
+                TRACE_LEVEL_VERBOSE as u8,
+                keywords,
+                0,
+                0,
+                ptr::null(),
+            )
+        };
+        if code != ERROR_SUCCESS {
+            return Err(Error::io(io::Error::from_raw_os_error(code as i32)));
+        }
+        Ok(session)
+    }
+
+    /// Open the session for consuming its events with [event_record_callback].
+    fn open(&self, context: *mut Context) -> Result<PROCESSTRACE_HANDLE> {
+        let mut logfile: EVENT_TRACE_LOGFILEW = unsafe { mem::zeroed() };
+        logfile.LoggerName = self.name.as_ptr() as *mut u16;
+        logfile.Anonymous1.ProcessTraceMode =
+            PROCESS_TRACE_MODE_REAL_TIME | PROCESS_TRACE_MODE_EVENT_RECORD;
+        logfile.Anonymous2.EventRecordCallback = Some(event_record_callback);
+        logfile.Context = context as *mut c_void;
+        let trace = unsafe { OpenTraceW(&mut logfile) };
+        if trace == INVALID_PROCESSTRACE_HANDLE {
+            return Err(Error::io(io::Error::last_os_error()));
+        }
+        Ok(trace)
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        // ends `ProcessTrace` once the remaining events are delivered.
+        unsafe {
+            ControlTraceW(
+                self.handle,
+                ptr::null(),
+                &mut Properties::new().properties,
+                EVENT_TRACE_CONTROL_STOP,
+            )
+        };
+    }
+}
+
+/// The parts of a watcher shared with its event loop.
+struct Shared {
+    registry: Mutex<WatchRegistry>,
+    delivery: Mutex<Delivery>,
+}
+
+impl Shared {
+    fn registry(&self) -> MutexGuard<'_, WatchRegistry> {
+        self.registry.lock().unwrap_or_else(|p| p.into_inner())
+    }
+
+    fn delivery(&self) -> MutexGuard<'_, Delivery> {
+        self.delivery.lock().unwrap_or_else(|p| p.into_inner())
+    }
+}
+
+/// The state of the event loop, reached through the context of the trace.
+struct Context {
+    shared: Arc<Shared>,
+    /// The paths of the watched files opened, by their file object, to tell which file a write
+    /// goes to.
+    files: HashMap<u64, PathBuf>,
+    /// The NT device paths of the volumes, and their drive.
+    devices: Vec<(PathBuf, PathBuf)>,
+}
+
+unsafe extern "system" fn event_record_callback(record: *mut EVENT_RECORD) {
+    let record = &*record;
+    let context = &mut *(record.UserContext as *mut Context);
+    context.handle(record);
+}
+
+impl Context {
+    fn handle(&mut self, record: &EVENT_RECORD) {
+        if let Some(event) = self.event(record, |name| property(record, name)) {
+            self.shared.delivery().handle_event(Ok(event));
+        }
+    }
+
+    /// The event of `record` for a watched path, with its properties read by `property`.
+    fn event(
+        &mut self,
+        record: &EVENT_RECORD,
+        property: impl Fn(&str) -> Option<Vec<u8>>,
+    ) -> Option<Event> {
+        let header = &record.EventHeader;
+        let (kind, path) = match header.EventDescriptor.Id {
+            EVENT_CREATE => {
+                let file = property("FileObject").map(|bytes| integer(&bytes));
+                let path = property("FileName").and_then(|bytes| self.path(&bytes));
+                if let (Some(file), Some(path)) = (file, path) {
+                    if self.shared.registry().is_watched(&path) {
+                        self.files.insert(file, path);
+                    }
+                }
+                return None;
+            }
+            EVENT_CLOSE => {
+                if let Some(file) = property("FileObject") {
+                    self.files.remove(&integer(&file));
+                }
+                return None;
+            }
+            EVENT_WRITE => {
+                let file = property("FileObject")?;
+                let path = self.files.get(&integer(&file))?;
+                (
+                    EventKind::Modify(ModifyKind::Data(DataChange::Any)),
+                    path.clone(),
+                )
+            }
+            EVENT_CREATE_NEW_FILE => {
+                let path = self.path(&property("FileName")?)?;
+                let options = property("CreateOptions").map_or(0, |bytes| integer(&bytes)) as u32;
+                if options & FILE_DIRECTORY_FILE != 0 {
+                    (EventKind::Create(CreateKind::Folder), path)
+                } else {
+                    (EventKind::Create(CreateKind::File), path)
+                }
+            }
+            EVENT_DELETE_PATH => (
+                EventKind::Remove(RemoveKind::Any),
+                self.path(&property("FilePath")?)?,
+            ),
+            EVENT_RENAME_PATH => (
+                EventKind::Modify(ModifyKind::Name(RenameMode::Any)),
+                self.path(&property("FilePath")?)?,
+            ),
+            _ => return None,
+        };
+
+        if !self.shared.registry().is_watched(&path) {
+            return None;
+        }
+        let mut event = Event::new(kind)
+            .add_path(path)
+            .set_process_id(header.ProcessId);
+        if let Some(time) = system_time(header.TimeStamp) {
+            event = event.set_source_time(time);
+        }
+        Some(event)
+    }
+
+    /// The path in a string property, with the drive instead of the device.
+    fn path(&self, bytes: &[u8]) -> Option<PathBuf> {
+        let wide: Vec<u16> = bytes
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .take_while(|&c| c != 0)
+            .collect();
+        let path = PathBuf::from(OsString::from_wide(&wide));
+        self.devices.iter().find_map(|(device, drive)| {
+            path.strip_prefix(device)
+                .ok()
+                .map(|relative| drive.join(relative))
+        })
+    }
+}
+
+/// Reads a property of up to 8 bytes as an integer, like a pointer.
+fn integer(bytes: &[u8]) -> u64 {
+    let mut value = [0; 8];
+    let len = bytes.len().min(8);
+    value[..len].copy_from_slice(&bytes[..len]);
+    u64::from_le_bytes(value)
+}
+
+/// The time of an event header timestamp, in 100 nanosecond intervals since 1601 like a
+/// `FILETIME`.
+fn system_time(timestamp: i64) -> Option<SystemTime> {
+    const UNIX_EPOCH_INTERVALS: i64 = 116_444_736_000_000_000;
+
+    let intervals = timestamp.checked_sub(UNIX_EPOCH_INTERVALS)?;
+    let since_epoch = Duration::from_nanos(intervals.unsigned_abs().checked_mul(100)?);
+    if intervals >= 0 {
+        UNIX_EPOCH.checked_add(since_epoch)
+    } else {
+        UNIX_EPOCH.checked_sub(since_epoch)
+    }
+}
+
+/// Reads the property `name` of an event, as described by the provider's manifest.
+fn property(record: &EVENT_RECORD, name: &str) -> Option<Vec<u8>> {
+    let name: Vec<u16> = OsStr::new(name).encode_wide().chain(Some(0)).collect();
+    let descriptor = PROPERTY_DATA_DESCRIPTOR {
+        PropertyName: name.as_ptr() as u64,
+        ArrayIndex: u32::MAX,
+        Reserved: 0,
+    };
+    let mut size = 0;
+    let code = unsafe { TdhGetPropertySize(record, 0, ptr::null(), 1, &descriptor, &mut size) };
+    if code != ERROR_SUCCESS {
+        return None;
+    }
+    let mut buffer = vec![0; size as usize];
+    let code = unsafe {
+        TdhGetProperty(
+            record,
+            0,
+            ptr::null(),
+            1,
+            &descriptor,
+            size,
+            buffer.as_mut_ptr(),
+        )
+    };
+    if code != ERROR_SUCCESS {
+        return None;
+    }
+    Some(buffer)
+}
+
+/// The NT device paths of the drives, like `\Device\HarddiskVolume3` for `C:\`.
+fn devices() -> Vec<(PathBuf, PathBuf)> {
+    let mut drives = vec![0; 1024];
+    let len = unsafe { GetLogicalDriveStringsW(drives.len() as u32, drives.as_mut_ptr()) };
+    drives.truncate((len as usize).min(drives.len()));
+
+    drives
+        .split(|&c| c == 0)
+        .filter(|drive| !drive.is_empty())
+        .filter_map(|drive| {
+            // `C:\` is queried as `C:`.
+            let name: Vec<u16> = drive
+                .iter()
+                .copied()
+                .take_while(|&c| c != u16::from(b'\\'))
+                .chain(Some(0))
+                .collect();
+            let mut target = vec![0; 1024];
+            let len =
+                unsafe { QueryDosDeviceW(name.as_ptr(), target.as_mut_ptr(), target.len() as u32) };
+            let device = target[..len as usize].split(|&c| c == 0).next()?;
+            if device.is_empty() {
+                return None;
+            }
+            Some((
+                PathBuf::from(OsString::from_wide(device)),
+                PathBuf::from(OsString::from_wide(drive)),
+            ))
+        })
+        .collect()
+}
+
+/// Watcher implementation based on Event Tracing for Windows, see the [module](self) docs
+pub struct EtwWatcher {
+    shared: Arc<Shared>,
+    session: Option<Session>,
+    thread: Option<JoinHandle<()>>,
+    tokens: WatchTokens,
+    options: DeliveryOptions,
+    suppressions: Suppressions,
+    initial_scan: bool,
+}
+
+impl fmt::Debug for EtwWatcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EtwWatcher")
+            .field("registry", &*self.shared.registry())
+            .field("initial_scan", &self.initial_scan)
+            .finish_non_exhaustive()
+    }
+}
+
+impl EtwWatcher {
+    fn watch_inner(&mut self, path: &Path, recursive_mode: RecursiveMode) -> Result<()> {
+        let path = registry::absolute(path);
+        if !path.exists() {
+            return Err(Error::path_not_found().add_path(path));
+        }
+        {
+            let mut registry = self.shared.registry();
+            registry.plan_watch(&path, recursive_mode)?;
+            registry.insert(&path, recursive_mode);
+        }
+        if self.initial_scan {
+            let mut delivery = self.shared.delivery();
+            for event in snapshot::initial_scan_events(&path, recursive_mode) {
+                delivery.handle_event(Ok(event));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Watcher for EtwWatcher {
+    /// Create a new watcher, starting a trace session.
+    fn new<F: EventHandler>(event_handler: F, config: Config) -> Result<Self> {
+        let delivery = Delivery::new(event_handler, &config);
+        let tokens = delivery.tokens();
+        let options = delivery.options();
+        let suppressions = delivery.suppressions();
+        let shared = Arc::new(Shared {
+            registry: Mutex::new(WatchRegistry::new(config.watch_overlap())),
+            delivery: Mutex::new(delivery),
+        });
+
+        let session = Session::start()?;
+        let context = Box::into_raw(Box::new(Context {
+            shared: shared.clone(),
+            files: HashMap::new(),
+            devices: devices(),
+        }));
+        let trace = match session.open(context) {
+            Ok(trace) => trace,
+            Err(e) => {
+                drop(unsafe { Box::from_raw(context) });
+                return Err(e);
+            }
+        };
+        let audit = Audit::new("etw", &config);
+        audit.acquire(Resource::Handle, trace);
+        let scheduling = Scheduling::new(&config);
+
+        // the context is only used by the loop, which frees it once the session is stopped.
+        let context = context as usize;
+        let thread = thread::Builder::new()
+            .name("notify-rs etw loop".to_string())
+            .spawn(move || {
+                let thread = thread::current().id();
+                audit.acquire(Resource::Thread, thread);
+                let _ = scheduling.apply();
+                unsafe {
+                    ProcessTrace(&trace, 1, ptr::null(), ptr::null());
+                    CloseTrace(trace);
+                }
+                audit.release(Resource::Handle, trace);
+                drop(unsafe { Box::from_raw(context as *mut Context) });
+                audit.release(Resource::Thread, thread);
+            });
+        let thread = match thread {
+            Ok(thread) => thread,
+            Err(e) => {
+                unsafe {
+                    CloseTrace(trace);
+                    drop(Box::from_raw(context as *mut Context));
+                }
+                return Err(Error::io(e));
+            }
+        };
+
+        Ok(EtwWatcher {
+            shared,
+            session: Some(session),
+            thread: Some(thread),
+            tokens,
+            options,
+            suppressions,
+            initial_scan: config.initial_scan(),
+        })
+    }
+
+    fn watch(&mut self, path: &Path, recursive_mode: RecursiveMode) -> Result<()> {
+        self.watch_inner(path, recursive_mode)
+    }
+
+    fn watch_with_token(
+        &mut self,
+        path: &Path,
+        recursive_mode: RecursiveMode,
+        token: u64,
+    ) -> Result<()> {
+        // registered first, so that the initial scan carries the token already.
+        self.tokens.insert(path, token);
+        if let Err(e) = self.watch_inner(path, recursive_mode) {
+            self.tokens.remove(path);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn unwatch(&mut self, path: &Path) -> Result<()> {
+        let mut registry = self.shared.registry();
+        if registry.recursive_mode(path).is_none() {
+            return Err(Error::watch_not_found().add_path(path.to_owned()));
+        }
+        registry.remove(path);
+        self.tokens.remove(path);
+        Ok(())
+    }
+
+    fn set_recursive_mode(&mut self, path: &Path, recursive_mode: RecursiveMode) -> Result<()> {
+        // the watches only select the events, so there is nothing to install.
+        let mut registry = self.shared.registry();
+        registry.plan_set_recursive_mode(path, recursive_mode)?;
+        registry.insert(path, recursive_mode);
+        Ok(())
+    }
+
+    fn suppress(&self, path: &Path) -> Result<SuppressGuard> {
+        Ok(self.suppressions.guard(path))
+    }
+
+    fn is_watched(&self, path: &Path) -> bool {
+        self.shared.registry().is_watched(path)
+    }
+
+    fn configure(&mut self, config: Config) -> Result<bool> {
+        self.options.configure(&config);
+        Ok(true)
+    }
+
+    fn kind() -> WatcherKind {
+        WatcherKind::Etw
+    }
+}
+
+impl Drop for EtwWatcher {
+    fn drop(&mut self) {
+        self.session.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[test]
+fn records_of_watched_paths_are_parsed_into_events() {
+    use std::sync::mpsc;
+
+    let (tx, _rx) = mpsc::channel();
+    let config = Config::default();
+    let shared = Arc::new(Shared {
+        registry: Mutex::new(WatchRegistry::new(config.watch_overlap())),
+        delivery: Mutex::new(Delivery::new(tx, &config)),
+    });
+    shared
+        .registry()
+        .insert(Path::new(r"C:\watched"), RecursiveMode::Recursive);
+    let mut context = Context {
+        shared,
+        files: HashMap::new(),
+        devices: vec![(
+            PathBuf::from(r"\Device\HarddiskVolume3"),
+            PathBuf::from(r"C:\"),
+        )],
+    };
+
+    let wide = |path: &str| -> Vec<u8> {
+        OsStr::new(path)
+            .encode_wide()
+            .chain(Some(0))
+            .flat_map(|c| c.to_le_bytes())
+            .collect()
+    };
+    let record = |id: u16| {
+        let mut record: EVENT_RECORD = unsafe { mem::zeroed() };
+        record.EventHeader.EventDescriptor.Id = id;
+        record.EventHeader.ProcessId = 42;
+        // 2021-01-01T00:00:00Z
+        record.EventHeader.TimeStamp = 132_539_328_000_000_000;
+        record
+    };
+    let properties = |properties: Vec<(&'static str, Vec<u8>)>| {
+        let properties: HashMap<_, _> = properties.into_iter().collect();
+        move |name: &str| properties.get(name).cloned()
+    };
+
+    let event = context
+        .event(
+            &record(EVENT_CREATE_NEW_FILE),
+            properties(vec![
+                ("FileName", wide(r"\Device\HarddiskVolume3\watched\new")),
+                ("CreateOptions", 0u32.to_le_bytes().to_vec()),
+            ]),
+        )
+        .unwrap();
+    assert_eq!(event.kind, EventKind::Create(CreateKind::File));
+    assert_eq!(event.paths, [PathBuf::from(r"C:\watched\new")]);
+    assert_eq!(event.attrs.process_id(), Some(42));
+    assert_eq!(
+        event.source_time(),
+        Some(UNIX_EPOCH + Duration::from_secs(1_609_459_200))
+    );
+
+    // writes are told apart by the file object of the file opened
+    let opened = properties(vec![
+        ("FileObject", 7u64.to_le_bytes().to_vec()),
+        ("FileName", wide(r"\Device\HarddiskVolume3\watched\new")),
+    ]);
+    assert!(context.event(&record(EVENT_CREATE), opened).is_none());
+    let written = || properties(vec![("FileObject", 7u64.to_le_bytes().to_vec())]);
+    let event = context.event(&record(EVENT_WRITE), written()).unwrap();
+    assert_eq!(
+        event.kind,
+        EventKind::Modify(ModifyKind::Data(DataChange::Any))
+    );
+    assert_eq!(event.paths, [PathBuf::from(r"C:\watched\new")]);
+    assert!(context.event(&record(EVENT_CLOSE), written()).is_none());
+    assert!(context.event(&record(EVENT_WRITE), written()).is_none());
+
+    let removed = properties(vec![(
+        "FilePath",
+        wide(r"\Device\HarddiskVolume3\elsewhere\file"),
+    )]);
+    assert!(context.event(&record(EVENT_DELETE_PATH), removed).is_none());
+}
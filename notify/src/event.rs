@@ -6,6 +6,7 @@ use std::{
     fmt,
     hash::{Hash, Hasher},
     path::PathBuf,
+    time::SystemTime,
 };
 
 #[cfg(feature = "serde")]
@@ -413,6 +414,142 @@ struct EventAttributesInner {
         serde(default, skip_serializing, skip_deserializing)
     )]
     process_id: Option<u32>,
+
+    /// Size and modification time of the path before and after the event.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    metadata_delta: Option<MetadataDelta>,
+
+    /// When the change happened, according to the OS or filesystem.
+    ///
+    /// This is distinct from the time the event was received, which may be much later if events
+    /// queue up. It is only present for backends which are told when a change happened: the
+    /// [`PollWatcher`](crate::PollWatcher) uses the new modification time of created and
+    /// modified files.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    source_time: Option<SystemTime>,
+
+    /// The token of the watch the event originates from.
+    ///
+    /// Only present for watches added with [`Watcher::watch_with_token`]. If watches overlap,
+    /// this is the token of the innermost one.
+    ///
+    /// [`Watcher::watch_with_token`]: crate::Watcher::watch_with_token
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    watch_token: Option<u64>,
+
+    /// The blake3 hash of the file's contents after the change.
+    ///
+    /// Only present if enabled with `Config::with_content_hash`, for created and written files.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    content_hash: Option<[u8; 32]>,
+
+    /// The paths of the file's hardlinks.
+    ///
+    /// Only present if enabled with `Config::with_hardlink_dedup`, for files with several
+    /// hardlinks. Events for the other paths of the file were merged into this one.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    hardlinks: Option<Vec<PathBuf>>,
+
+    /// Whether the backend reported exactly this change, or Notify inferred it.
+    ///
+    /// Only present on inferred events, events without it were reported as they are.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    precision: Option<Precision>,
+
+    /// The intermediate paths of a rename resolved from a chain of renames.
+    ///
+    /// Only present on renames merged by [`resolve_rename_chains`](crate::resolve_rename_chains),
+    /// in the order the file passed them.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    rename_hops: Option<Vec<PathBuf>>,
+
+    /// The number of events known to be lost before a rescan event.
+    ///
+    /// Only present on events flagged [`Flag::Rescan`] whose source counts what it dropped, such
+    /// as the event queue of `Config::with_event_queue`. Backends whose OS doesn't report how
+    /// much overflowed leave it unset.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    dropped: Option<u64>,
+}
+
+/// Size and modification time of a path before and after a change.
+///
+/// This is only provided by backends which know both states, such as the
+/// [`PollWatcher`](crate::PollWatcher). The `old_*` fields are `None` for paths which didn't exist
+/// before, and the `new_*` fields are `None` for paths which don't exist anymore.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct MetadataDelta {
+    /// Size in bytes before the change.
+    pub old_size: Option<u64>,
+
+    /// Size in bytes after the change.
+    pub new_size: Option<u64>,
+
+    /// Modification time before the change.
+    pub old_mtime: Option<SystemTime>,
+
+    /// Modification time after the change.
+    pub new_mtime: Option<SystemTime>,
+
+    /// Which part of a modified file changed, if known.
+    ///
+    /// Only present if the [`PollWatcher`](crate::PollWatcher) is configured with
+    /// `Config::with_change_regions`.
+    pub region: Option<ChangeRegion>,
+}
+
+/// The part of a file changed by a modification, found by comparing the hashes of its first and
+/// last bytes.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[non_exhaustive]
+pub enum ChangeRegion {
+    /// Only the start of the file changed, for example a header was rewritten.
+    Head,
+
+    /// Only the end of the file changed, for example data was appended or truncated.
+    Tail,
+
+    /// Both ends of the file changed.
+    HeadAndTail,
+
+    /// Neither end of the file changed, the modification is somewhere in between.
+    Middle,
+}
+
+impl MetadataDelta {
+    /// Difference between the new and the old size, if both are known.
+    pub fn size_change(&self) -> Option<i128> {
+        match (self.old_size, self.new_size) {
+            (Some(old), Some(new)) => Some(new as i128 - old as i128),
+            _ => None,
+        }
+    }
 }
 
 impl EventAttributes {
@@ -451,6 +588,54 @@ impl EventAttributes {
         self.inner.as_ref().and_then(|inner| inner.process_id)
     }
 
+    /// Retrieves the metadata before and after the event, if known.
+    pub fn metadata_delta(&self) -> Option<&MetadataDelta> {
+        self.inner
+            .as_ref()
+            .and_then(|inner| inner.metadata_delta.as_ref())
+    }
+
+    /// Retrieves when the change happened according to the OS or filesystem, if known.
+    pub fn source_time(&self) -> Option<SystemTime> {
+        self.inner.as_ref().and_then(|inner| inner.source_time)
+    }
+
+    /// Retrieves the token of the watch the event originates from, if it has one.
+    pub fn watch_token(&self) -> Option<u64> {
+        self.inner.as_ref().and_then(|inner| inner.watch_token)
+    }
+
+    /// Retrieves the hash of the file's contents after the change, if computed.
+    pub fn content_hash(&self) -> Option<&[u8; 32]> {
+        self.inner
+            .as_ref()
+            .and_then(|inner| inner.content_hash.as_ref())
+    }
+
+    /// Retrieves the known paths of the file's hardlinks, if it has several.
+    pub fn hardlinks(&self) -> Option<&[PathBuf]> {
+        self.inner
+            .as_ref()
+            .and_then(|inner| inner.hardlinks.as_deref())
+    }
+
+    /// Retrieves whether the event was reported by the backend or inferred, if known.
+    pub fn precision(&self) -> Option<Precision> {
+        self.inner.as_ref().and_then(|inner| inner.precision)
+    }
+
+    /// Retrieves the intermediate paths of a resolved chain of renames.
+    pub fn rename_hops(&self) -> Option<&[PathBuf]> {
+        self.inner
+            .as_ref()
+            .and_then(|inner| inner.rename_hops.as_deref())
+    }
+
+    /// Retrieves the number of events lost before a rescan event, if known.
+    pub fn dropped(&self) -> Option<u64> {
+        self.inner.as_ref().and_then(|inner| inner.dropped)
+    }
+
     /// Sets the tracker.
     pub fn set_tracker(&mut self, tracker: usize) {
         self.inner_mut().tracker = Some(tracker);
@@ -471,18 +656,79 @@ impl EventAttributes {
         self.inner_mut().process_id = Some(process_id)
     }
 
+    /// Sets the metadata before and after the event.
+    pub fn set_metadata_delta(&mut self, delta: MetadataDelta) {
+        self.inner_mut().metadata_delta = Some(delta)
+    }
+
+    /// Sets when the change happened according to the OS or filesystem.
+    pub fn set_source_time(&mut self, time: SystemTime) {
+        self.inner_mut().source_time = Some(time)
+    }
+
+    /// Sets the token of the watch the event originates from.
+    pub fn set_watch_token(&mut self, token: u64) {
+        self.inner_mut().watch_token = Some(token)
+    }
+
+    /// Sets the hash of the file's contents after the change.
+    pub fn set_content_hash(&mut self, hash: [u8; 32]) {
+        self.inner_mut().content_hash = Some(hash)
+    }
+
+    /// Sets the known paths of the file's hardlinks.
+    pub fn set_hardlinks(&mut self, paths: Vec<PathBuf>) {
+        self.inner_mut().hardlinks = Some(paths)
+    }
+
+    /// Sets whether the event was reported by the backend or inferred.
+    pub fn set_precision(&mut self, precision: Precision) {
+        self.inner_mut().precision = Some(precision)
+    }
+
+    /// Sets the intermediate paths of a resolved chain of renames.
+    pub fn set_rename_hops(&mut self, paths: Vec<PathBuf>) {
+        self.inner_mut().rename_hops = Some(paths)
+    }
+
+    /// Sets the number of events lost before a rescan event.
+    pub fn set_dropped(&mut self, dropped: u64) {
+        self.inner_mut().dropped = Some(dropped)
+    }
+
     fn inner_mut(&mut self) -> &mut EventAttributesInner {
-        self.inner
-            .get_or_insert_with(|| Box::new(Default::default()))
+        self.inner.get_or_insert_with(Box::default)
     }
 }
 
+/// How exactly an event describes the change it reports.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+#[non_exhaustive]
+pub enum Precision {
+    /// The backend reported exactly this change.
+    Precise,
+
+    /// Notify inferred the change, by comparing scans of the filesystem like the
+    /// [`PollWatcher`](crate::PollWatcher), by listing entries which already existed like initial
+    /// scans, or by guessing the kind of a change the backend reported incompletely, like an entry
+    /// moved in from outside of the watches which is reported as created.
+    ///
+    /// The change may have been a different one, or may be reverted already. Consumers which need
+    /// certainty should check the filesystem again.
+    ///
+    /// Precisions added by newer versions of Notify are read as this one.
+    #[cfg_attr(feature = "serde", serde(other))]
+    Inferred,
+}
+
 /// Special Notify flag on the event.
 ///
 /// This attribute is used to flag certain kinds of events that Notify either marks or generates in
 /// particular ways.
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 #[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+#[non_exhaustive]
 pub enum Flag {
     /*
         /// Event notices are emitted by debounced watchers immediately after the _first_ event of that
@@ -505,6 +751,23 @@ pub enum Flag {
     /// that keeps an in-memory representation of the filesystem will need to care, and will need
     /// to refresh that representation directly from the filesystem.
     Rescan,
+
+    /// Initial scan notices are emitted by all watchers right after a path is watched, for every
+    /// entry that already exists there, if [`Config::with_initial_scan`] is enabled.
+    ///
+    /// They are `Create` events, but the entries may have been created at any point before. This
+    /// avoids the race between listing the existing entries and starting to watch for new ones.
+    ///
+    /// [`Config::with_initial_scan`]: crate::Config::with_initial_scan
+    InitialScan,
+
+    /// Suppressed notices are events for paths the application suppressed with a tagging
+    /// [`SuppressGuard`](crate::SuppressGuard), because it changed them itself.
+    Suppressed,
+
+    /// A flag added by a newer version of Notify, read from a serialized event.
+    #[cfg_attr(feature = "serde", serde(other))]
+    Other,
 }
 
 impl Event {
@@ -515,6 +778,21 @@ impl Event {
     pub fn need_rescan(&self) -> bool {
         matches!(self.flag(), Some(Flag::Rescan))
     }
+
+    /// Returns whether this event reports an entry which existed before a path was watched.
+    ///
+    /// See [`Flag::InitialScan`] for more information.
+    pub fn is_initial_scan(&self) -> bool {
+        matches!(self.flag(), Some(Flag::InitialScan))
+    }
+
+    /// Returns whether Notify inferred this event instead of the backend reporting it, in which
+    /// case the change should be verified on the filesystem if it matters.
+    ///
+    /// See [`Precision::Inferred`] for more information.
+    pub fn is_inferred(&self) -> bool {
+        matches!(self.attrs.precision(), Some(Precision::Inferred))
+    }
     /// Retrieves the tracker ID for an event directly, if present.
     pub fn tracker(&self) -> Option<usize> {
         self.attrs.tracker()
@@ -535,6 +813,46 @@ impl Event {
         self.attrs.source()
     }
 
+    /// Retrieves the metadata before and after the event directly, if known.
+    pub fn metadata_delta(&self) -> Option<&MetadataDelta> {
+        self.attrs.metadata_delta()
+    }
+
+    /// Retrieves when the change happened according to the OS or filesystem directly, if known.
+    pub fn source_time(&self) -> Option<SystemTime> {
+        self.attrs.source_time()
+    }
+
+    /// Retrieves the token of the watch the event originates from directly, if it has one.
+    pub fn watch_token(&self) -> Option<u64> {
+        self.attrs.watch_token()
+    }
+
+    /// Retrieves the hash of the file's contents after the change directly, if computed.
+    pub fn content_hash(&self) -> Option<&[u8; 32]> {
+        self.attrs.content_hash()
+    }
+
+    /// Retrieves the known paths of the file's hardlinks directly, if it has several.
+    pub fn hardlinks(&self) -> Option<&[PathBuf]> {
+        self.attrs.hardlinks()
+    }
+
+    /// Retrieves whether the event was reported by the backend or inferred, if known.
+    pub fn precision(&self) -> Option<Precision> {
+        self.attrs.precision()
+    }
+
+    /// Retrieves the intermediate paths of a resolved chain of renames directly.
+    pub fn rename_hops(&self) -> Option<&[PathBuf]> {
+        self.attrs.rename_hops()
+    }
+
+    /// Retrieves the number of events lost before a rescan event directly, if known.
+    pub fn dropped(&self) -> Option<u64> {
+        self.attrs.dropped()
+    }
+
     /// Creates a new `Event` given a kind.
     pub fn new(kind: EventKind) -> Self {
         Self {
@@ -588,6 +906,48 @@ impl Event {
         self.attrs.set_process_id(process_id);
         self
     }
+
+    /// Sets the metadata before and after the event.
+    pub fn set_metadata_delta(mut self, delta: MetadataDelta) -> Self {
+        self.attrs.set_metadata_delta(delta);
+        self
+    }
+
+    /// Sets when the change happened according to the OS or filesystem.
+    pub fn set_source_time(mut self, time: SystemTime) -> Self {
+        self.attrs.set_source_time(time);
+        self
+    }
+
+    /// Sets the token of the watch the event originates from.
+    pub fn set_watch_token(mut self, token: u64) -> Self {
+        self.attrs.set_watch_token(token);
+        self
+    }
+
+    /// Sets the hash of the file's contents after the change.
+    pub fn set_content_hash(mut self, hash: [u8; 32]) -> Self {
+        self.attrs.set_content_hash(hash);
+        self
+    }
+
+    /// Sets the known paths of the file's hardlinks.
+    pub fn set_hardlinks(mut self, paths: Vec<PathBuf>) -> Self {
+        self.attrs.set_hardlinks(paths);
+        self
+    }
+
+    /// Sets whether the event was reported by the backend or inferred.
+    pub fn set_precision(mut self, precision: Precision) -> Self {
+        self.attrs.set_precision(precision);
+        self
+    }
+
+    /// Sets the number of events lost before a rescan event.
+    pub fn set_dropped(mut self, dropped: u64) -> Self {
+        self.attrs.set_dropped(dropped);
+        self
+    }
 }
 
 impl fmt::Debug for Event {
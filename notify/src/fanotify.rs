@@ -0,0 +1,867 @@
+//! Watcher implementation for fanotify
+//!
+//! Recursive watches mark the whole filesystem of the watched path with
+//! `FAN_MARK_FILESYSTEM`, or its mount with `FAN_MARK_MOUNT` if
+//! [`Config::with_mount_marks`] is set, so one mark covers any number of directories, and none
+//! have to be registered or rescanned as the tree grows. Non-recursive watches mark the watched
+//! directory, or the directory of a watched file. The kernel reports a change with the handle of
+//! its directory and the name changed in it, the directory is opened from the handle to get its
+//! path, and the events of paths outside of the watches are dropped. Changes are reported with
+//! the id of the process making them.
+//!
+//! Marking a filesystem or a mount requires the `CAP_SYS_ADMIN` capability, opening directories
+//! from their handles `CAP_DAC_READ_SEARCH`, and reporting directory handles and names Linux 5.9.
+//! Mount marks only see the writes to files: the kernel doesn't report creations, removals and
+//! renames for them. Paths are reported as the kernel resolves them, without symbolic links, so
+//! the watched paths should be canonical. Renames are reported as their two halves, the kernel
+//! tells nothing to join them.
+
+use crate::audit::{Audit, Resource};
+use crate::delivery::{Delivery, DeliveryOptions, WatchTokens};
+use crate::event::*;
+use crate::registry::{self, WatchRegistry};
+use crate::scheduling::Scheduling;
+use crate::suppress::{SuppressGuard, Suppressions};
+use crate::{snapshot, Config, Error, EventHandler, RecursiveMode, Result, Watcher, WatcherKind};
+use std::collections::HashMap;
+use std::ffi::{c_void, CString, OsStr, OsString};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::mem;
+use std::os::raw::{c_char, c_int, c_uint};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::RawFd;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+
+const FANOTIFY: mio::Token = mio::Token(0);
+const WAKER: mio::Token = mio::Token(1);
+
+extern "C" {
+    fn fanotify_init(flags: c_uint, event_f_flags: c_uint) -> c_int;
+    fn fanotify_mark(
+        fanotify_fd: c_int,
+        flags: c_uint,
+        mask: u64,
+        dirfd: c_int,
+        pathname: *const c_char,
+    ) -> c_int;
+    fn open_by_handle_at(mount_fd: c_int, handle: *mut c_void, flags: c_int) -> c_int;
+}
+
+const FAN_CLOEXEC: c_uint = 0x1;
+const FAN_NONBLOCK: c_uint = 0x2;
+const FAN_REPORT_DFID_NAME: c_uint = 0xc00;
+
+const FAN_MARK_ADD: c_uint = 0x1;
+const FAN_MARK_REMOVE: c_uint = 0x2;
+const FAN_MARK_INODE: c_uint = 0x0;
+const FAN_MARK_MOUNT: c_uint = 0x10;
+const FAN_MARK_FILESYSTEM: c_uint = 0x100;
+
+const FAN_MODIFY: u64 = 0x2;
+const FAN_ATTRIB: u64 = 0x4;
+const FAN_CLOSE_WRITE: u64 = 0x8;
+const FAN_MOVED_FROM: u64 = 0x40;
+const FAN_MOVED_TO: u64 = 0x80;
+const FAN_CREATE: u64 = 0x100;
+const FAN_DELETE: u64 = 0x200;
+const FAN_Q_OVERFLOW: u64 = 0x4000;
+const FAN_EVENT_ON_CHILD: u64 = 0x0800_0000;
+const FAN_ONDIR: u64 = 0x4000_0000;
+
+/// The events of filesystem marks
+const FILESYSTEM_EVENTS: u64 = FAN_CREATE
+    | FAN_DELETE
+    | FAN_MOVED_FROM
+    | FAN_MOVED_TO
+    | FAN_MODIFY
+    | FAN_ATTRIB
+    | FAN_CLOSE_WRITE
+    | FAN_ONDIR;
+/// The events of mount marks, which can't report changes of directory entries
+const MOUNT_EVENTS: u64 = FAN_MODIFY | FAN_CLOSE_WRITE;
+/// The events of the marks of single directories
+const INODE_EVENTS: u64 = FILESYSTEM_EVENTS | FAN_EVENT_ON_CHILD;
+
+const FANOTIFY_METADATA_VERSION: u8 = 3;
+const METADATA_LEN: usize = 24;
+const INFO_HEADER_LEN: usize = 4;
+const FAN_EVENT_INFO_TYPE_DFID_NAME: u8 = 2;
+const FAN_EVENT_INFO_TYPE_DFID: u8 = 3;
+
+/// The size of the buffer events are read into, fitting many events with names of the longest
+/// length.
+const BUFFER_SIZE: usize = 64 * 1024;
+
+/// The id of a filesystem, as `statfs` reports it
+type Fsid = [i32; 2];
+
+/// A file descriptor, closed when dropped.
+struct Fd(RawFd);
+
+impl Drop for Fd {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.0) };
+    }
+}
+
+/// The directory of a change, and the name changed in it
+#[derive(Debug, PartialEq)]
+struct Directory {
+    fsid: Fsid,
+    /// A `struct file_handle`, to open the directory with `open_by_handle_at`
+    handle: Vec<u8>,
+    name: OsString,
+}
+
+/// An event read from the fanotify descriptor
+#[derive(Debug, PartialEq)]
+struct RawEvent {
+    mask: u64,
+    pid: i32,
+    directory: Option<Directory>,
+}
+
+fn u16_at(data: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_ne_bytes([*data.get(at)?, *data.get(at + 1)?]))
+}
+
+fn u32_at(data: &[u8], at: usize) -> Option<u32> {
+    let bytes = data.get(at..at + 4)?;
+    Some(u32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn u64_at(data: &[u8], at: usize) -> Option<u64> {
+    Some(u64::from_ne_bytes(data.get(at..at + 8)?.try_into().ok()?))
+}
+
+/// Split the data read from the fanotify descriptor into its events.
+fn parse_events(mut data: &[u8]) -> Vec<RawEvent> {
+    let mut events = Vec::new();
+    while data.len() >= METADATA_LEN {
+        let len = u32_at(data, 0).unwrap_or(0) as usize;
+        if len < METADATA_LEN || len > data.len() {
+            break;
+        }
+        let (event, rest) = data.split_at(len);
+        data = rest;
+        if event[4] != FANOTIFY_METADATA_VERSION {
+            continue;
+        }
+        // the events of groups reporting handles carry no file descriptor.
+        let mut raw = RawEvent {
+            mask: u64_at(event, 8).unwrap_or(0),
+            pid: u32_at(event, 20).unwrap_or(0) as i32,
+            directory: None,
+        };
+
+        let mut info = &event[(u16_at(event, 6).unwrap_or(0) as usize).min(len)..];
+        while info.len() >= INFO_HEADER_LEN {
+            let info_len = u16_at(info, 2).unwrap_or(0) as usize;
+            if info_len < INFO_HEADER_LEN || info_len > info.len() {
+                break;
+            }
+            let (record, rest) = info.split_at(info_len);
+            info = rest;
+            if record[0] == FAN_EVENT_INFO_TYPE_DFID_NAME || record[0] == FAN_EVENT_INFO_TYPE_DFID {
+                raw.directory = parse_directory(record);
+            }
+        }
+        events.push(raw);
+    }
+    events
+}
+
+/// Parse an info record of the directory of a change: a header, the id of the filesystem, a
+/// `struct file_handle` and the name, terminated by a zero, if the record has one.
+fn parse_directory(record: &[u8]) -> Option<Directory> {
+    let fsid = [u32_at(record, 4)? as i32, u32_at(record, 8)? as i32];
+    let handle_end = 20 + u32_at(record, 12)? as usize;
+    let handle = record.get(12..handle_end)?.to_vec();
+    let name = record[handle_end..]
+        .split(|&b| b == 0)
+        .next()
+        .unwrap_or_default();
+    Some(Directory {
+        fsid,
+        handle,
+        name: OsStr::from_bytes(name).to_owned(),
+    })
+}
+
+/// The events of a change of `path`.
+fn raw_events(raw: &RawEvent, path: &Path) -> Vec<Event> {
+    let folder = raw.mask & FAN_ONDIR != 0;
+    let kinds = [
+        (
+            FAN_CREATE,
+            EventKind::Create(if folder {
+                CreateKind::Folder
+            } else {
+                CreateKind::File
+            }),
+        ),
+        (
+            FAN_MOVED_TO,
+            EventKind::Modify(ModifyKind::Name(RenameMode::To)),
+        ),
+        (
+            FAN_MODIFY,
+            EventKind::Modify(ModifyKind::Data(DataChange::Any)),
+        ),
+        (
+            FAN_ATTRIB,
+            EventKind::Modify(ModifyKind::Metadata(MetadataKind::Any)),
+        ),
+        (
+            FAN_CLOSE_WRITE,
+            EventKind::Access(AccessKind::Close(AccessMode::Write)),
+        ),
+        (
+            FAN_MOVED_FROM,
+            EventKind::Modify(ModifyKind::Name(RenameMode::From)),
+        ),
+        (
+            FAN_DELETE,
+            EventKind::Remove(if folder {
+                RemoveKind::Folder
+            } else {
+                RemoveKind::File
+            }),
+        ),
+    ];
+    kinds
+        .iter()
+        .filter(|(bit, _)| raw.mask & bit != 0)
+        .map(|(_, kind)| {
+            let event = Event::new(kind.clone()).add_path(path.to_owned());
+            // processes of other pid namespaces are reported as 0
+            match raw.pid {
+                pid if pid > 0 => event.set_process_id(pid as u32),
+                _ => event,
+            }
+        })
+        .collect()
+}
+
+/// The `statfs` id of the filesystem of `path`.
+fn fsid(path: &Path) -> io::Result<Fsid> {
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    let mut stat: libc::statfs = unsafe { mem::zeroed() };
+    if unsafe { libc::statfs(path.as_ptr(), &mut stat) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { mem::transmute::<libc::fsid_t, Fsid>(stat.f_fsid) })
+}
+
+/// Open the directory `path` to resolve the handles of its filesystem, which takes a descriptor
+/// opened for reading.
+fn open_directory(path: &Path) -> io::Result<Fd> {
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    let flags = libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC;
+    let fd = unsafe { libc::open(path.as_ptr(), flags) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(Fd(fd))
+}
+
+/// The path of the file of `handle`, on the filesystem of `mount`.
+fn resolve(mount: &Fd, handle: &[u8]) -> io::Result<PathBuf> {
+    let mut handle = handle.to_vec();
+    let fd = unsafe {
+        open_by_handle_at(
+            mount.0,
+            handle.as_mut_ptr().cast(),
+            libc::O_PATH | libc::O_CLOEXEC,
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let fd = Fd(fd);
+    fs::read_link(format!("/proc/self/fd/{}", fd.0))
+}
+
+/// The error of a call which the process lacks the capabilities or the kernel for.
+fn access_error(e: io::Error) -> Error {
+    match e.raw_os_error() {
+        Some(libc::EPERM) => Error::generic(
+            "The fanotify backend requires the CAP_SYS_ADMIN and CAP_DAC_READ_SEARCH capabilities.",
+        ),
+        Some(libc::EINVAL) => Error::generic("The fanotify backend requires Linux 5.9 or newer."),
+        _ => Error::io(e),
+    }
+}
+
+/// What a mark is placed on
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Mark {
+    Filesystem(Fsid),
+    /// The mount of a device
+    Mount(u64),
+    Inode(PathBuf),
+}
+
+impl Mark {
+    fn flags(&self) -> (c_uint, u64) {
+        match self {
+            Mark::Filesystem(_) => (FAN_MARK_FILESYSTEM, FILESYSTEM_EVENTS),
+            Mark::Mount(_) => (FAN_MARK_MOUNT, MOUNT_EVENTS),
+            Mark::Inode(_) => (FAN_MARK_INODE, INODE_EVENTS),
+        }
+    }
+}
+
+/// The mark a watch uses, and the path it is placed with
+#[derive(Debug)]
+struct WatchMark {
+    mark: Mark,
+    path: PathBuf,
+    fsid: Fsid,
+}
+
+/// The parts of a watcher shared with its event loop.
+struct Shared {
+    fanotify: Fd,
+    registry: Mutex<WatchRegistry>,
+    delivery: Mutex<Delivery>,
+    /// A file of every watched filesystem, to open the directories of its handles
+    mounts: Mutex<HashMap<Fsid, Fd>>,
+}
+
+impl Shared {
+    fn registry(&self) -> MutexGuard<'_, WatchRegistry> {
+        self.registry.lock().unwrap_or_else(|p| p.into_inner())
+    }
+
+    fn delivery(&self) -> MutexGuard<'_, Delivery> {
+        self.delivery.lock().unwrap_or_else(|p| p.into_inner())
+    }
+
+    fn mounts(&self) -> MutexGuard<'_, HashMap<Fsid, Fd>> {
+        self.mounts.lock().unwrap_or_else(|p| p.into_inner())
+    }
+}
+
+/// Reads the events of the watcher's marks, until woken.
+struct EventLoop {
+    shared: Arc<Shared>,
+    poll: mio::Poll,
+    buffer: Vec<u8>,
+    audit: Audit,
+    scheduling: Scheduling,
+}
+
+impl EventLoop {
+    fn run(mut self) {
+        let thread = thread::current().id();
+        self.audit.acquire(Resource::Thread, thread);
+        let _ = self.scheduling.apply();
+        let mut events = mio::Events::with_capacity(16);
+        'run: loop {
+            if let Err(e) = self.poll.poll(&mut events, None) {
+                if e.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                self.shared.delivery().handle_event(Err(Error::io(e)));
+                break;
+            }
+            for event in &events {
+                match event.token() {
+                    WAKER => break 'run,
+                    FANOTIFY => self.handle_events(),
+                    _ => unreachable!(),
+                }
+            }
+        }
+        self.audit.release(Resource::Thread, thread);
+    }
+
+    fn handle_events(&mut self) {
+        loop {
+            let len = unsafe {
+                libc::read(
+                    self.shared.fanotify.0,
+                    self.buffer.as_mut_ptr().cast(),
+                    self.buffer.len(),
+                )
+            };
+            if len == 0 {
+                return;
+            }
+            if len < 0 {
+                let e = io::Error::last_os_error();
+                match e.kind() {
+                    io::ErrorKind::WouldBlock => return,
+                    io::ErrorKind::Interrupted => continue,
+                    _ => {
+                        self.shared.delivery().handle_event(Err(Error::io(e)));
+                        return;
+                    }
+                }
+            }
+            let events = self.events(parse_events(&self.buffer[..len as usize]));
+            let mut delivery = self.shared.delivery();
+            for event in events {
+                delivery.handle_event(Ok(event));
+            }
+        }
+    }
+
+    /// The events of the watched paths.
+    fn events(&self, raw: Vec<RawEvent>) -> Vec<Event> {
+        let mut changes = Vec::new();
+        {
+            let mounts = self.shared.mounts();
+            for raw in raw {
+                if raw.mask & FAN_Q_OVERFLOW != 0 {
+                    changes.push((raw, None));
+                    continue;
+                }
+                let directory = match &raw.directory {
+                    Some(directory) => directory,
+                    None => continue,
+                };
+                // the directory may be gone already, or belong to a filesystem unwatched since.
+                let path = match mounts
+                    .get(&directory.fsid)
+                    .map(|m| resolve(m, &directory.handle))
+                {
+                    Some(Ok(path)) => path,
+                    _ => continue,
+                };
+                let path = match directory.name.as_bytes() {
+                    b"" | b"." => path,
+                    _ => path.join(&directory.name),
+                };
+                changes.push((raw, Some(path)));
+            }
+        }
+
+        let registry = self.shared.registry();
+        let mut events = Vec::new();
+        for (raw, path) in changes {
+            match path {
+                None => events.push(Event::new(EventKind::Other).set_flag(Flag::Rescan)),
+                Some(path) if registry.is_watched(&path) => events.extend(raw_events(&raw, &path)),
+                Some(_) => {}
+            }
+        }
+        events
+    }
+}
+
+/// Watcher implementation based on fanotify, see the [module](self) docs
+pub struct FanotifyWatcher {
+    shared: Arc<Shared>,
+    mount_marks: bool,
+    /// The marks placed, with the path they were placed with and the number of watches using them
+    marks: HashMap<Mark, (PathBuf, usize)>,
+    /// The number of watches of every filesystem in the mounts of the event loop
+    filesystems: HashMap<Fsid, usize>,
+    watches: HashMap<PathBuf, WatchMark>,
+    waker: mio::Waker,
+    thread: Option<JoinHandle<()>>,
+    tokens: WatchTokens,
+    options: DeliveryOptions,
+    suppressions: Suppressions,
+    initial_scan: bool,
+    audit: Audit,
+}
+
+impl fmt::Debug for FanotifyWatcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FanotifyWatcher")
+            .field("mount_marks", &self.mount_marks)
+            .field("watches", &self.watches)
+            .field("registry", &*self.shared.registry())
+            .field("initial_scan", &self.initial_scan)
+            .finish_non_exhaustive()
+    }
+}
+
+impl FanotifyWatcher {
+    /// The mark covering a watch of `path`.
+    fn watch_mark(&self, path: &Path, recursive_mode: RecursiveMode) -> io::Result<WatchMark> {
+        let fsid = fsid(path)?;
+        let (mark, path) = if !path.is_dir() {
+            let parent = path.parent().unwrap_or(path);
+            (Mark::Inode(parent.to_owned()), parent)
+        } else if !recursive_mode.is_recursive() {
+            (Mark::Inode(path.to_owned()), path)
+        } else if self.mount_marks {
+            (Mark::Mount(fs::metadata(path)?.dev()), path)
+        } else {
+            (Mark::Filesystem(fsid), path)
+        };
+        Ok(WatchMark {
+            mark,
+            path: path.to_owned(),
+            fsid,
+        })
+    }
+
+    fn mark(&self, flags: c_uint, mask: u64, path: &Path) -> io::Result<()> {
+        let path = CString::new(path.as_os_str().as_bytes())?;
+        let code = unsafe {
+            fanotify_mark(
+                self.shared.fanotify.0,
+                flags,
+                mask,
+                libc::AT_FDCWD,
+                path.as_ptr(),
+            )
+        };
+        if code != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Place the mark of a watch, unless another watch placed it already.
+    fn acquire(&mut self, watch: &WatchMark) -> io::Result<()> {
+        if !self.marks.contains_key(&watch.mark) {
+            let (flags, mask) = watch.mark.flags();
+            self.mark(FAN_MARK_ADD | flags, mask, &watch.path)?;
+            self.audit.acquire(Resource::Handle, &watch.path);
+            self.marks
+                .insert(watch.mark.clone(), (watch.path.clone(), 0));
+        }
+        if !self.filesystems.contains_key(&watch.fsid) {
+            let mount = match open_directory(&watch.path) {
+                Ok(mount) => mount,
+                Err(e) => {
+                    self.release_mark(&watch.mark)?;
+                    return Err(e);
+                }
+            };
+            self.shared.mounts().insert(watch.fsid, mount);
+        }
+        if let Some((_, count)) = self.marks.get_mut(&watch.mark) {
+            *count += 1;
+        }
+        *self.filesystems.entry(watch.fsid).or_insert(0) += 1;
+        Ok(())
+    }
+
+    /// Remove the mark of a watch, unless other watches use it.
+    fn release(&mut self, watch: &WatchMark) -> io::Result<()> {
+        if let Some(count) = self.filesystems.get_mut(&watch.fsid) {
+            *count -= 1;
+            if *count == 0 {
+                self.filesystems.remove(&watch.fsid);
+                self.shared.mounts().remove(&watch.fsid);
+            }
+        }
+        if let Some((_, count)) = self.marks.get_mut(&watch.mark) {
+            *count = count.saturating_sub(1);
+            if *count > 0 {
+                return Ok(());
+            }
+        }
+        self.release_mark(&watch.mark)
+    }
+
+    fn release_mark(&mut self, mark: &Mark) -> io::Result<()> {
+        if let Some((path, _)) = self.marks.remove(mark) {
+            self.audit.release(Resource::Handle, &path);
+            let (flags, mask) = mark.flags();
+            match self.mark(FAN_MARK_REMOVE | flags, mask, &path) {
+                // the kernel removed the mark of a deleted inode already.
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Watch `path` with the mark of `recursive_mode`, releasing the mark of its old watch.
+    fn replace_watch(&mut self, path: &Path, recursive_mode: RecursiveMode) -> Result<()> {
+        let error = |e| access_error(e).add_path(path.to_owned());
+        let watch = self.watch_mark(path, recursive_mode).map_err(error)?;
+        self.acquire(&watch).map_err(error)?;
+        if let Some(old) = self.watches.insert(path.to_owned(), watch) {
+            self.release(&old).map_err(error)?;
+        }
+        Ok(())
+    }
+
+    fn watch_inner(&mut self, path: &Path, recursive_mode: RecursiveMode) -> Result<()> {
+        let path = registry::absolute(path);
+        if !path.exists() {
+            return Err(Error::path_not_found().add_path(path));
+        }
+        self.shared.registry().plan_watch(&path, recursive_mode)?;
+        self.replace_watch(&path, recursive_mode)?;
+        self.shared.registry().insert(&path, recursive_mode);
+
+        if self.initial_scan {
+            let mut delivery = self.shared.delivery();
+            for event in snapshot::initial_scan_events(&path, recursive_mode) {
+                delivery.handle_event(Ok(event));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Watcher for FanotifyWatcher {
+    /// Create a new watcher, with a fanotify group reporting the handles of directories.
+    fn new<F: EventHandler>(event_handler: F, config: Config) -> Result<Self> {
+        let fd = unsafe {
+            fanotify_init(
+                FAN_CLOEXEC | FAN_NONBLOCK | FAN_REPORT_DFID_NAME,
+                (libc::O_RDONLY | libc::O_CLOEXEC | libc::O_LARGEFILE) as c_uint,
+            )
+        };
+        if fd < 0 {
+            return Err(access_error(io::Error::last_os_error()));
+        }
+        let fanotify = Fd(fd);
+
+        let poll = mio::Poll::new()?;
+        let waker = mio::Waker::new(poll.registry(), WAKER)?;
+        poll.registry().register(
+            &mut mio::unix::SourceFd(&fanotify.0),
+            FANOTIFY,
+            mio::Interest::READABLE,
+        )?;
+
+        let delivery = Delivery::new(event_handler, &config);
+        let tokens = delivery.tokens();
+        let options = delivery.options();
+        let suppressions = delivery.suppressions();
+        let shared = Arc::new(Shared {
+            fanotify,
+            registry: Mutex::new(WatchRegistry::new(config.watch_overlap())),
+            delivery: Mutex::new(delivery),
+            mounts: Mutex::new(HashMap::new()),
+        });
+        let audit = Audit::new("fanotify", &config);
+
+        let event_loop = EventLoop {
+            shared: shared.clone(),
+            poll,
+            buffer: vec![0; BUFFER_SIZE],
+            audit: audit.clone(),
+            scheduling: Scheduling::new(&config),
+        };
+        let thread = thread::Builder::new()
+            .name("notify-rs fanotify loop".to_string())
+            .spawn(move || event_loop.run())?;
+
+        Ok(FanotifyWatcher {
+            shared,
+            mount_marks: config.mount_marks(),
+            marks: HashMap::new(),
+            filesystems: HashMap::new(),
+            watches: HashMap::new(),
+            waker,
+            thread: Some(thread),
+            tokens,
+            options,
+            suppressions,
+            initial_scan: config.initial_scan(),
+            audit,
+        })
+    }
+
+    fn watch(&mut self, path: &Path, recursive_mode: RecursiveMode) -> Result<()> {
+        self.watch_inner(path, recursive_mode)
+    }
+
+    fn watch_with_token(
+        &mut self,
+        path: &Path,
+        recursive_mode: RecursiveMode,
+        token: u64,
+    ) -> Result<()> {
+        // registered first, so that the initial scan carries the token already.
+        self.tokens.insert(path, token);
+        if let Err(e) = self.watch_inner(path, recursive_mode) {
+            self.tokens.remove(path);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn unwatch(&mut self, path: &Path) -> Result<()> {
+        {
+            let mut registry = self.shared.registry();
+            if registry.recursive_mode(path).is_none() {
+                return Err(Error::watch_not_found().add_path(path.to_owned()));
+            }
+            registry.remove(path);
+        }
+        self.tokens.remove(path);
+        match self.watches.remove(&registry::absolute(path)) {
+            Some(watch) => self
+                .release(&watch)
+                .map_err(|e| access_error(e).add_path(path.to_owned())),
+            None => Ok(()),
+        }
+    }
+
+    fn set_recursive_mode(&mut self, path: &Path, recursive_mode: RecursiveMode) -> Result<()> {
+        self.shared
+            .registry()
+            .plan_set_recursive_mode(path, recursive_mode)?;
+        self.replace_watch(&registry::absolute(path), recursive_mode)?;
+        self.shared.registry().insert(path, recursive_mode);
+        Ok(())
+    }
+
+    fn suppress(&self, path: &Path) -> Result<SuppressGuard> {
+        Ok(self.suppressions.guard(path))
+    }
+
+    fn is_watched(&self, path: &Path) -> bool {
+        self.shared.registry().is_watched(path)
+    }
+
+    fn configure(&mut self, config: Config) -> Result<bool> {
+        self.options.configure(&config);
+        Ok(true)
+    }
+
+    fn kind() -> WatcherKind {
+        WatcherKind::Fanotify
+    }
+}
+
+impl Drop for FanotifyWatcher {
+    fn drop(&mut self) {
+        // closing the fanotify descriptor removes the marks.
+        for (path, _) in self.marks.values() {
+            self.audit.release(Resource::Handle, path);
+        }
+        let _ = self.waker.wake();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[test]
+fn events_are_parsed_from_their_records() {
+    let event = |mask: u64, pid: i32, info: &[u8]| {
+        let mut event = Vec::new();
+        event.extend_from_slice(&((METADATA_LEN + info.len()) as u32).to_ne_bytes());
+        event.extend_from_slice(&[FANOTIFY_METADATA_VERSION, 0]);
+        event.extend_from_slice(&(METADATA_LEN as u16).to_ne_bytes());
+        event.extend_from_slice(&mask.to_ne_bytes());
+        event.extend_from_slice(&(-1i32).to_ne_bytes());
+        event.extend_from_slice(&pid.to_ne_bytes());
+        event.extend_from_slice(info);
+        event
+    };
+    let handle = [
+        8u32.to_ne_bytes(),
+        1u32.to_ne_bytes(),
+        [1, 2, 3, 4],
+        [5, 6, 7, 8],
+    ]
+    .concat();
+    let mut info = vec![FAN_EVENT_INFO_TYPE_DFID_NAME, 0];
+    let name = b"new dir\0\0\0\0";
+    info.extend_from_slice(&((4 + 8 + handle.len() + name.len()) as u16).to_ne_bytes());
+    info.extend_from_slice(&[7i32.to_ne_bytes(), 9i32.to_ne_bytes()].concat());
+    info.extend_from_slice(&handle);
+    info.extend_from_slice(name);
+
+    let data = [
+        event(FAN_CREATE | FAN_ONDIR, 42, &info),
+        event(FAN_Q_OVERFLOW, 0, &[]),
+    ]
+    .concat();
+    let events = parse_events(&data);
+    assert_eq!(
+        events,
+        [
+            RawEvent {
+                mask: FAN_CREATE | FAN_ONDIR,
+                pid: 42,
+                directory: Some(Directory {
+                    fsid: [7, 9],
+                    handle,
+                    name: OsString::from("new dir"),
+                }),
+            },
+            RawEvent {
+                mask: FAN_Q_OVERFLOW,
+                pid: 0,
+                directory: None,
+            },
+        ]
+    );
+
+    let converted = raw_events(&events[0], Path::new("/w/new dir"));
+    assert_eq!(converted.len(), 1);
+    assert_eq!(converted[0].kind, EventKind::Create(CreateKind::Folder));
+    assert_eq!(converted[0].paths, [PathBuf::from("/w/new dir")]);
+    assert_eq!(converted[0].attrs.process_id(), Some(42));
+
+    // merged events are reported in the order of their changes, truncated data is dropped
+    let merged = RawEvent {
+        mask: FAN_CREATE | FAN_MODIFY | FAN_CLOSE_WRITE,
+        pid: 0,
+        directory: None,
+    };
+    let kinds: Vec<_> = raw_events(&merged, Path::new("/w/f"))
+        .into_iter()
+        .map(|event| event.kind)
+        .collect();
+    assert_eq!(
+        kinds,
+        [
+            EventKind::Create(CreateKind::File),
+            EventKind::Modify(ModifyKind::Data(DataChange::Any)),
+            EventKind::Access(AccessKind::Close(AccessMode::Write)),
+        ]
+    );
+    assert!(parse_events(&data[..data.len() - 1]).len() == 1);
+}
+
+#[test]
+fn filesystem_marks_report_the_changes_of_the_watched_paths() {
+    use std::time::Duration;
+
+    let (tx, rx) = std::sync::mpsc::channel();
+    let mut watcher = match FanotifyWatcher::new(tx, Config::default()) {
+        Ok(watcher) => watcher,
+        // the test runs without CAP_SYS_ADMIN, or on an older kernel
+        Err(_) => return,
+    };
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path().canonicalize().unwrap();
+    fs::create_dir(root.join("a")).unwrap();
+    if watcher.watch(&root, RecursiveMode::Recursive).is_err() {
+        // the filesystem can't be marked, or its handles can't be opened
+        return;
+    }
+    let outside = tempfile::tempdir().unwrap();
+    fs::write(outside.path().join("file"), b"x").unwrap();
+    let file = root.join("a").join("file");
+    fs::write(&file, b"x").unwrap();
+
+    let mut created = None;
+    while let Ok(event) = rx.recv_timeout(Duration::from_secs(1)) {
+        let event = event.unwrap();
+        assert!(event.paths.iter().all(|path| path.starts_with(&root)));
+        if event.kind == EventKind::Create(CreateKind::File) {
+            created = Some(event);
+            break;
+        }
+    }
+    let created = created.expect("no create event");
+    assert_eq!(created.paths, [file]);
+    assert_eq!(created.attrs.process_id(), Some(std::process::id()));
+
+    watcher.unwatch(&root).unwrap();
+    assert!(watcher.marks.is_empty() && watcher.shared.mounts().is_empty());
+}
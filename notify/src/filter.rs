@@ -0,0 +1,368 @@
+//! Matching events by kind and path
+
+use crate::{registry, Error, Event, EventKind};
+#[cfg(feature = "serde")]
+use serde::Deserialize;
+use std::path::{Component, Path, PathBuf};
+
+bitflags::bitflags! {
+    /// A set of top-level [EventKind]s, for [EventFilter::kinds].
+    pub struct EventKinds: u8 {
+        /// [EventKind::Any]
+        const ANY = 0b0000_0001;
+        /// [EventKind::Access]
+        const ACCESS = 0b0000_0010;
+        /// [EventKind::Create]
+        const CREATE = 0b0000_0100;
+        /// [EventKind::Modify]
+        const MODIFY = 0b0000_1000;
+        /// [EventKind::Remove]
+        const REMOVE = 0b0001_0000;
+        /// [EventKind::Other]
+        const OTHER = 0b0010_0000;
+    }
+}
+
+impl EventKinds {
+    /// The set containing just the top-level kind of `kind`.
+    pub fn of(kind: &EventKind) -> Self {
+        match kind {
+            EventKind::Any => Self::ANY,
+            EventKind::Access(_) => Self::ACCESS,
+            EventKind::Create(_) => Self::CREATE,
+            EventKind::Modify(_) => Self::MODIFY,
+            EventKind::Remove(_) => Self::REMOVE,
+            EventKind::Other => Self::OTHER,
+        }
+    }
+}
+
+/// The temporary, swap and backup files an editor writes next to the files it edits.
+///
+/// Select them with [Config::with_editor_noise](crate::Config::with_editor_noise) or
+/// [EventFilter::exclude_editor_noise] to drop their events. The lists are maintained here, so
+/// that applications don't have to keep their own.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+#[non_exhaustive]
+pub enum EditorNoise {
+    /// Vim's `.file.swp` swap files, its `4913` write test and its `file~` backups
+    Vim,
+    /// Emacs' `#file#` auto-save files, its `.#file` locks and its `file~` backups
+    Emacs,
+    /// The `file___jb_tmp___` and `file___jb_old___` files of JetBrains IDEs' safe writes
+    #[cfg_attr(feature = "serde", serde(rename = "jetbrains"))]
+    JetBrains,
+    /// The `file.crswap` files VS Code writes through in web browsers
+    #[cfg_attr(feature = "serde", serde(rename = "vscode"))]
+    VsCode,
+    /// Kate's `.file.kate-swp` swap files
+    Kate,
+    /// The `.goutputstream-*` files of gedit's and other GTK applications' safe writes
+    Gedit,
+}
+
+impl EditorNoise {
+    /// All editors
+    pub const ALL: [EditorNoise; 6] = [
+        EditorNoise::Vim,
+        EditorNoise::Emacs,
+        EditorNoise::JetBrains,
+        EditorNoise::VsCode,
+        EditorNoise::Kate,
+        EditorNoise::Gedit,
+    ];
+
+    /// The patterns of the file names, see [EventFilter::glob] for the syntax.
+    pub fn globs(self) -> &'static [&'static str] {
+        match self {
+            EditorNoise::Vim => &["*.swp", "*.swo", "*.swn", "*.swx", "4913", "*~"],
+            EditorNoise::Emacs => &["#*#", ".#*", "*~"],
+            EditorNoise::JetBrains => &["*___jb_tmp___", "*___jb_old___", "*___jb_bak___"],
+            EditorNoise::VsCode => &["*.crswap"],
+            EditorNoise::Kate => &["*.kate-swp"],
+            EditorNoise::Gedit => &[".goutputstream-*"],
+        }
+    }
+
+    /// Whether `path` is one of the editor's files.
+    pub fn matches(self, path: &Path) -> bool {
+        let name = match path.file_name() {
+            Some(name) => name.to_string_lossy(),
+            None => return false,
+        };
+        self.globs()
+            .iter()
+            .any(|glob| Glob::matches_component(glob, &name))
+    }
+}
+
+impl std::str::FromStr for EditorNoise {
+    type Err = Error;
+
+    /// Parses the names `vim`, `emacs`, `jetbrains`, `vscode`, `kate` and `gedit`, ignoring case.
+    fn from_str(s: &str) -> crate::Result<Self> {
+        Ok(match s.to_ascii_lowercase().as_str() {
+            "vim" => EditorNoise::Vim,
+            "emacs" => EditorNoise::Emacs,
+            "jetbrains" => EditorNoise::JetBrains,
+            "vscode" => EditorNoise::VsCode,
+            "kate" => EditorNoise::Kate,
+            "gedit" => EditorNoise::Gedit,
+            _ => return Err(Error::generic(&format!("unknown editor {:?}", s))),
+        })
+    }
+}
+
+/// Matches events by their kind and paths.
+///
+/// ```
+/// # use std::path::{Path, PathBuf};
+/// use notify::event::{CreateKind, Event, EventKind};
+/// use notify::filter::{EventFilter, EventKinds};
+///
+/// let filter = EventFilter::new()
+///     .kinds(EventKinds::CREATE | EventKinds::REMOVE)
+///     .glob("**/*.toml")
+///     .under("/project");
+///
+/// let created = Event::new(EventKind::Create(CreateKind::File));
+/// assert!(filter.matches(&created.clone().add_path(PathBuf::from("/project/a/Cargo.toml"))));
+/// assert!(!filter.matches(&created.add_path(PathBuf::from("/project/a/main.rs"))));
+/// ```
+///
+/// An event matches if its kind is one of the [kinds](EventFilter::kinds), and one of its paths
+/// matches one of the [globs](EventFilter::glob), is [under](EventFilter::under) one of the given
+/// paths and doesn't match any [excluded glob](EventFilter::exclude_glob). Conditions which
+/// weren't set match everything, so the empty filter matches all events, including those without
+/// paths.
+///
+/// Use it as a predicate with [EventFilter::matches], or give it to a watcher with
+/// [Config::with_event_filter](crate::Config::with_event_filter) to drop the events which don't
+/// match before they reach the event handler.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct EventFilter {
+    kinds: Option<EventKinds>,
+    globs: Vec<Glob>,
+    excluded: Vec<Glob>,
+    under: Vec<PathBuf>,
+}
+
+impl EventFilter {
+    /// A filter matching all events.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only match events of these kinds.
+    ///
+    /// Replaces the kinds set before.
+    pub fn kinds(mut self, kinds: EventKinds) -> Self {
+        self.kinds = Some(kinds);
+        self
+    }
+
+    /// Match paths matching `pattern`, in addition to the globs added before.
+    ///
+    /// Patterns are matched against whole path components: `*` matches any part of a component,
+    /// `?` any single character of it, and a `**` component any number of components.
+    /// Patterns starting with `/` are matched against the whole absolute path, others against
+    /// its end, so `*.toml` and `**/*.toml` are the same.
+    pub fn glob(mut self, pattern: &str) -> Self {
+        self.globs.push(Glob::new(pattern));
+        self
+    }
+
+    /// Don't match paths matching `pattern`, see [EventFilter::glob] for the syntax.
+    pub fn exclude_glob(mut self, pattern: &str) -> Self {
+        self.excluded.push(Glob::new(pattern));
+        self
+    }
+
+    /// Don't match the files of `editor`, see [EditorNoise].
+    pub fn exclude_editor_noise(mut self, editor: EditorNoise) -> Self {
+        self.excluded
+            .extend(editor.globs().iter().map(|glob| Glob::new(glob)));
+        self
+    }
+
+    /// Match paths below `path` or `path` itself, in addition to the paths added before.
+    ///
+    /// Relative paths are resolved against the current directory now.
+    pub fn under<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.under.push(registry::absolute(path.as_ref()));
+        self
+    }
+
+    /// Whether `event` matches this filter.
+    pub fn matches(&self, event: &Event) -> bool {
+        if let Some(kinds) = self.kinds {
+            if !kinds.intersects(EventKinds::of(&event.kind)) {
+                return false;
+            }
+        }
+
+        if self.globs.is_empty() && self.excluded.is_empty() && self.under.is_empty() {
+            return true;
+        }
+        event.paths.iter().any(|path| self.matches_path(path))
+    }
+
+    /// Whether `path` matches the path conditions of this filter.
+    pub fn matches_path(&self, path: &Path) -> bool {
+        let path = registry::absolute(path);
+        let components = Glob::components(&path);
+
+        (self.under.is_empty() || self.under.iter().any(|under| path.starts_with(under)))
+            && (self.globs.is_empty() || self.globs.iter().any(|glob| glob.matches(&components)))
+            && !self.excluded.iter().any(|glob| glob.matches(&components))
+    }
+}
+
+/// A path pattern, see [EventFilter::glob].
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct Glob {
+    components: Vec<String>,
+}
+
+impl Glob {
+    fn new(pattern: &str) -> Self {
+        let mut components: Vec<String> = pattern
+            .split('/')
+            .filter(|component| !component.is_empty())
+            .map(str::to_owned)
+            .collect();
+        if !pattern.starts_with('/') && components.first().map_or(true, |first| first != "**") {
+            components.insert(0, "**".to_owned());
+        }
+        Self { components }
+    }
+
+    /// The components of a path, as matched by globs.
+    fn components(path: &Path) -> Vec<String> {
+        path.components()
+            .filter_map(|component| match component {
+                Component::Normal(name) => Some(name.to_string_lossy().into_owned()),
+                Component::Prefix(prefix) => {
+                    Some(prefix.as_os_str().to_string_lossy().into_owned())
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn matches(&self, path: &[String]) -> bool {
+        Self::matches_components(&self.components, path)
+    }
+
+    fn matches_components(pattern: &[String], path: &[String]) -> bool {
+        match pattern.split_first() {
+            None => path.is_empty(),
+            Some((first, rest)) if first == "**" => {
+                (0..=path.len()).any(|skip| Self::matches_components(rest, &path[skip..]))
+            }
+            Some((first, rest)) => match path.split_first() {
+                Some((component, path_rest)) => {
+                    Self::matches_component(first, component)
+                        && Self::matches_components(rest, path_rest)
+                }
+                None => false,
+            },
+        }
+    }
+
+    fn matches_component(pattern: &str, component: &str) -> bool {
+        let pattern: Vec<char> = pattern.chars().collect();
+        let component: Vec<char> = component.chars().collect();
+
+        // the positions after the last `*` seen, to retry from when a match fails.
+        let (mut p, mut c) = (0, 0);
+        let mut retry = None;
+        while c < component.len() {
+            match pattern.get(p) {
+                Some('*') => {
+                    retry = Some((p + 1, c));
+                    p += 1;
+                }
+                Some(&ch) if ch == '?' || ch == component[c] => {
+                    p += 1;
+                    c += 1;
+                }
+                _ => match retry {
+                    Some((retry_p, retry_c)) => {
+                        p = retry_p;
+                        c = retry_c + 1;
+                        retry = Some((retry_p, retry_c + 1));
+                    }
+                    None => return false,
+                },
+            }
+        }
+        pattern[p..].iter().all(|&ch| ch == '*')
+    }
+}
+
+#[test]
+fn event_filter_matches_kinds_and_paths() {
+    use crate::event::{CreateKind, ModifyKind, RemoveKind};
+
+    let event = |kind, path: &str| Event::new(kind).add_path(PathBuf::from(path));
+    let create = || EventKind::Create(CreateKind::File);
+
+    let filter = EventFilter::new()
+        .kinds(EventKinds::CREATE | EventKinds::REMOVE)
+        .glob("**/*.toml")
+        .glob("/srv/?.log")
+        .exclude_glob("target/**")
+        .under("/srv");
+    assert!(filter.matches(&event(create(), "/srv/Cargo.toml")));
+    assert!(filter.matches(&event(create(), "/srv/a/b/Cargo.toml")));
+    assert!(filter.matches(&event(EventKind::Remove(RemoveKind::Any), "/srv/a.log")));
+    assert!(!filter.matches(&event(create(), "/srv/ab.log")));
+    assert!(!filter.matches(&event(create(), "/srv/a/b.log")));
+    assert!(!filter.matches(&event(create(), "/srv/target/debug/Cargo.toml")));
+    assert!(!filter.matches(&event(create(), "/elsewhere/Cargo.toml")));
+    assert!(!filter.matches(&event(
+        EventKind::Modify(ModifyKind::Any),
+        "/srv/Cargo.toml"
+    )));
+    assert!(!filter.matches(&Event::new(create())));
+
+    assert!(EventFilter::new().matches(&Event::new(EventKind::Other)));
+    assert!(Glob::matches_component("*.tar.*", "a.tar.gz"));
+    assert!(Glob::matches_component("a*b*c", "aXbYbZc"));
+    assert!(!Glob::matches_component("a*b*c", "aXbYcZ"));
+}
+
+#[test]
+fn editor_noise_matches_the_files_of_editors() {
+    let noise = |path: &str| {
+        EditorNoise::ALL
+            .iter()
+            .find(|editor| editor.matches(Path::new(path)))
+            .copied()
+    };
+    assert_eq!(noise("src/.main.rs.swp"), Some(EditorNoise::Vim));
+    assert_eq!(noise("src/4913"), Some(EditorNoise::Vim));
+    assert_eq!(noise("src/#main.rs#"), Some(EditorNoise::Emacs));
+    assert_eq!(noise("src/.#main.rs"), Some(EditorNoise::Emacs));
+    assert_eq!(
+        noise("src/main.rs___jb_tmp___"),
+        Some(EditorNoise::JetBrains)
+    );
+    assert_eq!(noise("src/main.rs.crswap"), Some(EditorNoise::VsCode));
+    assert_eq!(noise("src/.goutputstream-4LZ9K1"), Some(EditorNoise::Gedit));
+    assert_eq!(noise("src/main.rs"), None);
+    assert_eq!(noise("src/49130"), None);
+
+    // a file saved by renaming a temporary file over it is still reported
+    let filter = EventFilter::new().exclude_editor_noise(EditorNoise::JetBrains);
+    let rename = Event::new(EventKind::Any)
+        .add_path(PathBuf::from("main.rs___jb_tmp___"))
+        .add_path(PathBuf::from("main.rs"));
+    assert!(filter.matches(&rename));
+    assert!(
+        !filter.matches(&Event::new(EventKind::Any).add_path(PathBuf::from("main.rs___jb_old___")))
+    );
+}
@@ -0,0 +1,249 @@
+//! One-line descriptions of events, for logs and command line tools
+//!
+//! The [Display](fmt::Display) implementations of [Event] and [EventKind] use
+//! [Verbosity::Normal]:
+//!
+//! ```
+//! # use std::path::PathBuf;
+//! use notify::event::{DataChange, Event, EventKind, ModifyKind};
+//! use notify::format::{self, Verbosity};
+//!
+//! let event = Event::new(EventKind::Modify(ModifyKind::Data(DataChange::Content)))
+//!     .add_path(PathBuf::from("src/lib.rs"))
+//!     .set_info("example");
+//!
+//! assert_eq!(event.to_string(), "MODIFY(data) src/lib.rs");
+//! assert_eq!(format::event(&event, Verbosity::Brief).to_string(), "MODIFY src/lib.rs");
+//! assert_eq!(
+//!     format::event(&event, Verbosity::Verbose).to_string(),
+//!     r#"MODIFY(data:content) src/lib.rs [info="example"]"#
+//! );
+//! ```
+
+use crate::event::{
+    AccessKind, AccessMode, CreateKind, DataChange, Event, EventKind, MetadataKind, ModifyKind,
+    RemoveKind, RenameMode,
+};
+use std::fmt;
+
+/// How much of an event is described.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[non_exhaustive]
+pub enum Verbosity {
+    /// The top-level kind and the paths, like `MODIFY src/lib.rs`
+    Brief,
+
+    /// The kind with its first detail and the paths, like `MODIFY(data) src/lib.rs`
+    Normal,
+
+    /// The kind with all its details, the paths and the attributes, like
+    /// `MODIFY(data:content) src/lib.rs [tracker=3]`
+    Verbose,
+}
+
+impl Default for Verbosity {
+    fn default() -> Self {
+        Self::Normal
+    }
+}
+
+/// Describe `kind` at the given verbosity.
+pub fn kind(kind: &EventKind, verbosity: Verbosity) -> KindDisplay<'_> {
+    KindDisplay { kind, verbosity }
+}
+
+/// Describe `event` at the given verbosity.
+pub fn event(event: &Event, verbosity: Verbosity) -> EventDisplay<'_> {
+    EventDisplay { event, verbosity }
+}
+
+/// Describes an [EventKind], see [kind()].
+#[derive(Clone, Copy, Debug)]
+pub struct KindDisplay<'a> {
+    kind: &'a EventKind,
+    verbosity: Verbosity,
+}
+
+impl fmt::Display for KindDisplay<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (name, details) = describe(self.kind);
+        f.write_str(name)?;
+
+        // trailing unspecific details say nothing.
+        let details = match details.iter().rposition(|detail| *detail != "any") {
+            Some(last) => &details[..=last],
+            None => &[],
+        };
+        match self.verbosity {
+            Verbosity::Brief => Ok(()),
+            _ if details.is_empty() => Ok(()),
+            Verbosity::Normal => write!(f, "({})", details[0]),
+            Verbosity::Verbose => write!(f, "({})", details.join(":")),
+        }
+    }
+}
+
+/// Describes an [Event], see [event()].
+#[derive(Clone, Copy, Debug)]
+pub struct EventDisplay<'a> {
+    event: &'a Event,
+    verbosity: Verbosity,
+}
+
+impl fmt::Display for EventDisplay<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let event = self.event;
+        kind(&event.kind, self.verbosity).fmt(f)?;
+
+        let separator = match event.kind {
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => " -> ",
+            _ => " ",
+        };
+        for (i, path) in event.paths.iter().enumerate() {
+            f.write_str(if i == 0 { " " } else { separator })?;
+            path.display().fmt(f)?;
+        }
+
+        if self.verbosity < Verbosity::Verbose {
+            return Ok(());
+        }
+        let mut attrs = Vec::new();
+        if let Some(tracker) = event.tracker() {
+            attrs.push(format!("tracker={}", tracker));
+        }
+        if let Some(flag) = event.flag() {
+            attrs.push(format!("flag={:?}", flag));
+        }
+        if let Some(info) = event.info() {
+            attrs.push(format!("info={:?}", info));
+        }
+        if let Some(source) = event.source() {
+            attrs.push(format!("source={:?}", source));
+        }
+        if let Some(token) = event.watch_token() {
+            attrs.push(format!("token={}", token));
+        }
+        if !attrs.is_empty() {
+            write!(f, " [{}]", attrs.join(" "))?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for EventKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        kind(self, Verbosity::Normal).fmt(f)
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        event(self, Verbosity::Normal).fmt(f)
+    }
+}
+
+/// The name of a top-level kind, and the names of its details from the most general one.
+fn describe(kind: &EventKind) -> (&'static str, Vec<&'static str>) {
+    match kind {
+        EventKind::Any => ("ANY", vec![]),
+        EventKind::Access(kind) => ("ACCESS", access(kind)),
+        EventKind::Create(kind) => ("CREATE", vec![create(kind)]),
+        EventKind::Modify(kind) => ("MODIFY", modify(kind)),
+        EventKind::Remove(kind) => ("REMOVE", vec![remove(kind)]),
+        EventKind::Other => ("OTHER", vec![]),
+    }
+}
+
+fn access(kind: &AccessKind) -> Vec<&'static str> {
+    let mode = |mode: &AccessMode| match mode {
+        AccessMode::Any => "any",
+        AccessMode::Execute => "execute",
+        AccessMode::Read => "read",
+        AccessMode::Write => "write",
+        AccessMode::Other => "other",
+    };
+    match kind {
+        AccessKind::Any => vec!["any"],
+        AccessKind::Read => vec!["read"],
+        AccessKind::Open(m) => vec!["open", mode(m)],
+        AccessKind::Close(m) => vec!["close", mode(m)],
+        AccessKind::Other => vec!["other"],
+    }
+}
+
+fn create(kind: &CreateKind) -> &'static str {
+    match kind {
+        CreateKind::Any => "any",
+        CreateKind::File => "file",
+        CreateKind::Folder => "folder",
+        CreateKind::Other => "other",
+    }
+}
+
+fn modify(kind: &ModifyKind) -> Vec<&'static str> {
+    match kind {
+        ModifyKind::Any => vec!["any"],
+        ModifyKind::Data(data) => vec![
+            "data",
+            match data {
+                DataChange::Any => "any",
+                DataChange::Size => "size",
+                DataChange::Content => "content",
+                DataChange::Other => "other",
+            },
+        ],
+        ModifyKind::Metadata(metadata) => vec![
+            "metadata",
+            match metadata {
+                MetadataKind::Any => "any",
+                MetadataKind::AccessTime => "access-time",
+                MetadataKind::WriteTime => "write-time",
+                MetadataKind::Permissions => "permissions",
+                MetadataKind::Ownership => "ownership",
+                MetadataKind::Extended => "extended",
+                MetadataKind::Other => "other",
+            },
+        ],
+        ModifyKind::Name(mode) => vec![
+            "name",
+            match mode {
+                RenameMode::Any => "any",
+                RenameMode::To => "to",
+                RenameMode::From => "from",
+                RenameMode::Both => "both",
+                RenameMode::Other => "other",
+            },
+        ],
+        ModifyKind::Other => vec!["other"],
+    }
+}
+
+fn remove(kind: &RemoveKind) -> &'static str {
+    match kind {
+        RemoveKind::Any => "any",
+        RemoveKind::File => "file",
+        RemoveKind::Folder => "folder",
+        RemoveKind::Other => "other",
+    }
+}
+
+#[test]
+fn events_are_described_on_one_line() {
+    use std::path::PathBuf;
+
+    let rename = Event::new(EventKind::Modify(ModifyKind::Name(RenameMode::Both)))
+        .add_path(PathBuf::from("a"))
+        .add_path(PathBuf::from("b"))
+        .set_tracker(3);
+    assert_eq!(rename.to_string(), "MODIFY(name) a -> b");
+    assert_eq!(
+        event(&rename, Verbosity::Verbose).to_string(),
+        "MODIFY(name:both) a -> b [tracker=3]"
+    );
+
+    let create = EventKind::Create(CreateKind::Any);
+    assert_eq!(create.to_string(), "CREATE");
+    let open = EventKind::Access(AccessKind::Open(AccessMode::Any));
+    assert_eq!(kind(&open, Verbosity::Verbose).to_string(), "ACCESS(open)");
+    assert_eq!(EventKind::Other.to_string(), "OTHER");
+}
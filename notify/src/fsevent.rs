@@ -14,8 +14,15 @@
 
 #![allow(non_upper_case_globals, dead_code)]
 
+use crate::audit::{Audit, Resource};
+use crate::delivery::{Delivery, DeliveryOptions, WatchTokens};
 use crate::event::*;
-use crate::{unbounded, Config, Error, EventHandler, RecursiveMode, Result, Sender, Watcher};
+use crate::registry::{InstallWatches, WatchRegistry};
+use crate::scheduling::Scheduling;
+use crate::suppress::{SuppressGuard, Suppressions};
+use crate::{
+    snapshot, unbounded, Config, Error, EventHandler, RecursiveMode, Result, Sender, Watcher,
+};
 use fsevent_sys as fs;
 use fsevent_sys::core_foundation as cf;
 use std::collections::HashMap;
@@ -66,6 +73,13 @@ pub struct FsEventWatcher {
     event_handler: Arc<Mutex<dyn EventHandler>>,
     runloop: Option<(cf::CFRunLoopRef, thread::JoinHandle<()>)>,
     recursive_info: HashMap<PathBuf, bool>,
+    initial_scan: bool,
+    registry: WatchRegistry,
+    tokens: WatchTokens,
+    options: DeliveryOptions,
+    suppressions: Suppressions,
+    audit: Audit,
+    scheduling: Scheduling,
 }
 
 impl fmt::Debug for FsEventWatcher {
@@ -78,6 +92,8 @@ impl fmt::Debug for FsEventWatcher {
             .field("event_handler", &Arc::as_ptr(&self.event_handler))
             .field("runloop", &self.runloop)
             .field("recursive_info", &self.recursive_info)
+            .field("initial_scan", &self.initial_scan)
+            .field("registry", &self.registry)
             .finish()
     }
 }
@@ -265,17 +281,30 @@ extern "C" {
 }
 
 impl FsEventWatcher {
-    fn from_event_handler(event_handler: Arc<Mutex<dyn EventHandler>>) -> Result<Self> {
+    fn from_delivery(delivery: Delivery, config: &Config) -> Result<Self> {
+        let mut flags =
+            fs::kFSEventStreamCreateFlagFileEvents | fs::kFSEventStreamCreateFlagNoDefer;
+        if config.ignore_own_events() {
+            // own events are then flagged, and get the process id of the watcher
+            flags |= fs::kFSEventStreamCreateFlagMarkSelf;
+        }
         Ok(FsEventWatcher {
             paths: unsafe {
                 cf::CFArrayCreateMutable(cf::kCFAllocatorDefault, 0, &cf::kCFTypeArrayCallBacks)
             },
             since_when: fs::kFSEventStreamEventIdSinceNow,
             latency: 0.0,
-            flags: fs::kFSEventStreamCreateFlagFileEvents | fs::kFSEventStreamCreateFlagNoDefer,
-            event_handler,
+            flags,
+            tokens: delivery.tokens(),
+            options: delivery.options(),
+            suppressions: delivery.suppressions(),
+            event_handler: Arc::new(Mutex::new(delivery)),
             runloop: None,
             recursive_info: HashMap::new(),
+            initial_scan: config.initial_scan(),
+            registry: WatchRegistry::new(config.watch_overlap()),
+            audit: Audit::new("fsevent", config),
+            scheduling: Scheduling::new(config),
         })
     }
 
@@ -284,9 +313,38 @@ impl FsEventWatcher {
         let result = self.append_path(path, recursive_mode);
         // ignore return error: may be empty path list
         let _ = self.run();
+        if result.is_ok() && self.initial_scan {
+            // report the same paths as FSEvents does.
+            let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+            let mut event_handler = self.event_handler.lock().expect("lock not to be poisoned");
+            for event in snapshot::initial_scan_events(&path, recursive_mode) {
+                event_handler.handle_event(Ok(event));
+            }
+        }
         result
     }
 
+    fn set_recursive_mode_inner(
+        &mut self,
+        path: &Path,
+        recursive_mode: RecursiveMode,
+    ) -> Result<()> {
+        match self.registry.recursive_mode(path) {
+            None => return Err(Error::watch_not_found().add_path(path.to_owned())),
+            Some(current) if current == recursive_mode => return Ok(()),
+            Some(_) => {}
+        }
+
+        // FSEvents always reports the whole tree, only the filtering of its events changes.
+        let canonical_path = path.canonicalize()?;
+        self.stop();
+        self.recursive_info
+            .insert(canonical_path, recursive_mode.is_recursive());
+        // ignore return error: may be empty path list
+        let _ = self.run();
+        Ok(())
+    }
+
     fn unwatch_inner(&mut self, path: &Path) -> Result<()> {
         self.stop();
         let result = self.remove_path(path);
@@ -426,7 +484,10 @@ impl FsEventWatcher {
         unsafe impl Send for CFSendWrapper {}
 
         // move into thread
+        self.audit.acquire(Resource::Handle, stream as usize);
         let stream = CFSendWrapper(stream);
+        let audit = self.audit.clone();
+        let scheduling = self.scheduling.clone();
 
         // channel to pass runloop around
         let (rl_tx, rl_rx) = unbounded();
@@ -436,6 +497,9 @@ impl FsEventWatcher {
             .spawn(move || {
                 let _ = &stream;
                 let stream = stream.0;
+                let thread = thread::current().id();
+                audit.acquire(Resource::Thread, thread);
+                let _ = scheduling.apply();
 
                 unsafe {
                     let cur_runloop = cf::CFRunLoopGetCurrent();
@@ -457,6 +521,8 @@ impl FsEventWatcher {
                     fs::FSEventStreamInvalidate(stream);
                     fs::FSEventStreamRelease(stream);
                 }
+                audit.release(Resource::Handle, stream as usize);
+                audit.release(Resource::Thread, thread);
             })?;
         // block until runloop has been sent
         self.runloop = Some((rl_rx.recv().unwrap().0, thread_handle));
@@ -464,9 +530,10 @@ impl FsEventWatcher {
         Ok(())
     }
 
-    fn configure_raw_mode(&mut self, _config: Config, tx: Sender<Result<bool>>) {
-        tx.send(Ok(false))
-            .expect("configuration channel disconnect");
+    fn configure_raw_mode(&mut self, config: Config, tx: Sender<Result<bool>>) {
+        self.options.configure(&config);
+        self.initial_scan = config.initial_scan();
+        tx.send(Ok(true)).expect("configuration channel disconnect");
     }
 }
 
@@ -541,18 +608,71 @@ unsafe fn callback_impl(
     }
 }
 
+impl InstallWatches for FsEventWatcher {
+    fn install(&mut self, path: &Path, recursive_mode: RecursiveMode) -> Result<()> {
+        self.watch_inner(path, recursive_mode)
+    }
+
+    fn uninstall(&mut self, path: &Path) -> Result<()> {
+        self.unwatch_inner(path)
+    }
+}
+
 impl Watcher for FsEventWatcher {
     /// Create a new watcher.
-    fn new<F: EventHandler>(event_handler: F, _config: Config) -> Result<Self> {
-        Self::from_event_handler(Arc::new(Mutex::new(event_handler)))
+    fn new<F: EventHandler>(event_handler: F, config: Config) -> Result<Self> {
+        Self::from_delivery(Delivery::new(event_handler, &config), &config)
     }
 
     fn watch(&mut self, path: &Path, recursive_mode: RecursiveMode) -> Result<()> {
-        self.watch_inner(path, recursive_mode)
+        let plan = self.registry.plan_watch(path, recursive_mode)?;
+        plan.apply(self, |watcher| watcher.watch_inner(path, recursive_mode))?;
+        self.registry.insert(path, recursive_mode);
+        Ok(())
+    }
+
+    fn watch_with_token(
+        &mut self,
+        path: &Path,
+        recursive_mode: RecursiveMode,
+        token: u64,
+    ) -> Result<()> {
+        let plan = self.registry.plan_watch(path, recursive_mode)?;
+        // registered first, so that the initial scan carries the token already.
+        self.tokens.insert(path, token);
+        if let Err(e) = plan.apply(self, |watcher| watcher.watch_inner(path, recursive_mode)) {
+            self.tokens.remove(path);
+            return Err(e);
+        }
+        self.registry.insert(path, recursive_mode);
+        Ok(())
     }
 
     fn unwatch(&mut self, path: &Path) -> Result<()> {
-        self.unwatch_inner(path)
+        let plan = self.registry.plan_unwatch(path);
+        plan.apply(self, |watcher| watcher.unwatch_inner(path))?;
+        self.registry.remove(path);
+        self.tokens.remove(path);
+        Ok(())
+    }
+
+    fn set_recursive_mode(&mut self, path: &Path, recursive_mode: RecursiveMode) -> Result<()> {
+        let plan = self
+            .registry
+            .plan_set_recursive_mode(path, recursive_mode)?;
+        plan.apply(self, |watcher| {
+            watcher.set_recursive_mode_inner(path, recursive_mode)
+        })?;
+        self.registry.insert(path, recursive_mode);
+        Ok(())
+    }
+
+    fn suppress(&self, path: &Path) -> Result<SuppressGuard> {
+        Ok(self.suppressions.guard(path))
+    }
+
+    fn is_watched(&self, path: &Path) -> bool {
+        self.registry.is_watched(path)
     }
 
     fn configure(&mut self, config: Config) -> Result<bool> {
@@ -0,0 +1,603 @@
+//! Watching a git repository, without the events of its internals and ignored paths
+
+use crate::event::{CreateKind, EventKind, RemoveKind};
+use crate::{registry, Config, Event, EventHandler, RecursiveMode, Result, Watcher};
+use std::{
+    collections::{HashMap, HashSet},
+    fs, io,
+    path::{Component, Path, PathBuf},
+    process::{Command, Stdio},
+    sync::{Arc, Mutex, MutexGuard},
+    thread,
+    time::Duration,
+};
+
+/// How long the index has to stay unchanged before the tracked files are listed again
+const INDEX_DEBOUNCE: Duration = Duration::from_millis(100);
+
+/// The infos of the events tagged by [GitWatcher::with_tracked]
+const TRACKED_INFO: &str = "git: tracked";
+const UNTRACKED_INFO: &str = "git: untracked";
+
+/// A watcher of the working tree of a git repository, dropping the events of paths inside `.git`
+/// and of paths ignored by git.
+///
+/// ```no_run
+/// # use std::path::Path;
+/// use notify::{Config, GitWatcher, RecommendedWatcher};
+///
+/// # fn main() -> notify::Result<()> {
+/// let watcher = GitWatcher::<RecommendedWatcher>::new(
+///     Path::new("."),
+///     |event| println!("{:?}", event),
+///     Config::default(),
+/// )?
+/// .with_tracked(true)?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// The root is watched recursively. Paths are ignored by the `.gitignore` files of the working
+/// tree and by `.git/info/exclude`, which are read again when they change, but not by a global
+/// `core.excludesFile`. An event with several paths, like a rename, is only dropped if all of
+/// them are ignored. The backend still watches ignored directories, only their events are dropped.
+///
+/// With [GitWatcher::with_tracked], events are tagged with whether their path is tracked, see
+/// [GitTracked]. Like in git, tracked files are never ignored then.
+#[derive(Debug)]
+pub struct GitWatcher<W> {
+    watcher: W,
+    repository: Arc<Mutex<Repository>>,
+}
+
+impl<W: Watcher> GitWatcher<W> {
+    /// Watch the working tree at `root`, delivering the events which aren't ignored to
+    /// `event_handler`.
+    pub fn new<F: EventHandler>(root: &Path, event_handler: F, config: Config) -> Result<Self> {
+        let root = registry::absolute(root);
+        let repository = Arc::new(Mutex::new(Repository::new(root.clone())));
+        let mut watcher = W::new(
+            Forward {
+                event_handler,
+                repository: repository.clone(),
+            },
+            config,
+        )?;
+        watcher.watch(&root, RecursiveMode::Recursive)?;
+        Ok(Self {
+            watcher,
+            repository,
+        })
+    }
+
+    /// Tag events with whether their path is tracked, listing the tracked files with `git`.
+    ///
+    /// The tag is the info of the event, read with [GitTracked::git_tracked]. Events carrying an
+    /// info of their backend already are left untagged. The list is updated on another thread
+    /// once the index stopped changing for a moment, so events right after a change of the index
+    /// are tagged by the previous list. Off by default.
+    pub fn with_tracked(self, tracked: bool) -> Result<Self> {
+        {
+            let mut repository = self.repository();
+            if tracked {
+                repository.load_tracked().map_err(crate::Error::io)?;
+            } else {
+                repository.tracked = None;
+            }
+        }
+        Ok(self)
+    }
+
+    /// The root of the working tree watched
+    pub fn root(&self) -> PathBuf {
+        self.repository().root.clone()
+    }
+
+    /// Whether the events of `path` are dropped.
+    pub fn is_ignored(&self, path: &Path) -> bool {
+        let path = registry::absolute(path);
+        let is_dir = path.is_dir();
+        self.repository().is_ignored(&path, is_dir)
+    }
+
+    /// The watcher of the working tree
+    pub fn watcher(&mut self) -> &mut W {
+        &mut self.watcher
+    }
+
+    fn repository(&self) -> MutexGuard<'_, Repository> {
+        lock(&self.repository)
+    }
+}
+
+/// Whether the paths of the events of a [GitWatcher] are tracked by git
+pub trait GitTracked {
+    /// Whether one of the event's paths is tracked by git, if it was tagged by a [GitWatcher]
+    /// with [GitWatcher::with_tracked].
+    fn git_tracked(&self) -> Option<bool>;
+}
+
+impl GitTracked for Event {
+    fn git_tracked(&self) -> Option<bool> {
+        match self.info() {
+            Some(TRACKED_INFO) => Some(true),
+            Some(UNTRACKED_INFO) => Some(false),
+            _ => None,
+        }
+    }
+}
+
+/// Drops the events of ignored paths on their way to the event handler.
+struct Forward<F> {
+    event_handler: F,
+    repository: Arc<Mutex<Repository>>,
+}
+
+impl<F: EventHandler> EventHandler for Forward<F> {
+    fn handle_event(&mut self, event: Result<Event>) {
+        let mut event = match event {
+            Ok(event) => event,
+            Err(e) => return self.event_handler.handle_event(Err(e)),
+        };
+        let (keep, index_changed) = {
+            let mut repository = lock(&self.repository);
+            let index_changed = repository.update(&event);
+            let is_dir = matches!(
+                event.kind,
+                EventKind::Create(CreateKind::Folder) | EventKind::Remove(RemoveKind::Folder)
+            );
+            let keep = event.paths.is_empty()
+                || event.paths.iter().any(|path| {
+                    let is_dir = is_dir || path.is_dir();
+                    !repository.is_ignored(&registry::absolute(path), is_dir)
+                });
+            if keep && repository.tracked.is_some() && event.info().is_none() {
+                let tracked = event
+                    .paths
+                    .iter()
+                    .any(|path| repository.is_tracked(&registry::absolute(path)));
+                let info = if tracked {
+                    TRACKED_INFO
+                } else {
+                    UNTRACKED_INFO
+                };
+                event.attrs.set_info(info);
+            }
+            (keep, index_changed)
+        };
+        if index_changed {
+            refresh_tracked(&self.repository);
+        }
+        if keep {
+            self.event_handler.handle_event(Ok(event));
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Repository {
+    root: PathBuf,
+    /// The patterns of `.git/info/exclude`, if read
+    exclude: Option<Vec<Pattern>>,
+    /// The patterns of the `.gitignore` files read, by the directory relative to the root.
+    ignores: HashMap<PathBuf, Vec<Pattern>>,
+    /// The tracked files and the directories containing them, relative to the root, if listed.
+    tracked: Option<Tracked>,
+    /// Whether a thread is listing the tracked files again
+    refreshing: bool,
+    /// Whether the index changed since the thread started listing
+    index_changed: bool,
+}
+
+type Tracked = (HashSet<PathBuf>, HashSet<PathBuf>);
+
+impl Repository {
+    fn new(root: PathBuf) -> Self {
+        Self {
+            root,
+            exclude: None,
+            ignores: HashMap::new(),
+            tracked: None,
+            refreshing: false,
+            index_changed: false,
+        }
+    }
+
+    fn load_tracked(&mut self) -> io::Result<()> {
+        self.tracked = Some(list_tracked(&self.root)?);
+        Ok(())
+    }
+
+    /// Forget the ignore files changed by `event`, returning whether the tracked files have to be
+    /// listed again.
+    fn update(&mut self, event: &Event) -> bool {
+        let mut index_changed = false;
+        for path in &event.paths {
+            let relative = match path.strip_prefix(&self.root) {
+                Ok(relative) => relative,
+                Err(_) => continue,
+            };
+            if relative == Path::new(".git/info/exclude") {
+                self.exclude = None;
+            } else if relative == Path::new(".git/index") && self.tracked.is_some() {
+                index_changed = true;
+            } else if relative.file_name() == Some(".gitignore".as_ref()) {
+                let dir = relative.parent().unwrap_or_else(|| Path::new(""));
+                self.ignores.remove(dir);
+            }
+        }
+        index_changed
+    }
+
+    fn is_tracked(&self, path: &Path) -> bool {
+        match (&self.tracked, path.strip_prefix(&self.root)) {
+            (Some((files, dirs)), Ok(relative)) => {
+                files.contains(relative) || dirs.contains(relative)
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether `path` is inside `.git` or ignored by git.
+    fn is_ignored(&mut self, path: &Path, is_dir: bool) -> bool {
+        let relative = match path.strip_prefix(&self.root) {
+            Ok(relative) => relative.to_path_buf(),
+            Err(_) => return false,
+        };
+        let names: Vec<String> = relative
+            .components()
+            .filter_map(|component| match component {
+                Component::Normal(name) => Some(name.to_string_lossy().into_owned()),
+                _ => None,
+            })
+            .collect();
+        if names.first().map_or(false, |first| first == ".git") {
+            return true;
+        }
+        if self.is_tracked(path) {
+            return false;
+        }
+
+        // like git, don't look into ignored directories.
+        for depth in 1..=names.len() {
+            let is_dir = is_dir || depth < names.len();
+            if self.is_ignored_at(&names[..depth], is_dir) {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Whether the path made of `names` is ignored by the patterns of its own directories.
+    fn is_ignored_at(&mut self, names: &[String], is_dir: bool) -> bool {
+        let mut ignored = false;
+        if self.exclude.is_none() {
+            let exclude = self.root.join(".git").join("info").join("exclude");
+            self.exclude = Some(Pattern::read(&exclude));
+        }
+        let exclude = self.exclude.as_deref().unwrap_or_default();
+        for pattern in exclude {
+            if pattern.matches(names, is_dir) {
+                ignored = !pattern.negated;
+            }
+        }
+
+        // deeper files take precedence, and later patterns within a file.
+        for depth in 0..names.len() {
+            let dir: PathBuf = names[..depth].iter().collect();
+            let root = &self.root;
+            let patterns = self
+                .ignores
+                .entry(dir)
+                .or_insert_with_key(|dir| Pattern::read(&root.join(dir).join(".gitignore")));
+            for pattern in patterns.iter() {
+                if pattern.matches(&names[depth..], is_dir) {
+                    ignored = !pattern.negated;
+                }
+            }
+        }
+        ignored
+    }
+}
+
+fn lock(repository: &Mutex<Repository>) -> MutexGuard<'_, Repository> {
+    repository.lock().unwrap_or_else(|p| p.into_inner())
+}
+
+/// List the tracked files of `repository` again on another thread, once its index stopped
+/// changing, so that neither the backend nor the events wait for `git`.
+fn refresh_tracked(repository: &Arc<Mutex<Repository>>) {
+    {
+        let mut repository = lock(repository);
+        repository.index_changed = true;
+        if repository.refreshing {
+            return;
+        }
+        repository.refreshing = true;
+    }
+    let shared = repository.clone();
+    let spawned = thread::Builder::new()
+        .name("notify-rs git ls-files".to_string())
+        .spawn(move || loop {
+            thread::sleep(INDEX_DEBOUNCE);
+            let root = {
+                let mut repository = lock(&shared);
+                repository.index_changed = false;
+                repository.root.clone()
+            };
+            let listed = list_tracked(&root);
+            let mut repository = lock(&shared);
+            // keeps the previous list if git fails meanwhile, or if it was turned off
+            if let (Ok(tracked), Some(_)) = (listed, &repository.tracked) {
+                repository.tracked = Some(tracked);
+            }
+            if !repository.index_changed {
+                repository.refreshing = false;
+                return;
+            }
+        });
+    if spawned.is_err() {
+        lock(repository).refreshing = false;
+    }
+}
+
+/// The tracked files of the working tree at `root` and the directories containing them
+fn list_tracked(root: &Path) -> io::Result<Tracked> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(root)
+        .args(["ls-files", "-z"])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("{} is not a git working tree", root.display()),
+        ));
+    }
+    let mut files = HashSet::new();
+    let mut dirs = HashSet::new();
+    for file in output.stdout.split(|&b| b == 0).filter(|f| !f.is_empty()) {
+        let file = PathBuf::from(String::from_utf8_lossy(file).into_owned());
+        dirs.extend(file.ancestors().skip(1).map(Path::to_path_buf));
+        files.insert(file);
+    }
+    Ok((files, dirs))
+}
+
+/// A pattern of a `.gitignore` file
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Pattern {
+    /// The components of an anchored pattern, or the only component of one matching names at
+    /// any depth.
+    components: Vec<String>,
+    anchored: bool,
+    dir_only: bool,
+    negated: bool,
+}
+
+impl Pattern {
+    /// The patterns of the ignore file at `path`, none if it can't be read.
+    fn read(path: &Path) -> Vec<Pattern> {
+        fs::read_to_string(path)
+            .map(|patterns| patterns.lines().filter_map(Pattern::parse).collect())
+            .unwrap_or_default()
+    }
+
+    fn parse(line: &str) -> Option<Self> {
+        let mut line = line.trim_end_matches('\r');
+        // trailing spaces are ignored, unless escaped
+        while line.ends_with(' ') && !line.ends_with("\\ ") {
+            line = &line[..line.len() - 1];
+        }
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let negated = line.starts_with('!');
+        if negated {
+            line = &line[1..];
+        }
+        let dir_only = line.ends_with('/');
+        let line = line.trim_end_matches('/');
+        if line.is_empty() {
+            return None;
+        }
+        let components: Vec<String> = line
+            .split('/')
+            .filter(|component| !component.is_empty())
+            .map(str::to_owned)
+            .collect();
+        Some(Self {
+            anchored: line.contains('/'),
+            components,
+            dir_only,
+            negated,
+        })
+    }
+
+    /// Whether the path made of `names`, relative to the directory of the pattern, matches.
+    fn matches(&self, names: &[String], is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        if self.anchored {
+            matches_components(&self.components, names)
+        } else {
+            match names.last() {
+                Some(name) => wildmatch(&chars(&self.components[0]), &chars(name)),
+                None => false,
+            }
+        }
+    }
+}
+
+fn chars(s: &str) -> Vec<char> {
+    s.chars().collect()
+}
+
+fn matches_components(pattern: &[String], names: &[String]) -> bool {
+    match pattern.split_first() {
+        None => names.is_empty(),
+        Some((first, rest)) if first == "**" => {
+            (0..=names.len()).any(|skip| matches_components(rest, &names[skip..]))
+        }
+        Some((first, rest)) => match names.split_first() {
+            Some((name, names)) => {
+                wildmatch(&chars(first), &chars(name)) && matches_components(rest, names)
+            }
+            None => false,
+        },
+    }
+}
+
+/// Match a name against a pattern with `*`, `?`, `[...]` classes and `\` escapes.
+fn wildmatch(pattern: &[char], name: &[char]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some(('*', rest)) => (0..=name.len()).any(|skip| wildmatch(rest, &name[skip..])),
+        Some(('?', rest)) => !name.is_empty() && wildmatch(rest, &name[1..]),
+        Some(('[', _)) if class(pattern).is_some() => {
+            let (matches, len) = class(pattern).expect("the class is complete");
+            match name.split_first() {
+                Some((&c, name)) => matches(c) && wildmatch(&pattern[len..], name),
+                None => false,
+            }
+        }
+        Some(('\\', rest)) if !rest.is_empty() => {
+            name.first() == Some(&rest[0]) && wildmatch(&rest[1..], &name[1..])
+        }
+        Some((c, rest)) => name.first() == Some(c) && wildmatch(rest, &name[1..]),
+    }
+}
+
+/// The matcher of the `[...]` class at the start of `pattern` and its length, if it is closed.
+fn class(pattern: &[char]) -> Option<(impl Fn(char) -> bool + '_, usize)> {
+    let mut i = 1;
+    let negated = matches!(pattern.get(i), Some('!') | Some('^'));
+    if negated {
+        i += 1;
+    }
+    let start = i;
+    // a `]` right at the start is part of the class
+    if pattern.get(i) == Some(&']') {
+        i += 1;
+    }
+    while pattern.get(i)? != &']' {
+        i += 1;
+    }
+    let items = &pattern[start..i];
+    let matches = move |c: char| {
+        let mut found = false;
+        let mut j = 0;
+        while j < items.len() {
+            if j + 2 < items.len() && items[j + 1] == '-' {
+                found |= items[j] <= c && c <= items[j + 2];
+                j += 3;
+            } else {
+                found |= items[j] == c;
+                j += 1;
+            }
+        }
+        found != negated
+    };
+    Some((matches, i + 1))
+}
+
+#[test]
+fn git_watcher_drops_internal_and_ignored_paths() {
+    let root = tempfile::tempdir().unwrap();
+    let root = root.path();
+    fs::create_dir_all(root.join(".git").join("info")).unwrap();
+    fs::write(root.join(".git/info/exclude"), "*.local\n").unwrap();
+    fs::write(
+        root.join(".gitignore"),
+        "# build output\n/target/\n*.log\n!keep.log\nbuild-[0-9]?\n",
+    )
+    .unwrap();
+    fs::create_dir_all(root.join("docs")).unwrap();
+    fs::write(root.join("docs/.gitignore"), "/api/**/*.html\n*.log\n").unwrap();
+
+    let mut repository = Repository::new(root.to_path_buf());
+    let mut ignored = |path: &str, is_dir| repository.is_ignored(&root.join(path), is_dir);
+    assert!(ignored(".git/index", false));
+    assert!(ignored("settings.local", false));
+    assert!(ignored("target", true));
+    assert!(ignored("target/debug/app", false));
+    assert!(!ignored("src/target", true));
+    assert!(ignored("src/debug.log", false));
+    assert!(!ignored("src/keep.log", false));
+    // deeper files take precedence
+    assert!(ignored("docs/keep.log", false));
+    assert!(ignored("build-1a", true));
+    assert!(!ignored("build-a1", true));
+    assert!(ignored("docs/api/v1/index.html", false));
+    assert!(!ignored("docs/index.html", false));
+    assert!(!ignored("src/main.rs", false));
+
+    // ignore files are read again once changed
+    fs::write(root.join(".gitignore"), "*.rs\n").unwrap();
+    let changed = Event::new(EventKind::Any).add_path(root.join(".gitignore"));
+    repository.update(&changed);
+    assert!(repository.is_ignored(&root.join("src/main.rs"), false));
+}
+
+#[test]
+fn tracked_files_are_listed_again_off_the_event_path() {
+    use std::time::Instant;
+
+    let root = tempfile::tempdir().unwrap();
+    let root = root.path();
+    let git = |args: &[&str]| {
+        Command::new("git")
+            .arg("-C")
+            .arg(root)
+            .args(args)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .map_or(false, |status| status.success())
+    };
+    if !git(&["init", "-q"]) {
+        // git isn't installed
+        return;
+    }
+    fs::write(root.join("a"), "").unwrap();
+    fs::write(root.join("b"), "").unwrap();
+    assert!(git(&["add", "a"]));
+
+    let repository = Arc::new(Mutex::new(Repository::new(root.to_path_buf())));
+    lock(&repository).load_tracked().unwrap();
+    assert!(lock(&repository).is_tracked(&root.join("a")));
+    assert!(!lock(&repository).is_tracked(&root.join("b")));
+
+    // the events are tagged through their info
+    let (tx, rx) = std::sync::mpsc::channel();
+    let mut forward = Forward {
+        event_handler: tx,
+        repository: repository.clone(),
+    };
+    forward.handle_event(Ok(Event::new(EventKind::Any).add_path(root.join("a"))));
+    forward.handle_event(Ok(Event::new(EventKind::Any).add_path(root.join("b"))));
+    let tags: Vec<_> = rx.try_iter().map(|e| e.unwrap().git_tracked()).collect();
+    assert_eq!(tags, [Some(true), Some(false)]);
+
+    assert!(git(&["add", "b"]));
+    let changed = Event::new(EventKind::Any).add_path(root.join(".git/index"));
+    assert!(lock(&repository).update(&changed));
+    refresh_tracked(&repository);
+    refresh_tracked(&repository);
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !lock(&repository).is_tracked(&root.join("b")) {
+        assert!(
+            Instant::now() < deadline,
+            "the tracked files weren't listed again"
+        );
+        thread::sleep(Duration::from_millis(10));
+    }
+    while lock(&repository).refreshing {
+        assert!(Instant::now() < deadline, "the listing thread didn't end");
+        thread::sleep(Duration::from_millis(10));
+    }
+}
@@ -0,0 +1,349 @@
+//! Named groups of watches on one watcher
+
+use crate::{Error, RecursiveMode, Result, Watcher};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+/// A watcher whose watches are organized in named groups, which can be removed or paused as a
+/// whole.
+///
+/// ```no_run
+/// # use std::path::Path;
+/// use notify::{Config, PollWatcher, RecursiveMode, WatchGroups, Watcher};
+///
+/// # fn main() -> notify::Result<()> {
+/// let watcher = PollWatcher::new(|event| println!("{:?}", event), Config::default())?;
+/// let mut groups = WatchGroups::new(watcher);
+/// groups.watch("project-a", Path::new("a/src"), RecursiveMode::Recursive)?;
+/// groups.watch("project-a", Path::new("a/Cargo.toml"), RecursiveMode::NonRecursive)?;
+/// groups.watch("project-b", Path::new("b"), RecursiveMode::Recursive)?;
+///
+/// groups.pause_group("project-a")?;
+/// // ...
+/// groups.resume_group("project-a")?;
+/// groups.unwatch_group("project-b")?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Group operations either succeed for all watches of the group, or leave the group as it was.
+/// Changes made while a group is paused are not reported. A path may be part of several groups,
+/// it stays watched as long as one of them is active, recursively if one of the active groups
+/// watches it recursively.
+#[derive(Debug)]
+pub struct WatchGroups<W> {
+    watcher: W,
+    groups: HashMap<String, Group>,
+    /// The watches of the active groups on each path the watcher watches
+    watched: HashMap<PathBuf, Watches>,
+}
+
+#[derive(Debug, Default)]
+struct Group {
+    watches: Vec<(PathBuf, RecursiveMode)>,
+    paused: bool,
+}
+
+/// The number of active watches of a path in each mode
+#[derive(Debug, Clone, Copy, Default)]
+struct Watches {
+    recursive: usize,
+    non_recursive: usize,
+}
+
+impl Watches {
+    /// The mode the path is watched with for these watches, `None` if there are none
+    fn mode(self) -> Option<RecursiveMode> {
+        if self.recursive > 0 {
+            Some(RecursiveMode::Recursive)
+        } else if self.non_recursive > 0 {
+            Some(RecursiveMode::NonRecursive)
+        } else {
+            None
+        }
+    }
+
+    fn count(&mut self, mode: RecursiveMode) -> &mut usize {
+        match mode {
+            RecursiveMode::Recursive => &mut self.recursive,
+            RecursiveMode::NonRecursive => &mut self.non_recursive,
+        }
+    }
+}
+
+impl<W: Watcher> WatchGroups<W> {
+    /// Organize the watches of `watcher` in groups.
+    ///
+    /// Watches `watcher` already has are not part of any group.
+    pub fn new(watcher: W) -> Self {
+        Self {
+            watcher,
+            groups: HashMap::new(),
+            watched: HashMap::new(),
+        }
+    }
+
+    /// The underlying watcher.
+    pub fn watcher(&self) -> &W {
+        &self.watcher
+    }
+
+    /// The underlying watcher, e.g. to configure it.
+    ///
+    /// Watches changed directly on it are not tracked by the groups.
+    pub fn watcher_mut(&mut self) -> &mut W {
+        &mut self.watcher
+    }
+
+    /// Stop organizing the watches in groups, keeping them as they are.
+    pub fn into_inner(self) -> W {
+        self.watcher
+    }
+
+    /// Watch `path` as part of `group`, creating the group if needed.
+    ///
+    /// If the group is paused, `path` is only watched once the group is resumed.
+    pub fn watch(&mut self, group: &str, path: &Path, recursive_mode: RecursiveMode) -> Result<()> {
+        let (paused, replaced) = match self.groups.get(group) {
+            Some(group) => (
+                group.paused,
+                group
+                    .watches
+                    .iter()
+                    .find(|(watched, _)| watched == path)
+                    .map(|(_, mode)| *mode),
+            ),
+            None => (false, None),
+        };
+        if !paused {
+            self.change(path, Some(recursive_mode), replaced)?;
+        }
+
+        let group = self.groups.entry(group.to_owned()).or_default();
+        group.watches.retain(|(watched, _)| watched != path);
+        group.watches.push((path.to_owned(), recursive_mode));
+        Ok(())
+    }
+
+    /// Stop watching `path` as part of `group`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `path` is not part of `group`, or if removing the watch fails.
+    pub fn unwatch(&mut self, group: &str, path: &Path) -> Result<()> {
+        let (paused, index, recursive_mode) = self
+            .groups
+            .get(group)
+            .and_then(|group| {
+                let index = group
+                    .watches
+                    .iter()
+                    .position(|(watched, _)| watched == path)?;
+                Some((group.paused, index, group.watches[index].1))
+            })
+            .ok_or_else(|| Error::watch_not_found().add_path(path.to_owned()))?;
+
+        if !paused {
+            self.change(path, None, Some(recursive_mode))?;
+        }
+        if let Some(group) = self.groups.get_mut(group) {
+            group.watches.remove(index);
+        }
+        Ok(())
+    }
+
+    /// Stop watching all paths of `group`, and forget the group.
+    pub fn unwatch_group(&mut self, group: &str) -> Result<()> {
+        self.pause_group(group)?;
+        self.groups.remove(group);
+        Ok(())
+    }
+
+    /// Stop watching all paths of `group` until it is resumed.
+    ///
+    /// Pausing a paused group does nothing.
+    pub fn pause_group(&mut self, group: &str) -> Result<()> {
+        let watches = match self.groups.get(group) {
+            Some(group) if group.paused => return Ok(()),
+            Some(group) => group.watches.clone(),
+            None => return Err(Error::watch_not_found()),
+        };
+
+        let mut unwatched: Vec<(PathBuf, RecursiveMode)> = Vec::new();
+        for (path, recursive_mode) in watches {
+            if let Err(e) = self.change(&path, None, Some(recursive_mode)) {
+                for (path, recursive_mode) in unwatched {
+                    let _ = self.change(&path, Some(recursive_mode), None);
+                }
+                return Err(e);
+            }
+            unwatched.push((path, recursive_mode));
+        }
+
+        if let Some(group) = self.groups.get_mut(group) {
+            group.paused = true;
+        }
+        Ok(())
+    }
+
+    /// Watch all paths of a paused `group` again.
+    ///
+    /// Resuming an active group does nothing.
+    pub fn resume_group(&mut self, group: &str) -> Result<()> {
+        let watches = match self.groups.get(group) {
+            Some(group) if !group.paused => return Ok(()),
+            Some(group) => group.watches.clone(),
+            None => return Err(Error::watch_not_found()),
+        };
+
+        let mut watched: Vec<(PathBuf, RecursiveMode)> = Vec::new();
+        for (path, recursive_mode) in watches {
+            if let Err(e) = self.change(&path, Some(recursive_mode), None) {
+                for (path, recursive_mode) in watched {
+                    let _ = self.change(&path, None, Some(recursive_mode));
+                }
+                return Err(e);
+            }
+            watched.push((path, recursive_mode));
+        }
+
+        if let Some(group) = self.groups.get_mut(group) {
+            group.paused = false;
+        }
+        Ok(())
+    }
+
+    /// Whether `group` exists and is paused.
+    pub fn is_paused(&self, group: &str) -> bool {
+        self.groups.get(group).map_or(false, |group| group.paused)
+    }
+
+    /// The names of all groups, in no particular order.
+    pub fn groups(&self) -> impl Iterator<Item = &str> {
+        self.groups.keys().map(String::as_str)
+    }
+
+    /// The paths of `group`, in the order they were added.
+    pub fn paths(&self, group: &str) -> impl Iterator<Item = &Path> {
+        self.groups
+            .get(group)
+            .into_iter()
+            .flat_map(|group| group.watches.iter().map(|(path, _)| path.as_path()))
+    }
+
+    /// Add an active watch of `path` in the mode `add` and remove one in the mode `remove`,
+    /// watching it with the widest mode still in use.
+    fn change(
+        &mut self,
+        path: &Path,
+        add: Option<RecursiveMode>,
+        remove: Option<RecursiveMode>,
+    ) -> Result<()> {
+        let before = self.watched.get(path).copied().unwrap_or_default();
+        let mut after = before;
+        if let Some(mode) = add {
+            *after.count(mode) += 1;
+        }
+        if let Some(mode) = remove {
+            let count = after.count(mode);
+            *count = count.saturating_sub(1);
+        }
+
+        match (before.mode(), after.mode()) {
+            (before, after) if before == after => {}
+            (None, Some(mode)) => self.watcher.watch(path, mode)?,
+            (Some(_), None) => self.watcher.unwatch(path)?,
+            (_, Some(mode)) => self.watcher.set_recursive_mode(path, mode)?,
+            (None, None) => {}
+        }
+        match after.mode() {
+            Some(_) => self.watched.insert(path.to_owned(), after),
+            None => self.watched.remove(path),
+        };
+        Ok(())
+    }
+}
+
+#[test]
+fn groups_are_paused_and_removed_as_a_whole() {
+    use crate::{Config, EventHandler, WatcherKind};
+
+    /// Records which paths are watched, and how.
+    #[derive(Default)]
+    struct Recorder(HashMap<PathBuf, RecursiveMode>);
+
+    impl Watcher for Recorder {
+        fn new<F: EventHandler>(_event_handler: F, _config: Config) -> Result<Self> {
+            Ok(Self::default())
+        }
+
+        fn watch(&mut self, path: &Path, recursive_mode: RecursiveMode) -> Result<()> {
+            self.0.insert(path.to_owned(), recursive_mode);
+            Ok(())
+        }
+
+        fn unwatch(&mut self, path: &Path) -> Result<()> {
+            match self.0.remove(path) {
+                Some(_) => Ok(()),
+                None => Err(Error::watch_not_found()),
+            }
+        }
+
+        fn kind() -> WatcherKind {
+            WatcherKind::NullWatcher
+        }
+    }
+
+    let watched = |groups: &WatchGroups<Recorder>| {
+        let mut paths: Vec<_> = groups.watcher().0.keys().cloned().collect();
+        paths.sort();
+        paths
+    };
+
+    let mut groups = WatchGroups::new(Recorder::default());
+    let (one, shared) = (Path::new("/a/1"), Path::new("/shared"));
+    groups.watch("a", one, RecursiveMode::Recursive).unwrap();
+    groups.watch("a", shared, RecursiveMode::Recursive).unwrap();
+    groups.watch("b", shared, RecursiveMode::Recursive).unwrap();
+
+    groups.pause_group("a").unwrap();
+    assert!(groups.is_paused("a"));
+    assert_eq!(watched(&groups), [shared]);
+
+    groups.resume_group("a").unwrap();
+    assert!(!groups.is_paused("a"));
+    assert_eq!(watched(&groups), [one, shared]);
+    assert_eq!(groups.paths("a").collect::<Vec<_>>(), [one, shared]);
+
+    groups.unwatch_group("b").unwrap();
+    assert_eq!(watched(&groups), [one, shared]);
+    groups.unwatch("a", one).unwrap();
+    assert!(groups.unwatch("a", one).is_err());
+    assert_eq!(watched(&groups), [shared]);
+    assert_eq!(groups.groups().collect::<Vec<_>>(), ["a"]);
+    assert!(groups.pause_group("b").is_err());
+
+    // a path of several groups is watched with the widest mode of the active groups
+    let mode = |groups: &WatchGroups<Recorder>| groups.watcher().0.get(shared).copied();
+    groups
+        .watch("a", shared, RecursiveMode::NonRecursive)
+        .unwrap();
+    assert_eq!(mode(&groups), Some(RecursiveMode::NonRecursive));
+    groups.watch("b", shared, RecursiveMode::Recursive).unwrap();
+    groups.watch("c", shared, RecursiveMode::Recursive).unwrap();
+    groups.pause_group("b").unwrap();
+    assert_eq!(mode(&groups), Some(RecursiveMode::Recursive));
+    groups.unwatch_group("c").unwrap();
+    assert_eq!(mode(&groups), Some(RecursiveMode::NonRecursive));
+    groups.resume_group("b").unwrap();
+    assert_eq!(mode(&groups), Some(RecursiveMode::Recursive));
+    groups
+        .watch("b", shared, RecursiveMode::NonRecursive)
+        .unwrap();
+    assert_eq!(mode(&groups), Some(RecursiveMode::NonRecursive));
+    groups.unwatch("b", shared).unwrap();
+    groups.unwatch("a", shared).unwrap();
+    assert_eq!(mode(&groups), None);
+}
@@ -0,0 +1,131 @@
+//! Watches which end with the scope of a guard
+
+use crate::persist::Registration;
+use crate::{Result, Watcher};
+use std::fmt;
+use std::path::Path;
+
+/// Unwatches a path when it is dropped, see [`Watcher::watch_handle`].
+///
+/// ```no_run
+/// # use std::path::Path;
+/// use notify::persist::Registration;
+/// use notify::{RecursiveMode, Watcher};
+///
+/// # fn main() -> notify::Result<()> {
+/// let mut watcher = notify::recommended_watcher(|event| println!("{:?}", event))?;
+/// {
+///     let mut handle =
+///         watcher.watch_handle(Registration::new("build", RecursiveMode::Recursive))?;
+///     // more watches are made through the handle while it is alive
+///     handle
+///         .watcher()
+///         .watch(Path::new("Cargo.toml"), RecursiveMode::NonRecursive)?;
+/// }
+/// // "build" isn't watched anymore, "Cargo.toml" still is
+/// # Ok(())
+/// # }
+/// ```
+///
+/// An error unwatching the path on drop is ignored, [`WatchHandle::unwatch`] returns it instead.
+pub struct WatchHandle<'a, W: Watcher + ?Sized> {
+    watcher: &'a mut W,
+    registration: Registration,
+    armed: bool,
+}
+
+impl<'a, W: Watcher + ?Sized> WatchHandle<'a, W> {
+    /// Watch `registration` with `watcher`, like [`Watcher::watch_handle`] for watchers which
+    /// are trait objects.
+    pub fn new(watcher: &'a mut W, registration: Registration) -> Result<Self> {
+        registration.install(watcher)?;
+        Ok(Self {
+            watcher,
+            registration,
+            armed: true,
+        })
+    }
+
+    /// The path, modes and token of the watch
+    pub fn registration(&self) -> &Registration {
+        &self.registration
+    }
+
+    /// The absolute path of the watch
+    pub fn path(&self) -> &Path {
+        self.registration.path()
+    }
+
+    /// The token attached to the events of the watch, see [`Watcher::watch_with_token`]
+    pub fn token(&self) -> Option<u64> {
+        self.registration.token()
+    }
+
+    /// The watcher of the watch, to watch other paths while the handle is alive.
+    ///
+    /// Unwatching the path of the handle through it leaves the handle failing to unwatch it again.
+    pub fn watcher(&mut self) -> &mut W {
+        self.watcher
+    }
+
+    /// Keep watching the path after the handle is gone, until it is unwatched with the watcher.
+    pub fn forget(mut self) {
+        self.armed = false;
+    }
+
+    /// Unwatch the path now, returning the error which dropping the handle would ignore.
+    pub fn unwatch(mut self) -> Result<()> {
+        self.armed = false;
+        self.watcher.unwatch(self.registration.path())
+    }
+}
+
+impl<W: Watcher + ?Sized> fmt::Debug for WatchHandle<'_, W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WatchHandle")
+            .field("registration", &self.registration)
+            .field("armed", &self.armed)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<W: Watcher + ?Sized> Drop for WatchHandle<'_, W> {
+    fn drop(&mut self) {
+        if self.armed {
+            let _ = self.watcher.unwatch(self.registration.path());
+        }
+    }
+}
+
+#[test]
+fn dropped_handles_unwatch_their_path() {
+    use crate::{Config, PollWatcher, RecursiveMode};
+
+    let dir = tempfile::tempdir().unwrap();
+    let (a, b) = (dir.path().join("a"), dir.path().join("b"));
+    std::fs::create_dir(&a).unwrap();
+    std::fs::create_dir(&b).unwrap();
+    let mut watcher = PollWatcher::new(|_| {}, Config::default()).unwrap();
+
+    {
+        let mut handle = watcher
+            .watch_handle(Registration::new(&a, RecursiveMode::Recursive).with_token(Some(3)))
+            .unwrap();
+        assert_eq!((handle.path(), handle.token()), (a.as_path(), Some(3)));
+        let kept = WatchHandle::new(
+            handle.watcher(),
+            Registration::new(&b, RecursiveMode::Recursive),
+        )
+        .unwrap();
+        kept.forget();
+        assert!(handle.watcher().is_watched(&a));
+    }
+    assert!(!watcher.is_watched(&a));
+    assert!(watcher.is_watched(&b));
+
+    let handle = watcher
+        .watch_handle(Registration::new(&a, RecursiveMode::NonRecursive))
+        .unwrap();
+    handle.unwatch().unwrap();
+    assert!(watcher.unwatch(&a).is_err());
+}
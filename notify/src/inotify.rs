@@ -5,19 +5,34 @@
 //! will return events for the directory itself, and for files inside the directory.
 
 use super::event::*;
-use super::{Config, Error, ErrorKind, EventHandler, RecursiveMode, Result, Watcher};
-use crate::{bounded, unbounded, BoundSender, Receiver, Sender};
+use super::{Config, Error, ErrorKind, EventHandler, RecursiveMode, Result, SymlinkMode, Watcher};
+use crate::audit::{Audit, Resource};
+use crate::delivery::{Delivery, DeliveryOptions, SharedHandler, WatchTokens};
+use crate::registry::{self, InstallWatches, WatchRegistry};
+use crate::retry::{Retry, RetryAction};
+use crate::scheduling::Scheduling;
+use crate::snapshot::{self, Scanner, Snapshot};
+use crate::suppress::{SuppressGuard, Suppressions};
+use crate::{bounded, unbounded, BoundSender, PollWatcher, Receiver, Sender};
 use inotify as inotify_sys;
 use inotify_sys::{EventMask, Inotify, WatchDescriptor, WatchMask};
-use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::collections::{HashMap, HashSet};
 use std::env;
-use std::ffi::OsStr;
-use std::fs::metadata;
+use std::ffi::{CString, OsStr};
+use std::fmt;
+use std::fs::{self, metadata, File, OpenOptions};
+use std::io;
+use std::mem::MaybeUninit;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::OpenOptionsExt;
 use std::os::unix::io::AsRawFd;
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Once, Weak};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use walkdir::WalkDir;
 
 const INOTIFY: mio::Token = mio::Token(0);
@@ -39,6 +54,52 @@ struct EventLoop {
     watches: HashMap<PathBuf, (WatchDescriptor, WatchMask, bool)>,
     paths: HashMap<WatchDescriptor, PathBuf>,
     rename_event: Option<Event>,
+    initial_scan: bool,
+    retry: Retry,
+    audit: Audit,
+    scheduling: Scheduling,
+    anchored_watches: bool,
+    anchors: HashMap<PathBuf, Anchor>,
+    overflow_recovery: Option<OverflowRecovery>,
+    /// Run by [ManualINotifyWatcher::poll_events] instead of a thread of its own
+    manual: bool,
+    /// Run by the [Reactor] instead of a thread of its own
+    shared: bool,
+    /// When the pending rename event is sent on its own, in manual and shared mode
+    rename_deadline: Option<Instant>,
+}
+
+/// An open file descriptor of a watched path, which keeps referring to it when it is moved.
+struct Anchor {
+    file: File,
+    // where the watched path was found the last time.
+    location: PathBuf,
+}
+
+impl Anchor {
+    fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_PATH | libc::O_CLOEXEC)
+            .open(path)?;
+        let location =
+            Self::resolve_file(&file).ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))?;
+        Ok(Anchor { file, location })
+    }
+
+    /// The current location of the anchored path, `None` if it was deleted.
+    fn resolve(&self) -> Option<PathBuf> {
+        Self::resolve_file(&self.file)
+    }
+
+    fn resolve_file(file: &File) -> Option<PathBuf> {
+        let location = fs::read_link(format!("/proc/self/fd/{}", file.as_raw_fd())).ok()?;
+        // the kernel marks deleted paths this way.
+        if location.as_os_str().as_bytes().ends_with(b" (deleted)") {
+            return None;
+        }
+        Some(location)
+    }
 }
 
 /// Watcher implementation based on inotify
@@ -46,14 +107,22 @@ struct EventLoop {
 pub struct INotifyWatcher {
     channel: Sender<EventLoopMsg>,
     waker: Arc<mio::Waker>,
+    /// The reactor running the event loop, if it is shared
+    reactor: Option<Arc<Reactor>>,
+    registry: WatchRegistry,
+    tokens: WatchTokens,
+    options: DeliveryOptions,
+    suppressions: Suppressions,
+    fallback: Option<PollFallback>,
 }
 
 enum EventLoopMsg {
-    AddWatch(PathBuf, RecursiveMode, Sender<Result<()>>),
+    AddWatch(PathBuf, RecursiveMode, SymlinkMode, Sender<Result<()>>),
     RemoveWatch(PathBuf, Sender<Result<()>>),
+    SetRecursiveMode(PathBuf, RecursiveMode, Sender<Result<()>>),
     Shutdown,
     RenameTimeout(usize),
-    Configure(Config, BoundSender<Result<bool>>),
+    Configure(Box<Config>, BoundSender<Result<bool>>),
 }
 
 #[inline]
@@ -100,7 +169,11 @@ fn remove_watch_by_event(
 }
 
 impl EventLoop {
-    pub fn new(inotify: Inotify, event_handler: Box<dyn EventHandler>) -> Result<Self> {
+    pub fn new(
+        inotify: Inotify,
+        event_handler: Box<dyn EventHandler>,
+        config: &Config,
+    ) -> Result<Self> {
         let (event_loop_tx, event_loop_rx) = unbounded::<EventLoopMsg>();
         let poll = mio::Poll::new()?;
 
@@ -122,40 +195,73 @@ impl EventLoop {
             watches: HashMap::new(),
             paths: HashMap::new(),
             rename_event: None,
+            initial_scan: config.initial_scan(),
+            retry: Retry::new(config.retry_policy()),
+            audit: Audit::new("inotify", config),
+            scheduling: Scheduling::new(config),
+            anchored_watches: config.anchored_watches(),
+            anchors: HashMap::new(),
+            overflow_recovery: config
+                .overflow_recovery()
+                .then(|| OverflowRecovery::new(config)),
+            manual: false,
+            shared: config.shared_event_loop(),
+            rename_deadline: None,
         };
         Ok(event_loop)
     }
 
-    // Run the event loop.
-    pub fn run(self) {
-        let _ = thread::Builder::new()
+    // Run the event loop, on the shared reactor if configured, which is returned then.
+    pub fn run(self) -> Result<Option<Arc<Reactor>>> {
+        if self.shared {
+            let reactor = Reactor::get()?;
+            reactor.add(self)?;
+            return Ok(Some(reactor));
+        }
+        thread::Builder::new()
             .name("notify-rs inotify loop".to_string())
-            .spawn(|| self.event_loop_thread());
+            .spawn(|| self.event_loop_thread())
+            .map_err(Error::io)?;
+        Ok(None)
     }
 
     fn event_loop_thread(mut self) {
+        let thread = thread::current().id();
+        self.audit.acquire(Resource::Thread, thread);
+        let _ = self.scheduling.apply();
         let mut events = mio::Events::with_capacity(16);
-        loop {
-            // Wait for something to happen.
-            match self.poll.poll(&mut events, None) {
-                Err(ref e) if matches!(e.kind(), std::io::ErrorKind::Interrupted) => {
-                    // System call was interrupted, we will retry
-                    // TODO: Not covered by tests (to reproduce likely need to setup signal handlers)
-                }
-                Err(e) => panic!("poll failed: {}", e),
-                Ok(()) => {}
-            }
+        while self.turn(&mut events, None) {}
+        self.finish();
+        self.audit.release(Resource::Thread, thread);
+    }
 
-            // Process whatever happened.
-            for event in &events {
-                self.handle_event(event);
-            }
+    /// Wait at most `timeout` for events and handle them, returns false once the loop stopped.
+    fn turn(&mut self, events: &mut mio::Events, timeout: Option<Duration>) -> bool {
+        // Wait for something to happen.
+        match self.poll.poll(events, timeout) {
+            Ok(()) => self.retry.reset(),
+            Err(e) => match self.retry.fail(e, &mut *self.event_handler) {
+                RetryAction::Retry => return true,
+                RetryAction::Stop => return false,
+            },
+        }
 
-            // Stop, if we're done.
-            if !self.running {
-                break;
-            }
+        // Process whatever happened.
+        for event in events.iter() {
+            self.handle_event(event);
+        }
+
+        // Stop, if we're done.
+        if !self.running {
+            return false;
         }
+        self.audit_watches();
+        true
+    }
+
+    fn finish(&self) {
+        // the descriptors still held are closed with inotify
+        self.audit.release_all(Resource::WatchDescriptor);
     }
 
     // Handle a single event.
@@ -176,14 +282,57 @@ impl EventLoop {
     fn handle_messages(&mut self) {
         while let Ok(msg) = self.event_loop_rx.try_recv() {
             match msg {
-                EventLoopMsg::AddWatch(path, recursive_mode, tx) => {
-                    let _ = tx.send(self.add_watch(path, recursive_mode.is_recursive(), true));
+                EventLoopMsg::AddWatch(path, recursive_mode, symlink_mode, tx) => {
+                    if symlink_mode == SymlinkMode::Link && is_symlink(&path) {
+                        let result = self.add_link_watch(path.clone());
+                        if result.is_ok() && self.initial_scan {
+                            let event = snapshot::initial_scan_link_event(&path);
+                            self.event_handler.handle_event(Ok(event));
+                        }
+                        let _ = tx.send(result);
+                        continue;
+                    }
+                    let mut result =
+                        self.add_watch(path.clone(), recursive_mode.is_recursive(), true);
+                    if result.is_ok() && self.anchored_watches {
+                        match Anchor::open(&path) {
+                            Ok(anchor) => {
+                                self.anchors.insert(path.clone(), anchor);
+                            }
+                            Err(e) => {
+                                let _ = self.remove_watch(path.clone(), false);
+                                result = Err(Error::io(e).add_path(path.clone()));
+                            }
+                        }
+                    }
+                    if let (Ok(()), Some(recovery)) = (&result, &mut self.overflow_recovery) {
+                        recovery.add(&path, recursive_mode.is_recursive());
+                    }
+                    if result.is_ok() && self.initial_scan {
+                        for event in snapshot::initial_scan_events(&path, recursive_mode) {
+                            self.event_handler.handle_event(Ok(event));
+                        }
+                    }
+                    let _ = tx.send(result);
                 }
                 EventLoopMsg::RemoveWatch(path, tx) => {
+                    self.anchors.remove(&path);
+                    if let Some(ref mut recovery) = self.overflow_recovery {
+                        recovery.remove(&path);
+                    }
                     let _ = tx.send(self.remove_watch(path, false));
                 }
+                EventLoopMsg::SetRecursiveMode(path, recursive_mode, tx) => {
+                    let result =
+                        self.set_recursive_mode(path.clone(), recursive_mode.is_recursive());
+                    if let (Ok(()), Some(recovery)) = (&result, &mut self.overflow_recovery) {
+                        recovery.add(&path, recursive_mode.is_recursive());
+                    }
+                    let _ = tx.send(result);
+                }
                 EventLoopMsg::Shutdown => {
                     let _ = self.remove_all_watches();
+                    self.audit_watches();
                     if let Some(inotify) = self.inotify.take() {
                         let _ = inotify.close();
                     }
@@ -198,42 +347,155 @@ impl EventLoop {
                     }
                 }
                 EventLoopMsg::Configure(config, tx) => {
-                    self.configure_raw_mode(config, tx);
+                    self.configure(&config);
+                    let _ = tx.send(Ok(true));
                 }
             }
         }
     }
 
-    fn configure_raw_mode(&mut self, _config: Config, tx: BoundSender<Result<bool>>) {
-        tx.send(Ok(false))
-            .expect("configuration channel disconnected");
+    /// Apply the options of `config` which the event loop handles and can be changed while it
+    /// runs.
+    fn configure(&mut self, config: &Config) {
+        self.initial_scan = config.initial_scan();
+    }
+
+    /// Follow the anchored watches to their current location, reporting their moves.
+    fn update_anchors(&mut self) {
+        let mut moves = Vec::new();
+        for (path, anchor) in &mut self.anchors {
+            if let Some(location) = anchor.resolve() {
+                if location != anchor.location {
+                    anchor.location = location.clone();
+                    moves.push((path.clone(), location));
+                }
+            }
+        }
+
+        for (from, to) in moves {
+            self.relocate(&from, &to);
+            self.event_handler
+                .handle_event(Ok(Event::new(EventKind::Modify(ModifyKind::Name(
+                    RenameMode::Both,
+                )))
+                .add_path(from)
+                .add_path(to)));
+        }
+    }
+
+    /// Replace the `from` prefix of all watched paths by `to`.
+    fn relocate(&mut self, from: &Path, to: &Path) {
+        let relocated = |path: &Path| path.strip_prefix(from).ok().map(|rest| to.join(rest));
+
+        let moved_watches: Vec<_> = self
+            .watches
+            .keys()
+            .filter(|path| path.starts_with(from))
+            .cloned()
+            .collect();
+        for path in moved_watches {
+            if let (Some(watch), Some(new_path)) = (self.watches.remove(&path), relocated(&path)) {
+                self.watches.insert(new_path, watch);
+            }
+        }
+
+        for path in self.paths.values_mut() {
+            if let Some(new_path) = relocated(path) {
+                *path = new_path;
+            }
+        }
+
+        let moved_anchors: Vec<_> = self
+            .anchors
+            .keys()
+            .filter(|path| path.starts_with(from))
+            .cloned()
+            .collect();
+        for path in moved_anchors {
+            if let (Some(anchor), Some(new_path)) = (self.anchors.remove(&path), relocated(&path)) {
+                self.anchors.insert(new_path, anchor);
+            }
+        }
+
+        if let Some(ref mut recovery) = self.overflow_recovery {
+            recovery.relocate(from, to);
+        }
     }
 
     fn handle_inotify(&mut self) {
         let mut add_watches = Vec::new();
         let mut remove_watches = Vec::new();
 
-        if let Some(ref mut inotify) = self.inotify {
+        // taken while reading, as following anchors needs the whole event loop.
+        if let Some(mut inotify) = self.inotify.take() {
             let mut buffer = [0; 1024];
             // Read all buffers available.
             loop {
                 match inotify.read_events(&mut buffer) {
                     Ok(events) => {
+                        self.retry.reset();
+                        // renaming an ancestor of a watch doesn't cause any events, so the
+                        // anchors are followed once for every batch before its events get paths.
+                        if !self.anchors.is_empty() {
+                            self.update_anchors();
+                        }
                         let mut num_events = 0;
                         for event in events {
                             num_events += 1;
                             if event.mask.contains(EventMask::Q_OVERFLOW) {
                                 let ev = Ok(Event::new(EventKind::Other).set_flag(Flag::Rescan));
                                 self.event_handler.handle_event(ev);
+
+                                if let Some(ref mut recovery) = self.overflow_recovery {
+                                    for ev in recovery.rescan() {
+                                        // the directories created meanwhile need watches as well.
+                                        let path = ev.paths.first().cloned();
+                                        match ev.kind {
+                                            EventKind::Create(_)
+                                                if path.as_ref().map_or(false, |p| p.is_dir()) =>
+                                            {
+                                                let parent_is_recursive = path
+                                                    .as_ref()
+                                                    .and_then(|p| p.parent())
+                                                    .and_then(|parent| self.watches.get(parent))
+                                                    .map_or(false, |&(_, _, is_recursive)| {
+                                                        is_recursive
+                                                    });
+                                                if parent_is_recursive {
+                                                    add_watches.extend(path);
+                                                }
+                                            }
+                                            EventKind::Remove(_) => remove_watch_by_event(
+                                                &path,
+                                                &self.watches,
+                                                &mut remove_watches,
+                                            ),
+                                            _ => {}
+                                        }
+                                        self.event_handler.handle_event(Ok(ev));
+                                    }
+                                }
                             }
 
                             let path = match event.name {
-                                Some(name) => {
-                                    self.paths.get(&event.wd).map(|root| root.join(&name))
-                                }
+                                Some(name) => self.paths.get(&event.wd).map(|root| root.join(name)),
                                 None => self.paths.get(&event.wd).cloned(),
                             };
 
+                            // the watched directories themselves are rescanned as a whole.
+                            if let (Some(recovery), Some(path), Some(_)) =
+                                (&mut self.overflow_recovery, &path, event.name)
+                            {
+                                let with_children = event.mask.contains(EventMask::ISDIR)
+                                    && event.mask.intersects(
+                                        EventMask::CREATE
+                                            | EventMask::DELETE
+                                            | EventMask::MOVED_FROM
+                                            | EventMask::MOVED_TO,
+                                    );
+                                recovery.record(path, with_children);
+                            }
+
                             if event.mask.contains(EventMask::MOVED_FROM) {
                                 send_pending_rename_event(
                                     &mut self.rename_event,
@@ -278,7 +540,8 @@ impl EventLoop {
                                                         CreateKind::File
                                                     },
                                                 ))
-                                                .add_some_path(path.clone()),
+                                                .add_some_path(path.clone())
+                                                .set_precision(Precision::Inferred),
                                             );
                                         }
                                     } else {
@@ -291,7 +554,8 @@ impl EventLoop {
                                                     CreateKind::File
                                                 },
                                             ))
-                                            .add_some_path(path.clone()),
+                                            .add_some_path(path.clone())
+                                            .set_precision(Precision::Inferred),
                                         );
                                     }
                                     add_watch_by_event(
@@ -301,7 +565,12 @@ impl EventLoop {
                                         &mut add_watches,
                                     );
                                 }
-                                if event.mask.contains(EventMask::MOVE_SELF) {
+                                // anchored watches report their moves by themselves.
+                                if event.mask.contains(EventMask::MOVE_SELF)
+                                    && !path
+                                        .as_ref()
+                                        .map_or(false, |p| self.anchors.contains_key(p))
+                                {
                                     evs.push(
                                         Event::new(EventKind::Modify(ModifyKind::Name(
                                             RenameMode::From,
@@ -416,27 +685,39 @@ impl EventLoop {
                         // TODO: don't do this here, instead leave it entirely to the debounce
                         // -> related to some rename events being reported as creates.
 
-                        if let Some(ref rename_event) = self.rename_event {
+                        if (self.manual || self.shared) && self.rename_event.is_some() {
+                            // there's no thread to wait on, the next poll or the reactor sends it
+                            self.rename_deadline = Some(Instant::now() + Duration::from_millis(10));
+                        } else if let Some(ref rename_event) = self.rename_event {
                             let event_loop_tx = self.event_loop_tx.clone();
                             let waker = self.event_loop_waker.clone();
                             let cookie = rename_event.tracker().unwrap(); // unwrap is safe because rename_event is always set with some cookie
+                            let audit = self.audit.clone();
                             let _ = thread::Builder::new()
                                 .name("notify-rs inotify rename".to_string())
                                 .spawn(move || {
+                                    let thread = thread::current().id();
+                                    audit.acquire(Resource::Thread, thread);
                                     thread::sleep(Duration::from_millis(10)); // wait up to 10 ms for a subsequent event
 
                                     // An error here means the other end of the channel was closed, a thing that can
                                     // happen normally.
                                     let _ = event_loop_tx.send(EventLoopMsg::RenameTimeout(cookie));
                                     let _ = waker.wake();
+                                    audit.release(Resource::Thread, thread);
                                 });
                         }
                     }
-                    Err(e) => {
-                        self.event_handler.handle_event(Err(Error::io(e)));
-                    }
+                    Err(e) => match self.retry.fail(e, &mut *self.event_handler) {
+                        RetryAction::Retry => continue,
+                        RetryAction::Stop => {
+                            self.running = false;
+                            break;
+                        }
+                    },
                 }
             }
+            self.inotify = Some(inotify);
         }
 
         for path in remove_watches {
@@ -448,6 +729,23 @@ impl EventLoop {
         }
     }
 
+    /// Send the pending rename event if no matching event arrived in time, in manual and shared
+    /// mode.
+    fn send_expired_rename_event(&mut self) {
+        match self.rename_deadline {
+            Some(deadline) if Instant::now() >= deadline => {
+                self.rename_deadline = None;
+                send_pending_rename_event(&mut self.rename_event, &mut *self.event_handler);
+            }
+            _ => {}
+        }
+    }
+
+    /// When the pending rename event is due, if there is one
+    fn pending_rename_deadline(&self) -> Option<Instant> {
+        self.rename_event.as_ref().and(self.rename_deadline)
+    }
+
     fn add_watch(&mut self, path: PathBuf, is_recursive: bool, mut watch_self: bool) -> Result<()> {
         // If the watch is not recursive, or if we determine (by stat'ing the path to get its
         // metadata) that the watched path is not a directory, add a single path watch.
@@ -467,6 +765,15 @@ impl EventLoop {
         Ok(())
     }
 
+    /// Watch the symbolic link at `path` itself, not its target.
+    fn add_link_watch(&mut self, path: PathBuf) -> Result<()> {
+        let watchmask = WatchMask::ATTRIB
+            | WatchMask::DELETE_SELF
+            | WatchMask::MOVE_SELF
+            | WatchMask::DONT_FOLLOW;
+        self.add_watch_with_mask(path, watchmask, false)
+    }
+
     fn add_single_watch(
         &mut self,
         path: PathBuf,
@@ -486,6 +793,15 @@ impl EventLoop {
             watchmask.insert(WatchMask::MOVE_SELF);
         }
 
+        self.add_watch_with_mask(path, watchmask, is_recursive)
+    }
+
+    fn add_watch_with_mask(
+        &mut self,
+        path: PathBuf,
+        mut watchmask: WatchMask,
+        is_recursive: bool,
+    ) -> Result<()> {
         if let Some(&(_, old_watchmask, _)) = self.watches.get(&path) {
             watchmask.insert(old_watchmask);
             watchmask.insert(WatchMask::MASK_ADD);
@@ -504,6 +820,7 @@ impl EventLoop {
                 }
                 Ok(w) => {
                     watchmask.remove(WatchMask::MASK_ADD);
+                    self.audit.acquire(Resource::WatchDescriptor, &w);
                     self.watches
                         .insert(path.clone(), (w.clone(), watchmask, is_recursive));
                     self.paths.insert(w, path);
@@ -516,51 +833,107 @@ impl EventLoop {
     }
 
     fn remove_watch(&mut self, path: PathBuf, remove_recursive: bool) -> Result<()> {
-        match self.watches.remove(&path) {
-            None => return Err(Error::watch_not_found().add_path(path)),
+        match self.watches.get(&path) {
+            None => Err(Error::watch_not_found().add_path(path)),
             Some((w, _, is_recursive)) => {
-                if let Some(ref mut inotify) = self.inotify {
-                    inotify
-                        .rm_watch(w.clone())
-                        .map_err(|e| Error::io(e).add_path(path.clone()))?;
-                    self.paths.remove(&w);
-
-                    if is_recursive || remove_recursive {
-                        let mut remove_list = Vec::new();
-                        for (w, p) in &self.paths {
-                            if p.starts_with(&path) {
-                                inotify
-                                    .rm_watch(w.clone())
-                                    .map_err(|e| Error::io(e).add_path(p.into()))?;
-                                self.watches.remove(p);
-                                remove_list.push(w.clone());
-                            }
-                        }
-                        for w in remove_list {
-                            self.paths.remove(&w);
-                        }
-                    }
+                let mut remove_list = vec![(w.clone(), path.clone())];
+                if *is_recursive || remove_recursive {
+                    remove_list.extend(
+                        self.paths
+                            .iter()
+                            .filter(|(_, p)| p.starts_with(&path) && **p != path)
+                            .map(|(w, p)| (w.clone(), p.clone())),
+                    );
                 }
+                self.remove_descriptors(remove_list)
             }
         }
-        Ok(())
+    }
+
+    /// Add or remove the watches below the directory at `path`, which keeps its own watch.
+    fn set_recursive_mode(&mut self, path: PathBuf, is_recursive: bool) -> Result<()> {
+        if is_recursive {
+            // the watch of `path` itself is only extended.
+            return self.add_watch(path, true, true);
+        }
+
+        let remove_list = self
+            .paths
+            .iter()
+            .filter(|(_, p)| p.starts_with(&path) && **p != path)
+            .map(|(w, p)| (w.clone(), p.clone()))
+            .collect();
+        let result = self.remove_descriptors(remove_list);
+        if let Some((_, _, is_recursive)) = self.watches.get_mut(&path) {
+            *is_recursive = false;
+        }
+        result
     }
 
     fn remove_all_watches(&mut self) -> Result<()> {
-        if let Some(ref mut inotify) = self.inotify {
-            for (w, p) in &self.paths {
-                inotify
-                    .rm_watch(w.clone())
-                    .map_err(|e| Error::io(e).add_path(p.into()))?;
+        let remove_list = self
+            .paths
+            .iter()
+            .map(|(w, p)| (w.clone(), p.clone()))
+            .collect();
+        self.remove_descriptors(remove_list)
+    }
+
+    /// Remove these watches, returning the first error of removing them from inotify.
+    ///
+    /// The watches are forgotten even then, inotify fails to remove watches it already dropped
+    /// itself, like those of deleted directories.
+    fn remove_descriptors(&mut self, remove_list: Vec<(WatchDescriptor, PathBuf)>) -> Result<()> {
+        let mut result = Ok(());
+        for (w, path) in remove_list {
+            self.watches.remove(&path);
+            if self.paths.remove(&w).is_some() {
+                self.audit.release(Resource::WatchDescriptor, &w);
+            }
+            if let Some(ref mut inotify) = self.inotify {
+                if let Err(e) = inotify.rm_watch(w) {
+                    if result.is_ok() {
+                        result = Err(Error::io(e).add_path(path));
+                    }
+                }
             }
-            self.watches.clear();
-            self.paths.clear();
         }
-        Ok(())
+        result
+    }
+
+    /// Check that the watches and the paths of their descriptors agree, with the `debug-audit`
+    /// feature.
+    fn audit_watches(&self) {
+        let (watches, paths, audit) = (&self.watches, &self.paths, &self.audit);
+        audit.check(|| {
+            for (w, path) in paths {
+                match watches.get(path) {
+                    Some((watch, _, _)) if watch == w => {}
+                    _ => return Some(format!("the descriptor of {} has no watch", path.display())),
+                }
+            }
+            for (path, (w, _, _)) in watches {
+                if !paths.contains_key(w) {
+                    return Some(format!("the watch of {} has no descriptor", path.display()));
+                }
+            }
+            let held = audit.held(Resource::WatchDescriptor);
+            (held != paths.len()).then(|| {
+                format!(
+                    "{} watch descriptors are held for {} watches",
+                    held,
+                    paths.len()
+                )
+            })
+        });
     }
 }
 
 /// return `DirEntry` when it is a directory
+fn is_symlink(path: &Path) -> bool {
+    std::fs::symlink_metadata(path).map_or(false, |m| m.file_type().is_symlink())
+}
+
 fn filter_dir(e: walkdir::Result<walkdir::DirEntry>) -> Option<walkdir::DirEntry> {
     if let Ok(e) = e {
         if let Ok(metadata) = e.metadata() {
@@ -572,17 +945,216 @@ fn filter_dir(e: walkdir::Result<walkdir::DirEntry>) -> Option<walkdir::DirEntry
     None
 }
 
+/// Token of the waker of the [Reactor], the event loops get the tokens after it
+const REACTOR: mio::Token = mio::Token(0);
+
+/// The slot of the reactor of the process, allocated once and never freed
+static SHARED_REACTOR: AtomicPtr<Mutex<Weak<Reactor>>> = AtomicPtr::new(ptr::null_mut());
+static SHARED_REACTOR_INIT: Once = Once::new();
+
+/// The thread running the event loops of all watchers with [Config::with_shared_event_loop]
+///
+/// The poll of every event loop is registered with the poll of the reactor, which becomes
+/// readable whenever one of them does. The event loop whose poll is readable then handles its
+/// events without waiting. The reactor is shared by the watchers using it and stops once the
+/// last one is dropped.
+#[derive(Debug)]
+pub(crate) struct Reactor {
+    registry: mio::Registry,
+    waker: mio::Waker,
+    tx: Mutex<Sender<(mio::Token, EventLoop)>>,
+    next_token: AtomicUsize,
+    /// Cleared once the reactor takes no more event loops, because it failed or was dropped
+    running: Arc<AtomicBool>,
+    /// The reactor thread, which mustn't wait for itself
+    thread: thread::ThreadId,
+}
+
+impl Reactor {
+    /// The reactor of the process, started again if there is none or the last one failed
+    fn get() -> Result<Arc<Reactor>> {
+        SHARED_REACTOR_INIT.call_once(|| {
+            let slot = Box::new(Mutex::new(Weak::new()));
+            SHARED_REACTOR.store(Box::into_raw(slot), Ordering::Release);
+        });
+        let slot = unsafe { &*SHARED_REACTOR.load(Ordering::Acquire) };
+        let mut slot = slot.lock().unwrap_or_else(|p| p.into_inner());
+        if let Some(reactor) = slot.upgrade() {
+            if reactor.running.load(Ordering::Acquire) {
+                return Ok(reactor);
+            }
+        }
+
+        let poll = mio::Poll::new()?;
+        let registry = poll.registry().try_clone()?;
+        let waker = mio::Waker::new(poll.registry(), REACTOR)?;
+        let (tx, rx) = unbounded();
+        let running = Arc::new(AtomicBool::new(true));
+        let running_c = running.clone();
+        let thread = thread::Builder::new()
+            .name("notify-rs inotify reactor".to_string())
+            .spawn(move || Reactor::run(poll, rx, &running_c))
+            .map_err(Error::io)?;
+        let reactor = Arc::new(Reactor {
+            registry,
+            waker,
+            tx: Mutex::new(tx),
+            next_token: AtomicUsize::new(REACTOR.0 + 1),
+            running,
+            thread: thread.thread().id(),
+        });
+        *slot = Arc::downgrade(&reactor);
+        Ok(reactor)
+    }
+
+    /// Run `event_loop` on the reactor thread until it shuts down.
+    fn add(&self, event_loop: EventLoop) -> Result<()> {
+        let token = mio::Token(self.next_token.fetch_add(1, Ordering::Relaxed));
+        let fd = event_loop.poll.as_raw_fd();
+        self.registry.register(
+            &mut mio::unix::SourceFd(&fd),
+            token,
+            mio::Interest::READABLE,
+        )?;
+        let sent = match self.tx.lock() {
+            Ok(tx) => tx.send((token, event_loop)).is_ok(),
+            Err(_) => false,
+        };
+        if !sent {
+            let _ = self.registry.deregister(&mut mio::unix::SourceFd(&fd));
+            return Err(Error::generic("The shared inotify event loop stopped."));
+        }
+        self.waker.wake()?;
+        Ok(())
+    }
+
+    fn run(mut poll: mio::Poll, rx: Receiver<(mio::Token, EventLoop)>, running: &AtomicBool) {
+        let mut loops: HashMap<mio::Token, EventLoop> = HashMap::new();
+        // readable event loops, including those which didn't arrive over the channel yet
+        let mut ready = HashSet::new();
+        let mut events = mio::Events::with_capacity(64);
+        let mut loop_events = mio::Events::with_capacity(16);
+        loop {
+            // the pending rename events are timers of the reactor
+            let deadline = loops
+                .values()
+                .filter_map(EventLoop::pending_rename_deadline)
+                .min();
+            let timeout =
+                deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+            if let Err(e) = poll.poll(&mut events, timeout) {
+                if e.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                // the next watcher starts another reactor
+                running.store(false, Ordering::Release);
+                // the event loops can't be waited for anymore, each reports the error and stops
+                while let Ok((token, event_loop)) = rx.try_recv() {
+                    loops.insert(token, event_loop);
+                }
+                for (_, mut event_loop) in loops.drain() {
+                    let e = io::Error::new(e.kind(), e.to_string());
+                    event_loop.event_handler.handle_event(Err(Error::io(e)));
+                    event_loop.finish();
+                }
+                return;
+            }
+
+            ready.extend(
+                events
+                    .iter()
+                    .map(|event| event.token())
+                    .filter(|token| *token != REACTOR),
+            );
+            while let Ok((token, event_loop)) = rx.try_recv() {
+                loops.insert(token, event_loop);
+            }
+
+            let mut stopped = Vec::new();
+            ready.retain(|token| match loops.get_mut(token) {
+                Some(event_loop) => {
+                    if !event_loop.turn(&mut loop_events, Some(Duration::ZERO)) {
+                        stopped.push(*token);
+                    }
+                    false
+                }
+                None => true,
+            });
+            if deadline.map_or(false, |deadline| Instant::now() >= deadline) {
+                for event_loop in loops.values_mut() {
+                    event_loop.send_expired_rename_event();
+                }
+            }
+            for token in stopped {
+                if let Some(event_loop) = loops.remove(&token) {
+                    let fd = event_loop.poll.as_raw_fd();
+                    let _ = poll.registry().deregister(&mut mio::unix::SourceFd(&fd));
+                    event_loop.finish();
+                }
+            }
+
+            // the watchers shut their event loops down before they drop the reactor
+            if loops.is_empty() && !running.load(Ordering::Acquire) {
+                return;
+            }
+        }
+    }
+}
+
+impl Drop for Reactor {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Release);
+        let _ = self.waker.wake();
+    }
+}
+
 impl INotifyWatcher {
-    fn from_event_handler(event_handler: Box<dyn EventHandler>) -> Result<Self> {
+    fn from_delivery(delivery: Delivery, config: &Config) -> Result<Self> {
+        let tokens = delivery.tokens();
+        let options = delivery.options();
+        let suppressions = delivery.suppressions();
         let inotify = Inotify::init()?;
-        let event_loop = EventLoop::new(inotify, event_handler)?;
+        let event_loop = EventLoop::new(inotify, Box::new(delivery), config)?;
         let channel = event_loop.event_loop_tx.clone();
         let waker = event_loop.event_loop_waker.clone();
-        event_loop.run();
-        Ok(INotifyWatcher { channel, waker })
+        let reactor = event_loop.run()?;
+        Ok(INotifyWatcher {
+            channel,
+            waker,
+            reactor,
+            registry: WatchRegistry::new(config.watch_overlap()),
+            tokens,
+            options,
+            suppressions,
+            fallback: None,
+        })
     }
 
-    fn watch_inner(&mut self, path: &Path, recursive_mode: RecursiveMode) -> Result<()> {
+    /// Send `msg` to the event loop, for a reply which the caller waits for.
+    ///
+    /// Fails on the thread of a shared event loop, which would wait for itself.
+    fn send(&self, msg: EventLoopMsg) -> Result<()> {
+        if let Some(ref reactor) = self.reactor {
+            if thread::current().id() == reactor.thread {
+                return Err(Error::generic(
+                    "A watcher on the shared inotify event loop can't be changed from an event \
+                     handler.",
+                ));
+            }
+        }
+        self.channel
+            .send(msg)
+            .map_err(|_| Error::generic("The inotify event loop stopped."))?;
+        self.waker.wake()?;
+        Ok(())
+    }
+
+    fn watch_inner(
+        &mut self,
+        path: &Path,
+        recursive_mode: RecursiveMode,
+        symlink_mode: SymlinkMode,
+    ) -> Result<()> {
         let pb = if path.is_absolute() {
             path.to_owned()
         } else {
@@ -590,12 +1162,11 @@ impl INotifyWatcher {
             p.join(path)
         };
         let (tx, rx) = unbounded();
-        let msg = EventLoopMsg::AddWatch(pb, recursive_mode, tx);
+        let msg = EventLoopMsg::AddWatch(pb, recursive_mode, symlink_mode, tx);
 
-        // we expect the event loop to live and reply => unwraps must not panic
-        self.channel.send(msg).unwrap();
-        self.waker.wake().unwrap();
-        rx.recv().unwrap()
+        self.send(msg)?;
+        rx.recv()
+            .map_err(|_| Error::generic("The inotify event loop stopped."))?
     }
 
     fn unwatch_inner(&mut self, path: &Path) -> Result<()> {
@@ -608,31 +1179,166 @@ impl INotifyWatcher {
         let (tx, rx) = unbounded();
         let msg = EventLoopMsg::RemoveWatch(pb, tx);
 
-        // we expect the event loop to live and reply => unwraps must not panic
-        self.channel.send(msg).unwrap();
-        self.waker.wake().unwrap();
-        rx.recv().unwrap()
+        self.send(msg)?;
+        rx.recv()
+            .map_err(|_| Error::generic("The inotify event loop stopped."))?
+    }
+
+    fn set_recursive_mode_inner(
+        &mut self,
+        path: &Path,
+        recursive_mode: RecursiveMode,
+    ) -> Result<()> {
+        match self.registry.recursive_mode(path) {
+            None => return Err(Error::watch_not_found().add_path(path.to_owned())),
+            Some(current) if current == recursive_mode => return Ok(()),
+            Some(_) => {}
+        }
+
+        let pb = if path.is_absolute() {
+            path.to_owned()
+        } else {
+            let p = env::current_dir().map_err(Error::io)?;
+            p.join(path)
+        };
+        let (tx, rx) = unbounded();
+        let msg = EventLoopMsg::SetRecursiveMode(pb, recursive_mode, tx);
+
+        self.send(msg)?;
+        rx.recv()
+            .map_err(|_| Error::generic("The inotify event loop stopped."))?
+    }
+}
+
+impl InstallWatches for INotifyWatcher {
+    /// Watches with inotify, or the poll fallback if `path` needs polling.
+    fn install(&mut self, path: &Path, recursive_mode: RecursiveMode) -> Result<()> {
+        match self.fallback {
+            Some(ref mut fallback) if needs_polling(path) => {
+                fallback.watch(path, recursive_mode, None)
+            }
+            _ => self.watch_inner(path, recursive_mode, SymlinkMode::Target),
+        }
+    }
+
+    fn uninstall(&mut self, path: &Path) -> Result<()> {
+        match self.fallback {
+            Some(ref mut fallback) if fallback.contains(path) => fallback.unwatch(path),
+            _ => self.unwatch_inner(path),
+        }
     }
 }
 
 impl Watcher for INotifyWatcher {
     /// Create a new watcher.
-    fn new<F: EventHandler>(event_handler: F, _config: Config) -> Result<Self> {
-        Self::from_event_handler(Box::new(event_handler))
+    fn new<F: EventHandler>(event_handler: F, config: Config) -> Result<Self> {
+        if !config.poll_fallback() {
+            return Self::from_delivery(Delivery::new(event_handler, &config), &config);
+        }
+
+        let event_handler = SharedHandler::new(event_handler);
+        let mut watcher =
+            Self::from_delivery(Delivery::new(event_handler.clone(), &config), &config)?;
+        watcher.fallback = Some(PollFallback::new(event_handler, config));
+        Ok(watcher)
     }
 
     fn watch(&mut self, path: &Path, recursive_mode: RecursiveMode) -> Result<()> {
-        self.watch_inner(path, recursive_mode)
+        let plan = self.registry.plan_watch(path, recursive_mode)?;
+        plan.apply(self, |watcher| watcher.install(path, recursive_mode))?;
+        self.registry.insert(path, recursive_mode);
+        Ok(())
+    }
+
+    fn watch_with_symlink_mode(
+        &mut self,
+        path: &Path,
+        recursive_mode: RecursiveMode,
+        symlink_mode: SymlinkMode,
+    ) -> Result<()> {
+        if symlink_mode == SymlinkMode::Target {
+            return self.watch(path, recursive_mode);
+        }
+        // the poll fallback can't tell links from their targets, links are always watched here.
+        let plan = self.registry.plan_watch(path, recursive_mode)?;
+        plan.apply(self, |watcher| {
+            watcher.watch_inner(path, recursive_mode, symlink_mode)
+        })?;
+        self.registry.insert(path, recursive_mode);
+        Ok(())
+    }
+
+    fn watch_with_token(
+        &mut self,
+        path: &Path,
+        recursive_mode: RecursiveMode,
+        token: u64,
+    ) -> Result<()> {
+        let plan = self.registry.plan_watch(path, recursive_mode)?;
+        let polled = self.fallback.is_some() && needs_polling(path);
+        if !polled {
+            // registered first, so that the initial scan carries the token already.
+            self.tokens.insert(path, token);
+        }
+        let result = plan.apply(self, |watcher| match watcher.fallback {
+            Some(ref mut fallback) if polled => fallback.watch(path, recursive_mode, Some(token)),
+            _ => watcher.watch_inner(path, recursive_mode, SymlinkMode::Target),
+        });
+        if let Err(e) = result {
+            if !polled {
+                self.tokens.remove(path);
+            }
+            return Err(e);
+        }
+        self.registry.insert(path, recursive_mode);
+        Ok(())
     }
 
     fn unwatch(&mut self, path: &Path) -> Result<()> {
-        self.unwatch_inner(path)
+        let plan = self.registry.plan_unwatch(path);
+        plan.apply(self, |watcher| watcher.uninstall(path))?;
+        self.registry.remove(path);
+        self.tokens.remove(path);
+        Ok(())
+    }
+
+    fn set_recursive_mode(&mut self, path: &Path, recursive_mode: RecursiveMode) -> Result<()> {
+        let plan = self
+            .registry
+            .plan_set_recursive_mode(path, recursive_mode)?;
+        plan.apply(self, |watcher| match watcher.fallback {
+            Some(ref mut fallback) if fallback.contains(path) => {
+                fallback.set_recursive_mode(path, recursive_mode)
+            }
+            _ => watcher.set_recursive_mode_inner(path, recursive_mode),
+        })?;
+        self.registry.insert(path, recursive_mode);
+        Ok(())
+    }
+
+    fn suppress(&self, path: &Path) -> Result<SuppressGuard> {
+        let guard = self.suppressions.guard(path);
+        match self
+            .fallback
+            .as_ref()
+            .and_then(|fallback| fallback.watcher.as_ref())
+        {
+            Some(watcher) => Ok(guard.join(watcher.suppress(path)?)),
+            None => Ok(guard),
+        }
+    }
+
+    fn is_watched(&self, path: &Path) -> bool {
+        self.registry.is_watched(path)
     }
 
     fn configure(&mut self, config: Config) -> Result<bool> {
+        self.options.configure(&config);
+        if let Some(ref mut fallback) = self.fallback {
+            fallback.configure(&config)?;
+        }
         let (tx, rx) = bounded(1);
-        self.channel.send(EventLoopMsg::Configure(config, tx))?;
-        self.waker.wake()?;
+        self.send(EventLoopMsg::Configure(Box::new(config), tx))?;
         rx.recv()?
     }
 
@@ -641,11 +1347,473 @@ impl Watcher for INotifyWatcher {
     }
 }
 
+/// Watcher implementation based on inotify, which doesn't spawn a thread but reads the events
+/// when asked to with [ManualINotifyWatcher::poll_events].
+///
+/// ```no_run
+/// # use std::path::Path;
+/// # use std::time::Duration;
+/// use notify::{Config, ManualINotifyWatcher, RecursiveMode};
+///
+/// # fn main() -> notify::Result<()> {
+/// let mut watcher = ManualINotifyWatcher::new(Config::default())?;
+/// watcher.watch(Path::new("."), RecursiveMode::Recursive)?;
+/// loop {
+///     // the application's own work goes here
+///     for event in watcher.poll_events(Duration::from_millis(16))? {
+///         println!("{:?}", event);
+///     }
+/// }
+/// # }
+/// ```
+///
+/// Everything else happens on the calling thread as well, like the
+/// [initial scan](Config::with_initial_scan) when a path is watched. Options which need threads of
+/// their own, the [poll fallback](Config::with_poll_fallback) and content hashes, are rejected
+/// with [ErrorKind::InvalidConfig].
+pub struct ManualINotifyWatcher {
+    event_loop: EventLoop,
+    events: Arc<Mutex<VecDeque<Result<Event>>>>,
+    registry: WatchRegistry,
+    tokens: WatchTokens,
+    options: DeliveryOptions,
+    suppressions: Suppressions,
+}
+
+impl fmt::Debug for ManualINotifyWatcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ManualINotifyWatcher")
+            .field("registry", &self.registry)
+            .field("tokens", &self.tokens)
+            .finish_non_exhaustive()
+    }
+}
+
+impl InstallWatches for ManualINotifyWatcher {
+    fn install(&mut self, path: &Path, recursive_mode: RecursiveMode) -> Result<()> {
+        let path_abs = registry::absolute(path);
+        self.request(|tx| EventLoopMsg::AddWatch(path_abs, recursive_mode, SymlinkMode::Target, tx))
+    }
+
+    fn uninstall(&mut self, path: &Path) -> Result<()> {
+        self.request(|tx| EventLoopMsg::RemoveWatch(registry::absolute(path), tx))
+    }
+}
+
+/// Queues the events read for [ManualINotifyWatcher::poll_events].
+struct EventQueue(Arc<Mutex<VecDeque<Result<Event>>>>);
+
+impl EventHandler for EventQueue {
+    fn handle_event(&mut self, event: Result<Event>) {
+        self.0
+            .lock()
+            .expect("Can't lock event queue!")
+            .push_back(event);
+    }
+}
+
+impl ManualINotifyWatcher {
+    /// Create a new watcher.
+    pub fn new(config: Config) -> Result<Self> {
+        #[cfg(feature = "blake3")]
+        let hashes = config.content_hash().is_some();
+        #[cfg(not(feature = "blake3"))]
+        let hashes = false;
+        if config.poll_fallback() || hashes {
+            return Err(Error::invalid_config(&config));
+        }
+
+        let events = Arc::new(Mutex::new(VecDeque::new()));
+        let delivery = Delivery::new(EventQueue(events.clone()), &config);
+        let tokens = delivery.tokens();
+        let options = delivery.options();
+        let suppressions = delivery.suppressions();
+        let mut event_loop = EventLoop::new(Inotify::init()?, Box::new(delivery), &config)?;
+        event_loop.manual = true;
+        Ok(Self {
+            event_loop,
+            events,
+            registry: WatchRegistry::new(config.watch_overlap()),
+            tokens,
+            options,
+            suppressions,
+        })
+    }
+
+    /// Returns the events which happened since the last call, waiting at most `max_wait` for
+    /// one if there were none
+    ///
+    /// Returns right away without reading if events are waiting already, like those of an initial
+    /// scan. An error reported while reading is returned by the next call after the events read
+    /// before it. Once the [RetryPolicy](crate::RetryPolicy) gave up on an error, no more events
+    /// are read and every further call returns an error.
+    pub fn poll_events(&mut self, max_wait: Duration) -> Result<Vec<Event>> {
+        if self.event_loop.running && self.lock_events().is_empty() {
+            let mut timeout = max_wait;
+            if let (Some(_), Some(deadline)) = (
+                &self.event_loop.rename_event,
+                self.event_loop.rename_deadline,
+            ) {
+                timeout = timeout.min(deadline.saturating_duration_since(Instant::now()));
+            }
+
+            let mut events = mio::Events::with_capacity(16);
+            match self.event_loop.poll.poll(&mut events, Some(timeout)) {
+                Ok(()) => self.event_loop.retry.reset(),
+                // the error is queued by the policy if reported
+                Err(e) => {
+                    let event_loop = &mut self.event_loop;
+                    let action = event_loop.retry.fail(e, &mut *event_loop.event_handler);
+                    if let RetryAction::Stop = action {
+                        event_loop.running = false;
+                    }
+                }
+            }
+            for event in &events {
+                self.event_loop.handle_event(event);
+            }
+            self.event_loop.send_expired_rename_event();
+            self.event_loop.audit_watches();
+        }
+
+        let mut queue = self.lock_events();
+        let mut events = Vec::new();
+        while let Some(event) = queue.pop_front() {
+            match event {
+                Ok(event) => events.push(event),
+                Err(e) if events.is_empty() => return Err(e),
+                Err(e) => {
+                    queue.push_front(Err(e));
+                    break;
+                }
+            }
+        }
+        if events.is_empty() && !self.event_loop.running {
+            return Err(Error::generic("the inotify event loop stopped after an error"));
+        }
+        Ok(events)
+    }
+
+    /// Begin watching a new path, see [Watcher::watch].
+    pub fn watch(&mut self, path: &Path, recursive_mode: RecursiveMode) -> Result<()> {
+        self.watch_with_symlink_mode(path, recursive_mode, SymlinkMode::Target)
+    }
+
+    /// Begin watching a new path or the symbolic link at it, see
+    /// [Watcher::watch_with_symlink_mode].
+    pub fn watch_with_symlink_mode(
+        &mut self,
+        path: &Path,
+        recursive_mode: RecursiveMode,
+        symlink_mode: SymlinkMode,
+    ) -> Result<()> {
+        let plan = self.registry.plan_watch(path, recursive_mode)?;
+        plan.apply(self, |watcher| {
+            let path_abs = registry::absolute(path);
+            watcher.request(|tx| EventLoopMsg::AddWatch(path_abs, recursive_mode, symlink_mode, tx))
+        })?;
+        self.registry.insert(path, recursive_mode);
+        Ok(())
+    }
+
+    /// Begin watching a new path, attaching `token` to its events, see [Watcher::watch_with_token].
+    pub fn watch_with_token(
+        &mut self,
+        path: &Path,
+        recursive_mode: RecursiveMode,
+        token: u64,
+    ) -> Result<()> {
+        self.tokens.insert(path, token);
+        if let Err(e) = self.watch(path, recursive_mode) {
+            self.tokens.remove(path);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Stop watching a path, see [Watcher::unwatch].
+    pub fn unwatch(&mut self, path: &Path) -> Result<()> {
+        let plan = self.registry.plan_unwatch(path);
+        plan.apply(self, |watcher| watcher.uninstall(path))?;
+        self.registry.remove(path);
+        self.tokens.remove(path);
+        Ok(())
+    }
+
+    /// Change the recursive mode of a watch, see [Watcher::set_recursive_mode].
+    pub fn set_recursive_mode(&mut self, path: &Path, recursive_mode: RecursiveMode) -> Result<()> {
+        match self.registry.recursive_mode(path) {
+            None => return Err(Error::watch_not_found().add_path(path.to_owned())),
+            Some(current) if current == recursive_mode => return Ok(()),
+            Some(_) => {}
+        }
+        let plan = self
+            .registry
+            .plan_set_recursive_mode(path, recursive_mode)?;
+        plan.apply(self, |watcher| {
+            let msg =
+                |tx| EventLoopMsg::SetRecursiveMode(registry::absolute(path), recursive_mode, tx);
+            watcher.request(msg)
+        })?;
+        self.registry.insert(path, recursive_mode);
+        Ok(())
+    }
+
+    /// Suppress the events below a path while the guard is alive, see [Watcher::suppress].
+    pub fn suppress(&self, path: &Path) -> SuppressGuard {
+        self.suppressions.guard(path)
+    }
+
+    /// Whether `path` is covered by a watch, see [Watcher::is_watched].
+    pub fn is_watched(&self, path: &Path) -> bool {
+        self.registry.is_watched(path)
+    }
+
+    /// Change the options of the watcher which can be changed during runtime, see
+    /// [Watcher::configure].
+    pub fn configure(&mut self, config: Config) -> Result<bool> {
+        self.options.configure(&config);
+        self.event_loop.configure(&config);
+        Ok(true)
+    }
+
+    fn lock_events(&self) -> std::sync::MutexGuard<'_, VecDeque<Result<Event>>> {
+        self.events.lock().expect("Can't lock event queue!")
+    }
+
+    /// Handle a message on the calling thread, the event loop replies right away.
+    fn request<F>(&mut self, msg: F) -> Result<()>
+    where
+        F: FnOnce(Sender<Result<()>>) -> EventLoopMsg,
+    {
+        let (tx, rx) = unbounded();
+        // the event loop is owned by the watcher => unwraps must not panic
+        self.event_loop.event_loop_tx.send(msg(tx)).unwrap();
+        self.event_loop.handle_messages();
+        rx.recv().unwrap()
+    }
+}
+
+impl Drop for ManualINotifyWatcher {
+    fn drop(&mut self) {
+        let _ = self.event_loop.event_loop_tx.send(EventLoopMsg::Shutdown);
+        self.event_loop.handle_messages();
+    }
+}
+
+/// Snapshots of the watched paths, to find the changes lost when the event queue overflows.
+///
+/// The snapshots are only taken when watching and after an overflow. The paths which events
+/// were read for in between are recorded instead, so that their changes aren't reported again.
+struct OverflowRecovery {
+    scanner: Scanner,
+    snapshots: HashMap<PathBuf, Snapshot>,
+    // paths events were read for since the snapshots were taken, and whether the event stands
+    // for everything below the path as well, like creating or removing a directory.
+    seen: HashMap<PathBuf, bool>,
+}
+
+impl OverflowRecovery {
+    /// Recorded paths above which the snapshots are retaken rather than growing the record.
+    const MIN_SEEN_LIMIT: usize = 1024;
+
+    fn new(config: &Config) -> Self {
+        Self {
+            scanner: Scanner::new(config),
+            snapshots: HashMap::new(),
+            seen: HashMap::new(),
+        }
+    }
+
+    /// Start tracking the watch at `root`, replacing an existing snapshot of it.
+    fn add(&mut self, root: &Path, is_recursive: bool) {
+        let snapshot = self
+            .scanner
+            .scan(root.to_path_buf(), is_recursive, |_, _| {});
+        self.snapshots.insert(root.to_path_buf(), snapshot);
+    }
+
+    fn remove(&mut self, root: &Path) {
+        self.snapshots.remove(root);
+    }
+
+    /// Record that an event was read for `path`, and for everything below it if
+    /// `with_children`.
+    fn record(&mut self, path: &Path, with_children: bool) {
+        *self.seen.entry(path.to_path_buf()).or_insert(false) |= with_children;
+
+        // nothing was lost while events for many different paths were read, so the snapshots
+        // can be brought up to date instead.
+        let entries: usize = self.snapshots.values().map(Snapshot::len).sum();
+        if self.seen.len() > entries.max(Self::MIN_SEEN_LIMIT) {
+            self.rescan();
+        }
+    }
+
+    /// Rescan all watched paths, returning the changes no event was read for.
+    fn rescan(&mut self) -> Vec<Event> {
+        let seen = std::mem::take(&mut self.seen);
+        let is_seen = |path: &Path| {
+            seen.contains_key(path)
+                || path
+                    .ancestors()
+                    .skip(1)
+                    .any(|ancestor| seen.get(ancestor) == Some(&true))
+        };
+
+        let mut events = Vec::new();
+        for snapshot in self.snapshots.values_mut() {
+            let newer = self.scanner.scan(
+                snapshot.root().to_path_buf(),
+                snapshot.is_recursive(),
+                |_, _| {},
+            );
+            events.extend(
+                snapshot
+                    .diff(&newer)
+                    .into_iter()
+                    .filter(|event| !event.paths.iter().any(|path| is_seen(path))),
+            );
+            *snapshot = newer;
+        }
+        events
+    }
+
+    /// Follow watches whose path starts with `from` to `to`.
+    fn relocate(&mut self, from: &Path, to: &Path) {
+        let moved: Vec<_> = self
+            .snapshots
+            .iter()
+            .filter(|(root, _)| root.starts_with(from))
+            .map(|(root, snapshot)| (root.clone(), snapshot.is_recursive()))
+            .collect();
+        for (root, is_recursive) in moved {
+            self.snapshots.remove(&root);
+            if let Ok(rest) = root.strip_prefix(from) {
+                self.add(&to.join(rest), is_recursive);
+            }
+        }
+    }
+}
+
+/// The watches on filesystems inotify doesn't report changes for, served by a [PollWatcher].
+struct PollFallback {
+    event_handler: SharedHandler,
+    config: Config,
+    watcher: Option<PollWatcher>,
+    roots: HashSet<PathBuf>,
+}
+
+impl fmt::Debug for PollFallback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PollFallback")
+            .field("watcher", &self.watcher)
+            .field("roots", &self.roots)
+            .finish_non_exhaustive()
+    }
+}
+
+impl PollFallback {
+    fn new(event_handler: SharedHandler, config: Config) -> Self {
+        Self {
+            event_handler,
+            config,
+            watcher: None,
+            roots: HashSet::new(),
+        }
+    }
+
+    fn contains(&self, path: &Path) -> bool {
+        self.roots.contains(path)
+    }
+
+    fn watch(
+        &mut self,
+        path: &Path,
+        recursive_mode: RecursiveMode,
+        token: Option<u64>,
+    ) -> Result<()> {
+        let watcher = match self.watcher {
+            Some(ref mut watcher) => watcher,
+            None => self.watcher.insert(PollWatcher::new(
+                self.event_handler.clone(),
+                self.config.clone(),
+            )?),
+        };
+        match token {
+            Some(token) => watcher.watch_with_token(path, recursive_mode, token)?,
+            None => watcher.watch(path, recursive_mode)?,
+        }
+        self.roots.insert(path.to_owned());
+
+        let mut event = Event::new(EventKind::Other)
+            .add_path(path.to_owned())
+            .set_info("poll fallback");
+        if let Some(token) = token {
+            event = event.set_watch_token(token);
+        }
+        // filtered, mapped and counted like the events of the fallback's scans
+        watcher.emit(event);
+        Ok(())
+    }
+
+    fn unwatch(&mut self, path: &Path) -> Result<()> {
+        self.roots.remove(path);
+        match self.watcher {
+            Some(ref mut watcher) => watcher.unwatch(path),
+            None => Err(Error::watch_not_found().add_path(path.to_owned())),
+        }
+    }
+
+    fn set_recursive_mode(&mut self, path: &Path, recursive_mode: RecursiveMode) -> Result<()> {
+        match self.watcher {
+            Some(ref mut watcher) => watcher.set_recursive_mode(path, recursive_mode),
+            None => Err(Error::watch_not_found().add_path(path.to_owned())),
+        }
+    }
+
+    fn configure(&mut self, config: &Config) -> Result<()> {
+        self.config = config.clone();
+        if let Some(ref mut watcher) = self.watcher {
+            watcher.configure(config.clone())?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether `path` is on a filesystem whose changes inotify doesn't report.
+///
+/// These are pseudo filesystems generating their contents on access, and FUSE, whose changes
+/// only reach inotify if they are made through the mount itself.
+fn needs_polling(path: &Path) -> bool {
+    const PROC_SUPER_MAGIC: u32 = 0x9fa0;
+    const SYSFS_MAGIC: u32 = 0x6265_6572;
+    const DEBUGFS_MAGIC: u32 = 0x6462_6720;
+    const TRACEFS_MAGIC: u32 = 0x7472_6163;
+    const FUSE_SUPER_MAGIC: u32 = 0x6573_5546;
+
+    let path = match CString::new(path.as_os_str().as_bytes()) {
+        Ok(path) => path,
+        Err(_) => return false,
+    };
+    let mut stat = MaybeUninit::<libc::statfs>::uninit();
+    if unsafe { libc::statfs(path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+        return false;
+    }
+
+    // the width and signedness of `f_type` differ between targets, the magic numbers fit in 32 bits.
+    let f_type = unsafe { stat.assume_init() }.f_type as u32;
+    matches!(
+        f_type,
+        PROC_SUPER_MAGIC | SYSFS_MAGIC | DEBUGFS_MAGIC | TRACEFS_MAGIC | FUSE_SUPER_MAGIC
+    )
+}
+
 impl Drop for INotifyWatcher {
     fn drop(&mut self) {
-        // we expect the event loop to live => unwrap must not panic
-        self.channel.send(EventLoopMsg::Shutdown).unwrap();
-        self.waker.wake().unwrap();
+        // the event loop may have stopped after an error already
+        let _ = self.channel.send(EventLoopMsg::Shutdown);
+        let _ = self.waker.wake();
     }
 }
 
@@ -654,3 +1822,367 @@ fn inotify_watcher_is_send_and_sync() {
     fn check<T: Send + Sync>() {}
     check::<INotifyWatcher>();
 }
+
+#[test]
+fn initial_scan_is_delivered_before_watch_returns() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("file"), b"a").unwrap();
+
+    let (tx, rx) = std::sync::mpsc::channel();
+    let config = Config::default().with_initial_scan(true);
+    let mut watcher = INotifyWatcher::new(tx, config).unwrap();
+    watcher.watch(dir.path(), RecursiveMode::Recursive).unwrap();
+
+    let paths: Vec<_> = rx
+        .try_iter()
+        .map(|event| event.unwrap())
+        .filter(|event| event.is_initial_scan())
+        .flat_map(|event| event.paths)
+        .collect();
+    assert_eq!(paths, [dir.path().to_path_buf(), dir.path().join("file")]);
+}
+
+#[test]
+fn set_recursive_mode_adds_and_removes_subdirectory_watches() {
+    use std::time::Duration;
+
+    let dir = tempfile::tempdir().unwrap();
+    let sub = dir.path().join("sub");
+    std::fs::create_dir(&sub).unwrap();
+
+    let (tx, rx) = std::sync::mpsc::channel();
+    let mut watcher = INotifyWatcher::new(tx, Config::default()).unwrap();
+    watcher
+        .watch(dir.path(), RecursiveMode::NonRecursive)
+        .unwrap();
+
+    watcher
+        .set_recursive_mode(dir.path(), RecursiveMode::Recursive)
+        .unwrap();
+    std::fs::write(sub.join("a"), b"a").unwrap();
+    let event = rx.recv_timeout(Duration::from_secs(1)).unwrap().unwrap();
+    assert_eq!(event.paths, [sub.join("a")]);
+
+    watcher
+        .set_recursive_mode(dir.path(), RecursiveMode::NonRecursive)
+        .unwrap();
+    while rx.recv_timeout(Duration::from_millis(100)).is_ok() {}
+    std::fs::write(sub.join("b"), b"b").unwrap();
+    assert!(rx.recv_timeout(Duration::from_millis(200)).is_err());
+}
+
+#[test]
+fn anchored_watch_follows_renamed_ancestor() {
+    use std::time::Duration;
+
+    let dir = tempfile::tempdir().unwrap();
+    let base = dir.path().canonicalize().unwrap();
+    let watched = base.join("a").join("watched");
+    std::fs::create_dir_all(&watched).unwrap();
+
+    let (tx, rx) = std::sync::mpsc::channel();
+    let config = Config::default().with_anchored_watches(true);
+    let mut watcher = INotifyWatcher::new(tx, config).unwrap();
+    watcher.watch(&watched, RecursiveMode::Recursive).unwrap();
+
+    std::fs::write(watched.join("file"), b"a").unwrap();
+    while rx.recv_timeout(Duration::from_millis(100)).is_ok() {}
+
+    std::fs::rename(base.join("a"), base.join("b")).unwrap();
+    let moved = base.join("b").join("watched");
+    std::fs::write(moved.join("file"), b"b").unwrap();
+    let event = rx.recv_timeout(Duration::from_secs(1)).unwrap().unwrap();
+    assert_eq!(
+        event.kind,
+        EventKind::Modify(ModifyKind::Name(RenameMode::Both))
+    );
+    assert_eq!(event.paths, [watched, moved.clone()]);
+
+    let event = rx.recv_timeout(Duration::from_secs(1)).unwrap().unwrap();
+    assert_eq!(event.paths, [moved.join("file")]);
+}
+
+#[test]
+fn poll_fallback_serves_proc() {
+    assert!(needs_polling(Path::new("/proc/self")));
+    assert!(!needs_polling(&env::temp_dir()));
+
+    let (tx, rx) = std::sync::mpsc::channel();
+    // the notice is delivered like all other events
+    let config = Config::default()
+        .with_poll_fallback(true)
+        .with_event_map(Some(crate::EventMap::new(|event| event.set_watch_token(7))));
+    let mut watcher = INotifyWatcher::new(tx, config).unwrap();
+    let path = Path::new("/proc/self");
+    watcher.watch(path, RecursiveMode::NonRecursive).unwrap();
+
+    let event = rx.try_recv().unwrap().unwrap();
+    assert_eq!(event.kind, EventKind::Other);
+    assert_eq!(event.paths, [path]);
+    assert_eq!(event.info(), Some("poll fallback"));
+    assert_eq!(event.watch_token(), Some(7));
+    assert!(watcher.is_watched(path));
+
+    watcher.unwatch(path).unwrap();
+    assert!(!watcher.is_watched(path));
+}
+
+#[test]
+fn overflow_recovery_reports_changes_since_the_last_event() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path().to_path_buf();
+    fs::write(root.join("seen"), b"a").unwrap();
+    fs::write(root.join("lost"), b"a").unwrap();
+
+    let mut recovery = OverflowRecovery::new(&Config::default());
+    recovery.add(&root, true);
+
+    // an event was read for this change, so it mustn't be reported again.
+    fs::remove_file(root.join("seen")).unwrap();
+    recovery.record(&root.join("seen"), false);
+    fs::create_dir(root.join("dir")).unwrap();
+    fs::write(root.join("dir").join("file"), b"a").unwrap();
+    recovery.record(&root.join("dir"), true);
+
+    // while this one was lost.
+    fs::remove_file(root.join("lost")).unwrap();
+
+    let changes: Vec<_> = recovery
+        .rescan()
+        .into_iter()
+        .filter(|event| event.paths != [root.as_path()])
+        .map(|event| (event.kind, event.paths))
+        .collect();
+    assert_eq!(
+        changes,
+        vec![(EventKind::Remove(RemoveKind::Any), vec![root.join("lost")])]
+    );
+    assert!(recovery.rescan().is_empty());
+}
+
+#[test]
+fn watch_token_is_attached_to_events() {
+    let dir = tempfile::tempdir().unwrap();
+    let (tx, rx) = std::sync::mpsc::channel();
+    let mut watcher = INotifyWatcher::new(tx, Config::default()).unwrap();
+    watcher
+        .watch_with_token(dir.path(), RecursiveMode::Recursive, 7)
+        .unwrap();
+
+    fs::write(dir.path().join("file"), b"a").unwrap();
+    let event = rx
+        .recv_timeout(Duration::from_secs(1))
+        .expect("no event received")
+        .unwrap();
+    assert_eq!(event.watch_token(), Some(7));
+}
+
+#[test]
+fn manual_watcher_reads_events_when_polled() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut watcher = ManualINotifyWatcher::new(Config::default()).unwrap();
+    watcher.watch(dir.path(), RecursiveMode::Recursive).unwrap();
+    assert!(watcher.poll_events(Duration::ZERO).unwrap().is_empty());
+
+    fs::write(dir.path().join("a"), b"a").unwrap();
+    fs::rename(dir.path().join("a"), dir.path().join("b")).unwrap();
+    let mut events = Vec::new();
+    for _ in 0..10 {
+        events.extend(watcher.poll_events(Duration::from_millis(100)).unwrap());
+    }
+    assert!(events
+        .iter()
+        .any(|e| e.kind == EventKind::Create(CreateKind::File)));
+    assert!(events.iter().any(
+        |e| e.kind == EventKind::Modify(ModifyKind::Name(RenameMode::Both)) && e.paths.len() == 2
+    ));
+
+    assert!(ManualINotifyWatcher::new(Config::default().with_poll_fallback(true)).is_err());
+}
+
+#[test]
+fn manual_watcher_stops_reading_after_a_fatal_error() {
+    let mut watcher = ManualINotifyWatcher::new(Config::default()).unwrap();
+    // waiting on something which isn't an epoll instance fails for good.
+    let null = File::open("/dev/null").unwrap();
+    assert!(unsafe { libc::dup2(null.as_raw_fd(), watcher.event_loop.poll.as_raw_fd()) } >= 0);
+
+    let err = watcher.poll_events(Duration::ZERO).unwrap_err();
+    assert!(matches!(err.kind, crate::ErrorKind::Io(_)));
+    let err = watcher.poll_events(Duration::ZERO).unwrap_err();
+    assert!(matches!(err.kind, crate::ErrorKind::Generic(_)));
+}
+
+#[cfg(feature = "debug-audit")]
+#[test]
+fn removed_watches_release_their_descriptors() {
+    use crate::audit::{AuditHook, Violation};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static VIOLATIONS: AtomicUsize = AtomicUsize::new(0);
+    fn count(violation: &Violation) {
+        eprintln!("{}", violation);
+        VIOLATIONS.fetch_add(1, Ordering::SeqCst);
+    }
+
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir_all(dir.path().join("a/b")).unwrap();
+    let config = Config::default().with_audit_hook(Some(AuditHook(count)));
+    let mut watcher = ManualINotifyWatcher::new(config).unwrap();
+    watcher.watch(dir.path(), RecursiveMode::Recursive).unwrap();
+    assert_eq!(watcher.event_loop.audit.held(Resource::WatchDescriptor), 3);
+
+    fs::remove_dir_all(dir.path().join("a")).unwrap();
+    for _ in 0..5 {
+        watcher.poll_events(Duration::from_millis(50)).unwrap();
+    }
+    assert_eq!(watcher.event_loop.audit.held(Resource::WatchDescriptor), 1);
+    watcher
+        .set_recursive_mode(dir.path(), RecursiveMode::NonRecursive)
+        .unwrap();
+    watcher.unwatch(dir.path()).unwrap();
+    drop(watcher);
+    assert_eq!(VIOLATIONS.load(Ordering::SeqCst), 0);
+}
+
+#[test]
+fn configure_changes_the_filter_of_a_running_watcher() {
+    use crate::EventFilter;
+
+    let dir = tempfile::tempdir().unwrap();
+    let mut watcher = ManualINotifyWatcher::new(Config::default()).unwrap();
+    watcher.watch(dir.path(), RecursiveMode::Recursive).unwrap();
+    let poll = |watcher: &mut ManualINotifyWatcher| {
+        let mut events = Vec::new();
+        for _ in 0..3 {
+            events.extend(watcher.poll_events(Duration::from_millis(50)).unwrap());
+        }
+        events
+    };
+
+    let filter = EventFilter::new().glob("*.rs");
+    let config = Config::default().with_event_filter(Some(filter));
+    assert!(watcher.configure(config).unwrap());
+    fs::write(dir.path().join("a.txt"), b"a").unwrap();
+    fs::write(dir.path().join("b.rs"), b"b").unwrap();
+    let events = poll(&mut watcher);
+    assert!(!events.is_empty());
+    assert!(events
+        .iter()
+        .all(|event| event.paths[0] == dir.path().join("b.rs")));
+
+    assert!(watcher.configure(Config::default()).unwrap());
+    fs::write(dir.path().join("c.txt"), b"c").unwrap();
+    assert!(!poll(&mut watcher).is_empty());
+    assert!(watcher.is_watched(dir.path()));
+}
+
+#[test]
+fn link_watch_reports_the_link_and_not_its_target() {
+    let dir = tempfile::tempdir().unwrap();
+    let (target, link) = (dir.path().join("target"), dir.path().join("link"));
+    fs::write(&target, b"a").unwrap();
+    std::os::unix::fs::symlink(&target, &link).unwrap();
+
+    let mut watcher = ManualINotifyWatcher::new(Config::default()).unwrap();
+    watcher
+        .watch_with_symlink_mode(&link, RecursiveMode::NonRecursive, SymlinkMode::Link)
+        .unwrap();
+    fs::write(&target, b"b").unwrap();
+    assert!(watcher
+        .poll_events(Duration::from_millis(100))
+        .unwrap()
+        .is_empty());
+
+    fs::remove_file(&link).unwrap();
+    let events = watcher.poll_events(Duration::from_secs(1)).unwrap();
+    assert!(events
+        .iter()
+        .any(|e| e.kind.is_remove() && e.paths == [link.clone()]));
+}
+
+#[test]
+fn shared_event_loops_run_on_one_thread() {
+    use std::time::Duration;
+
+    let config = Config::default().with_shared_event_loop(true);
+    let watch = |dir: &Path| {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let mut watcher = INotifyWatcher::new(
+            move |event: Result<Event>| {
+                let thread = thread::current().name().map(str::to_owned);
+                let _ = tx.send((thread, event.unwrap()));
+            },
+            config.clone(),
+        )
+        .unwrap();
+        watcher.watch(dir, RecursiveMode::Recursive).unwrap();
+        (watcher, rx)
+    };
+    let (a, b) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+    let (watcher_a, rx_a) = watch(a.path());
+    let (watcher_b, rx_b) = watch(b.path());
+    let reactor = Some("notify-rs inotify reactor".to_owned());
+
+    std::fs::write(a.path().join("file"), "").unwrap();
+    let (thread, event) = rx_a.recv_timeout(Duration::from_secs(1)).unwrap();
+    assert_eq!(
+        (thread, event.paths),
+        (reactor.clone(), vec![a.path().join("file")])
+    );
+
+    // the other watcher keeps running on the thread
+    drop(watcher_a);
+    std::fs::write(b.path().join("file"), "").unwrap();
+    let (thread, event) = rx_b.recv_timeout(Duration::from_secs(1)).unwrap();
+    assert_eq!(
+        (thread, event.paths),
+        (reactor.clone(), vec![b.path().join("file")])
+    );
+
+    // a move out of the watches is reported once the reactor gave up on its other half
+    let outside = tempfile::tempdir().unwrap();
+    std::fs::rename(b.path().join("file"), outside.path().join("file")).unwrap();
+    let moved = loop {
+        let (thread, event) = rx_b.recv_timeout(Duration::from_secs(1)).unwrap();
+        if let EventKind::Modify(ModifyKind::Name(_)) = event.kind {
+            break (thread, event.kind, event.paths);
+        }
+    };
+    assert_eq!(
+        moved,
+        (
+            reactor,
+            EventKind::Modify(ModifyKind::Name(RenameMode::From)),
+            vec![b.path().join("file")]
+        )
+    );
+
+    // an event handler changing a watcher on the thread fails instead of waiting for itself
+    let watcher_d = Arc::new(Mutex::new(Some(
+        INotifyWatcher::new(|_: Result<Event>| {}, config.clone()).unwrap(),
+    )));
+    let (tx, rx) = std::sync::mpsc::channel();
+    let watcher_d_c = watcher_d.clone();
+    let dir = a.path().to_owned();
+    let mut watcher_c = INotifyWatcher::new(
+        move |_: Result<Event>| {
+            if let Some(watcher) = watcher_d_c.lock().unwrap().as_mut() {
+                let _ = tx.send(watcher.watch(&dir, RecursiveMode::NonRecursive));
+            }
+        },
+        config,
+    )
+    .unwrap();
+    watcher_c.watch(b.path(), RecursiveMode::Recursive).unwrap();
+    std::fs::write(b.path().join("other"), "").unwrap();
+    let result = rx.recv_timeout(Duration::from_secs(1)).unwrap();
+    assert!(matches!(result, Err(Error { kind: ErrorKind::Generic(_), .. })));
+
+    // the reactor is freed with the last watcher using it
+    drop(watcher_d.lock().unwrap().take());
+    drop(watcher_c);
+    drop(watcher_b);
+    let slot = unsafe { &*SHARED_REACTOR.load(Ordering::Acquire) };
+    assert!(slot.lock().unwrap().upgrade().is_none());
+}
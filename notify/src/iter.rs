@@ -0,0 +1,87 @@
+//! Blocking iteration over the events of a watcher
+
+use crate::{Config, Event, Result, Watcher};
+use std::{
+    sync::mpsc::{self, RecvTimeoutError, TryRecvError},
+    time::Duration,
+};
+
+/// A blocking [Iterator] over the events of a watcher, see [crate::recommended_watcher_iter].
+///
+/// ```no_run
+/// # use std::path::Path;
+/// use notify::{RecursiveMode, Watcher};
+///
+/// # fn main() -> notify::Result<()> {
+/// let (mut watcher, events) = notify::recommended_watcher_iter()?;
+/// watcher.watch(Path::new("."), RecursiveMode::Recursive)?;
+///
+/// for event in events {
+///     println!("{:?}", event?);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+///
+/// The iterator ends once the watcher is dropped and the remaining events are consumed.
+#[derive(Debug)]
+pub struct EventIter {
+    rx: mpsc::Receiver<Result<Event>>,
+}
+
+impl EventIter {
+    /// Create a watcher of type `W` and the iterator over its events.
+    pub fn new<W: Watcher>(config: Config) -> Result<(W, Self)> {
+        let (tx, rx) = mpsc::channel();
+        let watcher = W::new(tx, config)?;
+        Ok((watcher, Self { rx }))
+    }
+
+    /// Returns the next event if there is one, without blocking
+    ///
+    /// Returns `None` if no event is waiting, or the watcher was dropped.
+    pub fn try_next(&mut self) -> Option<Result<Event>> {
+        match self.rx.try_recv() {
+            Ok(event) => Some(event),
+            Err(TryRecvError::Empty) | Err(TryRecvError::Disconnected) => None,
+        }
+    }
+
+    /// Returns the next event, waiting at most `timeout` for it
+    ///
+    /// Returns `None` if no event arrived in time, or the watcher was dropped.
+    pub fn next_timeout(&mut self, timeout: Duration) -> Option<Result<Event>> {
+        match self.rx.recv_timeout(timeout) {
+            Ok(event) => Some(event),
+            Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => None,
+        }
+    }
+}
+
+impl Iterator for EventIter {
+    type Item = Result<Event>;
+
+    /// Waits for the next event, returning `None` once the watcher was dropped
+    fn next(&mut self) -> Option<Self::Item> {
+        self.rx.recv().ok()
+    }
+}
+
+#[test]
+fn event_iter_ends_with_the_watcher() {
+    use crate::{EventKind, NullWatcher};
+
+    let (tx, rx) = mpsc::channel();
+    let mut events = EventIter { rx };
+    assert!(events.try_next().is_none());
+    tx.send(Ok(Event::new(EventKind::Other))).unwrap();
+    assert!(events.next_timeout(Duration::from_millis(10)).is_some());
+    assert!(events.next_timeout(Duration::from_millis(10)).is_none());
+    tx.send(Ok(Event::new(EventKind::Any))).unwrap();
+    drop(tx);
+    assert_eq!(events.count(), 1);
+
+    // the null watcher drops its event handler right away
+    let (_watcher, mut events) = EventIter::new::<NullWatcher>(Config::default()).unwrap();
+    assert!(events.next().is_none());
+}
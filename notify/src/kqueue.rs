@@ -5,12 +5,19 @@
 //! pieces of kernel code termed filters.
 
 use super::event::*;
-use super::{Config, Error, EventHandler, RecursiveMode, Result, Watcher};
-use crate::{unbounded, Receiver, Sender};
+use super::{Config, Error, EventHandler, RecursiveMode, Result, SymlinkMode, Watcher};
+use crate::audit::{Audit, Resource};
+use crate::delivery::{Delivery, DeliveryOptions, WatchTokens};
+use crate::registry::{InstallWatches, WatchRegistry};
+use crate::retry::{Retry, RetryAction};
+use crate::scheduling::Scheduling;
+use crate::suppress::{SuppressGuard, Suppressions};
+use crate::{snapshot, unbounded, Receiver, Sender};
 use kqueue::{EventData, EventFilter, FilterFlag, Ident};
 use std::collections::HashMap;
 use std::env;
-use std::fs::metadata;
+use std::fs::{metadata, File};
+use std::io;
 use std::os::unix::io::AsRawFd;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
@@ -34,6 +41,12 @@ struct EventLoop {
     kqueue: kqueue::Watcher,
     event_handler: Box<dyn EventHandler>,
     watches: HashMap<PathBuf, bool>,
+    /// The opened symbolic links watched themselves, also in `watches`
+    links: HashMap<PathBuf, File>,
+    initial_scan: bool,
+    retry: Retry,
+    audit: Audit,
+    scheduling: Scheduling,
 }
 
 /// Watcher implementation based on inotify
@@ -41,16 +54,25 @@ struct EventLoop {
 pub struct KqueueWatcher {
     channel: Sender<EventLoopMsg>,
     waker: Arc<mio::Waker>,
+    registry: WatchRegistry,
+    tokens: WatchTokens,
+    options: DeliveryOptions,
+    suppressions: Suppressions,
 }
 
 enum EventLoopMsg {
-    AddWatch(PathBuf, RecursiveMode, Sender<Result<()>>),
+    AddWatch(PathBuf, RecursiveMode, SymlinkMode, Sender<Result<()>>),
     RemoveWatch(PathBuf, Sender<Result<()>>),
+    Configure(Box<Config>, Sender<Result<bool>>),
     Shutdown,
 }
 
 impl EventLoop {
-    pub fn new(kqueue: kqueue::Watcher, event_handler: Box<dyn EventHandler>) -> Result<Self> {
+    pub fn new(
+        kqueue: kqueue::Watcher,
+        event_handler: Box<dyn EventHandler>,
+        config: &Config,
+    ) -> Result<Self> {
         let (event_loop_tx, event_loop_rx) = unbounded::<EventLoopMsg>();
         let poll = mio::Poll::new()?;
 
@@ -70,6 +92,11 @@ impl EventLoop {
             kqueue,
             event_handler,
             watches: HashMap::new(),
+            links: HashMap::new(),
+            initial_scan: config.initial_scan(),
+            retry: Retry::new(config.retry_policy()),
+            audit: Audit::new("kqueue", config),
+            scheduling: Scheduling::new(config),
         };
         Ok(event_loop)
     }
@@ -82,16 +109,18 @@ impl EventLoop {
     }
 
     fn event_loop_thread(mut self) {
+        let thread = thread::current().id();
+        self.audit.acquire(Resource::Thread, thread);
+        let _ = self.scheduling.apply();
         let mut events = mio::Events::with_capacity(16);
         loop {
             // Wait for something to happen.
             match self.poll.poll(&mut events, None) {
-                Err(ref e) if matches!(e.kind(), std::io::ErrorKind::Interrupted) => {
-                    // System call was interrupted, we will retry
-                    // TODO: Not covered by tests (to reproduce likely need to setup signal handlers)
-                }
-                Err(e) => panic!("poll failed: {}", e),
-                Ok(()) => {}
+                Ok(()) => self.retry.reset(),
+                Err(e) => match self.retry.fail(e, &mut *self.event_handler) {
+                    RetryAction::Retry => continue,
+                    RetryAction::Stop => break,
+                },
             }
 
             // Process whatever happened.
@@ -103,7 +132,11 @@ impl EventLoop {
             if !self.running {
                 break;
             }
+            self.audit_watches();
         }
+        // the watched files still open are closed with the kqueue
+        self.audit.release_all(Resource::WatchDescriptor);
+        self.audit.release(Resource::Thread, thread);
     }
 
     // Handle a single event.
@@ -124,12 +157,34 @@ impl EventLoop {
     fn handle_messages(&mut self) {
         while let Ok(msg) = self.event_loop_rx.try_recv() {
             match msg {
-                EventLoopMsg::AddWatch(path, recursive_mode, tx) => {
-                    let _ = tx.send(self.add_watch(path, recursive_mode.is_recursive()));
+                EventLoopMsg::AddWatch(path, recursive_mode, symlink_mode, tx) => {
+                    let is_link = symlink_mode == SymlinkMode::Link
+                        && std::fs::symlink_metadata(&path)
+                            .map_or(false, |m| m.file_type().is_symlink());
+                    let result = if is_link {
+                        self.add_link_watch(path.clone())
+                    } else {
+                        self.add_watch(path.clone(), recursive_mode.is_recursive())
+                    };
+                    if result.is_ok() && self.initial_scan {
+                        if is_link {
+                            let event = snapshot::initial_scan_link_event(&path);
+                            self.event_handler.handle_event(Ok(event));
+                        } else {
+                            for event in snapshot::initial_scan_events(&path, recursive_mode) {
+                                self.event_handler.handle_event(Ok(event));
+                            }
+                        }
+                    }
+                    let _ = tx.send(result);
                 }
                 EventLoopMsg::RemoveWatch(path, tx) => {
                     let _ = tx.send(self.remove_watch(path, false));
                 }
+                EventLoopMsg::Configure(config, tx) => {
+                    self.initial_scan = config.initial_scan();
+                    let _ = tx.send(Ok(true));
+                }
                 EventLoopMsg::Shutdown => {
                     self.running = false;
                     break;
@@ -186,11 +241,13 @@ impl EventLoop {
                                             CreateKind::Other
                                         }))
                                         .add_path(file)
+                                        .set_precision(Precision::Inferred)
                                     } else {
                                         Event::new(EventKind::Modify(ModifyKind::Data(
                                             DataChange::Any,
                                         )))
                                         .add_path(path)
+                                        .set_precision(Precision::Inferred)
                                     }
                                 })
                                 .map_err(Into::into)
@@ -270,6 +327,32 @@ impl EventLoop {
                     };
                     self.event_handler.handle_event(event);
                 }
+                // a link watched itself, see `add_link_watch`
+                kqueue::Event {
+                    data: EventData::Vnode(data),
+                    ident: Ident::Fd(fd),
+                } => {
+                    let path = match self.links.iter().find(|(_, file)| file.as_raw_fd() == fd) {
+                        Some((path, _)) => path.clone(),
+                        None => continue,
+                    };
+                    let kind = match data {
+                        kqueue::Vnode::Delete | kqueue::Vnode::Revoke => {
+                            remove_watches.push(path.clone());
+                            EventKind::Remove(RemoveKind::Any)
+                        }
+                        kqueue::Vnode::Rename => {
+                            remove_watches.push(path.clone());
+                            EventKind::Modify(ModifyKind::Name(RenameMode::Any))
+                        }
+                        kqueue::Vnode::Attrib => {
+                            EventKind::Modify(ModifyKind::Metadata(MetadataKind::Any))
+                        }
+                        _ => continue,
+                    };
+                    self.event_handler
+                        .handle_event(Ok(Event::new(kind).add_path(path)));
+                }
                 // as we don't add any other EVFILTER to kqueue we should never get here
                 kqueue::Event { ident: _, data: _ } => unreachable!(),
             }
@@ -318,25 +401,66 @@ impl EventLoop {
         self.kqueue
             .add_filename(&path, event_filter, filter_flags)
             .map_err(|e| Error::io(e).add_path(path.clone()))?;
+        self.audit.acquire(Resource::WatchDescriptor, &path);
         self.watches.insert(path, is_recursive);
 
         Ok(())
     }
 
+    /// Watches the symbolic link at `path` itself, not its target.
+    fn add_link_watch(&mut self, path: PathBuf) -> Result<()> {
+        let file = open_link(&path).map_err(|e| Error::io(e).add_path(path.clone()))?;
+        let filter_flags = FilterFlag::NOTE_DELETE
+            | FilterFlag::NOTE_ATTRIB
+            | FilterFlag::NOTE_RENAME
+            | FilterFlag::NOTE_REVOKE;
+        self.kqueue
+            .add_fd(file.as_raw_fd(), EventFilter::EVFILT_VNODE, filter_flags)
+            .map_err(|e| Error::io(e).add_path(path.clone()))?;
+        self.kqueue.watch()?;
+        self.audit.acquire(Resource::WatchDescriptor, &path);
+        self.watches.insert(path.clone(), false);
+        self.links.insert(path, file);
+        Ok(())
+    }
+
+    /// Check that a file is watched for every watch, with the `debug-audit` feature.
+    fn audit_watches(&self) {
+        let (watches, audit) = (&self.watches, &self.audit);
+        audit.check(|| {
+            let held = audit.held(Resource::WatchDescriptor);
+            (held != watches.len())
+                .then(|| format!("{} files are watched for {} watches", held, watches.len()))
+        });
+    }
+
     fn remove_watch(&mut self, path: PathBuf, remove_recursive: bool) -> Result<()> {
         match self.watches.remove(&path) {
             None => return Err(Error::watch_not_found()),
             Some(is_recursive) => {
-                self.kqueue
-                    .remove_filename(&path, EventFilter::EVFILT_VNODE)
-                    .map_err(|e| Error::io(e).add_path(path.clone()))?;
+                match self.links.remove(&path) {
+                    // the link is closed when `file` is dropped
+                    Some(file) => self
+                        .kqueue
+                        .remove_fd(file.as_raw_fd(), EventFilter::EVFILT_VNODE),
+                    None => self
+                        .kqueue
+                        .remove_filename(&path, EventFilter::EVFILT_VNODE),
+                }
+                .map_err(|e| Error::io(e).add_path(path.clone()))?;
+                self.audit.release(Resource::WatchDescriptor, &path);
 
                 if is_recursive || remove_recursive {
-                    for entry in WalkDir::new(path).follow_links(true).into_iter() {
+                    // the watch of `path` itself is removed already
+                    for entry in WalkDir::new(path).min_depth(1).follow_links(true) {
                         let p = entry.map_err(map_walkdir_error)?.path().to_path_buf();
+                        if self.watches.remove(&p).is_none() {
+                            continue;
+                        }
                         self.kqueue
                             .remove_filename(&p, EventFilter::EVFILT_VNODE)
-                            .map_err(|e| Error::io(e).add_path(p))?;
+                            .map_err(|e| Error::io(e).add_path(p.clone()))?;
+                        self.audit.release(Resource::WatchDescriptor, &p);
                     }
                 }
                 self.kqueue.watch()?;
@@ -346,6 +470,35 @@ impl EventLoop {
     }
 }
 
+/// Opens the symbolic link at `path` itself, for kqueue to watch it.
+#[cfg(target_os = "macos")]
+fn open_link(path: &Path) -> io::Result<File> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    std::fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_SYMLINK | libc::O_EVTONLY)
+        .open(path)
+}
+
+#[cfg(target_os = "freebsd")]
+fn open_link(path: &Path) -> io::Result<File> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    std::fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_PATH | libc::O_NOFOLLOW)
+        .open(path)
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "freebsd")))]
+fn open_link(_path: &Path) -> io::Result<File> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "symbolic links can't be opened for kqueue on this platform",
+    ))
+}
+
 fn map_walkdir_error(e: walkdir::Error) -> Error {
     if e.io_error().is_some() {
         // save to unwrap otherwise we whouldn't be in this branch
@@ -356,16 +509,31 @@ fn map_walkdir_error(e: walkdir::Error) -> Error {
 }
 
 impl KqueueWatcher {
-    fn from_event_handler(event_handler: Box<dyn EventHandler>) -> Result<Self> {
+    fn from_delivery(delivery: Delivery, config: &Config) -> Result<Self> {
+        let tokens = delivery.tokens();
+        let options = delivery.options();
+        let suppressions = delivery.suppressions();
         let kqueue = kqueue::Watcher::new()?;
-        let event_loop = EventLoop::new(kqueue, event_handler)?;
+        let event_loop = EventLoop::new(kqueue, Box::new(delivery), config)?;
         let channel = event_loop.event_loop_tx.clone();
         let waker = event_loop.event_loop_waker.clone();
         event_loop.run();
-        Ok(KqueueWatcher { channel, waker })
+        Ok(KqueueWatcher {
+            channel,
+            waker,
+            registry: WatchRegistry::new(config.watch_overlap()),
+            tokens,
+            options,
+            suppressions,
+        })
     }
 
-    fn watch_inner(&mut self, path: &Path, recursive_mode: RecursiveMode) -> Result<()> {
+    fn watch_inner(
+        &mut self,
+        path: &Path,
+        recursive_mode: RecursiveMode,
+        symlink_mode: SymlinkMode,
+    ) -> Result<()> {
         let pb = if path.is_absolute() {
             path.to_owned()
         } else {
@@ -373,7 +541,7 @@ impl KqueueWatcher {
             p.join(path)
         };
         let (tx, rx) = unbounded();
-        let msg = EventLoopMsg::AddWatch(pb, recursive_mode, tx);
+        let msg = EventLoopMsg::AddWatch(pb, recursive_mode, symlink_mode, tx);
 
         self.channel
             .send(msg)
@@ -408,18 +576,92 @@ impl KqueueWatcher {
     }
 }
 
+impl InstallWatches for KqueueWatcher {
+    fn install(&mut self, path: &Path, recursive_mode: RecursiveMode) -> Result<()> {
+        self.watch_inner(path, recursive_mode, SymlinkMode::Target)
+    }
+
+    fn uninstall(&mut self, path: &Path) -> Result<()> {
+        self.unwatch_inner(path)
+    }
+}
+
 impl Watcher for KqueueWatcher {
     /// Create a new watcher.
-    fn new<F: EventHandler>(event_handler: F, _config: Config) -> Result<Self> {
-        Self::from_event_handler(Box::new(event_handler))
+    fn new<F: EventHandler>(event_handler: F, config: Config) -> Result<Self> {
+        Self::from_delivery(Delivery::new(event_handler, &config), &config)
     }
 
     fn watch(&mut self, path: &Path, recursive_mode: RecursiveMode) -> Result<()> {
-        self.watch_inner(path, recursive_mode)
+        self.watch_with_symlink_mode(path, recursive_mode, SymlinkMode::Target)
+    }
+
+    fn watch_with_symlink_mode(
+        &mut self,
+        path: &Path,
+        recursive_mode: RecursiveMode,
+        symlink_mode: SymlinkMode,
+    ) -> Result<()> {
+        let plan = self.registry.plan_watch(path, recursive_mode)?;
+        plan.apply(self, |watcher| {
+            watcher.watch_inner(path, recursive_mode, symlink_mode)
+        })?;
+        self.registry.insert(path, recursive_mode);
+        Ok(())
+    }
+
+    fn watch_with_token(
+        &mut self,
+        path: &Path,
+        recursive_mode: RecursiveMode,
+        token: u64,
+    ) -> Result<()> {
+        let plan = self.registry.plan_watch(path, recursive_mode)?;
+        // registered first, so that the initial scan carries the token already.
+        self.tokens.insert(path, token);
+        if let Err(e) = plan.apply(self, |watcher| watcher.install(path, recursive_mode)) {
+            self.tokens.remove(path);
+            return Err(e);
+        }
+        self.registry.insert(path, recursive_mode);
+        Ok(())
     }
 
     fn unwatch(&mut self, path: &Path) -> Result<()> {
-        self.unwatch_inner(path)
+        let plan = self.registry.plan_unwatch(path);
+        plan.apply(self, |watcher| watcher.unwatch_inner(path))?;
+        self.registry.remove(path);
+        self.tokens.remove(path);
+        Ok(())
+    }
+
+    fn set_recursive_mode(&mut self, path: &Path, recursive_mode: RecursiveMode) -> Result<()> {
+        // like the default, but keeping the token of the watch.
+        let plan = self
+            .registry
+            .plan_set_recursive_mode(path, recursive_mode)?;
+        plan.apply(self, |watcher| {
+            watcher.unwatch_inner(path)?;
+            watcher.install(path, recursive_mode)
+        })?;
+        self.registry.insert(path, recursive_mode);
+        Ok(())
+    }
+
+    fn suppress(&self, path: &Path) -> Result<SuppressGuard> {
+        Ok(self.suppressions.guard(path))
+    }
+
+    fn is_watched(&self, path: &Path) -> bool {
+        self.registry.is_watched(path)
+    }
+
+    fn configure(&mut self, config: Config) -> Result<bool> {
+        self.options.configure(&config);
+        let (tx, rx) = unbounded();
+        self.channel.send(EventLoopMsg::Configure(Box::new(config), tx))?;
+        self.waker.wake()?;
+        rx.recv()?
     }
 
     fn kind() -> crate::WatcherKind {
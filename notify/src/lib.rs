@@ -14,9 +14,41 @@
 //! List of compilation features, see below for details
 //! 
 //! - `serde` for serialization of events
+//! - `serialization` for the versioned JSON Lines and MessagePack formats of [serialization]
+//! - `linux_inotify` enabled by default, for inotify backend on linux and android
 //! - `macos_fsevent` enabled by default, for fsevent backend on macos
 //! - `macos_kqueue` for kqueue backend on macos
+//! - `bsd_kqueue` enabled by default, for kqueue backend on freebsd, openbsd, netbsd and dragonfly
+//! - `windows_read_directory_changes` enabled by default, for ReadDirectoryChangesW backend on windows
+//! - `macos_endpoint_security` for the `EndpointSecurityWatcher` of the `endpoint_security` module
+//!   on macos, seeing every change of every process, for entitled security tools
+//! - `linux_audit` for the `LinuxAuditWatcher` of the `linux_audit` module on Linux, watching
+//!   through audit rules with process attribution, which requires the audit capabilities
+//! - `linux_fanotify` for the `FanotifyWatcher` of the `fanotify` module on Linux, watching whole
+//!   filesystems or mounts with one fanotify mark, which requires `CAP_SYS_ADMIN`
+//! - `windows_etw` for the `EtwWatcher` of the `etw` module on Windows, watching whole volumes
+//!   with Event Tracing for Windows, which requires administrator rights
 //! - `crossbeam-channel` enabled by default, see below
+//! - `flume` for flume channels, see below
+//! - `blake3` for [Config::with_content_hash], attaching content hashes to events
+//! - `stream` for [stream::EventStream], consuming events from async code
+//! - `web` for the `FileSystemAccessWatcher` of the `web` module on `wasm32`, watching the
+//!   `FileSystemDirectoryHandle`s of web browsers
+//! - `mutations` for the `mutations` module, generating random file tree mutations with the
+//!   changes they are expected to cause, to test event handling
+//! - `debug-audit` for [audit], tracking the OS resources of the watchers and checking their
+//!   internal state, to hunt leaks
+//!
+//! ### Backends
+//!
+//! Every native backend is behind the feature above, and [RecommendedWatcher] is the backend of the
+//! platform if its feature is enabled. Otherwise it is the [PollWatcher], which is always compiled
+//! in: it only uses the standard library, so it has no platform code or dependencies to drop.
+//! Embedded users compiling nothing but the [PollWatcher] disable the default features:
+//!
+//! ```toml
+//! notify = { version = "5.1.0", default-features = false }
+//! ```
 //!
 //! ### Serde
 //!
@@ -34,10 +66,19 @@
 //! You can disable crossbeam-channel, letting notify fallback to std channels via
 //! 
 //! ```toml
-//! notify = { version = "5.1.0", default-features = false, features = ["macos_kqueue"] }
+//! notify = { version = "5.1.0", default-features = false, features = ["linux_inotify", "macos_kqueue", "bsd_kqueue", "windows_read_directory_changes"] }
 //! // Alternatively macos_fsevent instead of macos_kqueue
 //! ```
-//! Note the `macos_kqueue` requirement here, otherwise no backend is available on macos.
+//! Note the backend features here, otherwise the [PollWatcher] is used, see [Backends](#backends).
+//!
+//! ### Flume
+//!
+//! With the `flume` feature, [flume](https://docs.rs/flume) channels are used internally instead,
+//! taking precedence over crossbeam-channel, and `flume::Sender` can be used as an event handler.
+//!
+//! ```toml
+//! notify = { version = "5.1.0", features = ["flume"] }
+//! ```
 //! 
 //! # Known Problems
 //! 
@@ -137,89 +178,210 @@
 
 #![deny(missing_docs)]
 
-pub use config::{Config, RecursiveMode};
+pub use auto_watch::AutoWatcher;
+pub use config::{
+    Config, EventMap, LargeFileComparison, OverflowStrategy, RecursiveMode, RetryPolicy,
+    SymlinkMode, ThreadPriority, WatchOverlap,
+};
 pub use error::{Error, ErrorKind, Result};
 pub use event::{Event, EventKind};
+pub use filter::EventFilter;
+pub use git::{GitTracked, GitWatcher};
+pub use group::WatchGroups;
+pub use handle::WatchHandle;
+pub use iter::EventIter;
+pub use rename::resolve_rename_chains;
+pub use subscription::{SubscriptionId, Subscriptions};
+pub use suppress::SuppressGuard;
 use std::path::Path;
 
 #[allow(dead_code)]
-#[cfg(feature = "crossbeam-channel")]
+#[cfg(feature = "flume")]
+pub(crate) type Receiver<T> = flume::Receiver<T>;
+#[allow(dead_code)]
+#[cfg(all(feature = "crossbeam-channel", not(feature = "flume")))]
 pub(crate) type Receiver<T> = crossbeam_channel::Receiver<T>;
 #[allow(dead_code)]
-#[cfg(not(feature = "crossbeam-channel"))]
+#[cfg(not(any(feature = "crossbeam-channel", feature = "flume")))]
 pub(crate) type Receiver<T> = std::sync::mpsc::Receiver<T>;
 
 #[allow(dead_code)]
-#[cfg(feature = "crossbeam-channel")]
+#[cfg(feature = "flume")]
+pub(crate) type Sender<T> = flume::Sender<T>;
+#[allow(dead_code)]
+#[cfg(all(feature = "crossbeam-channel", not(feature = "flume")))]
 pub(crate) type Sender<T> = crossbeam_channel::Sender<T>;
 #[allow(dead_code)]
-#[cfg(not(feature = "crossbeam-channel"))]
+#[cfg(not(any(feature = "crossbeam-channel", feature = "flume")))]
 pub(crate) type Sender<T> = std::sync::mpsc::Sender<T>;
 
 // std limitation
 #[allow(dead_code)]
-#[cfg(feature = "crossbeam-channel")]
+#[cfg(feature = "flume")]
+pub(crate) type BoundSender<T> = flume::Sender<T>;
+#[allow(dead_code)]
+#[cfg(all(feature = "crossbeam-channel", not(feature = "flume")))]
 pub(crate) type BoundSender<T> = crossbeam_channel::Sender<T>;
 #[allow(dead_code)]
-#[cfg(not(feature = "crossbeam-channel"))]
+#[cfg(not(any(feature = "crossbeam-channel", feature = "flume")))]
 pub(crate) type BoundSender<T> = std::sync::mpsc::SyncSender<T>;
 
 #[allow(dead_code)]
 #[inline]
 pub(crate) fn unbounded<T>() -> (Sender<T>, Receiver<T>) {
-    #[cfg(feature = "crossbeam-channel")]
+    #[cfg(feature = "flume")]
+    return flume::unbounded();
+    #[cfg(all(feature = "crossbeam-channel", not(feature = "flume")))]
     return crossbeam_channel::unbounded();
-    #[cfg(not(feature = "crossbeam-channel"))]
+    #[cfg(not(any(feature = "crossbeam-channel", feature = "flume")))]
     return std::sync::mpsc::channel();
 }
 
 #[allow(dead_code)]
 #[inline]
 pub(crate) fn bounded<T>(cap: usize) -> (BoundSender<T>, Receiver<T>) {
-    #[cfg(feature = "crossbeam-channel")]
+    #[cfg(feature = "flume")]
+    return flume::bounded(cap);
+    #[cfg(all(feature = "crossbeam-channel", not(feature = "flume")))]
     return crossbeam_channel::bounded(cap);
-    #[cfg(not(feature = "crossbeam-channel"))]
+    #[cfg(not(any(feature = "crossbeam-channel", feature = "flume")))]
     return std::sync::mpsc::sync_channel(cap);
 }
 
-#[cfg(all(target_os = "macos", not(feature = "macos_kqueue")))]
+#[cfg(all(target_os = "macos", feature = "macos_fsevent", not(feature = "macos_kqueue")))]
 pub use crate::fsevent::FsEventWatcher;
-#[cfg(any(target_os = "linux", target_os = "android"))]
-pub use crate::inotify::INotifyWatcher;
+#[cfg(all(any(target_os = "linux", target_os = "android"), feature = "linux_inotify"))]
+pub use crate::inotify::{INotifyWatcher, ManualINotifyWatcher};
 #[cfg(any(
-    target_os = "freebsd",
-    target_os = "openbsd",
-    target_os = "netbsd",
-    target_os = "dragonflybsd",
+    all(
+        any(
+            target_os = "freebsd",
+            target_os = "openbsd",
+            target_os = "netbsd",
+            target_os = "dragonfly"
+        ),
+        feature = "bsd_kqueue"
+    ),
     all(target_os = "macos", feature = "macos_kqueue")
 ))]
 pub use crate::kqueue::KqueueWatcher;
 pub use null::NullWatcher;
 pub use poll::PollWatcher;
-#[cfg(target_os = "windows")]
+#[cfg(all(target_os = "windows", feature = "windows_read_directory_changes"))]
 pub use windows::ReadDirectoryChangesWatcher;
+#[cfg(all(target_os = "windows", feature = "windows_etw"))]
+pub use etw::EtwWatcher;
+#[cfg(all(target_os = "macos", feature = "macos_endpoint_security"))]
+pub use endpoint_security::EndpointSecurityWatcher;
+#[cfg(all(target_os = "linux", feature = "linux_audit"))]
+pub use linux_audit::LinuxAuditWatcher;
+#[cfg(all(target_os = "linux", feature = "linux_fanotify"))]
+pub use fanotify::FanotifyWatcher;
 
-#[cfg(all(target_os = "macos", not(feature = "macos_kqueue")))]
+#[cfg(all(target_os = "macos", feature = "macos_fsevent", not(feature = "macos_kqueue")))]
 pub mod fsevent;
-#[cfg(any(target_os = "linux", target_os = "android"))]
+#[cfg(all(any(target_os = "linux", target_os = "android"), feature = "linux_inotify"))]
 pub mod inotify;
 #[cfg(any(
-    target_os = "freebsd",
-    target_os = "openbsd",
-    target_os = "dragonflybsd",
-    target_os = "netbsd",
+    all(
+        any(
+            target_os = "freebsd",
+            target_os = "openbsd",
+            target_os = "netbsd",
+            target_os = "dragonfly"
+        ),
+        feature = "bsd_kqueue"
+    ),
     all(target_os = "macos", feature = "macos_kqueue")
 ))]
 pub mod kqueue;
-#[cfg(target_os = "windows")]
+#[cfg(all(target_os = "windows", feature = "windows_read_directory_changes"))]
 pub mod windows;
+#[cfg(all(target_os = "windows", feature = "windows_etw"))]
+pub mod etw;
+#[cfg(all(target_os = "macos", feature = "macos_endpoint_security"))]
+pub mod endpoint_security;
+#[cfg(all(target_os = "linux", feature = "linux_audit"))]
+pub mod linux_audit;
+#[cfg(all(target_os = "linux", feature = "linux_fanotify"))]
+pub mod fanotify;
 
+/// Auditing the OS resources and the internal state of the watchers, with the `debug-audit` feature
+///
+/// The backends register every watch descriptor, handle and thread they allocate, and release it
+/// again when they are done with it. When all parts of a watcher are dropped, none of its
+/// resources may be held anymore, and the event loops check their internal maps for orphaned
+/// entries as they run. Every violation found is passed to the [AuditHook](audit::AuditHook) of the watcher's
+/// config, or panics without one.
+///
+/// ```rust
+/// # use notify::audit::{self, AuditHook, Resource, Violation};
+/// # use notify::Config;
+/// fn report(violation: &Violation) {
+///     eprintln!("notify audit: {}", violation);
+/// }
+///
+/// # fn main() -> notify::Result<()> {
+/// let config = Config::default().with_audit_hook(Some(AuditHook(report)));
+/// let watcher = notify::PollWatcher::new(|_| {}, config)?;
+/// drop(watcher);
+/// println!("{} threads are left", audit::live(Resource::Thread));
+/// # Ok(())
+/// # }
+/// ```
+///
+/// This is meant for tests and leak hunts, tracking the resources takes a lock per allocation.
+#[cfg(feature = "debug-audit")]
+pub mod audit;
+#[cfg(not(feature = "debug-audit"))]
+mod audit;
 pub mod event;
+pub mod filter;
+pub mod format;
+#[cfg(any(test, feature = "mutations"))]
+pub mod mutations;
 pub mod null;
+pub mod persist;
 pub mod poll;
+#[cfg(feature = "serialization")]
+pub mod serialization;
+pub mod snapshot;
+pub mod stats;
+#[cfg(feature = "stream")]
+pub mod stream;
+pub mod units;
+#[cfg(unix)]
+pub mod volume;
+#[cfg(all(target_arch = "wasm32", feature = "web"))]
+pub mod web;
 
+mod auto_watch;
 mod config;
+mod delivery;
 mod error;
+mod git;
+mod group;
+mod handle;
+mod iter;
+mod registry;
+mod rename;
+#[cfg(any(
+    all(any(target_os = "linux", target_os = "android"), feature = "linux_inotify"),
+    all(
+        any(
+            target_os = "freebsd",
+            target_os = "openbsd",
+            target_os = "dragonfly",
+            target_os = "netbsd"
+        ),
+        feature = "bsd_kqueue"
+    ),
+    all(target_os = "macos", feature = "macos_kqueue")
+))]
+mod retry;
+mod scheduling;
+mod subscription;
+mod suppress;
 
 /// The set of requirements for watcher event handling functions.
 ///
@@ -260,12 +422,66 @@ impl EventHandler for crossbeam_channel::Sender<Result<Event>> {
     }
 }
 
+#[cfg(feature = "flume")]
+impl EventHandler for flume::Sender<Result<Event>> {
+    fn handle_event(&mut self, event: Result<Event>) {
+        let _ = self.send(event);
+    }
+}
+
 impl EventHandler for std::sync::mpsc::Sender<Result<Event>> {
     fn handle_event(&mut self, event: Result<Event>) {
         let _ = self.send(event);
     }
 }
 
+/// The set of requirements for watcher event handling functions which only inspect events.
+///
+/// Unlike an [`EventHandler`], this receives events by reference, for handlers which only look at
+/// events and have no use for owning them. Wrap it in [`Borrowing`] to pass it to a watcher, which
+/// still creates every event and drops it once the handler returns.
+///
+/// # Example implementation
+///
+/// ```no_run
+/// use notify::{Borrowing, Config, Error, Event, PollWatcher};
+///
+/// # fn main() -> notify::Result<()> {
+/// let _watcher = PollWatcher::new(
+///     Borrowing(|event: Result<&Event, &Error>| {
+///         if let Ok(event) = event {
+///             println!("Event: {:?}", event.paths);
+///         }
+///     }),
+///     Config::default(),
+/// )?;
+/// # Ok(())
+/// # }
+/// ```
+pub trait BorrowingEventHandler: Send + 'static {
+    /// Handles an event.
+    fn handle_event_ref(&mut self, event: std::result::Result<&Event, &Error>);
+}
+
+impl<F> BorrowingEventHandler for F
+where
+    F: FnMut(std::result::Result<&Event, &Error>) + Send + 'static,
+{
+    fn handle_event_ref(&mut self, event: std::result::Result<&Event, &Error>) {
+        (self)(event);
+    }
+}
+
+/// Adapter passing events to a [`BorrowingEventHandler`] by reference, dropping them afterwards.
+#[derive(Clone, Debug, Default)]
+pub struct Borrowing<H>(pub H);
+
+impl<H: BorrowingEventHandler> EventHandler for Borrowing<H> {
+    fn handle_event(&mut self, event: Result<Event>) {
+        self.0.handle_event_ref(event.as_ref());
+    }
+}
+
 /// Watcher kind enumeration
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 #[non_exhaustive]
@@ -282,6 +498,40 @@ pub enum WatcherKind {
     ReadDirectoryChangesWatcher,
     /// Fake watcher for testing
     NullWatcher,
+    /// File System Access API backend (web browsers)
+    FileSystemAccessWatcher,
+    /// Event Tracing for Windows backend
+    Etw,
+    /// Endpoint Security backend (mac)
+    EndpointSecurity,
+    /// Linux audit subsystem backend
+    LinuxAudit,
+    /// fanotify backend (linux)
+    Fanotify,
+}
+
+impl std::str::FromStr for WatcherKind {
+    type Err = Error;
+
+    /// Parses the backend names accepted in [`BACKEND_ENV_VAR`]: `inotify`, `fsevent`, `kqueue`,
+    /// `poll`, `windows`, `etw`, `endpoint_security`, `linux_audit`, `fanotify`, `null` and
+    /// `web`, ignoring case.
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s.to_ascii_lowercase().as_str() {
+            "inotify" => WatcherKind::Inotify,
+            "fsevent" => WatcherKind::Fsevent,
+            "kqueue" => WatcherKind::Kqueue,
+            "poll" => WatcherKind::PollWatcher,
+            "windows" => WatcherKind::ReadDirectoryChangesWatcher,
+            "etw" => WatcherKind::Etw,
+            "endpoint_security" => WatcherKind::EndpointSecurity,
+            "linux_audit" => WatcherKind::LinuxAudit,
+            "fanotify" => WatcherKind::Fanotify,
+            "null" => WatcherKind::NullWatcher,
+            "web" => WatcherKind::FileSystemAccessWatcher,
+            _ => return Err(Error::generic(&format!("unknown watcher backend {:?}", s))),
+        })
+    }
 }
 
 /// Type that can deliver file activity notifications
@@ -319,9 +569,109 @@ pub trait Watcher {
     /// fails.
     fn unwatch(&mut self, path: &Path) -> Result<()>;
 
+    /// Begin watching a new path like [`Watcher::watch`], attaching `token` to every event
+    /// originating from it.
+    ///
+    /// The token can be read with [`Event::watch_token`], to route events without matching their
+    /// paths. If watches overlap, events carry the token of the innermost watch with a token. The
+    /// token is kept until `path` is unwatched.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if watching `path` fails, or if the watcher doesn't support tokens, which
+    /// is the default.
+    fn watch_with_token(
+        &mut self,
+        path: &Path,
+        _recursive_mode: RecursiveMode,
+        _token: u64,
+    ) -> Result<()> {
+        let err = Error::generic("watch tokens are not supported by this watcher");
+        Err(err.add_path(path.to_owned()))
+    }
+
+    /// Begin watching a new path like [`Watcher::watch`], choosing with `symlink_mode` whether a
+    /// symbolic link at `path` or its target is watched.
+    ///
+    /// [`Watcher::watch`] watches the target on all backends. A watch of the link itself reports
+    /// its removal, which includes pointing it elsewhere by replacing it, its renames and changes
+    /// of its metadata, and never recurses. If `path` isn't a symbolic link, `symlink_mode` is
+    /// ignored.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if watching `path` fails, or if the watcher can't watch links themselves,
+    /// which is the default.
+    fn watch_with_symlink_mode(
+        &mut self,
+        path: &Path,
+        recursive_mode: RecursiveMode,
+        symlink_mode: SymlinkMode,
+    ) -> Result<()> {
+        let is_link = std::fs::symlink_metadata(path).map_or(false, |m| m.file_type().is_symlink());
+        if symlink_mode == SymlinkMode::Link && is_link {
+            let err = Error::generic("watching symbolic links is not supported by this watcher");
+            return Err(err.add_path(path.to_owned()));
+        }
+        self.watch(path, recursive_mode)
+    }
+
+    /// Change the [`RecursiveMode`] of the existing watch at `path`.
+    ///
+    /// Backends which can, only do the incremental work: upgrading to a recursive watch registers
+    /// the subdirectories, downgrading removes their registrations, and the watch of `path`
+    /// itself stays in place. Entries entering or leaving the watch this way are not reported as
+    /// created or removed. Otherwise the watch is removed and added again.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error in the case that `path` has not been watched or if changing the watch
+    /// fails.
+    fn set_recursive_mode(&mut self, path: &Path, recursive_mode: RecursiveMode) -> Result<()> {
+        self.unwatch(path)?;
+        self.watch(path, recursive_mode)
+    }
+
+    /// Suppress the events below `path` while the returned guard is alive, and for a grace
+    /// period after it is dropped.
+    ///
+    /// Applications which change the files they watch use this to bracket their own changes, so
+    /// they don't react to them again. See [`SuppressGuard`] for the options.
+    ///
+    /// Watchers which don't support this return an error.
+    fn suppress(&self, path: &Path) -> Result<SuppressGuard> {
+        let err = Error::generic("suppressing events is not supported by this watcher");
+        Err(err.add_path(path.to_owned()))
+    }
+
+    /// Begin watching the path of `registration` with its modes and token, returning a guard
+    /// which unwatches it when dropped.
+    ///
+    /// Scoped watches don't need to be unwatched by hand, see [`WatchHandle`] for keeping the
+    /// watch anyway.
+    fn watch_handle(&mut self, registration: persist::Registration) -> Result<WatchHandle<'_, Self>>
+    where
+        Self: Sized,
+    {
+        WatchHandle::new(self, registration)
+    }
+
+    /// Returns whether `path` is covered by one of the watches of this watcher.
+    ///
+    /// A non-recursive watch covers the watched path and its immediate children, a recursive
+    /// watch the whole tree below the watched path. This only consults the registered watches,
+    /// so `path` doesn't need to exist.
+    ///
+    /// Watchers which don't keep track of their watches always return `false`.
+    fn is_watched(&self, _path: &Path) -> bool {
+        false
+    }
+
     /// Configure the watcher at runtime.
     ///
-    /// See the [`Config`](config/enum.Config.html) enum for all configuration options.
+    /// Applies the options of `option` which can be changed during runtime, as documented on
+    /// [Config], keeping all watches. The options which can only be set when creating the
+    /// watcher are ignored.
     ///
     /// # Returns
     ///
@@ -339,33 +689,46 @@ pub trait Watcher {
 }
 
 /// The recommended `Watcher` implementation for the current platform
-#[cfg(any(target_os = "linux", target_os = "android"))]
+#[cfg(all(any(target_os = "linux", target_os = "android"), feature = "linux_inotify"))]
 pub type RecommendedWatcher = INotifyWatcher;
 /// The recommended `Watcher` implementation for the current platform
-#[cfg(all(target_os = "macos", not(feature = "macos_kqueue")))]
+#[cfg(all(target_os = "macos", feature = "macos_fsevent", not(feature = "macos_kqueue")))]
 pub type RecommendedWatcher = FsEventWatcher;
 /// The recommended `Watcher` implementation for the current platform
-#[cfg(target_os = "windows")]
+#[cfg(all(target_os = "windows", feature = "windows_read_directory_changes"))]
 pub type RecommendedWatcher = ReadDirectoryChangesWatcher;
 /// The recommended `Watcher` implementation for the current platform
 #[cfg(any(
-    target_os = "freebsd",
-    target_os = "openbsd",
-    target_os = "netbsd",
-    target_os = "dragonflybsd",
+    all(
+        any(
+            target_os = "freebsd",
+            target_os = "openbsd",
+            target_os = "netbsd",
+            target_os = "dragonfly"
+        ),
+        feature = "bsd_kqueue"
+    ),
     all(target_os = "macos", feature = "macos_kqueue")
 ))]
 pub type RecommendedWatcher = KqueueWatcher;
 /// The recommended `Watcher` implementation for the current platform
+///
+/// This is the [PollWatcher] on platforms without a native backend, or when the features of their
+/// backends are disabled.
 #[cfg(not(any(
-    target_os = "linux",
-    target_os = "android",
-    target_os = "macos",
-    target_os = "windows",
-    target_os = "freebsd",
-    target_os = "openbsd",
-    target_os = "netbsd",
-    target_os = "dragonflybsd"
+    all(any(target_os = "linux", target_os = "android"), feature = "linux_inotify"),
+    all(target_os = "macos", feature = "macos_fsevent"),
+    all(target_os = "windows", feature = "windows_read_directory_changes"),
+    all(
+        any(
+            target_os = "freebsd",
+            target_os = "openbsd",
+            target_os = "netbsd",
+            target_os = "dragonfly"
+        ),
+        feature = "bsd_kqueue"
+    ),
+    all(target_os = "macos", feature = "macos_kqueue")
 )))]
 pub type RecommendedWatcher = PollWatcher;
 
@@ -381,21 +744,185 @@ where
     RecommendedWatcher::new(event_handler, Config::default())
 }
 
+/// Convenience method for creating the `RecommendedWatcher` for the current platform together
+/// with a blocking iterator over its events.
+///
+/// See [`EventIter`].
+pub fn recommended_watcher_iter() -> Result<(RecommendedWatcher, EventIter)> {
+    EventIter::new(Config::default())
+}
+
+/// Convenience method for creating the `RecommendedWatcher` for the current platform together
+/// with a crossbeam-channel receiver of its events, to use them in `select!` loops.
+///
+/// ```no_run
+/// # use std::path::Path;
+/// use crossbeam_channel::{select, tick};
+/// use notify::{RecursiveMode, Watcher};
+/// # use std::time::Duration;
+///
+/// # fn main() -> notify::Result<()> {
+/// let (mut watcher, events) = notify::recommended_watcher_channel()?;
+/// watcher.watch(Path::new("."), RecursiveMode::Recursive)?;
+///
+/// let ticks = tick(Duration::from_secs(1));
+/// loop {
+///     select! {
+///         recv(events) -> event => println!("{:?}", event),
+///         recv(ticks) -> _ => println!("tick"),
+///     }
+/// }
+/// # }
+/// ```
+///
+/// The receiver is disconnected once the watcher is dropped.
+#[cfg(feature = "crossbeam-channel")]
+pub fn recommended_watcher_channel() -> Result<(
+    RecommendedWatcher,
+    crossbeam_channel::Receiver<Result<Event>>,
+)> {
+    let (tx, rx) = crossbeam_channel::unbounded();
+    let watcher = RecommendedWatcher::new(tx, Config::default())?;
+    Ok((watcher, rx))
+}
+
+/// Create a watcher of a kind chosen at runtime, for example from a command line flag or user
+/// preference.
+///
+/// Passing [`WatcherKind::NullWatcher`] disables watching without changing the rest of the code:
+///
+/// ```
+/// # use std::path::Path;
+/// use notify::{Config, RecursiveMode, Watcher, WatcherKind};
+///
+/// # fn main() -> notify::Result<()> {
+/// # let watch_files = false;
+/// let kind = match watch_files {
+///     true => notify::RecommendedWatcher::kind(),
+///     false => WatcherKind::NullWatcher,
+/// };
+/// let mut watcher = notify::new_watcher(kind, |event| println!("{:?}", event), Config::default())?;
+/// watcher.watch(Path::new("."), RecursiveMode::Recursive)?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Kinds which are not available on the current platform return an error. The web backend's
+/// `FileSystemAccessWatcher` isn't `Send`, so it can't be created this way either.
+pub fn new_watcher<F>(
+    kind: WatcherKind,
+    event_handler: F,
+    config: Config,
+) -> Result<Box<dyn Watcher + Send>>
+where
+    F: EventHandler,
+{
+    Ok(match kind {
+        #[cfg(all(any(target_os = "linux", target_os = "android"), feature = "linux_inotify"))]
+        WatcherKind::Inotify => Box::new(INotifyWatcher::new(event_handler, config)?),
+        #[cfg(all(target_os = "macos", feature = "macos_fsevent", not(feature = "macos_kqueue")))]
+        WatcherKind::Fsevent => Box::new(FsEventWatcher::new(event_handler, config)?),
+        #[cfg(any(
+            all(
+                any(
+                    target_os = "freebsd",
+                    target_os = "openbsd",
+                    target_os = "netbsd",
+                    target_os = "dragonfly"
+                ),
+                feature = "bsd_kqueue"
+            ),
+            all(target_os = "macos", feature = "macos_kqueue")
+        ))]
+        WatcherKind::Kqueue => Box::new(KqueueWatcher::new(event_handler, config)?),
+        #[cfg(all(target_os = "windows", feature = "windows_read_directory_changes"))]
+        WatcherKind::ReadDirectoryChangesWatcher => {
+            Box::new(ReadDirectoryChangesWatcher::new(event_handler, config)?)
+        }
+        #[cfg(all(target_os = "windows", feature = "windows_etw"))]
+        WatcherKind::Etw => Box::new(EtwWatcher::new(event_handler, config)?),
+        #[cfg(all(target_os = "macos", feature = "macos_endpoint_security"))]
+        WatcherKind::EndpointSecurity => {
+            Box::new(EndpointSecurityWatcher::new(event_handler, config)?)
+        }
+        #[cfg(all(target_os = "linux", feature = "linux_audit"))]
+        WatcherKind::LinuxAudit => Box::new(LinuxAuditWatcher::new(event_handler, config)?),
+        #[cfg(all(target_os = "linux", feature = "linux_fanotify"))]
+        WatcherKind::Fanotify => Box::new(FanotifyWatcher::new(event_handler, config)?),
+        WatcherKind::PollWatcher => Box::new(PollWatcher::new(event_handler, config)?),
+        WatcherKind::NullWatcher => Box::new(NullWatcher::new(event_handler, config)?),
+        #[allow(unreachable_patterns)]
+        kind => {
+            return Err(Error::generic(&format!(
+                "{:?} watchers are not available here",
+                kind
+            )))
+        }
+    })
+}
+
+/// The environment variable read by [`recommended_watcher_from_env`] to override the backend
+pub const BACKEND_ENV_VAR: &str = "NOTIFY_BACKEND";
+
+/// Convenience method for creating the `RecommendedWatcher` for the current platform, or the
+/// backend named in the `NOTIFY_BACKEND` environment variable if it is set.
+///
+/// This allows switching backends to debug platform specific issues without recompiling:
+///
+/// ```sh
+/// NOTIFY_BACKEND=poll cargo run
+/// ```
+///
+/// See [`WatcherKind`'s `FromStr` implementation](WatcherKind#impl-FromStr-for-WatcherKind) for
+/// the accepted names. Unknown names and backends which are not available return an error.
+pub fn recommended_watcher_from_env<F>(event_handler: F) -> Result<Box<dyn Watcher + Send>>
+where
+    F: EventHandler,
+{
+    let kind = match std::env::var(BACKEND_ENV_VAR) {
+        Ok(name) => name.parse()?,
+        Err(_) => RecommendedWatcher::kind(),
+    };
+    new_watcher(kind, event_handler, Config::default())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn watcher_kinds_are_parsed_from_backend_names() {
+        assert_eq!("poll".parse::<WatcherKind>().unwrap(), WatcherKind::PollWatcher);
+        assert_eq!("Null".parse::<WatcherKind>().unwrap(), WatcherKind::NullWatcher);
+        assert!("carrier-pigeon".parse::<WatcherKind>().is_err());
+    }
+
     #[test]
     fn test_object_safe() {
         let _watcher: &dyn Watcher = &NullWatcher;
     }
 
+    #[test]
+    fn test_borrowing_event_handler() {
+        use std::path::PathBuf;
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        let mut handler = Borrowing(move |event: std::result::Result<&Event, &Error>| {
+            let _ = tx.send(event.unwrap().paths.len());
+        });
+
+        handler.handle_event(Ok(Event::default().add_path(PathBuf::from("a"))));
+        handler.handle_event(Ok(Event::default()));
+
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), [1, 0]);
+    }
+
     #[test]
     fn test_debug_impl() {
         macro_rules! assert_debug_impl {
             ($t:ty) => {{
-                trait NeedsDebug: std::fmt::Debug {}
-                impl NeedsDebug for $t {}
+                fn needs_debug<T: std::fmt::Debug>() {}
+                needs_debug::<$t>();
             }};
         }
 
@@ -408,6 +935,7 @@ mod tests {
         assert_debug_impl!(event::DataChange);
         assert_debug_impl!(event::EventAttributes);
         assert_debug_impl!(event::Flag);
+        assert_debug_impl!(event::MetadataDelta);
         assert_debug_impl!(event::MetadataKind);
         assert_debug_impl!(event::ModifyKind);
         assert_debug_impl!(event::RemoveKind);
@@ -0,0 +1,795 @@
+//! Watcher implementation for the Linux audit subsystem
+//!
+//! Installs an audit rule for every watch, and reads the records of the rules from the multicast
+//! group of the audit netlink socket, next to `auditd` if it runs. The records tell which process
+//! made a change, and are reported with its id. Installing the rules requires the
+//! `CAP_AUDIT_CONTROL` capability, reading the records `CAP_AUDIT_READ`, and auditing has to be
+//! enabled, e.g. with `auditctl -e 1`.
+//!
+//! The kernel audits the system calls changing a path, not the writes to an open file: a write is
+//! reported once, when the file is opened for writing, as `Modify(Any)`, as are changes of the
+//! metadata. Processes started before auditing was enabled aren't audited at all. A directory's
+//! rule covers all of its subtree, a non-recursive watch only selects the events delivered. The
+//! rules are removed when the watcher is dropped, but are left behind if the process is killed,
+//! and can be listed with `auditctl -l`, they are keyed `notify-rs-<pid>-<n>`.
+
+use crate::audit::{Audit, Resource};
+use crate::delivery::{Delivery, DeliveryOptions, WatchTokens};
+use crate::event::*;
+use crate::registry::{self, WatchRegistry};
+use crate::scheduling::Scheduling;
+use crate::suppress::{SuppressGuard, Suppressions};
+use crate::{snapshot, Config, Error, EventHandler, RecursiveMode, Result, Watcher, WatcherKind};
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fmt;
+use std::io;
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::RawFd;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+
+const SOCKET: mio::Token = mio::Token(0);
+const WAKER: mio::Token = mio::Token(1);
+
+const NLMSG_HDRLEN: usize = 16;
+const NLMSG_ERROR: u16 = 2;
+const NLM_F_REQUEST: u16 = 0x1;
+const NLM_F_ACK: u16 = 0x4;
+
+/// The multicast group of the audit records
+const AUDIT_NLGRP_READLOG: u32 = 1;
+
+const AUDIT_GET: u16 = 1000;
+const AUDIT_ADD_RULE: u16 = 1011;
+const AUDIT_DEL_RULE: u16 = 1012;
+const AUDIT_SYSCALL: u16 = 1300;
+const AUDIT_PATH: u16 = 1302;
+const AUDIT_CWD: u16 = 1307;
+const AUDIT_EOE: u16 = 1320;
+
+const AUDIT_FILTER_EXIT: u32 = 4;
+const AUDIT_ALWAYS: u32 = 2;
+const AUDIT_WATCH: u32 = 105;
+const AUDIT_PERM: u32 = 106;
+const AUDIT_DIR: u32 = 107;
+const AUDIT_FILTERKEY: u32 = 210;
+const AUDIT_EQUAL: u32 = 0x4000_0000;
+const AUDIT_PERM_WRITE: u32 = 2;
+const AUDIT_PERM_ATTR: u32 = 8;
+const AUDIT_MAX_FIELDS: usize = 64;
+const AUDIT_BITMASK_SIZE: usize = 64;
+
+/// Separates the keys of all rules matching a system call.
+const AUDIT_KEY_SEPARATOR: u8 = 0x01;
+
+/// The size of the largest audit record, rounded up
+const MAX_MESSAGE: usize = 16 * 1024;
+
+/// The records of system calls kept until their end, any more are lost records.
+const MAX_PENDING: usize = 256;
+
+/// Numbers the watchers of this process, whose rule keys have to be unique system-wide.
+static WATCHERS: AtomicUsize = AtomicUsize::new(0);
+
+/// A message received from the audit socket
+struct Message {
+    kind: u16,
+    seq: u32,
+    payload: Vec<u8>,
+}
+
+/// An audit netlink socket, closed when dropped.
+struct Socket {
+    fd: RawFd,
+}
+
+impl Socket {
+    /// Open a socket, joining the multicast `groups`.
+    fn open(groups: u32, flags: i32) -> io::Result<Self> {
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC | flags,
+                libc::NETLINK_AUDIT,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let socket = Socket { fd };
+
+        let mut address: libc::sockaddr_nl = unsafe { mem::zeroed() };
+        address.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        address.nl_groups = groups;
+        let code = unsafe {
+            libc::bind(
+                fd,
+                &address as *const libc::sockaddr_nl as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+        if code != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(socket)
+    }
+
+    fn send(&self, kind: u16, seq: u32, payload: &[u8]) -> io::Result<()> {
+        let mut message = Vec::with_capacity(NLMSG_HDRLEN + payload.len());
+        message.extend_from_slice(&((NLMSG_HDRLEN + payload.len()) as u32).to_ne_bytes());
+        message.extend_from_slice(&kind.to_ne_bytes());
+        message.extend_from_slice(&(NLM_F_REQUEST | NLM_F_ACK).to_ne_bytes());
+        message.extend_from_slice(&seq.to_ne_bytes());
+        message.extend_from_slice(&0u32.to_ne_bytes());
+        message.extend_from_slice(payload);
+        let sent = unsafe { libc::send(self.fd, message.as_ptr().cast(), message.len(), 0) };
+        if sent < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Receive the messages of one datagram.
+    fn recv(&self) -> io::Result<Vec<Message>> {
+        let mut buffer = vec![0u8; MAX_MESSAGE];
+        let len = unsafe { libc::recv(self.fd, buffer.as_mut_ptr().cast(), buffer.len(), 0) };
+        if len < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(messages(&buffer[..len as usize]))
+    }
+}
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+/// Split a datagram into its netlink messages.
+fn messages(mut data: &[u8]) -> Vec<Message> {
+    let u32_at = |data: &[u8], at: usize| {
+        u32::from_ne_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]])
+    };
+    let mut messages = Vec::new();
+    while data.len() >= NLMSG_HDRLEN {
+        let len = u32_at(data, 0) as usize;
+        if len < NLMSG_HDRLEN || len > data.len() {
+            break;
+        }
+        messages.push(Message {
+            kind: u16::from_ne_bytes([data[4], data[5]]),
+            seq: u32_at(data, 8),
+            payload: data[NLMSG_HDRLEN..len].to_vec(),
+        });
+        // messages are aligned to 4 bytes
+        data = &data[((len + 3) & !3).min(data.len())..];
+    }
+    messages
+}
+
+/// The socket controlling the audit subsystem
+struct Control {
+    socket: Socket,
+    seq: u32,
+}
+
+impl Control {
+    fn open() -> io::Result<Self> {
+        Ok(Control {
+            socket: Socket::open(0, 0)?,
+            seq: 0,
+        })
+    }
+
+    /// Send a request and wait for its acknowledgement, and its reply if `reply` is set.
+    fn request(&mut self, kind: u16, payload: &[u8], reply: bool) -> io::Result<Vec<u8>> {
+        self.seq = self.seq.wrapping_add(1);
+        self.socket.send(kind, self.seq, payload)?;
+
+        // the kernel may send the reply after the acknowledgement.
+        let mut acknowledged = false;
+        let mut answer = None;
+        while !acknowledged || (reply && answer.is_none()) {
+            for message in self.socket.recv()? {
+                if message.seq != self.seq {
+                    continue;
+                }
+                if message.kind != NLMSG_ERROR {
+                    answer = Some(message.payload);
+                    continue;
+                }
+                let code = match message.payload.get(..4) {
+                    Some(code) => i32::from_ne_bytes([code[0], code[1], code[2], code[3]]),
+                    None => return Err(io::Error::from(io::ErrorKind::InvalidData)),
+                };
+                if code != 0 {
+                    return Err(io::Error::from_raw_os_error(-code));
+                }
+                acknowledged = true;
+            }
+        }
+        Ok(answer.unwrap_or_default())
+    }
+
+    /// Whether auditing is enabled, or enabled and locked.
+    fn enabled(&mut self) -> io::Result<bool> {
+        let status = self.request(AUDIT_GET, &[], true)?;
+        match status.get(4..8) {
+            Some(enabled) => {
+                Ok(u32::from_ne_bytes([enabled[0], enabled[1], enabled[2], enabled[3]]) != 0)
+            }
+            None => Err(io::Error::from(io::ErrorKind::InvalidData)),
+        }
+    }
+}
+
+/// The rule auditing every change of `path`, or of every path below it if it is a directory.
+fn rule(path: &Path, directory: bool, key: &str) -> Vec<u8> {
+    let path = path.as_os_str().as_bytes();
+    let fields = [
+        (
+            if directory { AUDIT_DIR } else { AUDIT_WATCH },
+            path.len() as u32,
+        ),
+        (AUDIT_PERM, AUDIT_PERM_WRITE | AUDIT_PERM_ATTR),
+        (AUDIT_FILTERKEY, key.len() as u32),
+    ];
+    let padded = |values: &mut Vec<u8>, value: &dyn Fn(&(u32, u32)) -> u32| {
+        for i in 0..AUDIT_MAX_FIELDS {
+            values.extend_from_slice(&fields.get(i).map_or(0, value).to_ne_bytes());
+        }
+    };
+
+    let mut rule = Vec::new();
+    for value in [AUDIT_FILTER_EXIT, AUDIT_ALWAYS, fields.len() as u32] {
+        rule.extend_from_slice(&value.to_ne_bytes());
+    }
+    // all system calls, the permissions select the ones changing a path
+    for _ in 0..AUDIT_BITMASK_SIZE {
+        rule.extend_from_slice(&u32::MAX.to_ne_bytes());
+    }
+    padded(&mut rule, &|field| field.0);
+    padded(&mut rule, &|field| field.1);
+    padded(&mut rule, &|_| AUDIT_EQUAL);
+    rule.extend_from_slice(&((path.len() + key.len()) as u32).to_ne_bytes());
+    rule.extend_from_slice(path);
+    rule.extend_from_slice(key.as_bytes());
+    rule
+}
+
+/// The error of a request which the process lacks the capabilities for.
+fn access_error(e: io::Error) -> Error {
+    if e.kind() == io::ErrorKind::PermissionDenied {
+        Error::generic(
+            "The audit backend requires the CAP_AUDIT_CONTROL and CAP_AUDIT_READ capabilities.",
+        )
+    } else {
+        Error::io(e)
+    }
+}
+
+/// The serial of the record `text` and its fields.
+fn parse_record(text: &str) -> Option<(u64, &str)> {
+    let text = text.strip_prefix("audit(")?;
+    let end = text.find("):")?;
+    let serial = text[..end].rsplit(':').next()?.parse().ok()?;
+    Some((serial, text[end + 2..].trim_start()))
+}
+
+/// The value of the field `name` of a record.
+fn field<'a>(fields: &'a str, name: &str) -> Option<&'a str> {
+    fields
+        .split(' ')
+        .find_map(|field| field.strip_prefix(name)?.strip_prefix('='))
+}
+
+/// Decode a value the kernel quotes, or hex-encodes if it contains spaces or control characters.
+fn untrusted(value: &str) -> Option<Vec<u8>> {
+    if value == "(null)" {
+        return None;
+    }
+    if let Some(quoted) = value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+        return Some(quoted.as_bytes().to_vec());
+    }
+    if value.len() % 2 != 0 {
+        return None;
+    }
+    (0..value.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(value.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Whether the `key` is one of the keys of a `SYSCALL` record.
+fn has_key(fields: &str, key: &str) -> bool {
+    field(fields, "key")
+        .and_then(untrusted)
+        .map_or(false, |keys| {
+            keys.split(|&b| b == AUDIT_KEY_SEPARATOR)
+                .any(|k| k == key.as_bytes())
+        })
+}
+
+/// A path of a system call, from its `PATH` record
+struct Item {
+    path: PathBuf,
+    nametype: String,
+    inode: Option<u64>,
+    directory: bool,
+}
+
+/// The events of the records of one system call, started by its `SYSCALL` record.
+fn record_events(records: &[(u16, String)]) -> Vec<Event> {
+    let syscall = match records.iter().find(|(kind, _)| *kind == AUDIT_SYSCALL) {
+        Some((_, fields)) => fields,
+        None => return Vec::new(),
+    };
+    if field(syscall, "success") != Some("yes") {
+        return Vec::new();
+    }
+    let pid = field(syscall, "pid").and_then(|pid| pid.parse().ok());
+    let cwd = records
+        .iter()
+        .find(|(kind, _)| *kind == AUDIT_CWD)
+        .and_then(|(_, fields)| untrusted(field(fields, "cwd")?))
+        .map(|cwd| PathBuf::from(OsStr::from_bytes(&cwd)));
+
+    let items: Vec<Item> = records
+        .iter()
+        .filter(|(kind, _)| *kind == AUDIT_PATH)
+        .filter_map(|(_, fields)| {
+            let name = untrusted(field(fields, "name")?)?;
+            let name = Path::new(OsStr::from_bytes(&name));
+            let path = match &cwd {
+                Some(cwd) if name.is_relative() => cwd.join(name),
+                _ => name.to_owned(),
+            };
+            let mode = field(fields, "mode").and_then(|mode| u32::from_str_radix(mode, 8).ok());
+            Some(Item {
+                path,
+                nametype: field(fields, "nametype")?.to_owned(),
+                inode: field(fields, "inode").and_then(|inode| inode.parse().ok()),
+                directory: mode.map_or(false, |mode| mode & libc::S_IFMT == libc::S_IFDIR),
+            })
+        })
+        .collect();
+
+    let mut events = Vec::new();
+    let mut renamed = vec![false; items.len()];
+    for item in items.iter().filter(|item| item.nametype == "DELETE") {
+        // a rename deletes the old name and creates the new one, of the same inode.
+        let target = items.iter().position(|other| {
+            other.nametype == "CREATE" && item.inode.is_some() && other.inode == item.inode
+        });
+        let event = match target {
+            Some(target) => {
+                renamed[target] = true;
+                Event::new(EventKind::Modify(ModifyKind::Name(RenameMode::Both)))
+                    .add_path(item.path.clone())
+                    .add_path(items[target].path.clone())
+            }
+            None if item.directory => {
+                Event::new(EventKind::Remove(RemoveKind::Folder)).add_path(item.path.clone())
+            }
+            None => Event::new(EventKind::Remove(RemoveKind::File)).add_path(item.path.clone()),
+        };
+        events.push(event);
+    }
+    for (item, _) in items.iter().zip(renamed).filter(|(_, renamed)| !renamed) {
+        let kind = match item.nametype.as_str() {
+            "CREATE" if item.directory => EventKind::Create(CreateKind::Folder),
+            "CREATE" => EventKind::Create(CreateKind::File),
+            "NORMAL" => EventKind::Modify(ModifyKind::Any),
+            _ => continue,
+        };
+        events.push(Event::new(kind).add_path(item.path.clone()));
+    }
+
+    match pid {
+        Some(pid) => events
+            .into_iter()
+            .map(|event| event.set_process_id(pid))
+            .collect(),
+        None => events,
+    }
+}
+
+/// The parts of a watcher shared with its event loop.
+struct Shared {
+    registry: Mutex<WatchRegistry>,
+    delivery: Mutex<Delivery>,
+}
+
+impl Shared {
+    fn registry(&self) -> MutexGuard<'_, WatchRegistry> {
+        self.registry.lock().unwrap_or_else(|p| p.into_inner())
+    }
+
+    fn delivery(&self) -> MutexGuard<'_, Delivery> {
+        self.delivery.lock().unwrap_or_else(|p| p.into_inner())
+    }
+}
+
+/// Reads the records of the watcher's rules, until woken.
+struct EventLoop {
+    shared: Arc<Shared>,
+    poll: mio::Poll,
+    socket: Socket,
+    key: String,
+    /// The records of the system calls matching the rules, by their serial, until their end.
+    pending: HashMap<u64, Vec<(u16, String)>>,
+    audit: Audit,
+    scheduling: Scheduling,
+}
+
+impl EventLoop {
+    fn run(mut self) {
+        let thread = thread::current().id();
+        self.audit.acquire(Resource::Thread, thread);
+        let _ = self.scheduling.apply();
+        let mut events = mio::Events::with_capacity(16);
+        'run: loop {
+            if let Err(e) = self.poll.poll(&mut events, None) {
+                if e.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                self.shared.delivery().handle_event(Err(Error::io(e)));
+                break;
+            }
+            for event in &events {
+                match event.token() {
+                    WAKER => break 'run,
+                    SOCKET => self.handle_records(),
+                    _ => unreachable!(),
+                }
+            }
+        }
+        self.audit.release(Resource::Thread, thread);
+    }
+
+    fn handle_records(&mut self) {
+        loop {
+            match self.socket.recv() {
+                Ok(messages) => {
+                    for message in messages {
+                        self.handle_message(message);
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return,
+                Err(e) if e.raw_os_error() == Some(libc::ENOBUFS) => {
+                    // the socket overflowed, records were lost
+                    self.pending.clear();
+                    let event = Event::new(EventKind::Other).set_flag(Flag::Rescan);
+                    self.shared.delivery().handle_event(Ok(event));
+                }
+                Err(e) => {
+                    self.shared.delivery().handle_event(Err(Error::io(e)));
+                    return;
+                }
+            }
+        }
+    }
+
+    fn handle_message(&mut self, message: Message) {
+        let text = String::from_utf8_lossy(&message.payload);
+        let (serial, fields) = match parse_record(text.trim_end_matches('\0')) {
+            Some(record) => record,
+            None => return,
+        };
+        match message.kind {
+            AUDIT_SYSCALL => {
+                if has_key(fields, &self.key) {
+                    if self.pending.len() >= MAX_PENDING {
+                        self.pending.clear();
+                    }
+                    self.pending
+                        .insert(serial, vec![(message.kind, fields.to_owned())]);
+                }
+            }
+            AUDIT_EOE => {
+                let records = match self.pending.remove(&serial) {
+                    Some(records) => records,
+                    None => return,
+                };
+                let events: Vec<Event> = {
+                    let registry = self.shared.registry();
+                    record_events(&records)
+                        .into_iter()
+                        .filter(|event| event.paths.iter().any(|path| registry.is_watched(path)))
+                        .collect()
+                };
+                let mut delivery = self.shared.delivery();
+                for event in events {
+                    delivery.handle_event(Ok(event));
+                }
+            }
+            kind => {
+                if let Some(records) = self.pending.get_mut(&serial) {
+                    records.push((kind, fields.to_owned()));
+                }
+            }
+        }
+    }
+}
+
+/// Watcher implementation based on the Linux audit subsystem, see the [module](self) docs
+pub struct LinuxAuditWatcher {
+    shared: Arc<Shared>,
+    control: Control,
+    key: String,
+    /// The rules installed, by their path.
+    rules: HashMap<PathBuf, Vec<u8>>,
+    waker: mio::Waker,
+    thread: Option<JoinHandle<()>>,
+    tokens: WatchTokens,
+    options: DeliveryOptions,
+    suppressions: Suppressions,
+    initial_scan: bool,
+    audit: Audit,
+}
+
+impl fmt::Debug for LinuxAuditWatcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LinuxAuditWatcher")
+            .field("key", &self.key)
+            .field("registry", &*self.shared.registry())
+            .field("initial_scan", &self.initial_scan)
+            .finish_non_exhaustive()
+    }
+}
+
+impl LinuxAuditWatcher {
+    fn watch_inner(&mut self, path: &Path, recursive_mode: RecursiveMode) -> Result<()> {
+        let path = registry::absolute(path);
+        if !path.exists() {
+            return Err(Error::path_not_found().add_path(path));
+        }
+        self.shared.registry().plan_watch(&path, recursive_mode)?;
+        if !self.rules.contains_key(&path) {
+            let rule = rule(&path, path.is_dir(), &self.key);
+            self.control
+                .request(AUDIT_ADD_RULE, &rule, false)
+                .map_err(|e| access_error(e).add_path(path.clone()))?;
+            self.audit.acquire(Resource::Handle, &path);
+            self.rules.insert(path.clone(), rule);
+        }
+        self.shared.registry().insert(&path, recursive_mode);
+
+        if self.initial_scan {
+            let mut delivery = self.shared.delivery();
+            for event in snapshot::initial_scan_events(&path, recursive_mode) {
+                delivery.handle_event(Ok(event));
+            }
+        }
+        Ok(())
+    }
+
+    fn remove_rule(&mut self, path: &Path) -> Result<()> {
+        if let Some(rule) = self.rules.remove(path) {
+            self.audit.release(Resource::Handle, path);
+            self.control
+                .request(AUDIT_DEL_RULE, &rule, false)
+                .map_err(|e| access_error(e).add_path(path.to_owned()))?;
+        }
+        Ok(())
+    }
+}
+
+impl Watcher for LinuxAuditWatcher {
+    /// Create a new watcher, joining the multicast group of the audit records.
+    fn new<F: EventHandler>(event_handler: F, config: Config) -> Result<Self> {
+        let mut control = Control::open().map_err(access_error)?;
+        if !control.enabled().map_err(access_error)? {
+            return Err(Error::generic(
+                "Auditing is disabled, it can be enabled with `auditctl -e 1`.",
+            ));
+        }
+        let socket =
+            Socket::open(AUDIT_NLGRP_READLOG, libc::SOCK_NONBLOCK).map_err(access_error)?;
+
+        let poll = mio::Poll::new()?;
+        let waker = mio::Waker::new(poll.registry(), WAKER)?;
+        poll.registry().register(
+            &mut mio::unix::SourceFd(&socket.fd),
+            SOCKET,
+            mio::Interest::READABLE,
+        )?;
+
+        let delivery = Delivery::new(event_handler, &config);
+        let tokens = delivery.tokens();
+        let options = delivery.options();
+        let suppressions = delivery.suppressions();
+        let shared = Arc::new(Shared {
+            registry: Mutex::new(WatchRegistry::new(config.watch_overlap())),
+            delivery: Mutex::new(delivery),
+        });
+        let key = format!(
+            "notify-rs-{}-{}",
+            process::id(),
+            WATCHERS.fetch_add(1, Ordering::Relaxed)
+        );
+        let audit = Audit::new("linux_audit", &config);
+
+        let event_loop = EventLoop {
+            shared: shared.clone(),
+            poll,
+            socket,
+            key: key.clone(),
+            pending: HashMap::new(),
+            audit: audit.clone(),
+            scheduling: Scheduling::new(&config),
+        };
+        let thread = thread::Builder::new()
+            .name("notify-rs linux audit loop".to_string())
+            .spawn(move || event_loop.run())?;
+
+        Ok(LinuxAuditWatcher {
+            shared,
+            control,
+            key,
+            rules: HashMap::new(),
+            waker,
+            thread: Some(thread),
+            tokens,
+            options,
+            suppressions,
+            initial_scan: config.initial_scan(),
+            audit,
+        })
+    }
+
+    fn watch(&mut self, path: &Path, recursive_mode: RecursiveMode) -> Result<()> {
+        self.watch_inner(path, recursive_mode)
+    }
+
+    fn watch_with_token(
+        &mut self,
+        path: &Path,
+        recursive_mode: RecursiveMode,
+        token: u64,
+    ) -> Result<()> {
+        // registered first, so that the initial scan carries the token already.
+        self.tokens.insert(path, token);
+        if let Err(e) = self.watch_inner(path, recursive_mode) {
+            self.tokens.remove(path);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn unwatch(&mut self, path: &Path) -> Result<()> {
+        {
+            let mut registry = self.shared.registry();
+            if registry.recursive_mode(path).is_none() {
+                return Err(Error::watch_not_found().add_path(path.to_owned()));
+            }
+            registry.remove(path);
+        }
+        self.tokens.remove(path);
+        self.remove_rule(&registry::absolute(path))
+    }
+
+    fn set_recursive_mode(&mut self, path: &Path, recursive_mode: RecursiveMode) -> Result<()> {
+        // the rule of a directory covers its subtree, the watches only select the events.
+        let mut registry = self.shared.registry();
+        registry.plan_set_recursive_mode(path, recursive_mode)?;
+        registry.insert(path, recursive_mode);
+        Ok(())
+    }
+
+    fn suppress(&self, path: &Path) -> Result<SuppressGuard> {
+        Ok(self.suppressions.guard(path))
+    }
+
+    fn is_watched(&self, path: &Path) -> bool {
+        self.shared.registry().is_watched(path)
+    }
+
+    fn configure(&mut self, config: Config) -> Result<bool> {
+        self.options.configure(&config);
+        Ok(true)
+    }
+
+    fn kind() -> WatcherKind {
+        WatcherKind::LinuxAudit
+    }
+}
+
+impl Drop for LinuxAuditWatcher {
+    fn drop(&mut self) {
+        let paths: Vec<PathBuf> = self.rules.keys().cloned().collect();
+        for path in paths {
+            let _ = self.remove_rule(&path);
+        }
+        let _ = self.waker.wake();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[test]
+fn records_are_converted_into_events() {
+    let records = |records: &[(u16, &str)]| -> Vec<(u16, String)> {
+        records
+            .iter()
+            .map(|(kind, fields)| (*kind, fields.to_string()))
+            .collect()
+    };
+    let paths = |event: &Event| -> Vec<String> {
+        event
+            .paths
+            .iter()
+            .map(|p| p.to_str().unwrap().to_owned())
+            .collect()
+    };
+
+    // `mv /tmp/w/x y` from `/tmp/w`, replacing `y`
+    let events = record_events(&records(&[
+        (AUDIT_SYSCALL, "arch=c000003e syscall=316 success=yes exit=0 pid=11368 comm=\"mv\" key=\"notify-rs-1-0\""),
+        (AUDIT_CWD, "cwd=\"/tmp/w\""),
+        (AUDIT_PATH, "item=0 name=\"/tmp/w/\" inode=13 mode=040755 nametype=PARENT"),
+        (AUDIT_PATH, "item=1 name=\"/tmp/w/x\" inode=43 mode=0100644 nametype=DELETE"),
+        (AUDIT_PATH, "item=2 name=\"y\" inode=44 mode=0100644 nametype=DELETE"),
+        (AUDIT_PATH, "item=3 name=\"y\" inode=43 mode=0100644 nametype=CREATE"),
+    ]));
+    let kinds: Vec<_> = events
+        .iter()
+        .map(|event| (event.kind.clone(), paths(event)))
+        .collect();
+    assert_eq!(
+        kinds,
+        [
+            (
+                EventKind::Modify(ModifyKind::Name(RenameMode::Both)),
+                vec!["/tmp/w/x".to_owned(), "/tmp/w/y".to_owned()]
+            ),
+            (
+                EventKind::Remove(RemoveKind::File),
+                vec!["/tmp/w/y".to_owned()]
+            ),
+        ]
+    );
+    assert!(events
+        .iter()
+        .all(|event| event.attrs.process_id() == Some(11368)));
+
+    // a hex-encoded name with a space, and a failed call
+    let events = record_events(&records(&[
+        (
+            AUDIT_SYSCALL,
+            "syscall=83 success=yes pid=7 key=\"notify-rs-1-0\"",
+        ),
+        (
+            AUDIT_PATH,
+            "item=1 name=2F746D702F612062 inode=45 mode=040755 nametype=CREATE",
+        ),
+    ]));
+    assert_eq!(events[0].kind, EventKind::Create(CreateKind::Folder));
+    assert_eq!(paths(&events[0]), ["/tmp/a b"]);
+    let events = record_events(&records(&[
+        (
+            AUDIT_SYSCALL,
+            "syscall=84 success=no exit=-39 pid=7 key=\"notify-rs-1-0\"",
+        ),
+        (
+            AUDIT_PATH,
+            "item=1 name=\"/tmp/d\" inode=46 mode=040755 nametype=DELETE",
+        ),
+    ]));
+    assert!(events.is_empty());
+
+    // several matching rules join their keys
+    assert!(has_key("key=6F746865720170617468", "path"));
+    assert!(!has_key("key=(null)", "path"));
+    assert_eq!(
+        parse_record("audit(1791992600.116:8): cwd=\"/\""),
+        Some((8, "cwd=\"/\""))
+    );
+}
@@ -0,0 +1,424 @@
+//! Randomized file tree mutations with the changes they are expected to cause, with the `mutations` feature
+//!
+//! A [MutationGenerator] produces reproducible sequences of [Mutation]s from a seed: files and
+//! directories are created, written, renamed and removed, whole trees included. Alongside it
+//! keeps a model of the tree, so every batch comes with the net changes it causes to the tree,
+//! the ground truth to check the events of a watcher against.
+//!
+//! ```
+//! # use std::path::PathBuf;
+//! use notify::mutations::MutationGenerator;
+//!
+//! # fn main() -> std::io::Result<()> {
+//! # let root = std::env::temp_dir().join(format!("notify-mutations-doc-{}", std::process::id()));
+//! # std::fs::create_dir_all(&root)?;
+//! let mut generator = MutationGenerator::new(42);
+//! generator.generate(20).apply(&root)?;
+//!
+//! // start watching `root`...
+//! let batch = generator.generate(50);
+//! batch.apply(&root)?;
+//! let events = Vec::new(); // ...and collect the events of the watcher
+//!
+//! for (path, change) in batch.expected().missing(&root, &events) {
+//!     println!("no event for {:?} of {}", change, path.display());
+//! }
+//! # std::fs::remove_dir_all(&root)?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Backends differ in the events they emit for the same change, so only the net change of every
+//! path is expected: a file written and renamed is removed at its old path and created at its new
+//! one. Directories whose children changed aren't expected to change themselves.
+
+use crate::Event;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+/// A change to a file tree, with paths relative to its root
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Mutation {
+    /// Create an empty file
+    CreateFile(PathBuf),
+    /// Create an empty directory
+    CreateDir(PathBuf),
+    /// Append these bytes to an existing file
+    Write(PathBuf, Vec<u8>),
+    /// Rename a file or directory to a path which doesn't exist yet
+    Rename(PathBuf, PathBuf),
+    /// Remove a file, or a directory with everything below it
+    Remove(PathBuf),
+}
+
+impl Mutation {
+    /// Apply the mutation to the tree at `root`.
+    pub fn apply(&self, root: &Path) -> io::Result<()> {
+        match self {
+            Mutation::CreateFile(path) => fs::File::create(root.join(path)).map(drop),
+            Mutation::CreateDir(path) => fs::create_dir(root.join(path)),
+            Mutation::Write(path, data) => fs::OpenOptions::new()
+                .append(true)
+                .open(root.join(path))?
+                .write_all(data),
+            Mutation::Rename(from, to) => fs::rename(root.join(from), root.join(to)),
+            Mutation::Remove(path) => {
+                let path = root.join(path);
+                if path.is_dir() {
+                    fs::remove_dir_all(path)
+                } else {
+                    fs::remove_file(path)
+                }
+            }
+        }
+    }
+}
+
+/// The net change of a path
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Change {
+    /// The path didn't exist before
+    Created,
+    /// The path doesn't exist anymore
+    Removed,
+    /// The contents of the file changed
+    Modified,
+}
+
+/// The net changes of a batch of mutations to the tree, by path relative to its root
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Expected {
+    changes: BTreeMap<PathBuf, Change>,
+}
+
+impl Expected {
+    /// The changed paths with their change, sorted by path
+    pub fn changes(&self) -> impl Iterator<Item = (&Path, Change)> {
+        self.changes
+            .iter()
+            .map(|(path, change)| (path.as_path(), *change))
+    }
+
+    /// The change of `path`, if it changed
+    pub fn get(&self, path: &Path) -> Option<Change> {
+        self.changes.get(path).copied()
+    }
+
+    /// Whether no path changed
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// The expected changes of the tree at `root` without any of `events` for their path
+    ///
+    /// Paths are reported relative to `root`. The kinds of the events aren't compared, as
+    /// backends report the same change with different kinds.
+    pub fn missing(&self, root: &Path, events: &[Event]) -> Vec<(PathBuf, Change)> {
+        let seen: BTreeSet<&Path> = events
+            .iter()
+            .flat_map(|event| &event.paths)
+            .filter_map(|path| path.strip_prefix(root).ok())
+            .collect();
+        self.changes
+            .iter()
+            .filter(|(path, _)| !seen.contains(path.as_path()))
+            .map(|(path, change)| (path.clone(), *change))
+            .collect()
+    }
+}
+
+/// A batch of mutations, see [MutationGenerator::generate]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mutations {
+    mutations: Vec<Mutation>,
+    expected: Expected,
+}
+
+impl Mutations {
+    /// The mutations in the order they are applied
+    pub fn iter(&self) -> std::slice::Iter<'_, Mutation> {
+        self.mutations.iter()
+    }
+
+    /// The net changes the mutations cause
+    pub fn expected(&self) -> &Expected {
+        &self.expected
+    }
+
+    /// Apply the mutations to the tree at `root`, which has to be in the state the generator left
+    /// it after the earlier batches.
+    pub fn apply(&self, root: &Path) -> io::Result<()> {
+        self.mutations
+            .iter()
+            .try_for_each(|mutation| mutation.apply(root))
+    }
+}
+
+impl<'a> IntoIterator for &'a Mutations {
+    type Item = &'a Mutation;
+    type IntoIter = std::slice::Iter<'a, Mutation>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Generates reproducible batches of mutations, see the [module](self) docs.
+///
+/// The generator starts from an empty tree and models the tree its batches leave behind, so
+/// they have to be applied in order, to a directory which is empty at first. Names are never
+/// reused, so a path changes at most once per batch.
+#[derive(Clone, Debug)]
+pub struct MutationGenerator {
+    rng: Rng,
+    max_entries: usize,
+    max_depth: usize,
+    next_name: u64,
+    /// Whether the paths of the tree are directories
+    tree: BTreeMap<PathBuf, bool>,
+}
+
+impl MutationGenerator {
+    /// Generate the mutations determined by `seed`.
+    pub fn new(seed: u64) -> Self {
+        Self {
+            rng: Rng::new(seed),
+            max_entries: 64,
+            max_depth: 4,
+            next_name: 0,
+            tree: BTreeMap::new(),
+        }
+    }
+
+    /// Keep the tree at no more than `max_entries` files and directories, 64 by default.
+    pub fn with_max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = max_entries.max(1);
+        self
+    }
+
+    /// Nest directories at most `max_depth` levels below the root, 4 by default.
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth.max(1);
+        self
+    }
+
+    /// The paths of the modelled tree with whether they are directories, sorted by path
+    pub fn tree(&self) -> impl Iterator<Item = (&Path, bool)> {
+        self.tree
+            .iter()
+            .map(|(path, is_dir)| (path.as_path(), *is_dir))
+    }
+
+    /// Generate the next `len` mutations, with their net changes to the tree.
+    pub fn generate(&mut self, len: usize) -> Mutations {
+        let before = self.tree.clone();
+        let mut written = BTreeSet::new();
+        let mut mutations = Vec::with_capacity(len);
+        for _ in 0..len {
+            let mutation = self.mutation();
+            match &mutation {
+                Mutation::Write(path, _) => {
+                    written.insert(path.clone());
+                }
+                Mutation::Rename(from, _) | Mutation::Remove(from) => {
+                    written.retain(|path: &PathBuf| !path.starts_with(from));
+                }
+                _ => {}
+            }
+            mutations.push(mutation);
+        }
+
+        let mut changes: BTreeMap<PathBuf, Change> = self
+            .tree
+            .keys()
+            .filter(|path| !before.contains_key(*path))
+            .map(|path| (path.clone(), Change::Created))
+            .collect();
+        changes.extend(
+            before
+                .keys()
+                .filter(|path| !self.tree.contains_key(*path))
+                .map(|path| (path.clone(), Change::Removed)),
+        );
+        changes.extend(
+            written
+                .into_iter()
+                .filter(|path| before.contains_key(path))
+                .map(|path| (path, Change::Modified)),
+        );
+        Mutations {
+            mutations,
+            expected: Expected { changes },
+        }
+    }
+
+    /// Pick a mutation possible in the modelled tree and apply it to the model
+    fn mutation(&mut self) -> Mutation {
+        let files: Vec<PathBuf> = self.paths(false);
+        let full = self.tree.len() >= self.max_entries;
+        let choice = match self.rng.below(10) {
+            _ if self.tree.is_empty() => 0,
+            _ if full => 7 + self.rng.below(3),
+            choice => choice,
+        };
+        match choice {
+            0..=2 => self.create(false),
+            3 => self.create(true),
+            4..=6 if !files.is_empty() => {
+                let path = files[self.rng.below(files.len())].clone();
+                let len = 1 + self.rng.below(64);
+                let data = (0..len).map(|_| self.rng.next() as u8).collect();
+                Mutation::Write(path, data)
+            }
+            4..=6 => self.create(false),
+            7 | 8 => self.rename(),
+            _ => {
+                let paths: Vec<PathBuf> = self.tree.keys().cloned().collect();
+                let path = paths[self.rng.below(paths.len())].clone();
+                self.tree.retain(|other, _| !other.starts_with(&path));
+                Mutation::Remove(path)
+            }
+        }
+    }
+
+    fn create(&mut self, is_dir: bool) -> Mutation {
+        let path = self.new_path(None, if is_dir { "d" } else { "f" });
+        self.tree.insert(path.clone(), is_dir);
+        if is_dir {
+            Mutation::CreateDir(path)
+        } else {
+            Mutation::CreateFile(path)
+        }
+    }
+
+    fn rename(&mut self) -> Mutation {
+        let paths: Vec<PathBuf> = self.tree.keys().cloned().collect();
+        let from = paths[self.rng.below(paths.len())].clone();
+        let to = self.new_path(Some(&from), "r");
+        let moved: Vec<(PathBuf, bool)> = self
+            .tree
+            .iter()
+            .filter(|(path, _)| path.starts_with(&from))
+            .map(|(path, is_dir)| (path.clone(), *is_dir))
+            .collect();
+        for (path, is_dir) in moved {
+            self.tree.remove(&path);
+            // joining an empty path would append a separator
+            let moved = match path.strip_prefix(&from) {
+                Ok(relative) if !relative.as_os_str().is_empty() => to.join(relative),
+                _ => to.clone(),
+            };
+            self.tree.insert(moved, is_dir);
+        }
+        Mutation::Rename(from, to)
+    }
+
+    /// A new path in a random directory, not below `excluded`, deep enough for the subtree of `excluded`
+    fn new_path(&mut self, excluded: Option<&Path>, prefix: &str) -> PathBuf {
+        let height = excluded.map_or(0, |excluded| {
+            self.tree
+                .keys()
+                .filter_map(|path| path.strip_prefix(excluded).ok())
+                .map(|relative| relative.components().count())
+                .max()
+                .unwrap_or(0)
+        });
+        let max_depth = self.max_depth;
+        let mut dirs: Vec<PathBuf> = self
+            .paths(true)
+            .into_iter()
+            .filter(|dir| excluded.map_or(true, |excluded| !dir.starts_with(excluded)))
+            .filter(|dir| dir.components().count() + height < max_depth)
+            .collect();
+        dirs.push(PathBuf::new());
+        let parent = dirs[self.rng.below(dirs.len())].clone();
+        self.next_name += 1;
+        parent.join(format!("{}{}", prefix, self.next_name))
+    }
+
+    fn paths(&self, dirs: bool) -> Vec<PathBuf> {
+        self.tree
+            .iter()
+            .filter(|(_, is_dir)| **is_dir == dirs)
+            .map(|(path, _)| path.clone())
+            .collect()
+    }
+}
+
+/// The xorshift64* generator, reproducible on every platform
+#[derive(Clone, Debug)]
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // the state must not be zero
+        Self(seed ^ 0x9E37_79B9_7F4A_7C15)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// A number in `0..n`
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+#[test]
+fn generated_mutations_cause_the_expected_changes() {
+    use crate::event::EventKind;
+    use crate::snapshot::Snapshot;
+    use crate::{Config, RecursiveMode};
+
+    // modification times may not change between quick writes
+    let config = Config::default().with_compare_contents(true);
+    let snapshot = |root: &Path| Snapshot::build_with(root, RecursiveMode::Recursive, &config);
+
+    for seed in 0..8 {
+        let dir = tempfile::tempdir().unwrap();
+        let mut generator = MutationGenerator::new(seed).with_max_entries(32);
+        generator.generate(30).apply(dir.path()).unwrap();
+        let before = snapshot(dir.path()).unwrap();
+        let batch = generator.generate(60);
+        batch.apply(dir.path()).unwrap();
+        let after = snapshot(dir.path()).unwrap();
+
+        let mut changes = BTreeMap::new();
+        for event in before.diff(&after) {
+            let path = event.paths[0]
+                .strip_prefix(dir.path())
+                .unwrap()
+                .to_path_buf();
+            let change = match event.kind {
+                EventKind::Create(_) => Change::Created,
+                EventKind::Remove(_) => Change::Removed,
+                // directories change with their children
+                _ if path.as_os_str().is_empty() || dir.path().join(&path).is_dir() => continue,
+                _ => Change::Modified,
+            };
+            changes.insert(path, change);
+        }
+        assert_eq!(batch.expected().changes, changes, "seed {}", seed);
+        assert!(batch
+            .expected()
+            .missing(dir.path(), &before.diff(&after))
+            .is_empty());
+
+        let mut model: Vec<_> = generator
+            .tree()
+            .map(|(path, _)| dir.path().join(path))
+            .collect();
+        model.insert(0, dir.path().to_path_buf());
+        let mut paths: Vec<_> = after.paths().map(Path::to_path_buf).collect();
+        paths.sort();
+        assert_eq!(paths, model, "seed {}", seed);
+    }
+}
@@ -4,13 +4,14 @@
 
 use crate::Config;
 
-use super::{RecursiveMode, Result, Watcher};
+use super::{RecursiveMode, Result, SuppressGuard, Watcher};
 use std::path::Path;
 
 /// Stub `Watcher` implementation
 ///
-/// Events are never delivered from this watcher.
-#[derive(Debug)]
+/// Events are never delivered from this watcher, and all calls succeed. It can stand in for a real
+/// watcher to disable watching in tests or by user preference, see [`new_watcher`](crate::new_watcher).
+#[derive(Debug, Clone, Copy, Default)]
 pub struct NullWatcher;
 
 impl Watcher for NullWatcher {
@@ -18,6 +19,15 @@ impl Watcher for NullWatcher {
         Ok(())
     }
 
+    fn watch_with_token(
+        &mut self,
+        path: &Path,
+        recursive_mode: RecursiveMode,
+        token: u64,
+    ) -> Result<()> {
+        Ok(())
+    }
+
     fn unwatch(&mut self, path: &Path) -> Result<()> {
         Ok(())
     }
@@ -33,6 +43,10 @@ impl Watcher for NullWatcher {
         Ok(false)
     }
 
+    fn suppress(&self, _path: &Path) -> Result<SuppressGuard> {
+        Ok(SuppressGuard::inert())
+    }
+
     fn kind() -> crate::WatcherKind {
         crate::WatcherKind::NullWatcher
     }
@@ -0,0 +1,342 @@
+//! Watches which survive a restart of the process
+//!
+//! [PersistedWatches] keeps the watches of an application in a state file, updated on every
+//! change, so the next start of the application establishes them again with one call. Together
+//! with [ChangesSince] it also reports what changed in between:
+//!
+//! ```no_run
+//! # use std::path::Path;
+//! use notify::persist::{PersistedWatches, Registration};
+//! use notify::snapshot::ChangesSince;
+//! use notify::{Config, RecursiveMode};
+//!
+//! # fn main() -> notify::Result<()> {
+//! let mut watcher = notify::recommended_watcher(|event| println!("live: {:?}", event))?;
+//! let mut watches = PersistedWatches::open("watches")?;
+//! let mut changes = ChangesSince::load("snapshots", &Config::default())?;
+//!
+//! let (events, errors) = watches.restore_since(&mut watcher, &mut changes);
+//! for event in events {
+//!     println!("while stopped: {:?}", event);
+//! }
+//! for error in errors {
+//!     println!("not watched again: {}", error);
+//! }
+//!
+//! // watches added later are restored on the next start as well
+//! let registration = Registration::new("src", RecursiveMode::Recursive);
+//! watches.watch(&mut watcher, registration.clone())?;
+//! changes.track(registration.path(), registration.recursive_mode());
+//!
+//! // ... on shutdown, while still watching:
+//! changes.save("snapshots")?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The event filter and the other settings of the watcher are part of its [Config](crate::Config),
+//! which the application creates again on start.
+
+use crate::registry;
+use crate::snapshot::{read_path, read_u64, read_u8, write_path, ChangesSince};
+use crate::{Event, RecursiveMode, SymlinkMode, Watcher};
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+
+/// Magic bytes and format version at the start of persisted watches.
+const WATCHES_HEADER: &[u8] = b"notify-rs watches v1\n";
+
+/// A watch as it is restored, see [PersistedWatches::watch]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Registration {
+    path: PathBuf,
+    recursive_mode: RecursiveMode,
+    symlink_mode: SymlinkMode,
+    token: Option<u64>,
+}
+
+impl Registration {
+    /// A watch of `path`, which is resolved against the current directory now if it is relative.
+    pub fn new<P: AsRef<Path>>(path: P, recursive_mode: RecursiveMode) -> Self {
+        Self {
+            path: registry::absolute(path.as_ref()),
+            recursive_mode,
+            symlink_mode: SymlinkMode::default(),
+            token: None,
+        }
+    }
+
+    /// Watch with [Watcher::watch_with_symlink_mode].
+    pub fn with_symlink_mode(mut self, symlink_mode: SymlinkMode) -> Self {
+        self.symlink_mode = symlink_mode;
+        self
+    }
+
+    /// Watch with [Watcher::watch_with_token], which always watches the target of links.
+    pub fn with_token(mut self, token: Option<u64>) -> Self {
+        self.token = token;
+        self
+    }
+
+    /// The absolute path of the watch
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns current setting
+    pub fn recursive_mode(&self) -> RecursiveMode {
+        self.recursive_mode
+    }
+
+    /// Returns current setting
+    pub fn symlink_mode(&self) -> SymlinkMode {
+        self.symlink_mode
+    }
+
+    /// Returns current setting
+    pub fn token(&self) -> Option<u64> {
+        self.token
+    }
+
+    pub(crate) fn install<W: Watcher + ?Sized>(&self, watcher: &mut W) -> crate::Result<()> {
+        match self.token {
+            Some(token) => watcher.watch_with_token(&self.path, self.recursive_mode, token),
+            None if self.symlink_mode == SymlinkMode::Link => {
+                watcher.watch_with_symlink_mode(&self.path, self.recursive_mode, self.symlink_mode)
+            }
+            None => watcher.watch(&self.path, self.recursive_mode),
+        }
+    }
+}
+
+/// The watches of an application, kept in a state file, see the [module](self) docs.
+///
+/// The state file is replaced atomically on every change, so a crash leaves either the old or
+/// the new set of watches behind, never a truncated file.
+#[derive(Debug)]
+pub struct PersistedWatches {
+    state: PathBuf,
+    registrations: Vec<Registration>,
+}
+
+impl PersistedWatches {
+    /// Load the watches saved in the file at `state`, which is created on the first change.
+    ///
+    /// A missing file is not an error, there are no watches to restore then.
+    pub fn open<P: Into<PathBuf>>(state: P) -> io::Result<Self> {
+        let state = state.into();
+        let registrations = load(&state)?;
+        Ok(Self {
+            state,
+            registrations,
+        })
+    }
+
+    /// The saved watches, in the order they were added
+    pub fn registrations(&self) -> &[Registration] {
+        &self.registrations
+    }
+
+    /// Watch `registration` with `watcher` and save it, replacing a saved watch of the same path.
+    ///
+    /// Nothing is saved if watching fails. An error saving the state is returned as
+    /// [ErrorKind::Io](crate::ErrorKind::Io), the path is watched then.
+    pub fn watch<W: Watcher + ?Sized>(
+        &mut self,
+        watcher: &mut W,
+        registration: Registration,
+    ) -> crate::Result<()> {
+        registration.install(watcher)?;
+        self.registrations
+            .retain(|saved| saved.path != registration.path);
+        self.registrations.push(registration);
+        self.save().map_err(crate::Error::io)
+    }
+
+    /// Unwatch `path` with `watcher` and remove it from the saved watches.
+    ///
+    /// The watch is removed from the state even if unwatching fails, e.g. because it couldn't be
+    /// restored.
+    pub fn unwatch<W: Watcher + ?Sized>(
+        &mut self,
+        watcher: &mut W,
+        path: &Path,
+    ) -> crate::Result<()> {
+        let path = registry::absolute(path);
+        let len = self.registrations.len();
+        self.registrations.retain(|saved| saved.path != path);
+        if self.registrations.len() != len {
+            self.save().map_err(crate::Error::io)?;
+        }
+        watcher.unwatch(&path)
+    }
+
+    /// Watch all saved watches with `watcher`, returning the errors of those which failed.
+    ///
+    /// Failed watches stay saved, to be restored on the next start, until they are unwatched.
+    pub fn restore<W: Watcher + ?Sized>(&self, watcher: &mut W) -> Vec<crate::Error> {
+        self.registrations
+            .iter()
+            .filter_map(|registration| registration.install(watcher).err())
+            .collect()
+    }
+
+    /// Like [PersistedWatches::restore], also returning the changes since the state of `changes`
+    /// was saved.
+    ///
+    /// The watches are tracked by `changes` like watches made with [ChangesSince::watch].
+    pub fn restore_since<W: Watcher + ?Sized>(
+        &self,
+        watcher: &mut W,
+        changes: &mut ChangesSince,
+    ) -> (Vec<Event>, Vec<crate::Error>) {
+        let mut events = Vec::new();
+        let mut errors = Vec::new();
+        for registration in &self.registrations {
+            match registration.install(watcher) {
+                Ok(()) => {
+                    events.extend(changes.track(&registration.path, registration.recursive_mode))
+                }
+                Err(e) => errors.push(e),
+            }
+        }
+        (events, errors)
+    }
+
+    /// Replace the state file with the current watches
+    fn save(&self) -> io::Result<()> {
+        let mut tmp_name = self.state.as_os_str().to_owned();
+        tmp_name.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_name);
+
+        let mut w = BufWriter::new(File::create(&tmp_path)?);
+        w.write_all(WATCHES_HEADER)?;
+        w.write_all(&(self.registrations.len() as u64).to_le_bytes())?;
+        for registration in &self.registrations {
+            write_path(&mut w, &registration.path)?;
+            w.write_all(&[
+                registration.recursive_mode.is_recursive() as u8,
+                (registration.symlink_mode == SymlinkMode::Link) as u8,
+                registration.token.is_some() as u8,
+            ])?;
+            w.write_all(&registration.token.unwrap_or(0).to_le_bytes())?;
+        }
+        w.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+
+        fs::rename(&tmp_path, &self.state)
+    }
+}
+
+/// Read the watches from the file at `path`, none if it doesn't exist
+fn load(path: &Path) -> io::Result<Vec<Registration>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut r = BufReader::new(file);
+
+    let mut header = [0; WATCHES_HEADER.len()];
+    r.read_exact(&mut header)?;
+    if header != WATCHES_HEADER {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not a notify-rs watches file",
+        ));
+    }
+
+    let mut registrations = Vec::new();
+    for _ in 0..read_u64(&mut r)? {
+        let path = read_path(&mut r)?;
+        let recursive_mode = if read_u8(&mut r)? != 0 {
+            RecursiveMode::Recursive
+        } else {
+            RecursiveMode::NonRecursive
+        };
+        let symlink_mode = if read_u8(&mut r)? != 0 {
+            SymlinkMode::Link
+        } else {
+            SymlinkMode::Target
+        };
+        let has_token = read_u8(&mut r)? != 0;
+        let token = read_u64(&mut r)?;
+        registrations.push(Registration {
+            path,
+            recursive_mode,
+            symlink_mode,
+            token: if has_token { Some(token) } else { None },
+        });
+    }
+
+    Ok(registrations)
+}
+
+#[test]
+fn watches_are_restored_with_the_changes_since() {
+    use crate::{Config, PollWatcher};
+
+    let dir = tempfile::tempdir().unwrap();
+    let (watches_file, snapshots) = (dir.path().join("watches"), dir.path().join("snapshots"));
+    let (a, b) = (dir.path().join("a"), dir.path().join("b"));
+    fs::create_dir(&a).unwrap();
+    fs::create_dir(&b).unwrap();
+    let config = Config::default();
+
+    {
+        let mut watcher = PollWatcher::new(|_| {}, config.clone()).unwrap();
+        let mut watches = PersistedWatches::open(&watches_file).unwrap();
+        let mut changes = ChangesSince::load(&snapshots, &config).unwrap();
+        let (events, errors) = watches.restore_since(&mut watcher, &mut changes);
+        assert!(events.is_empty() && errors.is_empty());
+
+        watches
+            .watch(
+                &mut watcher,
+                Registration::new(&a, RecursiveMode::Recursive),
+            )
+            .unwrap();
+        watches
+            .watch(
+                &mut watcher,
+                Registration::new(&b, RecursiveMode::NonRecursive).with_token(Some(7)),
+            )
+            .unwrap();
+        changes.track(&a, RecursiveMode::Recursive);
+        changes.save(&snapshots).unwrap();
+    }
+
+    fs::write(a.join("file"), "").unwrap();
+
+    let mut watcher = PollWatcher::new(|_| {}, config.clone()).unwrap();
+    let mut watches = PersistedWatches::open(&watches_file).unwrap();
+    assert_eq!(
+        watches.registrations(),
+        [
+            Registration::new(&a, RecursiveMode::Recursive),
+            Registration::new(&b, RecursiveMode::NonRecursive).with_token(Some(7)),
+        ]
+    );
+    let mut changes = ChangesSince::load(&snapshots, &config).unwrap();
+    let (events, errors) = watches.restore_since(&mut watcher, &mut changes);
+    // the directory itself may have been modified as well.
+    let paths: Vec<_> = events
+        .into_iter()
+        .flat_map(|event| event.paths)
+        .filter(|path| *path != a)
+        .collect();
+    assert_eq!(paths, [a.join("file")]);
+    assert!(errors.is_empty());
+    assert!(watcher.is_watched(&a) && watcher.is_watched(&b));
+
+    watches.unwatch(&mut watcher, &b).unwrap();
+    assert_eq!(
+        PersistedWatches::open(&watches_file)
+            .unwrap()
+            .registrations()
+            .len(),
+        1
+    );
+}
@@ -3,9 +3,23 @@
 //! Checks the `watch`ed paths periodically to detect changes. This implementation only uses
 //! Rust stdlib APIs and should work on all of the platforms it supports.
 
-use crate::{EventHandler, RecursiveMode, Watcher, Config};
+use crate::{
+    audit::{Audit, Resource},
+    delivery::{Delivery, DeliveryOptions, WatchTokens},
+    registry::{InstallWatches, WatchRegistry},
+    scheduling::Scheduling,
+    snapshot::{self, Scanner, Snapshot},
+    suppress::{SuppressGuard, Suppressions},
+    unbounded, Config, Event, EventHandler, Receiver, RecursiveMode, Sender, Watcher,
+};
+use instant::Instant;
 use std::{
-    collections::HashMap,
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    ffi::OsString,
+    fmt::{self, Debug},
+    fs::{self, Metadata},
+    io,
     path::{Path, PathBuf},
     sync::{
         atomic::{AtomicBool, Ordering},
@@ -15,383 +29,268 @@ use std::{
     time::Duration,
 };
 
-use data::{DataBuilder, WatchData};
-mod data {
-    use crate::{
-        event::{CreateKind, DataChange, Event, EventKind, MetadataKind, ModifyKind, RemoveKind},
-        EventHandler,
-    };
-    use filetime::FileTime;
-    use std::{
-        cell::RefCell,
-        collections::{hash_map::RandomState, HashMap},
-        fmt::{self, Debug},
-        fs::{self, File, Metadata},
-        hash::{BuildHasher, Hasher},
-        io::{self, Read},
-        path::{Path, PathBuf},
-        time::Instant,
-    };
-    use walkdir::WalkDir;
+/// The state of a watch and when it is scanned.
+#[derive(Debug)]
+struct WatchData {
+    snapshot: Snapshot,
 
-    /// Builder for [`WatchData`] & [`PathData`].
-    pub(super) struct DataBuilder {
-        emitter: EventEmitter,
+    // replaces the poll interval of the config for this watch if set.
+    interval: Option<Duration>,
 
-        // TODO: May allow user setup their custom BuildHasher / BuildHasherDefault
-        // in future.
-        build_hasher: Option<RandomState>,
+    // when the last scan finished.
+    scanned: Instant,
+}
 
-        // current timestamp for building Data.
-        #[cfg(target_arch = "wasm32-unknown-unknown")]
-        now: instant::Instant,
-        #[cfg(not(target_arch = "wasm32-unknown-unknown"))]
-        now: std::time::Instant,
+impl WatchData {
+    fn new(snapshot: Snapshot) -> Self {
+        Self {
+            snapshot,
+            interval: None,
+            scanned: Instant::now(),
+        }
     }
+}
 
-    impl DataBuilder {
-        pub(super) fn new<F>(event_handler: F, compare_content: bool) -> Self
-        where
-            F: EventHandler,
-        {
-            Self {
-                emitter: EventEmitter::new(event_handler),
-                build_hasher: compare_content.then(RandomState::default),
-                now: instant::Instant::now(),
-            }
-        }
+/// Takes a new [Snapshot] for every scan and emits the changes to the previous one.
+struct DataBuilder {
+    emitter: EventEmitter,
+    scanner: Scanner,
 
-        /// Update internal timestamp.
-        pub(super) fn update_timestamp(&mut self) {
-            self.now = instant::Instant::now();
-        }
+    // replaces the built-in change detection if set.
+    comparator: Option<Box<dyn ChangeComparator>>,
 
-        /// Create [`WatchData`].
-        ///
-        /// This function will return `Err(_)` if can not retrieve metadata from
-        /// the path location. (e.g., not found).
-        pub(super) fn build_watch_data(
-            &self,
-            root: PathBuf,
-            is_recursive: bool,
-        ) -> Option<WatchData> {
-            WatchData::new(self, root, is_recursive)
-        }
+    // whether to report the existing entries of new watches.
+    initial_scan: bool,
+}
 
-        /// Create [`PathData`].
-        fn build_path_data(&self, meta_path: &MetaPath) -> PathData {
-            PathData::new(self, meta_path)
+impl DataBuilder {
+    fn new(delivery: Delivery, config: &Config) -> Self {
+        Self {
+            emitter: EventEmitter::new(delivery),
+            scanner: Scanner::new(config),
+            comparator: None,
+            initial_scan: config.initial_scan(),
         }
     }
 
-    impl Debug for DataBuilder {
-        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-            f.debug_struct("DataBuilder")
-                .field("build_hasher", &self.build_hasher)
-                .field("now", &self.now)
-                .finish()
-        }
+    /// Replace the built-in change detection with a custom comparator.
+    fn set_comparator(&mut self, comparator: Box<dyn ChangeComparator>) {
+        self.scanner.set_keep_metadata(true);
+        self.comparator = Some(comparator);
     }
 
-    #[derive(Debug)]
-    pub(super) struct WatchData {
-        // config part, won't change.
-        root: PathBuf,
-        is_recursive: bool,
-
-        // current status part.
-        all_path_data: HashMap<PathBuf, PathData>,
-    }
-
-    impl WatchData {
-        /// Scan filesystem and create a new `WatchData`.
-        ///
-        /// # Side effect
-        ///
-        /// This function may send event by `data_builder.emitter`.
-        fn new(data_builder: &DataBuilder, root: PathBuf, is_recursive: bool) -> Option<Self> {
-            // If metadata read error at `root` path, it will emit
-            // a error event and stop to create the whole `WatchData`.
-            //
-            // QUESTION: inconsistent?
-            //
-            // When user try to *CREATE* a watch by `poll_watcher.watch(root, ..)`,
-            // if `root` path hit an io error, then watcher will reject to
-            // create this new watch.
-            //
-            // This may inconsistent with *POLLING* a watch. When watcher
-            // continue polling, io error at root path will not delete
-            // a existing watch. polling still working.
-            //
-            // So, consider a config file may not exists at first time but may
-            // create after a while, developer cannot watch it.
-            //
-            // FIXME: Can we always allow to watch a path, even file not
-            // found at this path?
-            if let Err(e) = fs::metadata(&root) {
-                data_builder.emitter.emit_io_err(e, &root);
-                return None;
-            }
-
-            let all_path_data =
-                Self::scan_all_path_data(data_builder, root.clone(), is_recursive).collect();
-
-            Some(Self {
-                root,
-                is_recursive,
-                all_path_data,
-            })
+    /// Take the first [Snapshot] of a new watch.
+    ///
+    /// # Side effect
+    ///
+    /// This function may send event by `self.emitter`.
+    fn build_watch_data(&self, root: PathBuf, is_recursive: bool) -> Option<Snapshot> {
+        // If metadata read error at `root` path, it will emit
+        // a error event and stop to create the whole watch.
+        //
+        // QUESTION: inconsistent?
+        //
+        // When user try to *CREATE* a watch by `poll_watcher.watch(root, ..)`,
+        // if `root` path hit an io error, then watcher will reject to
+        // create this new watch.
+        //
+        // This may inconsistent with *POLLING* a watch. When watcher
+        // continue polling, io error at root path will not delete
+        // a existing watch. polling still working.
+        //
+        // So, consider a config file may not exists at first time but may
+        // create after a while, developer cannot watch it.
+        //
+        // FIXME: Can we always allow to watch a path, even file not
+        // found at this path?
+        if let Err(e) = fs::metadata(&root) {
+            self.emitter.emit_io_err(e, root);
+            return None;
         }
 
-        /// Rescan filesystem and update this `WatchData`.
-        ///
-        /// # Side effect
-        ///
-        /// This function may emit event by `data_builder.emitter`.
-        pub(super) fn rescan(&mut self, data_builder: &mut DataBuilder) {
-            // scan current filesystem.
-            for (path, new_path_data) in
-                Self::scan_all_path_data(data_builder, self.root.clone(), self.is_recursive)
-            {
-                let old_path_data = self
-                    .all_path_data
-                    .insert(path.clone(), new_path_data.clone());
-
-                // emit event
-                let event =
-                    PathData::compare_to_event(path, old_path_data.as_ref(), Some(&new_path_data));
-                if let Some(event) = event {
-                    data_builder.emitter.emit_ok(event);
-                }
-            }
-
-            // scan for disappeared paths.
-            let mut disappeared_paths = Vec::new();
-            for (path, path_data) in self.all_path_data.iter() {
-                if path_data.last_check < data_builder.now {
-                    disappeared_paths.push(path.clone());
-                }
+        let snapshot = self.scan(root, is_recursive);
+        if self.initial_scan {
+            for event in snapshot.initial_scan_events() {
+                self.emitter.emit_ok(event);
             }
+        }
 
-            // remove disappeared paths
-            for path in disappeared_paths {
-                let old_path_data = self.all_path_data.remove(&path);
+        Some(snapshot)
+    }
 
-                // emit event
-                let event = PathData::compare_to_event(path, old_path_data.as_ref(), None);
-                if let Some(event) = event {
-                    data_builder.emitter.emit_ok(event);
-                }
-            }
+    /// Continue a watch from a previously persisted [Snapshot].
+    ///
+    /// The filesystem is rescanned right away, emitting an event for every difference
+    /// to the persisted state instead of an initial scan.
+    fn restore_watch_data(&self, mut snapshot: Snapshot) -> Option<Snapshot> {
+        if let Err(e) = fs::metadata(snapshot.root()) {
+            self.emitter.emit_io_err(e, snapshot.root());
+            return None;
         }
 
-        /// Get all `PathData` by given configuration.
-        ///
-        /// # Side Effect
-        ///
-        /// This function may emit some IO Error events by `data_builder.emitter`.
-        fn scan_all_path_data(
-            data_builder: &'_ DataBuilder,
-            root: PathBuf,
-            is_recursive: bool,
-        ) -> impl Iterator<Item = (PathBuf, PathData)> + '_ {
-            // WalkDir return only one entry if root is a file (not a folder),
-            // so we can use single logic to do the both file & dir's jobs.
-            //
-            // See: https://docs.rs/walkdir/2.0.1/walkdir/struct.WalkDir.html#method.new
-            WalkDir::new(root)
-                .follow_links(true)
-                .max_depth(Self::dir_scan_depth(is_recursive))
-                .into_iter()
-                //
-                // QUESTION: should we ignore IO Error?
-                //
-                // current implementation ignore some IO error, e.g.,
-                //
-                // - `.filter_map(|entry| entry.ok())`
-                // - all read error when hashing
-                //
-                // but the code also interest with `fs::metadata()` error and
-                // propagate to event handler. It may not consistent.
-                //
-                // FIXME: Should we emit all IO error events? Or ignore them all?
-                .filter_map(|entry| entry.ok())
-                .filter_map(|entry| match entry.metadata() {
-                    Ok(metadata) => {
-                        let path = entry.into_path();
-
-                        let meta_path = MetaPath::from_parts_unchecked(path, metadata);
-                        let data_path = data_builder.build_path_data(&meta_path);
-
-                        Some((meta_path.into_path(), data_path))
-                    }
-                    Err(e) => {
-                        // emit event.
-                        let path = entry.into_path();
-                        data_builder.emitter.emit_io_err(e, path);
+        // the paths excluded since the snapshot was saved didn't change, they're just not watched.
+        self.scanner.exclude(&mut snapshot);
 
-                        None
-                    }
-                })
-        }
+        self.rescan(&mut snapshot);
 
-        fn dir_scan_depth(is_recursive: bool) -> usize {
-            if is_recursive {
-                usize::max_value()
-            } else {
-                1
-            }
-        }
+        Some(snapshot)
     }
 
-    /// Stored data for a one path locations.
+    /// Rescan filesystem and replace `snapshot` by the new state.
     ///
-    /// See [`WatchData`] for more detail.
-    #[derive(Debug, Clone)]
-    struct PathData {
-        /// File updated time.
-        mtime: i64,
-
-        /// Content's hash value, only available if user request compare file
-        /// contents and read successful.
-        hash: Option<u64>,
-
-        /// Checked time.
-        last_check: Instant,
-    }
-
-    impl PathData {
-        /// Create a new `PathData`.
-        fn new(data_builder: &DataBuilder, meta_path: &MetaPath) -> PathData {
-            let metadata = meta_path.metadata();
-
-            PathData {
-                mtime: FileTime::from_last_modification_time(metadata).seconds(),
-                hash: data_builder
-                    .build_hasher
-                    .as_ref()
-                    .filter(|_| metadata.is_file())
-                    .and_then(|build_hasher| {
-                        Self::get_content_hash(build_hasher, meta_path.path()).ok()
-                    }),
-
-                last_check: data_builder.now,
-            }
-        }
-
-        /// Get hash value for the data content in given file `path`.
-        fn get_content_hash(build_hasher: &RandomState, path: &Path) -> io::Result<u64> {
-            let mut hasher = build_hasher.build_hasher();
-            let mut file = File::open(path)?;
-            let mut buf = [0; 512];
-
-            loop {
-                let n = match file.read(&mut buf) {
-                    Ok(0) => break,
-                    Ok(len) => len,
-                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
-                    Err(e) => return Err(e),
-                };
-
-                hasher.write(&buf[..n]);
-            }
+    /// # Side effect
+    ///
+    /// This function may emit event by `self.emitter`.
+    fn rescan(&self, snapshot: &mut Snapshot) {
+        let new_snapshot = self.scan(snapshot.root().to_path_buf(), snapshot.is_recursive());
 
-            Ok(hasher.finish())
+        for event in snapshot.diff_with(&new_snapshot, self.comparator.as_deref()) {
+            self.emitter.emit_ok(event);
         }
 
-        /// Get [`Event`] by compare two optional [`PathData`].
-        fn compare_to_event<P>(
-            path: P,
-            old: Option<&PathData>,
-            new: Option<&PathData>,
-        ) -> Option<Event>
-        where
-            P: Into<PathBuf>,
-        {
-            match (old, new) {
-                (Some(old), Some(new)) => {
-                    if new.mtime > old.mtime {
-                        Some(EventKind::Modify(ModifyKind::Metadata(
-                            MetadataKind::WriteTime,
-                        )))
-                    } else if new.hash != old.hash {
-                        Some(EventKind::Modify(ModifyKind::Data(DataChange::Any)))
-                    } else {
-                        None
-                    }
-                }
-                (None, Some(_new)) => Some(EventKind::Create(CreateKind::Any)),
-                (Some(_old), None) => Some(EventKind::Remove(RemoveKind::Any)),
-                (None, None) => None,
-            }
-            .map(|event_kind| Event::new(event_kind).add_path(path.into()))
-        }
+        *snapshot = new_snapshot;
     }
 
-    /// Compose path and its metadata.
+    /// Change which part of the tree below its root `snapshot` covers.
     ///
-    /// This data structure designed for make sure path and its metadata can be
-    /// transferred in consistent way, and may avoid some duplicated
-    /// `fs::metadata()` function call in some situations.
-    #[derive(Debug)]
-    pub(super) struct MetaPath {
-        path: PathBuf,
-        metadata: Metadata,
-    }
-
-    impl MetaPath {
-        /// Create `MetaPath` by given parts.
-        ///
-        /// # Invariant
-        ///
-        /// User must make sure the input `metadata` are associated with `path`.
-        fn from_parts_unchecked(path: PathBuf, metadata: Metadata) -> Self {
-            Self { path, metadata }
+    /// Entries which only enter or leave the snapshot by doing so are not reported, all others
+    /// are still compared on the next rescan.
+    fn set_recursive(&self, snapshot: &mut Snapshot, is_recursive: bool) {
+        if is_recursive {
+            let deep = self.scan(snapshot.root().to_path_buf(), true);
+            snapshot.make_recursive(deep);
+        } else {
+            snapshot.make_flat();
         }
+    }
 
-        fn path(&self) -> &Path {
-            &self.path
-        }
+    /// Take a [Snapshot], emitting errors for paths which can't be read.
+    fn scan(&self, root: PathBuf, is_recursive: bool) -> Snapshot {
+        self.scanner.scan(root, is_recursive, |e, path| {
+            self.emitter.emit_io_err(e, path)
+        })
+    }
+}
 
-        fn metadata(&self) -> &Metadata {
-            &self.metadata
-        }
+impl Debug for DataBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DataBuilder")
+            .field("scanner", &self.scanner)
+            .field("comparator", &self.comparator.is_some())
+            .field("initial_scan", &self.initial_scan)
+            .finish()
+    }
+}
 
-        fn into_path(self) -> PathBuf {
-            self.path
-        }
+/// Thin wrapper for outer event handler, for easy to use.
+struct EventEmitter(
+    // Use `RefCell` to make sure `emit()` only need shared borrow of self (&self).
+    // Use `Box` to make sure EventEmitter is Sized.
+    Box<RefCell<dyn EventHandler>>,
+);
+
+impl EventEmitter {
+    fn new<F: EventHandler>(event_handler: F) -> Self {
+        Self(Box::new(RefCell::new(event_handler)))
     }
 
-    /// Thin wrapper for outer event handler, for easy to use.
-    struct EventEmitter(
-        // Use `RefCell` to make sure `emit()` only need shared borrow of self (&self).
-        // Use `Box` to make sure EventEmitter is Sized.
-        Box<RefCell<dyn EventHandler>>,
-    );
+    /// Emit single event.
+    fn emit(&self, event: crate::Result<Event>) {
+        self.0.borrow_mut().handle_event(event);
+    }
 
-    impl EventEmitter {
-        fn new<F: EventHandler>(event_handler: F) -> Self {
-            Self(Box::new(RefCell::new(event_handler)))
-        }
+    /// Emit event.
+    fn emit_ok(&self, event: Event) {
+        self.emit(Ok(event))
+    }
 
-        /// Emit single event.
-        fn emit(&self, event: crate::Result<Event>) {
-            self.0.borrow_mut().handle_event(event);
-        }
+    /// Emit io error event.
+    fn emit_io_err<E, P>(&self, err: E, path: P)
+    where
+        E: Into<io::Error>,
+        P: Into<PathBuf>,
+    {
+        self.emit(Err(crate::Error::io(err.into()).add_path(path.into())))
+    }
+}
 
-        /// Emit event.
-        fn emit_ok(&self, event: Event) {
-            self.emit(Ok(event))
-        }
+/// Decides whether a file changed between two scans of the [PollWatcher].
+///
+/// Some filesystems have unreliable modification times, in which case the built-in
+/// change detection can be replaced by a comparator via [PollWatcher::set_comparator].
+/// It is implemented for all closures taking the path and its old and new metadata.
+///
+/// # Example implementation
+///
+/// ```no_run
+/// # use std::fs::Metadata;
+/// # use std::path::Path;
+/// use notify::{Config, PollWatcher};
+///
+/// /// Only consider the file size, for filesystems without reliable timestamps
+/// fn size_changed(_path: &Path, old: &Metadata, new: &Metadata) -> bool {
+///     old.len() != new.len()
+/// }
+///
+/// # fn main() -> notify::Result<()> {
+/// let (tx, _rx) = std::sync::mpsc::channel();
+/// let mut watcher = PollWatcher::new(tx, Config::default())?;
+/// watcher.set_comparator(size_changed);
+/// # Ok(())
+/// # }
+/// ```
+pub trait ChangeComparator: Send + 'static {
+    /// Returns whether `path` has changed from the `old` to the `new` metadata.
+    fn has_changed(&self, path: &Path, old: &Metadata, new: &Metadata) -> bool;
+}
 
-        /// Emit io error event.
-        fn emit_io_err<E, P>(&self, err: E, path: P)
-        where
-            E: Into<io::Error>,
-            P: Into<PathBuf>,
-        {
-            self.emit(Err(crate::Error::io(err.into()).add_path(path.into())))
-        }
+impl<F> ChangeComparator for F
+where
+    F: Fn(&Path, &Metadata, &Metadata) -> bool + Send + 'static,
+{
+    fn has_changed(&self, path: &Path, old: &Metadata, new: &Metadata) -> bool {
+        (self)(path, old, new)
+    }
+}
+
+/// Excludes directories from the scans of the [PollWatcher].
+///
+/// Excluded directories are not descended into at all, which saves the scan time that filtering
+/// events afterwards can't recover. It is implemented for all closures taking the directory path,
+/// and for [ExcludeDirNames]. See [PollWatcher::set_scan_filter].
+pub trait ScanFilter: Send + 'static {
+    /// Returns whether the directory at `path` and everything below it should be skipped.
+    fn exclude_dir(&self, path: &Path) -> bool;
+}
+
+impl<F> ScanFilter for F
+where
+    F: Fn(&Path) -> bool + Send + 'static,
+{
+    fn exclude_dir(&self, path: &Path) -> bool {
+        (self)(path)
+    }
+}
+
+/// [ScanFilter] excluding directories by their name, such as `node_modules` or `.git`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ExcludeDirNames(HashSet<OsString>);
+
+impl ExcludeDirNames {
+    /// Create a filter excluding all directories with one of the given `names`.
+    pub fn new<I, S>(names: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<OsString>,
+    {
+        Self(names.into_iter().map(Into::into).collect())
+    }
+}
+
+impl ScanFilter for ExcludeDirNames {
+    fn exclude_dir(&self, path: &Path) -> bool {
+        path.file_name()
+            .map_or(false, |name| self.0.contains(name))
     }
 }
 
@@ -406,7 +305,16 @@ pub struct PollWatcher {
     watches: Arc<Mutex<HashMap<PathBuf, WatchData>>>,
     data_builder: Arc<Mutex<DataBuilder>>,
     want_to_stop: Arc<AtomicBool>,
-    delay: Duration,
+    delay: Arc<Mutex<Duration>>,
+    // wakes the poll loop to reschedule its scans.
+    wakeup: Mutex<Sender<()>>,
+    snapshot_file: Option<PathBuf>,
+    persisted: Vec<Snapshot>,
+    registry: WatchRegistry,
+    tokens: WatchTokens,
+    options: DeliveryOptions,
+    suppressions: Suppressions,
+    audit: Audit,
 }
 
 impl PollWatcher {
@@ -415,29 +323,46 @@ impl PollWatcher {
         event_handler: F,
         config: Config,
     ) -> crate::Result<PollWatcher> {
-        let data_builder = DataBuilder::new(event_handler, config.compare_contents());
+        let delivery = Delivery::new(event_handler, &config);
+        let tokens = delivery.tokens();
+        let options = delivery.options();
+        let suppressions = delivery.suppressions();
+        let data_builder = DataBuilder::new(delivery, &config);
+        let (wakeup, wakeup_rx) = unbounded();
 
         let poll_watcher = PollWatcher {
             watches: Default::default(),
             data_builder: Arc::new(Mutex::new(data_builder)),
             want_to_stop: Arc::new(AtomicBool::new(false)),
-            delay: config.poll_interval(),
+            delay: Arc::new(Mutex::new(config.poll_interval())),
+            wakeup: Mutex::new(wakeup),
+            snapshot_file: None,
+            persisted: Vec::new(),
+            registry: WatchRegistry::new(config.watch_overlap()),
+            tokens,
+            options,
+            suppressions,
+            audit: Audit::new("poll", &config),
         };
 
-        poll_watcher.run();
+        poll_watcher.run(wakeup_rx, Scheduling::new(&config));
 
         Ok(poll_watcher)
     }
 
-    fn run(&self) {
+    fn run(&self, wakeup: Receiver<()>, scheduling: Scheduling) {
         let watches = Arc::clone(&self.watches);
         let data_builder = Arc::clone(&self.data_builder);
         let want_to_stop = Arc::clone(&self.want_to_stop);
-        let delay = self.delay;
+        let delay = Arc::clone(&self.delay);
+        let audit = self.audit.clone();
 
         let _ = thread::Builder::new()
             .name("notify-rs poll loop".to_string())
             .spawn(move || {
+                let thread = thread::current().id();
+                audit.acquire(Resource::Thread, thread);
+                let _ = scheduling.apply();
                 loop {
                     if want_to_stop.load(Ordering::SeqCst) {
                         break;
@@ -447,14 +372,21 @@ impl PollWatcher {
                     //
                     // FIXME: inconsistent: some place mutex poison cause panic,
                     // some place just ignore.
-                    if let (Ok(mut watches), Ok(mut data_builder)) =
+                    // the interval may be configured meanwhile.
+                    let default_interval = *delay.lock().unwrap_or_else(|e| e.into_inner());
+                    let mut next_scan: Option<Instant> = None;
+                    if let (Ok(mut watches), Ok(data_builder)) =
                         (watches.lock(), data_builder.lock())
                     {
-                        data_builder.update_timestamp();
-
                         let vals = watches.values_mut();
                         for watch_data in vals {
-                            watch_data.rescan(&mut data_builder);
+                            let interval = watch_data.interval.unwrap_or(default_interval);
+                            if watch_data.scanned.elapsed() >= interval {
+                                data_builder.rescan(&mut watch_data.snapshot);
+                                watch_data.scanned = Instant::now();
+                            }
+                            let due = watch_data.scanned + interval;
+                            next_scan = Some(next_scan.map_or(due, |next| next.min(due)));
                         }
                     }
 
@@ -463,37 +395,175 @@ impl PollWatcher {
                     // If not, consider fix it to:
                     //
                     // ```rust
-                    // let still_need_to_delay = delay.checked_sub(data_builder.now.elapsed());
+                    // let still_need_to_delay = delay.checked_sub(scan_start.elapsed());
                     // if let Some(delay) = still_need_to_delay {
                     //     thread::sleep(delay);
                     // }
                     // ```
-                    thread::sleep(delay);
+                    let timeout = next_scan.map_or(default_interval, |next| {
+                        next.saturating_duration_since(Instant::now())
+                    });
+                    // returns right away once the watcher is dropped, which stops the loop.
+                    let _ = wakeup.recv_timeout(timeout);
                 }
+                audit.release(Resource::Thread, thread);
             });
     }
 
-    /// Watch a path location.
+    /// Replace the built-in change detection with a custom [ChangeComparator].
     ///
-    /// QUESTION: this function never return an Error, is it as intend?
-    /// Please also consider the IO Error event problem.
-    fn watch_inner(&mut self, path: &Path, recursive_mode: RecursiveMode) {
+    /// Once set, the comparator alone decides whether an existing file has changed and
+    /// [Config::with_compare_contents] no longer applies. Detected changes are emitted as
+    /// `Modify(Any)` events. The comparator is consulted from the next scan on, paths seen for
+    /// the first time after it was set are still compared by the built-in logic once.
+    pub fn set_comparator<C: ChangeComparator>(&mut self, comparator: C) {
+        // FIXME: inconsistent: some place mutex poison cause panic, some place just ignore.
+        if let Ok(mut data_builder) = self.data_builder.lock() {
+            data_builder.set_comparator(Box::new(comparator));
+        }
+    }
+
+    /// Exclude directories from the scans with a [ScanFilter].
+    ///
+    /// Excluded directories are skipped from the next scan on. They and the paths below them
+    /// which were already known are forgotten without any events. The watched root itself is
+    /// always scanned.
+    ///
+    /// ```no_run
+    /// # use notify::{Config, PollWatcher};
+    /// use notify::poll::ExcludeDirNames;
+    ///
+    /// # fn main() -> notify::Result<()> {
+    /// let (tx, _rx) = std::sync::mpsc::channel();
+    /// let mut watcher = PollWatcher::new(tx, Config::default())?;
+    /// watcher.set_scan_filter(ExcludeDirNames::new(["node_modules", ".git", "target"]));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn set_scan_filter<S: ScanFilter>(&mut self, scan_filter: S) {
         // HINT: Make sure always lock in the same order to avoid deadlock.
         //
         // FIXME: inconsistent: some place mutex poison cause panic, some place just ignore.
         if let (Ok(mut watches), Ok(mut data_builder)) =
             (self.watches.lock(), self.data_builder.lock())
         {
-            data_builder.update_timestamp();
+            data_builder.scanner.set_scan_filter(Box::new(scan_filter));
+            // the watched paths below newly excluded directories are dropped without events.
+            for watch_data in watches.values_mut() {
+                data_builder.scanner.exclude(&mut watch_data.snapshot);
+            }
+        }
+    }
+
+    /// Scan the watch of `path` every `interval`, instead of with the poll interval of the
+    /// [Config], or with the poll interval again for `None`.
+    ///
+    /// This allows watching, say, a config directory every second and an archive share every few
+    /// minutes with one watcher. The scans of all watches are made on one thread, so a long scan
+    /// delays the others. The new interval counts from the last scan of the watch.
+    ///
+    /// Returns an error if `path` isn't watched.
+    pub fn set_poll_interval(
+        &mut self,
+        path: &Path,
+        interval: Option<Duration>,
+    ) -> crate::Result<()> {
+        let mut watches = self.watches.lock()?;
+        let watch_data = watches
+            .get_mut(path)
+            .ok_or_else(|| crate::Error::watch_not_found().add_path(path.to_path_buf()))?;
+        watch_data.interval = interval;
+        drop(watches);
+        let _ = self.wakeup.lock()?.send(());
+        Ok(())
+    }
+
+    /// Persist the scanned file tree in the file at `path`.
+    ///
+    /// If the file exists, the state it contains is loaded, and watching one of its roots
+    /// afterwards with [Watcher::watch] emits events for all changes that happened while no
+    /// watcher was running, instead of silently starting from the current state.
+    /// The state of all watches is written back when the watcher is dropped, and on
+    /// [PollWatcher::save_snapshot].
+    ///
+    /// This must be called before watching the paths it applies to.
+    pub fn set_snapshot_file<P: Into<PathBuf>>(&mut self, path: P) -> crate::Result<()> {
+        let path = path.into();
+        self.persisted =
+            snapshot::load_all(&path).map_err(|e| crate::Error::io(e).add_path(path.clone()))?;
+        self.snapshot_file = Some(path);
+        Ok(())
+    }
+
+    /// Write the scanned file tree to the snapshot file now.
+    ///
+    /// Does nothing if no file was set with [PollWatcher::set_snapshot_file].
+    pub fn save_snapshot(&self) -> crate::Result<()> {
+        let path = match self.snapshot_file {
+            Some(ref path) => path,
+            None => return Ok(()),
+        };
+
+        let watches = self.watches.lock()?;
+        snapshot::save_all(
+            path,
+            watches.values().map(|watch_data| &watch_data.snapshot),
+        )
+        .map_err(|e| crate::Error::io(e).add_path(path.clone()))
+    }
+
+    /// Watch a path location, returning whether it could be watched.
+    ///
+    /// QUESTION: this function never return an Error, is it as intend?
+    /// Please also consider the IO Error event problem.
+    fn watch_inner(&mut self, path: &Path, recursive_mode: RecursiveMode) -> bool {
+        // HINT: Make sure always lock in the same order to avoid deadlock.
+        //
+        // FIXME: inconsistent: some place mutex poison cause panic, some place just ignore.
+        if let (Ok(mut watches), Ok(data_builder)) = (self.watches.lock(), self.data_builder.lock())
+        {
+            let is_recursive = recursive_mode.is_recursive();
+            let persisted = self
+                .persisted
+                .iter()
+                .position(|persisted| {
+                    persisted.root() == path && persisted.is_recursive() == is_recursive
+                })
+                .map(|index| self.persisted.swap_remove(index));
 
-            let watch_data =
-                data_builder.build_watch_data(path.to_path_buf(), recursive_mode.is_recursive());
+            let watch_data = match persisted {
+                Some(persisted) => data_builder.restore_watch_data(persisted),
+                None => data_builder.build_watch_data(path.to_path_buf(), is_recursive),
+            };
 
             // if create watch_data successful, add it to watching list.
             if let Some(watch_data) = watch_data {
-                watches.insert(path.to_path_buf(), watch_data);
+                watches.insert(path.to_path_buf(), WatchData::new(watch_data));
+                return true;
             }
         }
+        false
+    }
+
+    /// Change the recursive mode of a watched path.
+    ///
+    /// Return `Err(_)` if given path has't be monitored.
+    fn set_recursive_mode_inner(
+        &mut self,
+        path: &Path,
+        recursive_mode: RecursiveMode,
+    ) -> crate::Result<()> {
+        // HINT: Make sure always lock in the same order to avoid deadlock.
+        let mut watches = self.watches.lock()?;
+        let data_builder = self.data_builder.lock()?;
+        let watch_data = watches
+            .get_mut(path)
+            .ok_or_else(|| crate::Error::watch_not_found().add_path(path.to_path_buf()))?;
+
+        if self.registry.recursive_mode(path) != Some(recursive_mode) {
+            data_builder.set_recursive(&mut watch_data.snapshot, recursive_mode.is_recursive());
+        }
+        Ok(())
     }
 
     /// Unwatch a path.
@@ -506,7 +576,30 @@ impl PollWatcher {
             .unwrap()
             .remove(path)
             .map(|_| ())
-            .ok_or_else(crate::Error::watch_not_found)
+            .ok_or_else(crate::Error::watch_not_found)?;
+        Ok(())
+    }
+
+    /// Pass `event` to the event handler like the events of the scans, for watchers which
+    /// serve some of their watches with a [PollWatcher].
+    #[cfg(all(any(target_os = "linux", target_os = "android"), feature = "linux_inotify"))]
+    pub(crate) fn emit(&self, event: Event) {
+        if let Ok(data_builder) = self.data_builder.lock() {
+            data_builder.emitter.emit_ok(event);
+        }
+    }
+}
+
+impl InstallWatches for PollWatcher {
+    fn install(&mut self, path: &Path, recursive_mode: RecursiveMode) -> crate::Result<()> {
+        match self.watch_inner(path, recursive_mode) {
+            true => Ok(()),
+            false => Err(crate::Error::path_not_found().add_path(path.to_path_buf())),
+        }
+    }
+
+    fn uninstall(&mut self, path: &Path) -> crate::Result<()> {
+        self.unwatch_inner(path)
     }
 }
 
@@ -517,13 +610,72 @@ impl Watcher for PollWatcher {
     }
 
     fn watch(&mut self, path: &Path, recursive_mode: RecursiveMode) -> crate::Result<()> {
-        self.watch_inner(path, recursive_mode);
+        let plan = self.registry.plan_watch(path, recursive_mode)?;
+        // paths which can't be scanned are ignored.
+        if plan
+            .apply(self, |watcher| watcher.install(path, recursive_mode))
+            .is_ok()
+        {
+            self.registry.insert(path, recursive_mode);
+        }
 
         Ok(())
     }
 
     fn unwatch(&mut self, path: &Path) -> crate::Result<()> {
-        self.unwatch_inner(path)
+        let plan = self.registry.plan_unwatch(path);
+        plan.apply(self, |watcher| watcher.unwatch_inner(path))?;
+        self.registry.remove(path);
+        self.tokens.remove(path);
+        Ok(())
+    }
+
+    fn watch_with_token(
+        &mut self,
+        path: &Path,
+        recursive_mode: RecursiveMode,
+        token: u64,
+    ) -> crate::Result<()> {
+        // registered first, so that the initial scan carries the token already.
+        self.tokens.insert(path, token);
+        let result = self.watch(path, recursive_mode);
+        if result.is_err() || self.registry.recursive_mode(path).is_none() {
+            self.tokens.remove(path);
+        }
+
+        result
+    }
+
+    fn set_recursive_mode(
+        &mut self,
+        path: &Path,
+        recursive_mode: RecursiveMode,
+    ) -> crate::Result<()> {
+        let plan = self
+            .registry
+            .plan_set_recursive_mode(path, recursive_mode)?;
+        plan.apply(self, |watcher| {
+            watcher.set_recursive_mode_inner(path, recursive_mode)
+        })?;
+        self.registry.insert(path, recursive_mode);
+        Ok(())
+    }
+
+    fn suppress(&self, path: &Path) -> crate::Result<SuppressGuard> {
+        Ok(self.suppressions.guard(path))
+    }
+
+    fn is_watched(&self, path: &Path) -> bool {
+        self.registry.is_watched(path)
+    }
+
+    fn configure(&mut self, config: Config) -> crate::Result<bool> {
+        *self.delay.lock()? = config.poll_interval();
+        self.options.configure(&config);
+        let mut data_builder = self.data_builder.lock()?;
+        data_builder.scanner.configure(&config);
+        data_builder.initial_scan = config.initial_scan();
+        Ok(true)
     }
 
     fn kind() -> crate::WatcherKind {
@@ -534,6 +686,12 @@ impl Watcher for PollWatcher {
 impl Drop for PollWatcher {
     fn drop(&mut self) {
         self.want_to_stop.store(true, Ordering::Relaxed);
+
+        if let Err(e) = self.save_snapshot() {
+            if let Ok(data_builder) = self.data_builder.lock() {
+                data_builder.emitter.emit(Err(e));
+            }
+        }
     }
 }
 
@@ -542,3 +700,221 @@ fn poll_watcher_is_send_and_sync() {
     fn check<T: Send + Sync>() {}
     check::<PollWatcher>();
 }
+
+#[test]
+fn snapshot_reports_changes_made_while_not_watching() {
+    let dir = tempfile::tempdir().unwrap();
+    let snapshot = dir.path().join("snapshot");
+    let root = dir.path().join("root");
+    std::fs::create_dir(&root).unwrap();
+    std::fs::write(root.join("kept"), b"a").unwrap();
+    std::fs::write(root.join("removed"), b"a").unwrap();
+
+    let (tx, rx) = std::sync::mpsc::channel();
+    let mut watcher = PollWatcher::new(tx, Config::default()).unwrap();
+    watcher.set_snapshot_file(&snapshot).unwrap();
+    watcher.watch(&root, RecursiveMode::Recursive).unwrap();
+    drop(watcher);
+    assert!(rx.try_recv().is_err());
+
+    std::fs::remove_file(root.join("removed")).unwrap();
+    std::fs::write(root.join("created"), b"a").unwrap();
+
+    let (tx, rx) = std::sync::mpsc::channel();
+    let mut watcher = PollWatcher::new(tx, Config::default()).unwrap();
+    watcher.set_snapshot_file(&snapshot).unwrap();
+    watcher.watch(&root, RecursiveMode::Recursive).unwrap();
+
+    // the directory itself may have been modified as well.
+    let events: Vec<_> = rx
+        .try_iter()
+        .map(|event| event.unwrap())
+        .filter(|event| event.paths != [root.clone()])
+        .map(|event| (event.kind, event.paths))
+        .collect();
+    assert_eq!(
+        events,
+        vec![
+            (
+                crate::EventKind::Create(crate::event::CreateKind::Any),
+                vec![root.join("created")]
+            ),
+            (
+                crate::EventKind::Remove(crate::event::RemoveKind::Any),
+                vec![root.join("removed")]
+            ),
+        ]
+    );
+}
+
+#[test]
+fn initial_scan_reports_existing_entries() {
+    use crate::event::{CreateKind, EventKind};
+
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir(dir.path().join("sub")).unwrap();
+    std::fs::write(dir.path().join("sub").join("file"), b"a").unwrap();
+
+    let (tx, rx) = std::sync::mpsc::channel();
+    let config = Config::default().with_initial_scan(true);
+    let mut watcher = PollWatcher::new(tx, config).unwrap();
+    watcher.watch(dir.path(), RecursiveMode::Recursive).unwrap();
+
+    let events: Vec<_> = rx.try_iter().map(|event| event.unwrap()).collect();
+    assert!(events.iter().all(|event| event.is_initial_scan()));
+    assert_eq!(
+        events
+            .into_iter()
+            .map(|event| (event.kind, event.paths))
+            .collect::<Vec<_>>(),
+        vec![
+            (
+                EventKind::Create(CreateKind::Folder),
+                vec![dir.path().to_path_buf()]
+            ),
+            (
+                EventKind::Create(CreateKind::Folder),
+                vec![dir.path().join("sub")]
+            ),
+            (
+                EventKind::Create(CreateKind::File),
+                vec![dir.path().join("sub").join("file")]
+            ),
+        ]
+    );
+}
+
+#[test]
+fn set_recursive_mode_does_not_report_entries_moving_in_or_out() {
+    let dir = tempfile::tempdir().unwrap();
+    let deep = dir.path().join("sub").join("deep");
+    std::fs::create_dir_all(&deep).unwrap();
+
+    let (tx, rx) = std::sync::mpsc::channel();
+    let mut watcher = PollWatcher::new(tx, Config::default()).unwrap();
+    watcher
+        .watch(dir.path(), RecursiveMode::NonRecursive)
+        .unwrap();
+    assert!(!watcher.is_watched(&deep));
+
+    watcher
+        .set_recursive_mode(dir.path(), RecursiveMode::Recursive)
+        .unwrap();
+    assert!(watcher.is_watched(&deep));
+    {
+        let watches = watcher.watches.lock().unwrap();
+        assert!(watches[dir.path()].snapshot.contains(&deep));
+    }
+
+    watcher
+        .set_recursive_mode(dir.path(), RecursiveMode::NonRecursive)
+        .unwrap();
+    assert!(!watcher.is_watched(&deep));
+    assert!(rx.try_recv().is_err());
+
+    assert!(watcher
+        .set_recursive_mode(&deep, RecursiveMode::Recursive)
+        .is_err());
+}
+
+#[test]
+fn watches_are_scanned_with_their_own_interval() {
+    let dir = tempfile::tempdir().unwrap();
+    let (fast, slow) = (dir.path().join("fast"), dir.path().join("slow"));
+    std::fs::create_dir(&fast).unwrap();
+    std::fs::create_dir(&slow).unwrap();
+
+    let (tx, rx) = std::sync::mpsc::channel();
+    let config = Config::default().with_poll_interval(Duration::from_secs(3600));
+    let mut watcher = PollWatcher::new(tx, config).unwrap();
+    watcher.watch(&fast, RecursiveMode::Recursive).unwrap();
+    watcher.watch(&slow, RecursiveMode::Recursive).unwrap();
+    watcher
+        .set_poll_interval(&fast, Some(Duration::from_millis(10)))
+        .unwrap();
+    assert!(watcher
+        .set_poll_interval(&dir.path().join("unwatched"), None)
+        .is_err());
+
+    std::fs::write(fast.join("file"), b"a").unwrap();
+    std::fs::write(slow.join("file"), b"a").unwrap();
+    let event = rx.recv_timeout(Duration::from_secs(5)).unwrap().unwrap();
+    assert!(event.paths[0].starts_with(&fast));
+    let later: Vec<_> = rx
+        .recv_timeout(Duration::from_millis(200))
+        .into_iter()
+        .chain(rx.try_iter())
+        .map(|event| event.unwrap())
+        .collect();
+    assert!(later.iter().all(|event| event.paths[0].starts_with(&fast)));
+}
+
+#[test]
+fn excluded_directories_are_not_scanned() {
+    let dir = tempfile::tempdir().unwrap();
+    let excluded = dir.path().join("node_modules");
+    std::fs::create_dir_all(excluded.join("inner")).unwrap();
+    std::fs::write(excluded.join("inner").join("file"), b"a").unwrap();
+    std::fs::create_dir(dir.path().join("src")).unwrap();
+    std::fs::write(dir.path().join("src").join("file"), b"a").unwrap();
+
+    let (tx, rx) = std::sync::mpsc::channel();
+    let config = Config::default().with_initial_scan(true);
+    let mut watcher = PollWatcher::new(tx, config).unwrap();
+    let visited = Arc::new(Mutex::new(Vec::new()));
+    let filter = {
+        let visited = visited.clone();
+        let names = ExcludeDirNames::new(["node_modules"]);
+        move |path: &Path| {
+            visited.lock().unwrap().push(path.to_path_buf());
+            names.exclude_dir(path)
+        }
+    };
+    watcher.set_scan_filter(filter);
+    watcher.watch(dir.path(), RecursiveMode::Recursive).unwrap();
+
+    let mut paths: Vec<_> = rx
+        .try_iter()
+        .flat_map(|event| event.unwrap().paths)
+        .collect();
+    paths.sort();
+    assert_eq!(
+        paths,
+        vec![
+            dir.path().to_path_buf(),
+            dir.path().join("src"),
+            dir.path().join("src").join("file"),
+        ]
+    );
+    // the poll loop may have rescanned the root in the meantime.
+    let mut visited = visited.lock().unwrap().clone();
+    visited.sort();
+    visited.dedup();
+    assert_eq!(visited, vec![excluded, dir.path().join("src")]);
+}
+
+#[test]
+fn newly_excluded_directories_are_forgotten_without_events() {
+    let dir = tempfile::tempdir().unwrap();
+    let excluded = dir.path().join("node_modules");
+    std::fs::create_dir_all(excluded.join("inner")).unwrap();
+    std::fs::write(excluded.join("inner").join("file"), b"a").unwrap();
+    std::fs::write(dir.path().join("file"), b"a").unwrap();
+
+    let (tx, rx) = std::sync::mpsc::channel();
+    let config = Config::default().with_poll_interval(Duration::from_millis(10));
+    let mut watcher = PollWatcher::new(tx, config).unwrap();
+    watcher.watch(dir.path(), RecursiveMode::Recursive).unwrap();
+    let known = |watcher: &PollWatcher, path: &Path| {
+        watcher.watches.lock().unwrap()[dir.path()]
+            .snapshot
+            .contains(path)
+    };
+    assert!(known(&watcher, &excluded.join("inner").join("file")));
+
+    watcher.set_scan_filter(ExcludeDirNames::new(["node_modules"]));
+    assert!(!known(&watcher, &excluded));
+    assert!(!known(&watcher, &excluded.join("inner").join("file")));
+    assert!(known(&watcher, &dir.path().join("file")));
+    assert!(rx.recv_timeout(Duration::from_millis(200)).is_err());
+}
@@ -0,0 +1,252 @@
+//! Bookkeeping of the watches registered with a watcher
+
+use crate::{Error, ErrorKind, RecursiveMode, Result, WatchOverlap};
+use std::{
+    collections::HashMap,
+    env,
+    path::{Path, PathBuf},
+};
+
+/// The paths a watcher watches successfully, and how.
+#[derive(Debug, Default)]
+pub(crate) struct WatchRegistry {
+    roots: HashMap<PathBuf, RecursiveMode>,
+    overlap: WatchOverlap,
+}
+
+/// The changes to the watches installed with the OS needed to add or remove a watch.
+#[derive(Debug, Default)]
+pub(crate) struct WatchPlan {
+    /// Whether the watched path itself is installed or uninstalled.
+    pub(crate) path: bool,
+    /// Other watches to uninstall first, because the new watch encloses them.
+    pub(crate) uninstall: Vec<(PathBuf, RecursiveMode)>,
+    /// Other watches to install afterwards, because no watch encloses them anymore.
+    pub(crate) install: Vec<(PathBuf, RecursiveMode)>,
+}
+
+/// Installing and uninstalling single watches with the OS, for [WatchPlan::apply].
+pub(crate) trait InstallWatches {
+    fn install(&mut self, path: &Path, recursive_mode: RecursiveMode) -> Result<()>;
+
+    fn uninstall(&mut self, path: &Path) -> Result<()>;
+}
+
+impl WatchPlan {
+    /// Uninstall the enclosed watches, run `change` on the watched path itself if it is installed,
+    /// then install the watches which aren't enclosed anymore.
+    ///
+    /// The enclosed watches are installed again if `change` fails.
+    pub(crate) fn apply<W: InstallWatches>(
+        &self,
+        watcher: &mut W,
+        change: impl FnOnce(&mut W) -> Result<()>,
+    ) -> Result<()> {
+        for (path, _) in &self.uninstall {
+            watcher.uninstall(path)?;
+        }
+        if self.path {
+            if let Err(e) = change(watcher) {
+                for (path, recursive_mode) in &self.uninstall {
+                    let _ = watcher.install(path, *recursive_mode);
+                }
+                return Err(e);
+            }
+        }
+        for (path, recursive_mode) in &self.install {
+            watcher.install(path, *recursive_mode)?;
+        }
+        Ok(())
+    }
+}
+
+impl WatchRegistry {
+    pub(crate) fn new(overlap: WatchOverlap) -> Self {
+        Self {
+            roots: HashMap::new(),
+            overlap,
+        }
+    }
+
+    /// Plan watching `path`, or changing how it is watched.
+    ///
+    /// Fails if the watch is rejected for overlapping another one.
+    pub(crate) fn plan_watch(
+        &self,
+        path: &Path,
+        recursive_mode: RecursiveMode,
+    ) -> Result<WatchPlan> {
+        let abs = absolute(path);
+        if self.overlap == WatchOverlap::Reject {
+            let overlapping = self.roots.iter().find(|(root, mode)| {
+                **root != abs
+                    && ((mode.is_recursive() && abs.starts_with(root))
+                        || (recursive_mode.is_recursive() && root.starts_with(&abs)))
+            });
+            if let Some((root, _)) = overlapping {
+                return Err(Error::new(ErrorKind::WatchOverlap(root.clone())).add_path(abs));
+            }
+        }
+
+        let mut after = self.roots.clone();
+        after.insert(abs.clone(), recursive_mode);
+        Ok(self.plan(&abs, &after))
+    }
+
+    /// Plan changing how `path` is watched.
+    pub(crate) fn plan_set_recursive_mode(
+        &self,
+        path: &Path,
+        recursive_mode: RecursiveMode,
+    ) -> Result<WatchPlan> {
+        match self.recursive_mode(path) {
+            Some(_) => self.plan_watch(path, recursive_mode),
+            None => Err(Error::watch_not_found().add_path(path.to_owned())),
+        }
+    }
+
+    /// Plan removing the watch of `path`.
+    pub(crate) fn plan_unwatch(&self, path: &Path) -> WatchPlan {
+        let abs = absolute(path);
+        let mut after = self.roots.clone();
+        after.remove(&abs);
+        let mut plan = self.plan(&abs, &after);
+        // unknown paths are left to the backend to report.
+        plan.path = !self.roots.contains_key(&abs) || self.is_installed(&self.roots, &abs);
+        plan
+    }
+
+    fn plan(&self, abs: &Path, after: &HashMap<PathBuf, RecursiveMode>) -> WatchPlan {
+        let installed =
+            |roots: &HashMap<PathBuf, RecursiveMode>| -> HashMap<PathBuf, RecursiveMode> {
+                roots
+                    .iter()
+                    .filter(|(root, _)| self.is_installed(roots, root))
+                    .map(|(root, mode)| (root.clone(), *mode))
+                    .collect()
+            };
+        let (before, after) = (installed(&self.roots), installed(after));
+        let mut plan = WatchPlan {
+            path: after.contains_key(abs),
+            ..WatchPlan::default()
+        };
+        for (root, mode) in before.iter().filter(|(root, _)| *root != abs) {
+            if !after.contains_key(root) {
+                plan.uninstall.push((root.clone(), *mode));
+            }
+        }
+        for (root, mode) in after.iter().filter(|(root, _)| *root != abs) {
+            if before.get(root) != Some(mode) {
+                plan.install.push((root.clone(), *mode));
+            }
+        }
+        plan.uninstall.sort();
+        plan.install.sort();
+        plan
+    }
+
+    /// Whether the watch of `root` is installed with the OS, with `roots` registered.
+    fn is_installed(&self, roots: &HashMap<PathBuf, RecursiveMode>, root: &Path) -> bool {
+        self.overlap != WatchOverlap::Merge
+            || !root
+                .ancestors()
+                .skip(1)
+                .any(|ancestor| matches!(roots.get(ancestor), Some(RecursiveMode::Recursive)))
+    }
+
+    /// Record a watch at `path`, replacing an existing one at the same path.
+    pub(crate) fn insert(&mut self, path: &Path, recursive_mode: RecursiveMode) {
+        self.roots.insert(absolute(path), recursive_mode);
+    }
+
+    /// Forget the watch at `path`.
+    pub(crate) fn remove(&mut self, path: &Path) {
+        self.roots.remove(&absolute(path));
+    }
+
+    /// How `path` is watched, if it was watched itself.
+    pub(crate) fn recursive_mode(&self, path: &Path) -> Option<RecursiveMode> {
+        self.roots.get(&absolute(path)).copied()
+    }
+
+    /// Whether `path` is covered by a watch: it is watched itself, is a direct child of a
+    /// watched directory, or is anywhere below a recursively watched directory.
+    pub(crate) fn is_watched(&self, path: &Path) -> bool {
+        let path = absolute(path);
+        if self.roots.contains_key(&path) {
+            return true;
+        }
+
+        path.ancestors()
+            .skip(1)
+            .enumerate()
+            .any(|(depth, ancestor)| match self.roots.get(ancestor) {
+                Some(RecursiveMode::Recursive) => true,
+                Some(RecursiveMode::NonRecursive) => depth == 0,
+                None => false,
+            })
+    }
+}
+
+/// Make `path` absolute the same way the watchers do, without touching the filesystem.
+pub(crate) fn absolute(path: &Path) -> PathBuf {
+    if path.is_absolute() {
+        return path.to_path_buf();
+    }
+
+    match env::current_dir() {
+        Ok(cwd) => cwd.join(path),
+        Err(_) => path.to_path_buf(),
+    }
+}
+
+#[test]
+fn merged_watches_install_only_the_outermost() {
+    let root = env::temp_dir();
+    let (outer, inner) = (root.join("a"), root.join("a").join("b"));
+    let mut registry = WatchRegistry::new(WatchOverlap::Merge);
+    registry.insert(&inner, RecursiveMode::Recursive);
+
+    let plan = registry
+        .plan_watch(&outer, RecursiveMode::Recursive)
+        .unwrap();
+    assert!(plan.path);
+    assert_eq!(plan.uninstall, [(inner.clone(), RecursiveMode::Recursive)]);
+    registry.insert(&outer, RecursiveMode::Recursive);
+
+    let plan = registry.plan_unwatch(&inner);
+    assert!(!plan.path && plan.uninstall.is_empty() && plan.install.is_empty());
+
+    let plan = registry.plan_unwatch(&outer);
+    assert!(plan.path);
+    assert_eq!(plan.install, [(inner.clone(), RecursiveMode::Recursive)]);
+
+    let rejecting = WatchRegistry {
+        overlap: WatchOverlap::Reject,
+        ..registry
+    };
+    let err = rejecting
+        .plan_watch(&inner.join("c"), RecursiveMode::NonRecursive)
+        .unwrap_err();
+    assert!(matches!(err.kind, ErrorKind::WatchOverlap(_)));
+    assert!(rejecting
+        .plan_watch(&outer, RecursiveMode::NonRecursive)
+        .is_ok());
+}
+
+#[test]
+fn is_watched_honors_recursive_mode() {
+    let root = env::temp_dir();
+    let mut registry = WatchRegistry::default();
+    registry.insert(&root.join("flat"), RecursiveMode::NonRecursive);
+    registry.insert(&root.join("deep"), RecursiveMode::Recursive);
+
+    assert!(registry.is_watched(&root.join("flat")));
+    assert!(registry.is_watched(&root.join("flat").join("a")));
+    assert!(!registry.is_watched(&root.join("flat").join("a").join("b")));
+    assert!(registry.is_watched(&root.join("deep").join("a").join("b")));
+    assert!(!registry.is_watched(&root));
+
+    registry.remove(&root.join("deep"));
+    assert!(!registry.is_watched(&root.join("deep").join("a")));
+}
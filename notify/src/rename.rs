@@ -0,0 +1,209 @@
+//! Resolving chains of renames in a batch of events
+
+use crate::event::{EventKind, ModifyKind, RenameMode};
+use crate::Event;
+use std::{collections::HashMap, path::PathBuf};
+
+/// Merges the renames of a file which was renamed several times within `events` into one.
+///
+/// A file renamed from `a` to `b` and then to `c` is reported as a single rename from `a` to `c`,
+/// with `b` in its [`rename_hops`](crate::Event::rename_hops). Renames are `Both` events, and the
+/// `From` and `To` halves some backends report, paired by their tracker or, without one, by
+/// following each other. The halves of the first rename of a chain are kept and updated, the
+/// events of the later renames are dropped. Other events of the intermediate paths are kept as
+/// they are, and a chain ends at a path which is created or removed in between.
+///
+/// ```
+/// use notify::event::{EventKind, ModifyKind, RenameMode};
+/// use notify::Event;
+///
+/// let rename = |from: &str, to: &str| {
+///     Event::new(EventKind::Modify(ModifyKind::Name(RenameMode::Both)))
+///         .add_path(from.into())
+///         .add_path(to.into())
+/// };
+/// let events = notify::resolve_rename_chains(vec![rename("a", "b"), rename("b", "c")]);
+/// assert_eq!(events.len(), 1);
+/// assert_eq!(events[0].paths, ["a", "c"].map(std::path::PathBuf::from));
+/// assert_eq!(events[0].rename_hops(), Some(&["b".into()][..]));
+/// ```
+pub fn resolve_rename_chains(events: Vec<Event>) -> Vec<Event> {
+    let (renames, owners) = find_renames(&events);
+
+    // the first rename of the chain each rename was merged into, and the final target and the
+    // hops of each first rename.
+    let mut heads: Vec<Option<usize>> = vec![None; renames.len()];
+    let mut chains: HashMap<usize, (PathBuf, Vec<PathBuf>)> = HashMap::new();
+    let mut ends: HashMap<PathBuf, usize> = HashMap::new();
+    for (i, event) in events.iter().enumerate() {
+        let r = match owners[i] {
+            Some(r) if renames[r].events[0] == i => r,
+            Some(_) => continue,
+            None => {
+                if matches!(event.kind, EventKind::Create(_) | EventKind::Remove(_)) {
+                    for path in &event.paths {
+                        ends.remove(path);
+                    }
+                }
+                continue;
+            }
+        };
+        let (from, to) = match (&renames[r].from, &renames[r].to) {
+            (Some(from), Some(to)) => (from, to),
+            _ => continue,
+        };
+        match ends.remove(from) {
+            Some(head) => {
+                heads[r] = Some(head);
+                let chain = chains.get_mut(&head).expect("chains have a head");
+                chain.0 = to.clone();
+                chain.1.push(from.clone());
+                ends.insert(to.clone(), head);
+            }
+            None => {
+                chains.insert(r, (to.clone(), Vec::new()));
+                ends.insert(to.clone(), r);
+            }
+        }
+    }
+
+    events
+        .into_iter()
+        .zip(owners)
+        .filter_map(|(mut event, owner)| {
+            let r = match owner {
+                Some(r) if heads[r].is_some() => return None,
+                Some(r) => r,
+                None => return Some(event),
+            };
+            match chains.get(&r) {
+                Some((to, hops)) if !hops.is_empty() => {
+                    match event.kind {
+                        EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => {
+                            event.paths[1] = to.clone();
+                        }
+                        EventKind::Modify(ModifyKind::Name(RenameMode::To)) => {
+                            event.paths[0] = to.clone();
+                        }
+                        _ => {}
+                    }
+                    event.attrs.set_rename_hops(hops.clone());
+                }
+                _ => {}
+            }
+            Some(event)
+        })
+        .collect()
+}
+
+#[derive(Default)]
+struct Rename {
+    from: Option<PathBuf>,
+    to: Option<PathBuf>,
+    /// The indices of the events making up the rename, in order.
+    events: Vec<usize>,
+}
+
+/// The renames in `events`, and the rename each event is part of.
+fn find_renames(events: &[Event]) -> (Vec<Rename>, Vec<Option<usize>>) {
+    let mut renames: Vec<Rename> = Vec::new();
+    let mut owners = vec![None; events.len()];
+    let mut trackers: HashMap<usize, usize> = HashMap::new();
+    // an untracked `From` half, waiting for the `To` half right after it.
+    let mut untracked_from: Option<usize> = None;
+
+    for (i, event) in events.iter().enumerate() {
+        let mode = match event.kind {
+            EventKind::Modify(ModifyKind::Name(mode)) => mode,
+            _ => {
+                untracked_from = None;
+                continue;
+            }
+        };
+        let (from, to) = match (mode, event.paths.as_slice()) {
+            (RenameMode::Both, [from, to]) => (Some(from), Some(to)),
+            (RenameMode::From, [from]) => (Some(from), None),
+            (RenameMode::To, [to]) => (None, Some(to)),
+            _ => {
+                untracked_from = None;
+                continue;
+            }
+        };
+
+        let r = match event.tracker() {
+            Some(tracker) => *trackers.entry(tracker).or_insert_with(|| {
+                renames.push(Rename::default());
+                renames.len() - 1
+            }),
+            None => match (mode, untracked_from.take()) {
+                (RenameMode::To, Some(r)) => r,
+                _ => {
+                    renames.push(Rename::default());
+                    if mode == RenameMode::From {
+                        untracked_from = Some(renames.len() - 1);
+                    }
+                    renames.len() - 1
+                }
+            },
+        };
+        let rename = &mut renames[r];
+        rename.from = from.cloned().or_else(|| rename.from.take());
+        rename.to = to.cloned().or_else(|| rename.to.take());
+        rename.events.push(i);
+        owners[i] = Some(r);
+    }
+    (renames, owners)
+}
+
+#[test]
+fn rename_chains_are_resolved_with_their_halves() {
+    use std::path::Path;
+
+    let name = |mode, paths: &[&str], tracker| {
+        let mut event = Event::new(EventKind::Modify(ModifyKind::Name(mode)));
+        event.paths = paths.iter().map(PathBuf::from).collect();
+        match tracker {
+            Some(tracker) => event.set_tracker(tracker),
+            None => event,
+        }
+    };
+    fn paths(event: &Event) -> Vec<&str> {
+        event.paths.iter().map(|p| p.to_str().unwrap()).collect()
+    }
+
+    // inotify reports both halves and the pair, with a tracker.
+    let events = resolve_rename_chains(vec![
+        name(RenameMode::From, &["a"], Some(1)),
+        name(RenameMode::To, &["b"], Some(1)),
+        name(RenameMode::Both, &["a", "b"], Some(1)),
+        name(RenameMode::From, &["b"], Some(2)),
+        name(RenameMode::To, &["c"], Some(2)),
+        name(RenameMode::Both, &["b", "c"], Some(2)),
+    ]);
+    let resolved: Vec<_> = events.iter().map(paths).collect();
+    assert_eq!(resolved, [vec!["a"], vec!["c"], vec!["a", "c"]]);
+    assert!(events
+        .iter()
+        .all(|event| event.rename_hops() == Some(&[PathBuf::from("b")][..])));
+
+    // Windows reports untracked halves, a path created in between ends the chain.
+    let events = resolve_rename_chains(vec![
+        name(RenameMode::From, &["a"], None),
+        name(RenameMode::To, &["b"], None),
+        name(RenameMode::From, &["b"], None),
+        name(RenameMode::To, &["c"], None),
+        Event::new(EventKind::Create(crate::event::CreateKind::File)).add_path("c".into()),
+        name(RenameMode::From, &["c"], None),
+        name(RenameMode::To, &["d"], None),
+    ]);
+    let resolved: Vec<_> = events.iter().map(paths).collect();
+    assert_eq!(
+        resolved,
+        [vec!["a"], vec!["c"], vec!["c"], vec!["c"], vec!["d"]]
+    );
+    assert_eq!(
+        events[0].rename_hops(),
+        Some(&[Path::new("b").to_owned()][..])
+    );
+    assert_eq!(events[3].rename_hops(), None);
+}
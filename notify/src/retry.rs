@@ -0,0 +1,91 @@
+//! Applying the [RetryPolicy] of a config to the failing calls of an event loop
+
+use crate::{Error, EventHandler, RetryPolicy};
+use std::io;
+
+/// What the event loop does after a failed call
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum RetryAction {
+    /// Retry the call, the error was reported to the event handler if the policy asks for it
+    Retry,
+    /// The error was reported to the event handler, stop the event loop
+    Stop,
+}
+
+/// Counts the failures of a call in a row
+#[derive(Debug)]
+pub(crate) struct Retry {
+    policy: RetryPolicy,
+    failures: u32,
+}
+
+impl Retry {
+    pub(crate) fn new(policy: RetryPolicy) -> Self {
+        Self {
+            policy,
+            failures: 0,
+        }
+    }
+
+    /// The call succeeded
+    pub(crate) fn reset(&mut self) {
+        self.failures = 0;
+    }
+
+    /// The call failed with `error`, reports it to `handler` as the policy says
+    pub(crate) fn fail(&mut self, error: io::Error, handler: &mut dyn EventHandler) -> RetryAction {
+        let action = self.action(&error);
+        let report = match (action, self.policy) {
+            (RetryAction::Stop, _) | (RetryAction::Retry, RetryPolicy::Escalate) => true,
+            (RetryAction::Retry, _) => false,
+        };
+        if report {
+            handler.handle_event(Err(Error::io(error)));
+        }
+        action
+    }
+
+    fn action(&mut self, error: &io::Error) -> RetryAction {
+        if !is_transient(error) {
+            return RetryAction::Stop;
+        }
+        self.failures = self.failures.saturating_add(1);
+        match self.policy {
+            RetryPolicy::Bounded(n) if self.failures > n => RetryAction::Stop,
+            _ => RetryAction::Retry,
+        }
+    }
+}
+
+fn is_transient(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock
+    )
+}
+
+#[test]
+fn retries_follow_the_policy() {
+    let interrupted = || io::Error::from(io::ErrorKind::Interrupted);
+    let (mut handler, errors) = std::sync::mpsc::channel();
+
+    let mut retry = Retry::new(RetryPolicy::Forever);
+    for _ in 0..100 {
+        assert_eq!(retry.fail(interrupted(), &mut handler), RetryAction::Retry);
+    }
+    let denied = io::Error::from(io::ErrorKind::PermissionDenied);
+    assert_eq!(retry.fail(denied, &mut handler), RetryAction::Stop);
+
+    let mut retry = Retry::new(RetryPolicy::Bounded(2));
+    assert_eq!(retry.fail(interrupted(), &mut handler), RetryAction::Retry);
+    retry.reset();
+    assert_eq!(retry.fail(interrupted(), &mut handler), RetryAction::Retry);
+    assert_eq!(retry.fail(interrupted(), &mut handler), RetryAction::Retry);
+    assert_eq!(retry.fail(interrupted(), &mut handler), RetryAction::Stop);
+
+    let mut retry = Retry::new(RetryPolicy::Escalate);
+    assert_eq!(retry.fail(interrupted(), &mut handler), RetryAction::Retry);
+
+    drop(handler);
+    assert_eq!(errors.iter().filter(|event| event.is_err()).count(), 3);
+}
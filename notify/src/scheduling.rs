@@ -0,0 +1,201 @@
+//! Priority and CPU affinity of the threads started by the watchers
+
+use crate::config::ThreadPriority;
+use crate::Config;
+use std::io;
+
+/// The scheduling of a watcher's threads, applied by each thread when it starts.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Scheduling {
+    priority: Option<ThreadPriority>,
+    affinity: Option<Vec<usize>>,
+}
+
+impl Scheduling {
+    pub(crate) fn new(config: &Config) -> Self {
+        Self {
+            priority: config.thread_priority(),
+            affinity: config.thread_affinity().map(<[usize]>::to_vec),
+        }
+    }
+
+    /// Apply the priority and the affinity to the calling thread.
+    ///
+    /// Both are tried, the first error is returned.
+    pub(crate) fn apply(&self) -> io::Result<()> {
+        let priority = match self.priority {
+            Some(priority) => set_priority(priority),
+            None => Ok(()),
+        };
+        let affinity = match &self.affinity {
+            Some(cpus) => set_affinity(cpus),
+            None => Ok(()),
+        };
+        priority.and(affinity)
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn set_priority(priority: ThreadPriority) -> io::Result<()> {
+    let nice = match priority {
+        ThreadPriority::Lowest => 19,
+        ThreadPriority::Low => 10,
+        ThreadPriority::Normal => 0,
+        ThreadPriority::High => -10,
+        ThreadPriority::Highest => -20,
+    };
+    // on Linux, the nice value of a thread id only applies to that thread
+    let tid = unsafe { libc::syscall(libc::SYS_gettid) } as libc::id_t;
+    if unsafe { libc::setpriority(libc::PRIO_PROCESS as _, tid, nice) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(all(unix, not(any(target_os = "linux", target_os = "android"))))]
+fn set_priority(priority: ThreadPriority) -> io::Result<()> {
+    unsafe {
+        let thread = libc::pthread_self();
+        let mut policy = 0;
+        let mut param: libc::sched_param = std::mem::zeroed();
+        let e = libc::pthread_getschedparam(thread, &mut policy, &mut param);
+        if e != 0 {
+            return Err(io::Error::from_raw_os_error(e));
+        }
+        let (min, max) = (
+            libc::sched_get_priority_min(policy),
+            libc::sched_get_priority_max(policy),
+        );
+        let mid = min + (max - min) / 2;
+        param.sched_priority = match priority {
+            ThreadPriority::Lowest => min,
+            ThreadPriority::Low => min + (mid - min) / 2,
+            ThreadPriority::Normal => mid,
+            ThreadPriority::High => mid + (max - mid) / 2,
+            ThreadPriority::Highest => max,
+        };
+        let e = libc::pthread_setschedparam(thread, policy, &param);
+        if e != 0 {
+            return Err(io::Error::from_raw_os_error(e));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(all(windows, feature = "windows-sys"))]
+fn set_priority(priority: ThreadPriority) -> io::Result<()> {
+    use windows_sys::Win32::System::Threading::{
+        GetCurrentThread, SetThreadPriority, THREAD_PRIORITY_ABOVE_NORMAL,
+        THREAD_PRIORITY_BELOW_NORMAL, THREAD_PRIORITY_HIGHEST, THREAD_PRIORITY_LOWEST,
+        THREAD_PRIORITY_NORMAL,
+    };
+
+    let priority = match priority {
+        ThreadPriority::Lowest => THREAD_PRIORITY_LOWEST,
+        ThreadPriority::Low => THREAD_PRIORITY_BELOW_NORMAL,
+        ThreadPriority::Normal => THREAD_PRIORITY_NORMAL,
+        ThreadPriority::High => THREAD_PRIORITY_ABOVE_NORMAL,
+        ThreadPriority::Highest => THREAD_PRIORITY_HIGHEST,
+    };
+    if unsafe { SetThreadPriority(GetCurrentThread(), priority) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(any(unix, all(windows, feature = "windows-sys"))))]
+fn set_priority(_priority: ThreadPriority) -> io::Result<()> {
+    Err(unsupported("thread priorities"))
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn set_affinity(cpus: &[usize]) -> io::Result<()> {
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        libc::CPU_ZERO(&mut set);
+        for &cpu in cpus {
+            if cpu >= libc::CPU_SETSIZE as usize {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("there is no CPU {}", cpu),
+                ));
+            }
+            libc::CPU_SET(cpu, &mut set);
+        }
+        // a pid of 0 is the calling thread
+        if libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(all(windows, feature = "windows-sys"))]
+fn set_affinity(cpus: &[usize]) -> io::Result<()> {
+    use windows_sys::Win32::System::Threading::{GetCurrentThread, SetThreadAffinityMask};
+
+    let mut mask = 0usize;
+    for &cpu in cpus {
+        if cpu >= usize::BITS as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("there is no CPU {} in the processor group", cpu),
+            ));
+        }
+        mask |= 1 << cpu;
+    }
+    if unsafe { SetThreadAffinityMask(GetCurrentThread(), mask) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    all(windows, feature = "windows-sys")
+)))]
+fn set_affinity(_cpus: &[usize]) -> io::Result<()> {
+    Err(unsupported("CPU affinity"))
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    all(windows, feature = "windows-sys")
+)))]
+fn unsupported(what: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("{} are not supported on this platform", what),
+    )
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+#[test]
+fn scheduling_is_applied_to_the_calling_thread() {
+    let affinity = || {
+        let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+        let size = std::mem::size_of::<libc::cpu_set_t>();
+        assert_eq!(unsafe { libc::sched_getaffinity(0, size, &mut set) }, 0);
+        (0..libc::CPU_SETSIZE as usize)
+            .filter(|&cpu| unsafe { libc::CPU_ISSET(cpu, &set) })
+            .collect::<Vec<_>>()
+    };
+    // the first CPU the tests may run on
+    let cpu = affinity()[0];
+    let config = Config::default()
+        .with_thread_priority(Some(ThreadPriority::Lowest))
+        .with_thread_affinity(Some(vec![cpu]));
+    std::thread::spawn(move || {
+        config.apply_thread_scheduling().unwrap();
+        let tid = unsafe { libc::syscall(libc::SYS_gettid) } as libc::id_t;
+        assert_eq!(
+            unsafe { libc::getpriority(libc::PRIO_PROCESS as _, tid) },
+            19
+        );
+        assert_eq!(affinity(), [cpu]);
+    })
+    .join()
+    .unwrap();
+}
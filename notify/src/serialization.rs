@@ -0,0 +1,265 @@
+//! Versioned wire formats for passing events between processes
+//!
+//! Values are wrapped in an envelope naming their type and schema version, and written as JSON
+//! Lines or MessagePack:
+//!
+//! ```
+//! # use std::path::PathBuf;
+//! use notify::event::{CreateKind, Event, EventKind};
+//! use notify::serialization;
+//!
+//! # fn main() -> Result<(), serialization::Error> {
+//! let event = Event::new(EventKind::Create(CreateKind::File)).add_path(PathBuf::from("/a"));
+//! let line = serialization::to_json_line(&event)?;
+//! assert_eq!(
+//!     line,
+//!     r#"{"type":"event","version":2,"data":{"type":{"create":{"kind":"file"}},"paths":["/a"],"attrs":{}}}"#
+//! );
+//! assert_eq!(serialization::from_json_line::<Event>(&line)?, event);
+//!
+//! let bytes = serialization::to_msgpack(&event)?;
+//! assert_eq!(serialization::from_msgpack::<Event>(&bytes)?, event);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The representation of a type only changes together with its [`Versioned::VERSION`], so
+//! readers built against another version of this crate fail with
+//! [`Error::UnsupportedVersion`] instead of misreading values.
+
+use crate::Event;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    fmt,
+    io::{self, BufRead, Write},
+};
+
+/// A type with a versioned wire representation.
+///
+/// Implementations must increase `VERSION` whenever the serde representation of the type
+/// changes.
+pub trait Versioned: Serialize + DeserializeOwned {
+    /// Name of the type in the envelope
+    const TYPE: &'static str;
+
+    /// Version of the representation
+    const VERSION: u32;
+}
+
+impl Versioned for Event {
+    const TYPE: &'static str = "event";
+    const VERSION: u32 = 2;
+}
+
+/// An error reading or writing a wire format.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// Reading or writing the underlying stream failed
+    Io(io::Error),
+
+    /// The value isn't valid JSON, or doesn't match the type
+    Json(serde_json::Error),
+
+    /// The value couldn't be encoded as MessagePack
+    MsgpackEncode(rmp_serde::encode::Error),
+
+    /// The value isn't valid MessagePack, or doesn't match the type
+    MsgpackDecode(rmp_serde::decode::Error),
+
+    /// The envelope holds another type than the one read
+    UnexpectedType {
+        /// The type read
+        expected: &'static str,
+        /// The type in the envelope
+        found: String,
+    },
+
+    /// The envelope holds a version of the type this crate doesn't know
+    UnsupportedVersion {
+        /// The type in the envelope
+        type_name: &'static str,
+        /// The version in the envelope
+        version: u32,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "IO error: {}", err),
+            Self::Json(err) => write!(f, "JSON error: {}", err),
+            Self::MsgpackEncode(err) => write!(f, "MessagePack error: {}", err),
+            Self::MsgpackDecode(err) => write!(f, "MessagePack error: {}", err),
+            Self::UnexpectedType { expected, found } => {
+                write!(f, "expected a {:?} but found a {:?}", expected, found)
+            }
+            Self::UnsupportedVersion { type_name, version } => {
+                write!(f, "unsupported version {} of {:?}", version, type_name)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            Self::Json(err) => Some(err),
+            Self::MsgpackEncode(err) => Some(err),
+            Self::MsgpackDecode(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Self::Json(err)
+    }
+}
+
+impl From<rmp_serde::encode::Error> for Error {
+    fn from(err: rmp_serde::encode::Error) -> Self {
+        Self::MsgpackEncode(err)
+    }
+}
+
+impl From<rmp_serde::decode::Error> for Error {
+    fn from(err: rmp_serde::decode::Error) -> Self {
+        Self::MsgpackDecode(err)
+    }
+}
+
+/// Type specific result for the wire formats.
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Serialize)]
+struct EnvelopeRef<'a, T> {
+    #[serde(rename = "type")]
+    type_name: &'static str,
+    version: u32,
+    data: &'a T,
+}
+
+/// The envelope without its data, to check it before decoding the data.
+#[derive(Deserialize)]
+struct Header {
+    #[serde(rename = "type")]
+    type_name: String,
+    version: u32,
+}
+
+#[derive(Deserialize)]
+struct Envelope<T> {
+    data: T,
+}
+
+fn envelope<T: Versioned>(value: &T) -> EnvelopeRef<'_, T> {
+    EnvelopeRef {
+        type_name: T::TYPE,
+        version: T::VERSION,
+        data: value,
+    }
+}
+
+fn check<T: Versioned>(header: Header) -> Result<()> {
+    if header.type_name != T::TYPE {
+        return Err(Error::UnexpectedType {
+            expected: T::TYPE,
+            found: header.type_name,
+        });
+    }
+    if header.version != T::VERSION {
+        return Err(Error::UnsupportedVersion {
+            type_name: T::TYPE,
+            version: header.version,
+        });
+    }
+    Ok(())
+}
+
+/// Encode `value` as one line of JSON, without the line break.
+pub fn to_json_line<T: Versioned>(value: &T) -> Result<String> {
+    Ok(serde_json::to_string(&envelope(value))?)
+}
+
+/// Decode a value from one line of JSON.
+pub fn from_json_line<T: Versioned>(line: &str) -> Result<T> {
+    check::<T>(serde_json::from_str(line)?)?;
+    let envelope: Envelope<T> = serde_json::from_str(line)?;
+    Ok(envelope.data)
+}
+
+/// Write `value` to `writer` as one line of JSON.
+pub fn write_json_line<W: Write, T: Versioned>(writer: &mut W, value: &T) -> Result<()> {
+    let mut line = to_json_line(value)?;
+    line.push('\n');
+    writer.write_all(line.as_bytes())?;
+    Ok(())
+}
+
+/// Read values from `reader`, one line of JSON each, skipping empty lines.
+pub fn read_json_lines<R: BufRead, T: Versioned>(reader: R) -> impl Iterator<Item = Result<T>> {
+    reader.lines().filter_map(|line| match line {
+        Ok(line) if line.trim().is_empty() => None,
+        Ok(line) => Some(from_json_line(&line)),
+        Err(err) => Some(Err(err.into())),
+    })
+}
+
+/// Encode `value` as MessagePack, with named fields.
+pub fn to_msgpack<T: Versioned>(value: &T) -> Result<Vec<u8>> {
+    Ok(rmp_serde::to_vec_named(&envelope(value))?)
+}
+
+/// Decode a value from MessagePack.
+pub fn from_msgpack<T: Versioned>(bytes: &[u8]) -> Result<T> {
+    check::<T>(rmp_serde::from_slice(bytes)?)?;
+    let envelope: Envelope<T> = rmp_serde::from_slice(bytes)?;
+    Ok(envelope.data)
+}
+
+#[test]
+fn wire_formats_check_type_and_version() {
+    use crate::event::{EventKind, ModifyKind};
+    use std::path::PathBuf;
+
+    let events = vec![
+        Event::new(EventKind::Modify(ModifyKind::Any)).add_path(PathBuf::from("/a")),
+        Event::new(EventKind::Other).set_info("rescan"),
+    ];
+    let mut buf = Vec::new();
+    for event in &events {
+        write_json_line(&mut buf, event).unwrap();
+    }
+    let read: Vec<Event> = read_json_lines(&buf[..]).map(|e| e.unwrap()).collect();
+    assert_eq!(read, events);
+
+    assert!(matches!(
+        from_json_line::<Event>(r#"{"type":"event","version":1,"data":null}"#),
+        Err(Error::UnsupportedVersion { version: 1, .. })
+    ));
+    assert!(matches!(
+        from_json_line::<Event>(r#"{"type":"debounced-event","version":1,"data":null}"#),
+        Err(Error::UnexpectedType { .. })
+    ));
+}
+
+#[test]
+fn unknown_flags_and_precisions_are_read() {
+    use crate::event::{Flag, Precision};
+
+    let event = from_json_line::<Event>(
+        r#"{"type":"event","version":2,"data":{"type":"other","paths":[],"attrs":{"flag":"Later","precision":"Later"}}}"#,
+    )
+    .unwrap();
+    assert_eq!(event.flag(), Some(Flag::Other));
+    assert_eq!(event.precision(), Some(Precision::Inferred));
+}
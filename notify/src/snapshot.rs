@@ -0,0 +1,1171 @@
+//! Snapshots of a file tree and the changes between them
+//!
+//! This is what the [PollWatcher](crate::PollWatcher) is built upon, but snapshots can also be
+//! taken and compared directly, e.g. before and after running a tool, without starting a watcher.
+//!
+//! ```no_run
+//! use notify::snapshot::Snapshot;
+//!
+//! # fn main() -> std::io::Result<()> {
+//! let before = Snapshot::build("target")?;
+//! // run the tool...
+//! let after = Snapshot::build("target")?;
+//!
+//! for event in before.diff(&after) {
+//!     println!("{:?}", event);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::{
+    event::{
+        ChangeRegion, CreateKind, DataChange, Event, EventKind, Flag, MetadataDelta, MetadataKind,
+        ModifyKind, Precision, RemoveKind,
+    },
+    poll::{ChangeComparator, ScanFilter},
+    Config, LargeFileComparison, RecursiveMode, Watcher,
+};
+use filetime::FileTime;
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    fmt::{self, Debug},
+    fs::{self, File, Metadata},
+    hash::{BuildHasher, BuildHasherDefault, Hasher},
+    io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use walkdir::WalkDir;
+
+type ContentHasher = BuildHasherDefault<DefaultHasher>;
+
+/// The state of all files below a root path at one point in time.
+///
+/// Only metadata is recorded, plus a hash of the contents if the snapshot was built
+/// with [Config::with_compare_contents].
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    root: PathBuf,
+    is_recursive: bool,
+
+    // identifies how the content hashes were created, `0` if they weren't.
+    hasher_fingerprint: u64,
+
+    entries: HashMap<PathBuf, PathData>,
+}
+
+impl Snapshot {
+    /// Take a recursive snapshot of `root`, comparing metadata only.
+    ///
+    /// Returns an error if `root` can't be accessed. Entries below it which can't be read are
+    /// left out of the snapshot.
+    pub fn build<P: Into<PathBuf>>(root: P) -> io::Result<Self> {
+        Self::build_with(root, RecursiveMode::Recursive, &Config::default())
+    }
+
+    /// Take a snapshot of `root`, honoring the content comparison settings of `config`.
+    pub fn build_with<P: Into<PathBuf>>(
+        root: P,
+        recursive_mode: RecursiveMode,
+        config: &Config,
+    ) -> io::Result<Self> {
+        let root = root.into();
+        fs::metadata(&root)?;
+
+        Ok(Scanner::new(config).scan(root, recursive_mode.is_recursive(), |_, _| {}))
+    }
+
+    /// The root path of this snapshot.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Number of paths in this snapshot, including the root.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether this snapshot contains no paths at all, not even the root.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Whether `path` existed when this snapshot was taken.
+    pub fn contains(&self, path: &Path) -> bool {
+        self.entries.contains_key(path)
+    }
+
+    /// All paths in this snapshot, in no particular order.
+    pub fn paths(&self) -> impl Iterator<Item = &Path> {
+        self.entries.keys().map(PathBuf::as_path)
+    }
+
+    /// Events describing the changes from this snapshot to the `newer` one, sorted by path.
+    ///
+    /// These are the same events the [PollWatcher](crate::PollWatcher) emits: a create, remove
+    /// or modify event with a [MetadataDelta] attribute for every changed path.
+    pub fn diff(&self, newer: &Snapshot) -> Vec<Event> {
+        self.diff_with(newer, None)
+    }
+
+    /// Like [Snapshot::diff], but a `comparator` decides whether an existing path has changed,
+    /// if the metadata of both sides is known.
+    pub(crate) fn diff_with(
+        &self,
+        newer: &Snapshot,
+        comparator: Option<&dyn ChangeComparator>,
+    ) -> Vec<Event> {
+        // hashes are only comparable if they were created the same way.
+        let compare_hashes = self.hasher_fingerprint == newer.hasher_fingerprint;
+
+        let mut changes: Vec<_> = newer
+            .entries
+            .iter()
+            .filter_map(|(path, new)| {
+                PathData::compare(
+                    path,
+                    self.entries.get(path),
+                    Some(new),
+                    compare_hashes,
+                    comparator,
+                )
+                .map(|kind| (path, kind))
+            })
+            .chain(
+                self.entries
+                    .iter()
+                    .filter(|(path, _)| !newer.entries.contains_key(*path))
+                    .map(|(path, _)| (path, EventKind::Remove(RemoveKind::Any))),
+            )
+            .collect();
+        changes.sort_by_key(|(path, _)| *path);
+
+        changes
+            .into_iter()
+            .map(|(path, kind)| {
+                let old = self.entries.get(path);
+                let new = newer.entries.get(path);
+                let old_mtime = old.and_then(|old| old.modified);
+                let new_mtime = new.and_then(|new| new.modified);
+                let delta = MetadataDelta {
+                    old_size: old.map(|old| old.size),
+                    new_size: new.map(|new| new.size),
+                    old_mtime,
+                    new_mtime,
+                    region: match (old, new, &kind) {
+                        (Some(old), Some(new), EventKind::Modify(ModifyKind::Data(_))) => {
+                            PathData::change_region(old, new)
+                        }
+                        _ => None,
+                    },
+                };
+                let event = Event::new(kind)
+                    .add_path(path.clone())
+                    .set_metadata_delta(delta)
+                    .set_precision(Precision::Inferred);
+
+                // the modification time only tells when the change happened if it changed.
+                match new_mtime {
+                    Some(time) if old_mtime != new_mtime => event.set_source_time(time),
+                    _ => event,
+                }
+            })
+            .collect()
+    }
+
+    /// Events reporting every path of this snapshot as created, flagged as [Flag::InitialScan].
+    pub(crate) fn initial_scan_events(&self) -> Vec<Event> {
+        let mut entries: Vec<_> = self.entries.iter().collect();
+        entries.sort_by_key(|(path, _)| *path);
+
+        entries
+            .into_iter()
+            .map(|(path, path_data)| {
+                let kind = if path_data.is_dir {
+                    CreateKind::Folder
+                } else {
+                    CreateKind::File
+                };
+                Event::new(EventKind::Create(kind))
+                    .add_path(path.clone())
+                    .set_flag(Flag::InitialScan)
+                    .set_precision(Precision::Inferred)
+            })
+            .collect()
+    }
+
+    /// Drop all entries below the direct children of the root.
+    pub(crate) fn make_flat(&mut self) {
+        let root = &self.root;
+        self.entries
+            .retain(|path, _| is_root_or_direct_child(root, path));
+        self.is_recursive = false;
+    }
+
+    /// Cover the whole tree, taking the entries below the direct children of the root from
+    /// `deep`, a recursive snapshot of the same root.
+    pub(crate) fn make_recursive(&mut self, deep: Snapshot) {
+        for (path, path_data) in deep.entries {
+            if !is_root_or_direct_child(&self.root, &path) {
+                self.entries.insert(path, path_data);
+            }
+        }
+        self.is_recursive = true;
+    }
+
+    /// Whether this snapshot includes the whole tree below the root, or only its direct children.
+    pub(crate) fn is_recursive(&self) -> bool {
+        self.is_recursive
+    }
+}
+
+/// Detects the changes made to watched paths while the application wasn't running.
+///
+/// The state of the watched paths is saved in a file when the application stops, and compared
+/// to the current state when they are watched again on the next start, with any watcher:
+///
+/// ```no_run
+/// # use std::path::Path;
+/// use notify::{snapshot::ChangesSince, Config, RecursiveMode};
+///
+/// # fn main() -> notify::Result<()> {
+/// let mut watcher = notify::recommended_watcher(|event| println!("live: {:?}", event))?;
+/// let mut changes = ChangesSince::load("state", &Config::default())?;
+/// for event in changes.watch(&mut watcher, Path::new("src"), RecursiveMode::Recursive)? {
+///     println!("while stopped: {:?}", event);
+/// }
+///
+/// // ... on shutdown, while still watching:
+/// changes.save("state")?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// The live watch starts before the current state is scanned, so no change is missed between
+/// both. A change made in that moment may be reported twice instead. For the same reason, save
+/// the state before the watcher is dropped.
+#[derive(Debug)]
+pub struct ChangesSince {
+    scanner: Scanner,
+    saved: Vec<Snapshot>,
+    roots: Vec<(PathBuf, RecursiveMode)>,
+}
+
+impl ChangesSince {
+    /// Load the state saved in the file at `state`, using the content comparison settings of
+    /// `config` for the current state.
+    ///
+    /// A missing file is not an error, there are no changes to report then.
+    pub fn load<P: AsRef<Path>>(state: P, config: &Config) -> io::Result<Self> {
+        Ok(Self {
+            scanner: Scanner::new(config),
+            saved: load_all(state.as_ref())?,
+            roots: Vec::new(),
+        })
+    }
+
+    /// Watch `root` with `watcher`, returning the changes since the saved state, sorted by path.
+    ///
+    /// Nothing is returned for paths which weren't watched the same way when the state was saved.
+    pub fn watch<W: Watcher + ?Sized>(
+        &mut self,
+        watcher: &mut W,
+        root: &Path,
+        recursive_mode: RecursiveMode,
+    ) -> crate::Result<Vec<Event>> {
+        watcher.watch(root, recursive_mode)?;
+        Ok(self.track(root, recursive_mode))
+    }
+
+    /// Like [ChangesSince::watch], for a `root` the caller watched in another way.
+    ///
+    /// This is how watches made with a token, or with [PersistedWatches](crate::persist::PersistedWatches),
+    /// are saved too.
+    pub fn track(&mut self, root: &Path, recursive_mode: RecursiveMode) -> Vec<Event> {
+        self.roots.retain(|(watched, _)| watched != root);
+        self.roots.push((root.to_path_buf(), recursive_mode));
+
+        let is_recursive = recursive_mode.is_recursive();
+        let saved = self
+            .saved
+            .iter()
+            .position(|saved| saved.root == root && saved.is_recursive == is_recursive)
+            .map(|index| self.saved.swap_remove(index));
+
+        match saved {
+            Some(saved) => {
+                let current = self
+                    .scanner
+                    .scan(root.to_path_buf(), is_recursive, |_, _| {});
+                saved.diff(&current)
+            }
+            None => Vec::new(),
+        }
+    }
+
+    /// Save the current state of all paths watched with [ChangesSince::watch] or
+    /// [ChangesSince::track] in the file at `state`, replacing the previous state.
+    pub fn save<P: AsRef<Path>>(&self, state: P) -> io::Result<()> {
+        let snapshots: Vec<_> = self
+            .roots
+            .iter()
+            .map(|(root, recursive_mode)| {
+                self.scanner
+                    .scan(root.clone(), recursive_mode.is_recursive(), |_, _| {})
+            })
+            .collect();
+        save_all(state.as_ref(), snapshots.iter())
+    }
+}
+
+fn is_root_or_direct_child(root: &Path, path: &Path) -> bool {
+    path.strip_prefix(root)
+        .map_or(false, |relative| relative.components().count() <= 1)
+}
+
+/// Events reporting everything below `root` as created, flagged as [Flag::InitialScan].
+///
+/// This is how all watchers implement [Config::with_initial_scan].
+// unused on platforms where the PollWatcher is the only watcher.
+#[allow(dead_code)]
+pub(crate) fn initial_scan_events(root: &Path, recursive_mode: RecursiveMode) -> Vec<Event> {
+    Scanner::new(&Config::default())
+        .scan(root.to_path_buf(), recursive_mode.is_recursive(), |_, _| {})
+        .initial_scan_events()
+}
+
+/// The event reporting a watched symbolic link itself in an initial scan.
+// not every backend watches links themselves
+#[allow(dead_code)]
+pub(crate) fn initial_scan_link_event(link: &Path) -> Event {
+    Event::new(EventKind::Create(CreateKind::Other))
+        .add_path(link.to_path_buf())
+        .set_flag(Flag::InitialScan)
+        .set_precision(Precision::Inferred)
+}
+
+/// Takes [`Snapshot`]s with a fixed configuration.
+pub(crate) struct Scanner {
+    // TODO: May allow user setup their custom BuildHasher / BuildHasherDefault
+    // in future.
+    //
+    // Not randomly seeded, so that hashes stay comparable across restarts when a
+    // snapshot is persisted.
+    build_hasher: Option<ContentHasher>,
+
+    // the number of bytes at each end of a file hashed to tell where it changed.
+    change_regions: Option<u64>,
+
+    // files larger than this are compared by `large_file_comparison` instead.
+    content_size_limit: Option<u64>,
+    large_file_comparison: LargeFileComparison,
+
+    // whether to keep the full metadata, for a custom comparator.
+    keep_metadata: bool,
+
+    // directories matching this filter are not scanned.
+    scan_filter: Option<Box<dyn ScanFilter>>,
+}
+
+impl Scanner {
+    pub(crate) fn new(config: &Config) -> Self {
+        Self {
+            build_hasher: config.compare_contents().then(ContentHasher::default),
+            change_regions: config.change_regions(),
+            content_size_limit: config.content_size_limit(),
+            large_file_comparison: config.large_file_comparison(),
+            keep_metadata: false,
+            scan_filter: None,
+        }
+    }
+
+    /// Apply the comparison options of `config` to the following scans.
+    pub(crate) fn configure(&mut self, config: &Config) {
+        self.build_hasher = config.compare_contents().then(ContentHasher::default);
+        self.change_regions = config.change_regions();
+        self.content_size_limit = config.content_size_limit();
+        self.large_file_comparison = config.large_file_comparison();
+    }
+
+    /// Keep the full metadata of every path in the snapshots, for a [ChangeComparator].
+    pub(crate) fn set_keep_metadata(&mut self, keep_metadata: bool) {
+        self.keep_metadata = keep_metadata;
+    }
+
+    /// Exclude directories matching `scan_filter` from all further scans.
+    pub(crate) fn set_scan_filter(&mut self, scan_filter: Box<dyn ScanFilter>) {
+        self.scan_filter = Some(scan_filter);
+    }
+
+    /// Drop the paths from `snapshot` which scans skip because of the scan filter, so that
+    /// comparing it to the next scan doesn't report them as removed.
+    pub(crate) fn exclude(&self, snapshot: &mut Snapshot) {
+        let scan_filter = match self.scan_filter {
+            Some(ref scan_filter) => scan_filter,
+            None => return,
+        };
+
+        let excluded: Vec<PathBuf> = snapshot
+            .entries
+            .iter()
+            .filter(|(path, data)| {
+                data.is_dir && **path != snapshot.root && scan_filter.exclude_dir(path)
+            })
+            .map(|(path, _)| path.clone())
+            .collect();
+        if !excluded.is_empty() {
+            snapshot
+                .entries
+                .retain(|path, _| !excluded.iter().any(|dir| path.starts_with(dir)));
+        }
+    }
+
+    /// Take a snapshot of `root`.
+    ///
+    /// `on_error` is called for every path whose metadata can't be read.
+    pub(crate) fn scan<E>(&self, root: PathBuf, is_recursive: bool, mut on_error: E) -> Snapshot
+    where
+        E: FnMut(io::Error, PathBuf),
+    {
+        // WalkDir return only one entry if root is a file (not a folder),
+        // so we can use single logic to do the both file & dir's jobs.
+        //
+        // See: https://docs.rs/walkdir/2.0.1/walkdir/struct.WalkDir.html#method.new
+        let entries = WalkDir::new(&root)
+            .follow_links(true)
+            .max_depth(Self::dir_scan_depth(is_recursive))
+            .into_iter()
+            // don't descend into excluded directories at all, the root is always scanned.
+            .filter_entry(|entry| {
+                entry.depth() == 0
+                    || !entry.file_type().is_dir()
+                    || !self
+                        .scan_filter
+                        .as_ref()
+                        .map_or(false, |filter| filter.exclude_dir(entry.path()))
+            })
+            //
+            // QUESTION: should we ignore IO Error?
+            //
+            // current implementation ignore some IO error, e.g.,
+            //
+            // - `.filter_map(|entry| entry.ok())`
+            // - all read error when hashing
+            //
+            // but the code also interest with `fs::metadata()` error and
+            // propagate to the caller. It may not consistent.
+            //
+            // FIXME: Should we emit all IO error events? Or ignore them all?
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| match entry.metadata() {
+                Ok(metadata) => {
+                    let path = entry.into_path();
+                    let path_data = PathData::new(self, &path, &metadata);
+
+                    Some((path, path_data))
+                }
+                Err(e) => {
+                    on_error(e.into(), entry.into_path());
+
+                    None
+                }
+            })
+            .collect();
+
+        Snapshot {
+            root,
+            is_recursive,
+            hasher_fingerprint: self.hasher_fingerprint(),
+            entries,
+        }
+    }
+
+    fn dir_scan_depth(is_recursive: bool) -> usize {
+        if is_recursive {
+            usize::MAX
+        } else {
+            1
+        }
+    }
+
+    /// Identifies how content hashes are created, `0` if they aren't.
+    fn hasher_fingerprint(&self) -> u64 {
+        self.build_hasher.as_ref().map_or(0, |build_hasher| {
+            let mut hasher = build_hasher.build_hasher();
+            hasher.write(b"notify-rs content hash");
+            hasher.finish() | 1
+        })
+    }
+}
+
+impl Debug for Scanner {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Scanner")
+            .field("build_hasher", &self.build_hasher)
+            .field("change_regions", &self.change_regions)
+            .field("content_size_limit", &self.content_size_limit)
+            .field("large_file_comparison", &self.large_file_comparison)
+            .field("keep_metadata", &self.keep_metadata)
+            .field("scan_filter", &self.scan_filter.is_some())
+            .finish()
+    }
+}
+
+/// Stored data for a one path locations.
+///
+/// See [`Snapshot`] for more detail.
+#[derive(Debug, Clone)]
+struct PathData {
+    /// File updated time.
+    mtime: i64,
+
+    /// File updated time, precise as far as the platform supports.
+    modified: Option<SystemTime>,
+
+    /// File size in bytes.
+    size: u64,
+
+    /// Whether this is a directory.
+    is_dir: bool,
+
+    /// Content's hash value, only available if user request compare file
+    /// contents and read successful.
+    hash: Option<u64>,
+
+    /// Hashes of the first and the last bytes of a file, to tell where it changed.
+    ///
+    /// Not persisted, as they are only needed from one scan to the next.
+    boundaries: Option<(u64, u64)>,
+
+    /// Full metadata, only kept if a custom comparator needs it.
+    metadata: Option<Metadata>,
+}
+
+impl PathData {
+    /// Create a new `PathData`.
+    fn new(scanner: &Scanner, path: &Path, metadata: &Metadata) -> PathData {
+        PathData {
+            mtime: FileTime::from_last_modification_time(metadata).seconds(),
+            modified: metadata.modified().ok(),
+            size: metadata.len(),
+            is_dir: metadata.is_dir(),
+            hash: scanner
+                .build_hasher
+                .as_ref()
+                .filter(|_| metadata.is_file())
+                .and_then(|build_hasher| {
+                    let len = metadata.len();
+                    match scanner.content_size_limit {
+                        Some(limit) if len > limit => match scanner.large_file_comparison {
+                            LargeFileComparison::MetadataOnly => None,
+                            LargeFileComparison::HeadAndTail(n) => {
+                                Self::get_head_tail_hash(build_hasher, path, len, n).ok()
+                            }
+                        },
+                        _ => Self::get_content_hash(build_hasher, path).ok(),
+                    }
+                }),
+
+            boundaries: scanner
+                .change_regions
+                .filter(|_| metadata.is_file())
+                .and_then(|n| Self::get_boundary_hashes(path, metadata.len(), n).ok()),
+
+            metadata: scanner.keep_metadata.then(|| metadata.clone()),
+        }
+    }
+
+    /// Get hash values for the first and the last `n` bytes of the file at `path`, separately.
+    fn get_boundary_hashes(path: &Path, len: u64, n: u64) -> io::Result<(u64, u64)> {
+        let build_hasher = ContentHasher::default();
+        let mut file = File::open(path)?;
+
+        let mut head = build_hasher.build_hasher();
+        Self::hash_reader(&mut head, &mut (&mut file).take(n))?;
+        let mut tail = build_hasher.build_hasher();
+        file.seek(SeekFrom::Start(len.saturating_sub(n)))?;
+        Self::hash_reader(&mut tail, &mut file)?;
+
+        Ok((head.finish(), tail.finish()))
+    }
+
+    /// Which part of a file changed between `old` and `new`, if both have boundary hashes.
+    fn change_region(old: &PathData, new: &PathData) -> Option<ChangeRegion> {
+        let ((old_head, old_tail), (new_head, new_tail)) = (old.boundaries?, new.boundaries?);
+        Some(match (old_head != new_head, old_tail != new_tail) {
+            (true, true) => ChangeRegion::HeadAndTail,
+            (true, false) => ChangeRegion::Head,
+            (false, true) => ChangeRegion::Tail,
+            (false, false) => ChangeRegion::Middle,
+        })
+    }
+
+    /// Get hash value for the data content in given file `path`.
+    fn get_content_hash(build_hasher: &ContentHasher, path: &Path) -> io::Result<u64> {
+        let mut hasher = build_hasher.build_hasher();
+        let mut file = File::open(path)?;
+        Self::hash_reader(&mut hasher, &mut file)?;
+
+        Ok(hasher.finish())
+    }
+
+    /// Get hash value for the first and last `n` bytes of the file at `path`.
+    ///
+    /// The file length is hashed as well, so that growing or shrinking a file is detected
+    /// even if both ends stay the same.
+    fn get_head_tail_hash(
+        build_hasher: &ContentHasher,
+        path: &Path,
+        len: u64,
+        n: u64,
+    ) -> io::Result<u64> {
+        let mut hasher = build_hasher.build_hasher();
+        let mut file = File::open(path)?;
+        hasher.write_u64(len);
+
+        if n.saturating_mul(2) >= len {
+            Self::hash_reader(&mut hasher, &mut file)?;
+        } else {
+            Self::hash_reader(&mut hasher, &mut (&mut file).take(n))?;
+            file.seek(SeekFrom::End(-(n as i64)))?;
+            Self::hash_reader(&mut hasher, &mut file)?;
+        }
+
+        Ok(hasher.finish())
+    }
+
+    /// Feed everything `reader` returns into `hasher`.
+    fn hash_reader<H: Hasher, R: Read>(hasher: &mut H, reader: &mut R) -> io::Result<()> {
+        let mut buf = [0; 512];
+
+        loop {
+            let n = match reader.read(&mut buf) {
+                Ok(0) => break,
+                Ok(len) => len,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+
+            hasher.write(&buf[..n]);
+        }
+
+        Ok(())
+    }
+
+    /// Get the [`EventKind`] of the change between two optional [`PathData`].
+    ///
+    /// If a `comparator` is given, it decides whether an existing path has changed, unless
+    /// the metadata of either side isn't known.
+    ///
+    /// Content hashes are compared as they are, so a file which could be hashed on one side
+    /// only, e.g. because it was unreadable, is reported as modified. `compare_hashes` is
+    /// only false if both sides were hashed differently, see [Snapshot::diff_with].
+    fn compare(
+        path: &Path,
+        old: Option<&PathData>,
+        new: Option<&PathData>,
+        compare_hashes: bool,
+        comparator: Option<&dyn ChangeComparator>,
+    ) -> Option<EventKind> {
+        match (old, new) {
+            (Some(old), Some(new)) => {
+                let custom = comparator.and_then(|comparator| {
+                    match (old.metadata.as_ref(), new.metadata.as_ref()) {
+                        (Some(old_meta), Some(new_meta)) => {
+                            Some(comparator.has_changed(path, old_meta, new_meta))
+                        }
+                        _ => None,
+                    }
+                });
+
+                if let Some(changed) = custom {
+                    changed.then(|| EventKind::Modify(ModifyKind::Any))
+                } else if new.mtime > old.mtime {
+                    Some(EventKind::Modify(ModifyKind::Metadata(
+                        MetadataKind::WriteTime,
+                    )))
+                } else if compare_hashes && new.hash != old.hash {
+                    Some(EventKind::Modify(ModifyKind::Data(DataChange::Any)))
+                } else {
+                    None
+                }
+            }
+            (None, Some(_new)) => Some(EventKind::Create(CreateKind::Any)),
+            (Some(_old), None) => Some(EventKind::Remove(RemoveKind::Any)),
+            (None, None) => None,
+        }
+    }
+}
+
+/// Magic bytes and format version at the start of persisted snapshots.
+const SNAPSHOT_HEADER: &[u8] = b"notify-rs poll snapshot v2\n";
+
+/// The persisted snapshots of all format versions start with this.
+const SNAPSHOT_MAGIC: &[u8] = b"notify-rs poll snapshot v";
+
+/// Write all `snapshots` to the file at `path`.
+///
+/// The snapshots are written to a temporary file first and then moved into place,
+/// so that a crash while writing never leaves a truncated file behind.
+pub(crate) fn save_all<'a>(
+    path: &Path,
+    snapshots: impl ExactSizeIterator<Item = &'a Snapshot>,
+) -> io::Result<()> {
+    let mut tmp_name = path.as_os_str().to_owned();
+    tmp_name.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_name);
+
+    let mut w = BufWriter::new(File::create(&tmp_path)?);
+    w.write_all(SNAPSHOT_HEADER)?;
+    w.write_all(&(snapshots.len() as u64).to_le_bytes())?;
+    for snapshot in snapshots {
+        write_path(&mut w, &snapshot.root)?;
+        w.write_all(&[snapshot.is_recursive as u8])?;
+        w.write_all(&snapshot.hasher_fingerprint.to_le_bytes())?;
+        w.write_all(&(snapshot.entries.len() as u64).to_le_bytes())?;
+        for (path, path_data) in &snapshot.entries {
+            write_path(&mut w, path)?;
+            path_data.write_to(&mut w)?;
+        }
+    }
+    w.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+
+    fs::rename(&tmp_path, path)
+}
+
+/// Read all snapshots from the file at `path`.
+///
+/// A missing file is not an error, it simply contains no snapshots.
+pub(crate) fn load_all(path: &Path) -> io::Result<Vec<Snapshot>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut r = BufReader::new(file);
+
+    let mut header = [0; SNAPSHOT_HEADER.len()];
+    r.read_exact(&mut header)?;
+    if header != SNAPSHOT_HEADER {
+        let msg = if header.starts_with(SNAPSHOT_MAGIC) {
+            "unsupported version of a notify-rs poll snapshot"
+        } else {
+            "not a notify-rs poll snapshot"
+        };
+        return Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+    }
+
+    let mut snapshots = Vec::new();
+    for _ in 0..read_u64(&mut r)? {
+        let root = read_path(&mut r)?;
+        let is_recursive = read_u8(&mut r)? != 0;
+        let hasher_fingerprint = read_u64(&mut r)?;
+        let mut entries = HashMap::new();
+        for _ in 0..read_u64(&mut r)? {
+            let path = read_path(&mut r)?;
+            entries.insert(path, PathData::read_from(&mut r)?);
+        }
+        snapshots.push(Snapshot {
+            root,
+            is_recursive,
+            hasher_fingerprint,
+            entries,
+        });
+    }
+
+    Ok(snapshots)
+}
+
+impl PathData {
+    fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(&self.mtime.to_le_bytes())?;
+        w.write_all(&self.size.to_le_bytes())?;
+        w.write_all(&[self.is_dir as u8])?;
+        match self
+            .modified
+            .map(|modified| modified.duration_since(UNIX_EPOCH))
+        {
+            Some(Ok(since_epoch)) => {
+                w.write_all(&[1])?;
+                w.write_all(&since_epoch.as_secs().to_le_bytes())?;
+                w.write_all(&since_epoch.subsec_nanos().to_le_bytes())?;
+            }
+            // times before the epoch are rare enough to be treated as unknown.
+            _ => w.write_all(&[0])?,
+        }
+        match self.hash {
+            Some(hash) => {
+                w.write_all(&[1])?;
+                w.write_all(&hash.to_le_bytes())
+            }
+            None => w.write_all(&[0]),
+        }
+    }
+
+    fn read_from<R: Read>(r: &mut R) -> io::Result<Self> {
+        let mtime = read_u64(r)? as i64;
+        let size = read_u64(r)?;
+        let is_dir = read_u8(r)? != 0;
+        let modified = match read_u8(r)? {
+            0 => None,
+            _ => {
+                let secs = read_u64(r)?;
+                let mut nanos = [0; 4];
+                r.read_exact(&mut nanos)?;
+                Some(UNIX_EPOCH + Duration::new(secs, u32::from_le_bytes(nanos)))
+            }
+        };
+        let hash = match read_u8(r)? {
+            0 => None,
+            _ => Some(read_u64(r)?),
+        };
+
+        Ok(PathData {
+            mtime,
+            modified,
+            size,
+            is_dir,
+            hash,
+            boundaries: None,
+            metadata: None,
+        })
+    }
+}
+
+pub(crate) fn read_u8<R: Read>(r: &mut R) -> io::Result<u8> {
+    let mut buf = [0; 1];
+    r.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+pub(crate) fn read_u64<R: Read>(r: &mut R) -> io::Result<u64> {
+    let mut buf = [0; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+pub(crate) fn write_path<W: Write>(w: &mut W, path: &Path) -> io::Result<()> {
+    let bytes = path_to_bytes(path);
+    w.write_all(&(bytes.len() as u64).to_le_bytes())?;
+    w.write_all(&bytes)
+}
+
+pub(crate) fn read_path<R: Read>(r: &mut R) -> io::Result<PathBuf> {
+    let len = read_u64(r)? as usize;
+    let mut bytes = Vec::with_capacity(len.min(4096));
+    r.take(len as u64).read_to_end(&mut bytes)?;
+    if bytes.len() != len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    path_from_bytes(bytes)
+}
+
+#[cfg(unix)]
+fn path_to_bytes(path: &Path) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+    path.as_os_str().as_bytes().to_vec()
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: Vec<u8>) -> io::Result<PathBuf> {
+    use std::os::unix::ffi::OsStringExt;
+    Ok(std::ffi::OsString::from_vec(bytes).into())
+}
+
+#[cfg(windows)]
+fn path_to_bytes(path: &Path) -> Vec<u8> {
+    use std::os::windows::ffi::OsStrExt;
+    path.as_os_str()
+        .encode_wide()
+        .flat_map(u16::to_le_bytes)
+        .collect()
+}
+
+#[cfg(windows)]
+fn path_from_bytes(bytes: Vec<u8>) -> io::Result<PathBuf> {
+    use std::os::windows::ffi::OsStringExt;
+    if bytes.len() % 2 != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "path of odd length in a notify-rs poll snapshot",
+        ));
+    }
+    let wide: Vec<u16> = bytes
+        .chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .collect();
+    Ok(std::ffi::OsString::from_wide(&wide).into())
+}
+
+#[cfg(not(any(unix, windows)))]
+fn path_to_bytes(path: &Path) -> Vec<u8> {
+    path.to_string_lossy().into_owned().into_bytes()
+}
+
+#[cfg(not(any(unix, windows)))]
+fn path_from_bytes(bytes: Vec<u8>) -> io::Result<PathBuf> {
+    String::from_utf8(bytes)
+        .map(PathBuf::from)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+#[test]
+fn head_tail_hash_ignores_the_middle_of_large_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let a = dir.path().join("a");
+    let b = dir.path().join("b");
+    let c = dir.path().join("c");
+    fs::write(&a, b"head-aaaaaaaa-tail").unwrap();
+    fs::write(&b, b"head-bbbbbbbb-tail").unwrap();
+    fs::write(&c, b"head-bbbbbbbb-TAIL").unwrap();
+
+    let build_hasher = ContentHasher::default();
+    let hash = |path: &Path| PathData::get_head_tail_hash(&build_hasher, path, 18, 5).unwrap();
+
+    assert_eq!(hash(&a), hash(&b));
+    assert_ne!(hash(&b), hash(&c));
+}
+
+#[test]
+fn diff_reports_changes_sorted_by_path() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("b"), b"b").unwrap();
+    fs::write(dir.path().join("c"), b"c").unwrap();
+    let before = Snapshot::build(dir.path()).unwrap();
+
+    fs::write(dir.path().join("a"), b"a").unwrap();
+    fs::remove_file(dir.path().join("c")).unwrap();
+    let after = Snapshot::build(dir.path()).unwrap();
+
+    // the directory itself may have been modified as well.
+    let changes: Vec<_> = before
+        .diff(&after)
+        .into_iter()
+        .filter(|event| event.paths != [dir.path()])
+        .map(|event| (event.kind, event.paths))
+        .collect();
+    assert_eq!(
+        changes,
+        vec![
+            (
+                EventKind::Create(CreateKind::Any),
+                vec![dir.path().join("a")]
+            ),
+            (
+                EventKind::Remove(RemoveKind::Any),
+                vec![dir.path().join("c")]
+            ),
+        ]
+    );
+    assert!(after.diff(&after).is_empty());
+
+    let diff = before.diff(&after);
+    let created = diff
+        .iter()
+        .find(|event| event.paths == [dir.path().join("a")])
+        .unwrap();
+    let mtime = fs::metadata(dir.path().join("a"))
+        .unwrap()
+        .modified()
+        .unwrap();
+    assert_eq!(created.source_time(), Some(mtime));
+    assert!(created.is_inferred());
+}
+
+#[test]
+fn changes_since_reports_changes_made_while_stopped() {
+    use crate::PollWatcher;
+
+    let dir = tempfile::tempdir().unwrap();
+    let state = dir.path().join("state");
+    let root = dir.path().join("root");
+    fs::create_dir(&root).unwrap();
+    fs::write(root.join("file"), b"a").unwrap();
+
+    let config = Config::default();
+    let mut watcher = PollWatcher::new(|_| {}, config.clone()).unwrap();
+    let mut changes = ChangesSince::load(&state, &config).unwrap();
+    let events = changes
+        .watch(&mut watcher, &root, RecursiveMode::Recursive)
+        .unwrap();
+    assert!(events.is_empty());
+    changes.save(&state).unwrap();
+    drop(watcher);
+
+    fs::remove_file(root.join("file")).unwrap();
+
+    let mut watcher = PollWatcher::new(|_| {}, config.clone()).unwrap();
+    let mut changes = ChangesSince::load(&state, &config).unwrap();
+    let events: Vec<_> = changes
+        .watch(&mut watcher, &root, RecursiveMode::Recursive)
+        .unwrap()
+        .into_iter()
+        .filter(|event| event.paths != [root.as_path()])
+        .map(|event| (event.kind, event.paths))
+        .collect();
+    assert_eq!(
+        events,
+        vec![(EventKind::Remove(RemoveKind::Any), vec![root.join("file")])]
+    );
+}
+
+#[test]
+fn change_region_tells_which_end_of_a_file_changed() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("file");
+    let config = Config::default()
+        .with_compare_contents(true)
+        .with_change_regions(Some(4));
+    let snapshot = || Snapshot::build_with(dir.path(), RecursiveMode::Recursive, &config).unwrap();
+    let region = |before: &Snapshot, after: &Snapshot| {
+        let events = before.diff(after);
+        assert_eq!(events.len(), 1);
+        events[0].metadata_delta().unwrap().region
+    };
+
+    fs::write(&file, b"head-middle-tail").unwrap();
+    let original = snapshot();
+    fs::write(&file, b"head-middle-tail-appended").unwrap();
+    let appended = snapshot();
+    fs::write(&file, b"HEAD-middle-tail-appended").unwrap();
+    let rewritten = snapshot();
+    fs::write(&file, b"HEAD-MIDDLE-tail-appended").unwrap();
+    let edited = snapshot();
+
+    assert_eq!(region(&original, &appended), Some(ChangeRegion::Tail));
+    assert_eq!(region(&appended, &rewritten), Some(ChangeRegion::Head));
+    assert_eq!(region(&rewritten, &edited), Some(ChangeRegion::Middle));
+    assert_eq!(region(&original, &edited), Some(ChangeRegion::HeadAndTail));
+}
+
+#[test]
+fn comparator_decides_whether_existing_paths_changed() {
+    let dir = tempfile::tempdir().unwrap();
+    let (touched, untouched) = (dir.path().join("touched"), dir.path().join("untouched"));
+    fs::write(&touched, b"a").unwrap();
+    fs::write(&untouched, b"a").unwrap();
+
+    let mut scanner = Scanner::new(&Config::default());
+    scanner.set_keep_metadata(true);
+    let scan = || scanner.scan(dir.path().to_path_buf(), true, |_, _| {});
+    let before = scan();
+    filetime::set_file_mtime(&touched, FileTime::from_unix_time(4_000_000_000, 0)).unwrap();
+    let after = scan();
+    let changes = |comparator: &dyn ChangeComparator| -> Vec<_> {
+        before
+            .diff_with(&after, Some(comparator))
+            .into_iter()
+            .map(|event| (event.kind, event.paths))
+            .collect()
+    };
+
+    // without a comparator, the newer modification time is a change.
+    assert!(before
+        .diff(&after)
+        .iter()
+        .any(|event| event.paths == [touched.clone()]));
+    let never = |_: &Path, _: &Metadata, _: &Metadata| false;
+    assert!(changes(&never).is_empty());
+    let untouched_only = |path: &Path, _: &Metadata, _: &Metadata| path.ends_with("untouched");
+    assert_eq!(
+        changes(&untouched_only),
+        vec![(EventKind::Modify(ModifyKind::Any), vec![untouched.clone()])]
+    );
+}
+
+#[test]
+fn metadata_delta_tells_old_and_new_size_and_mtime() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("file");
+    let config = Config::default().with_compare_contents(true);
+    let mut mtime = 1_000_000_000;
+    let mut write = |contents: &[u8]| {
+        fs::write(&file, contents).unwrap();
+        mtime += 10;
+        filetime::set_file_mtime(&file, FileTime::from_unix_time(mtime, 0)).unwrap();
+        let snapshot = Snapshot::build_with(&file, RecursiveMode::Recursive, &config).unwrap();
+        (snapshot, UNIX_EPOCH + Duration::from_secs(mtime as u64))
+    };
+    let delta = |before: &Snapshot, after: &Snapshot| {
+        let events = before.diff(after);
+        assert_eq!(events.len(), 1);
+        *events[0].metadata_delta().unwrap()
+    };
+
+    let (small, small_mtime) = write(b"abc");
+    let (large, large_mtime) = write(b"abcdef");
+    let (rewritten, rewritten_mtime) = write(b"ABCDEF");
+
+    let grown = delta(&small, &large);
+    assert_eq!((grown.old_size, grown.new_size), (Some(3), Some(6)));
+    assert_eq!(
+        (grown.old_mtime, grown.new_mtime),
+        (Some(small_mtime), Some(large_mtime))
+    );
+    assert_eq!(grown.size_change(), Some(3));
+
+    let shrunk = delta(&rewritten, &small);
+    assert_eq!((shrunk.old_size, shrunk.new_size), (Some(6), Some(3)));
+    assert_eq!(
+        (shrunk.old_mtime, shrunk.new_mtime),
+        (Some(rewritten_mtime), Some(small_mtime))
+    );
+    assert_eq!(shrunk.size_change(), Some(-3));
+
+    let same_size = delta(&large, &rewritten);
+    assert_eq!((same_size.old_size, same_size.new_size), (Some(6), Some(6)));
+    assert_eq!(
+        (same_size.old_mtime, same_size.new_mtime),
+        (Some(large_mtime), Some(rewritten_mtime))
+    );
+    assert_eq!(same_size.size_change(), Some(0));
+}
+
+#[test]
+fn compare_reports_a_hash_known_on_one_side_only() {
+    let path_data = |hash| PathData {
+        mtime: 0,
+        modified: None,
+        size: 1,
+        is_dir: false,
+        hash,
+        boundaries: None,
+        metadata: None,
+    };
+    let compare = |old, new, compare_hashes| {
+        PathData::compare(
+            Path::new("file"),
+            Some(&path_data(old)),
+            Some(&path_data(new)),
+            compare_hashes,
+            None,
+        )
+    };
+    let modified = Some(EventKind::Modify(ModifyKind::Data(DataChange::Any)));
+
+    assert_eq!(compare(None, Some(1), true), modified);
+    assert_eq!(compare(Some(1), None, true), modified);
+    assert_eq!(compare(Some(1), Some(2), true), modified);
+    assert_eq!(compare(Some(1), Some(1), true), None);
+    assert_eq!(compare(None, None, true), None);
+    assert_eq!(compare(Some(1), Some(2), false), None);
+}
+
+#[test]
+fn snapshots_of_older_versions_are_rejected() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = dir.path().join("state");
+    save_all(&state, std::iter::empty()).unwrap();
+    assert!(load_all(&state).unwrap().is_empty());
+
+    let mut old = b"notify-rs poll snapshot v1\n".to_vec();
+    old.extend_from_slice(&0u64.to_le_bytes());
+    fs::write(&state, old).unwrap();
+    let err = load_all(&state).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert_eq!(
+        err.to_string(),
+        "unsupported version of a notify-rs poll snapshot"
+    );
+}
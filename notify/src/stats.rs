@@ -0,0 +1,450 @@
+//! Rolling statistics over the events of the last moments
+
+use crate::filter::EventKinds;
+use crate::Event;
+use instant::Instant;
+use std::{
+    collections::{HashMap, VecDeque},
+    hash::{Hash, Hasher},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// Counts the events recorded within a rolling window, by kind, directory and path.
+///
+/// ```
+/// # use std::path::{Path, PathBuf};
+/// # use std::time::Duration;
+/// use notify::event::{Event, EventKind, ModifyKind};
+/// use notify::filter::EventKinds;
+/// use notify::stats::EventStats;
+///
+/// let mut stats = EventStats::new(Duration::from_secs(60));
+/// let modify = Event::new(EventKind::Modify(ModifyKind::Any));
+/// stats.record(&modify.clone().add_path(PathBuf::from("/log/a")));
+/// stats.record(&modify.clone().add_path(PathBuf::from("/log/a")));
+/// stats.record(&modify.add_path(PathBuf::from("/src/b")));
+///
+/// assert_eq!(stats.count(EventKinds::MODIFY), 3);
+/// assert_eq!(stats.count_in(Path::new("/log")), 2);
+/// assert_eq!(stats.top_paths(1), [(PathBuf::from("/log/a"), 2)]);
+/// ```
+///
+/// The events are counted in [`BUCKETS`](EventStats::BUCKETS) consecutive slices of the window,
+/// which expire as a whole once all of their events are out of the window. So events are counted
+/// for up to a slice longer than the window, and the memory used doesn't grow with the number of
+/// events.
+///
+/// Each slice counts the events of at most [`path_limit`](EventStats::path_limit) paths, events of
+/// further paths are left out of the path and directory statistics. Events with several paths
+/// count once for each of their paths there, and once in the others. Errors aren't events and
+/// can't be recorded.
+#[derive(Clone, Debug)]
+pub struct EventStats {
+    window: Duration,
+    path_limit: usize,
+    buckets: VecDeque<Bucket>,
+    total: u64,
+}
+
+/// The counts of the events in one slice of the window.
+#[derive(Clone, Debug)]
+struct Bucket {
+    start: Instant,
+    events: usize,
+    /// By the index of the bit of their [EventKinds]
+    kinds: [usize; 8],
+    paths: HashMap<PathBuf, usize>,
+}
+
+impl Bucket {
+    fn new(start: Instant) -> Self {
+        Self {
+            start,
+            events: 0,
+            kinds: [0; 8],
+            paths: HashMap::new(),
+        }
+    }
+}
+
+impl EventStats {
+    /// The number of slices the window is counted in
+    pub const BUCKETS: u32 = 60;
+
+    /// The default number of paths counted per slice of the window
+    pub const DEFAULT_PATH_LIMIT: usize = 1024;
+
+    /// Count the events of the last `window`.
+    pub fn new(window: Duration) -> Self {
+        Self::with_path_limit(window, Self::DEFAULT_PATH_LIMIT)
+    }
+
+    /// Count the events of the last `window`, and those of up to `path_limit` paths per slice of
+    /// the window by path.
+    pub fn with_path_limit(window: Duration, path_limit: usize) -> Self {
+        Self {
+            window,
+            path_limit,
+            buckets: VecDeque::new(),
+            total: 0,
+        }
+    }
+
+    /// Returns the length of the window
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    /// Returns the number of paths counted per slice of the window
+    pub fn path_limit(&self) -> usize {
+        self.path_limit
+    }
+
+    /// Record `event` as happening now.
+    pub fn record(&mut self, event: &Event) {
+        self.record_at(event, Instant::now())
+    }
+
+    /// Record `event` as happening at `at`.
+    pub fn record_at(&mut self, event: &Event, at: Instant) {
+        self.total += 1;
+        let path_limit = self.path_limit;
+        let bucket = self.bucket(at);
+        bucket.events += 1;
+        bucket.kinds[EventKinds::of(&event.kind).bits().trailing_zeros() as usize] += 1;
+        for path in &event.paths {
+            if let Some(count) = bucket.paths.get_mut(path) {
+                *count += 1;
+            } else if bucket.paths.len() < path_limit {
+                bucket.paths.insert(path.clone(), 1);
+            }
+        }
+        self.expire(at);
+    }
+
+    /// The bucket counting the events at `at`.
+    fn bucket(&mut self, at: Instant) -> &mut Bucket {
+        let width = self.bucket_width();
+        let index = match self.buckets.iter().rposition(|bucket| bucket.start <= at) {
+            Some(index)
+                if index + 1 < self.buckets.len() || at < self.buckets[index].start + width =>
+            {
+                index
+            }
+            Some(_) => {
+                self.buckets.push_back(Bucket::new(at));
+                self.buckets.len() - 1
+            }
+            // older than everything recorded so far
+            None => {
+                self.buckets.push_front(Bucket::new(at));
+                0
+            }
+        };
+        &mut self.buckets[index]
+    }
+
+    fn bucket_width(&self) -> Duration {
+        (self.window / Self::BUCKETS).max(Duration::from_micros(1))
+    }
+
+    /// Whether all events of `bucket` are out of the window at `now`.
+    fn is_expired(&self, bucket: &Bucket, now: Instant) -> bool {
+        now.saturating_duration_since(bucket.start) > self.window + self.bucket_width()
+    }
+
+    /// Drop the events which are out of the window at `now`.
+    ///
+    /// Recording events does this too, the statistics never include expired events either way.
+    pub fn expire(&mut self, now: Instant) {
+        while let Some(bucket) = self.buckets.front() {
+            if !self.is_expired(bucket, now) {
+                break;
+            }
+            self.buckets.pop_front();
+        }
+    }
+
+    /// Returns the number of events recorded since this was created, including expired ones
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// The slices of the window.
+    fn current(&self) -> impl Iterator<Item = &Bucket> {
+        let now = Instant::now();
+        self.buckets
+            .iter()
+            .filter(move |bucket| !self.is_expired(bucket, now))
+    }
+
+    /// The number of events of each path within the window.
+    fn path_counts(&self) -> HashMap<&Path, usize> {
+        let mut counts: HashMap<&Path, usize> = HashMap::new();
+        for bucket in self.current() {
+            for (path, count) in &bucket.paths {
+                *counts.entry(path).or_insert(0) += count;
+            }
+        }
+        counts
+    }
+
+    /// The number of events within the window.
+    pub fn len(&self) -> usize {
+        self.current().map(|bucket| bucket.events).sum()
+    }
+
+    /// Whether no events are within the window.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The number of events of the given kinds within the window.
+    pub fn count(&self, kinds: EventKinds) -> usize {
+        self.current()
+            .flat_map(|bucket| bucket.kinds.iter().enumerate())
+            .filter(|(bit, _)| kinds.bits() & (1 << bit) != 0)
+            .map(|(_, count)| count)
+            .sum()
+    }
+
+    /// The number of events within the window with a path in `dir` or below it.
+    pub fn count_in(&self, dir: &Path) -> usize {
+        self.path_counts()
+            .into_iter()
+            .filter(|(path, _)| path.starts_with(dir))
+            .map(|(_, count)| count)
+            .sum()
+    }
+
+    /// The number of events within the window by the directory directly containing their path.
+    pub fn per_directory(&self) -> HashMap<PathBuf, usize> {
+        let mut counts = HashMap::new();
+        for (path, count) in self.path_counts() {
+            if let Some(dir) = path.parent() {
+                *counts.entry(dir.to_owned()).or_insert(0) += count;
+            }
+        }
+        counts
+    }
+
+    /// The average number of events per second over the window.
+    pub fn events_per_second(&self) -> f64 {
+        let secs = self.window.as_secs_f64();
+        if secs == 0.0 {
+            return 0.0;
+        }
+        self.len() as f64 / secs
+    }
+
+    /// The `n` paths with the most events within the window, most first.
+    ///
+    /// Paths with the same number of events are sorted by path.
+    pub fn top_paths(&self, n: usize) -> Vec<(PathBuf, usize)> {
+        let mut counts: Vec<(&Path, usize)> = self.path_counts().into_iter().collect();
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+        counts
+            .into_iter()
+            .take(n)
+            .map(|(path, count)| (path.to_owned(), count))
+            .collect()
+    }
+
+    /// Forget all events, but not the [total](EventStats::total).
+    pub fn clear(&mut self) {
+        self.buckets.clear();
+    }
+}
+
+/// The totals of what a watcher lost, shared with it by
+/// [Config::with_loss_stats](crate::Config::with_loss_stats).
+///
+/// ```
+/// # use notify::{Config, RecommendedWatcher, Watcher};
+/// use notify::stats::LossStats;
+///
+/// # fn main() -> notify::Result<()> {
+/// let losses = LossStats::new();
+/// let config = Config::default()
+///     .with_event_queue(Some(1024))
+///     .with_loss_stats(Some(losses.clone()));
+/// let _watcher = RecommendedWatcher::new(|_| {}, config)?;
+///
+/// // ... later
+/// let total = losses.total();
+/// println!("{} rescans, {} events dropped", total.rescans, total.dropped);
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Every rescan event the watcher delivers is counted, whether the OS overflowed (an
+/// `IN_Q_OVERFLOW` of inotify, the buffer of `ReadDirectoryChangesW`, events dropped by
+/// FSEvents) or a bounded queue did. Only the event queue and the `EventStream` of the `stream`
+/// feature know how many events they dropped, the OS doesn't tell.
+///
+/// Losses of events with a [watch token](crate::Watcher::watch_with_token) are counted for that
+/// watch as well. Handles are equal if they share their counts.
+#[derive(Clone, Debug, Default)]
+pub struct LossStats(Arc<Mutex<Losses>>);
+
+#[derive(Debug, Default)]
+struct Losses {
+    total: Loss,
+    per_watch: HashMap<u64, Loss>,
+}
+
+/// How much was lost, see [LossStats]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Loss {
+    /// The number of rescan events, each replacing lost events
+    pub rescans: u64,
+    /// The number of events known to be dropped
+    pub dropped: u64,
+    /// The number of events replaced by a later event of the same paths in a full queue, see
+    /// [OverflowStrategy::CoalescePerPath](crate::OverflowStrategy::CoalescePerPath)
+    pub coalesced: u64,
+}
+
+impl LossStats {
+    /// Start counting from zero.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the losses of all watches
+    pub fn total(&self) -> Loss {
+        self.0.lock().map(|losses| losses.total).unwrap_or_default()
+    }
+
+    /// Returns the losses of the events of the watch with `token`
+    pub fn watch(&self, token: u64) -> Loss {
+        self.0
+            .lock()
+            .ok()
+            .and_then(|losses| losses.per_watch.get(&token).copied())
+            .unwrap_or_default()
+    }
+
+    /// Returns the losses of every watch token which lost events
+    pub fn per_watch(&self) -> HashMap<u64, Loss> {
+        self.0
+            .lock()
+            .map(|losses| losses.per_watch.clone())
+            .unwrap_or_default()
+    }
+
+    /// Start counting from zero again.
+    pub fn reset(&self) {
+        if let Ok(mut losses) = self.0.lock() {
+            *losses = Losses::default();
+        }
+    }
+
+    /// Count a rescan event of the watch with `token`, replacing `dropped` events.
+    pub(crate) fn record_rescan(&self, token: Option<u64>, dropped: u64) {
+        self.record(token, |loss| {
+            loss.rescans += 1;
+            loss.dropped += dropped;
+        });
+    }
+
+    /// Count a dropped event of the watch with `token`.
+    pub(crate) fn record_dropped(&self, token: Option<u64>) {
+        self.record(token, |loss| loss.dropped += 1);
+    }
+
+    /// Count a coalesced event of the watch with `token`.
+    pub(crate) fn record_coalesced(&self, token: Option<u64>) {
+        self.record(token, |loss| loss.coalesced += 1);
+    }
+
+    fn record(&self, token: Option<u64>, count: impl Fn(&mut Loss)) {
+        if let Ok(mut losses) = self.0.lock() {
+            count(&mut losses.total);
+            if let Some(token) = token {
+                count(losses.per_watch.entry(token).or_default());
+            }
+        }
+    }
+}
+
+impl PartialEq for LossStats {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for LossStats {}
+
+impl Hash for LossStats {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (Arc::as_ptr(&self.0) as usize).hash(state);
+    }
+}
+
+#[test]
+fn event_stats_only_count_the_window() {
+    use crate::event::{CreateKind, EventKind, RemoveKind};
+
+    let window = Duration::from_secs(10);
+    let mut stats = EventStats::new(window);
+    let now = Instant::now();
+    let create = |path: &str| Event::new(EventKind::Create(CreateKind::File)).add_path(path.into());
+
+    if let Some(old) = now.checked_sub(Duration::from_secs(20)) {
+        stats.record_at(&create("/old/a"), old);
+    }
+    stats.record_at(&create("/a/x"), now);
+    stats.record_at(&create("/a/y"), now);
+    stats.record_at(
+        &Event::new(EventKind::Remove(RemoveKind::Any)).add_path("/b/x".into()),
+        now,
+    );
+
+    assert_eq!(stats.len(), 3);
+    assert_eq!(stats.count(EventKinds::CREATE), 2);
+    assert_eq!(stats.count(EventKinds::CREATE | EventKinds::REMOVE), 3);
+    assert_eq!(stats.count_in(Path::new("/old")), 0);
+    assert_eq!(stats.per_directory()[Path::new("/a")], 2);
+    assert_eq!(stats.events_per_second(), 0.3);
+    assert_eq!(
+        stats.top_paths(2),
+        [(PathBuf::from("/a/x"), 1), (PathBuf::from("/a/y"), 1)]
+    );
+    assert!(stats.total() >= 3);
+
+    // only the first paths of a slice are counted by path
+    let mut stats = EventStats::with_path_limit(window, 1);
+    for path in ["/a/x", "/a/y", "/a/x"] {
+        stats.record_at(&create(path), now);
+    }
+    assert_eq!(stats.len(), 3);
+    assert_eq!(stats.top_paths(2), [(PathBuf::from("/a/x"), 2)]);
+    stats.expire(now + window * 2);
+    assert!(stats.is_empty());
+}
+
+#[test]
+fn losses_are_counted_in_total_and_per_watch() {
+    let losses = LossStats::new();
+    losses.record_rescan(None, 0);
+    losses.record_rescan(Some(3), 2);
+    losses.record_dropped(Some(3));
+    losses.record_coalesced(None);
+
+    let expected = |rescans, dropped, coalesced| Loss {
+        rescans,
+        dropped,
+        coalesced,
+    };
+    assert_eq!(losses.total(), expected(2, 3, 1));
+    assert_eq!(losses.watch(3), expected(1, 3, 0));
+    assert_eq!(losses.watch(4), Loss::default());
+    assert_eq!(losses.clone(), losses);
+    assert_ne!(LossStats::new(), losses);
+
+    losses.reset();
+    assert_eq!(losses.total(), Loss::default());
+}
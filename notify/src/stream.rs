@@ -0,0 +1,301 @@
+//! Consuming the events of any watcher as a [Stream]
+
+use crate::event::{EventKind, Flag};
+use crate::stats::LossStats;
+use crate::{Config, Event, EventHandler, OverflowStrategy, Result, Watcher};
+use futures_core::Stream;
+use std::{
+    collections::VecDeque,
+    pin::Pin,
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    task::{Context, Poll, Waker},
+};
+
+/// A [Stream] of the events of a watcher, buffered in between.
+///
+/// ```no_run
+/// # use std::path::Path;
+/// use futures::StreamExt;
+/// use notify::stream::EventStream;
+/// use notify::{Config, RecommendedWatcher, RecursiveMode, Watcher};
+///
+/// # fn main() -> notify::Result<()> {
+/// let (mut watcher, mut events) = EventStream::new::<RecommendedWatcher>(Config::default())?;
+/// watcher.watch(Path::new("."), RecursiveMode::Recursive)?;
+///
+/// futures::executor::block_on(async {
+///     while let Some(event) = events.next().await {
+///         println!("{:?}", event);
+///     }
+/// });
+/// # Ok(())
+/// # }
+/// ```
+///
+/// When events were dropped by an [OverflowStrategy] other than [OverflowStrategy::Block], the
+/// stream yields an [EventKind::Other] event flagged [Flag::Rescan] before the next buffered one,
+/// with the number of dropped events as its [dropped](Event::dropped) attribute. Events replaced by
+/// [OverflowStrategy::CoalescePerPath] aren't lost, and no rescan event is yielded for them. All of
+/// them are counted in the [LossStats] of the [Config] as well.
+/// The stream ends once the watcher is dropped and the buffered events are consumed.
+#[derive(Debug)]
+pub struct EventStream {
+    shared: Arc<Shared>,
+}
+
+#[derive(Debug)]
+struct Shared {
+    state: Mutex<State>,
+    /// Signalled when room is made in the buffer, or the stream is dropped
+    space: Condvar,
+}
+
+#[derive(Debug)]
+struct State {
+    buffer: VecDeque<Result<Event>>,
+    capacity: usize,
+    policy: OverflowStrategy,
+    waker: Option<Waker>,
+    /// The number of events dropped since the last rescan event
+    dropped: u64,
+    losses: Option<LossStats>,
+    /// The watcher dropped its event handler
+    finished: bool,
+    /// The stream was dropped
+    closed: bool,
+}
+
+impl State {
+    /// Count `event` as lost, to be replaced by a rescan event.
+    fn drop_event(&mut self, event: &Result<Event>) {
+        self.dropped += 1;
+        if let Some(losses) = &self.losses {
+            losses.record_dropped(event.as_ref().ok().and_then(Event::watch_token));
+        }
+    }
+
+    /// Count `event` as replaced by a later event of its paths.
+    fn coalesce_event(&mut self, event: &Result<Event>) {
+        if let Some(losses) = &self.losses {
+            losses.record_coalesced(event.as_ref().ok().and_then(Event::watch_token));
+        }
+    }
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().expect("Can't lock stream state!")
+    }
+}
+
+impl EventStream {
+    /// The default number of buffered events
+    pub const DEFAULT_CAPACITY: usize = 1024;
+
+    /// Create a watcher of type `W` and the stream of its events, buffering up to
+    /// [DEFAULT_CAPACITY](EventStream::DEFAULT_CAPACITY) events and blocking the watcher while the
+    /// buffer is full.
+    pub fn new<W: Watcher>(config: Config) -> Result<(W, Self)> {
+        Self::with_buffer(config, Self::DEFAULT_CAPACITY, OverflowStrategy::Block)
+    }
+
+    /// Create a watcher of type `W` and the stream of its events, buffering up to `capacity`
+    /// events and applying `policy` to the events arriving while the buffer is full.
+    ///
+    /// A capacity of zero is treated as one.
+    pub fn with_buffer<W: Watcher>(
+        config: Config,
+        capacity: usize,
+        policy: OverflowStrategy,
+    ) -> Result<(W, Self)> {
+        let (sender, stream) = channel(capacity, policy, config.loss_stats().cloned());
+        let watcher = W::new(sender, config)?;
+        Ok((watcher, stream))
+    }
+}
+
+impl Stream for EventStream {
+    type Item = Result<Event>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut state = self.shared.lock();
+        if state.dropped > 0 {
+            let dropped = std::mem::replace(&mut state.dropped, 0);
+            if let Some(losses) = &state.losses {
+                losses.record_rescan(None, 0);
+            }
+            let rescan = Event::new(EventKind::Other).set_flag(Flag::Rescan);
+            return Poll::Ready(Some(Ok(rescan.set_dropped(dropped))));
+        }
+        match state.buffer.pop_front() {
+            Some(event) => {
+                self.shared.space.notify_one();
+                Poll::Ready(Some(event))
+            }
+            None if state.finished => Poll::Ready(None),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.shared.lock().buffer.len(), None)
+    }
+}
+
+impl Drop for EventStream {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        state.closed = true;
+        state.buffer.clear();
+        self.shared.space.notify_all();
+    }
+}
+
+/// The event handler given to the watcher.
+struct StreamSender {
+    shared: Arc<Shared>,
+}
+
+fn channel(
+    capacity: usize,
+    policy: OverflowStrategy,
+    losses: Option<LossStats>,
+) -> (StreamSender, EventStream) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            buffer: VecDeque::new(),
+            capacity: capacity.max(1),
+            policy,
+            waker: None,
+            dropped: 0,
+            losses,
+            finished: false,
+            closed: false,
+        }),
+        space: Condvar::new(),
+    });
+    (
+        StreamSender {
+            shared: shared.clone(),
+        },
+        EventStream { shared },
+    )
+}
+
+impl EventHandler for StreamSender {
+    fn handle_event(&mut self, event: Result<Event>) {
+        let mut state = self.shared.lock();
+        while !state.closed && state.buffer.len() >= state.capacity {
+            match state.policy {
+                OverflowStrategy::Block => {
+                    state = self
+                        .shared
+                        .space
+                        .wait(state)
+                        .expect("Can't lock stream state!");
+                }
+                OverflowStrategy::DropNewest => {
+                    state.drop_event(&event);
+                    return;
+                }
+                OverflowStrategy::DropOldest => {
+                    if let Some(oldest) = state.buffer.pop_front() {
+                        state.drop_event(&oldest);
+                    }
+                }
+                OverflowStrategy::CoalescePerPath => {
+                    let same_paths = match &event {
+                        Ok(event) => state.buffer.iter().position(
+                            |buffered| matches!(buffered, Ok(buffered) if buffered.paths == event.paths),
+                        ),
+                        Err(_) => None,
+                    };
+                    match same_paths.and_then(|i| state.buffer.remove(i)) {
+                        Some(replaced) => state.coalesce_event(&replaced),
+                        None => {
+                            if let Some(oldest) = state.buffer.pop_front() {
+                                state.drop_event(&oldest);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        if state.closed {
+            return;
+        }
+        state.buffer.push_back(event);
+        let waker = state.waker.take();
+        drop(state);
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+impl Drop for StreamSender {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        state.finished = true;
+        let waker = state.waker.take();
+        drop(state);
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+#[test]
+fn event_stream_applies_overflow_policy() {
+    use futures::executor::block_on_stream;
+    use std::path::PathBuf;
+
+    let event = |path: &str| Ok(Event::new(EventKind::Any).add_path(PathBuf::from(path)));
+    let run = |policy| {
+        let (mut sender, stream) = channel(2, policy, None);
+        sender.handle_event(event("a"));
+        sender.handle_event(event("b"));
+        sender.handle_event(event("c"));
+        drop(sender);
+        block_on_stream(stream)
+            .map(|event| {
+                let event = event.unwrap();
+                match event.flag() {
+                    Some(Flag::Rescan) => format!("rescan {}", event.dropped().unwrap()),
+                    _ => event.paths[0].display().to_string(),
+                }
+            })
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(run(OverflowStrategy::DropNewest), ["rescan 1", "a", "b"]);
+    assert_eq!(run(OverflowStrategy::DropOldest), ["rescan 1", "b", "c"]);
+    assert_eq!(
+        run(OverflowStrategy::CoalescePerPath),
+        ["rescan 1", "b", "c"]
+    );
+
+    let (mut sender, stream) = channel(2, OverflowStrategy::CoalescePerPath, None);
+    for path in ["a", "b", "a"] {
+        sender.handle_event(event(path));
+    }
+    drop(sender);
+    let paths: Vec<PathBuf> = block_on_stream(stream)
+        .map(|event| event.unwrap().paths.remove(0))
+        .collect();
+    assert_eq!(paths, [PathBuf::from("b"), PathBuf::from("a")]);
+
+    let (mut sender, stream) = channel(1, OverflowStrategy::Block, None);
+    let thread = std::thread::spawn(move || {
+        sender.handle_event(event("a"));
+        sender.handle_event(event("b"));
+    });
+    let paths: Vec<PathBuf> = block_on_stream(stream)
+        .map(|event| event.unwrap().paths.remove(0))
+        .collect();
+    thread.join().unwrap();
+    assert_eq!(paths, [PathBuf::from("a"), PathBuf::from("b")]);
+}
@@ -0,0 +1,270 @@
+//! Fanning the events of one watcher out to several handlers
+
+use crate::{Error, ErrorKind, Event, EventHandler, Result};
+use std::{
+    fmt, io,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+};
+
+/// Identifies a handler added to [`Subscriptions`], to remove it again later.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SubscriptionId(u64);
+
+/// An event handler passing each event on to a changing set of other handlers.
+///
+/// Give a clone of it to a watcher, then add and remove handlers through the clone you kept, while
+/// the watcher is running:
+///
+/// ```no_run
+/// # use std::path::Path;
+/// use notify::{Config, Event, PollWatcher, RecursiveMode, Subscriptions, Watcher};
+///
+/// # fn main() -> notify::Result<()> {
+/// let subscriptions = Subscriptions::new();
+/// let mut watcher = PollWatcher::new(subscriptions.clone(), Config::default())?;
+/// watcher.watch(Path::new("."), RecursiveMode::Recursive)?;
+///
+/// let (tx, rx) = std::sync::mpsc::channel();
+/// let all = subscriptions.subscribe(tx);
+/// let removals = subscriptions.subscribe_filtered(
+///     |event: notify::Result<Event>| println!("removed: {:?}", event),
+///     |event: &Event| event.kind.is_remove(),
+/// );
+///
+/// // ...
+///
+/// subscriptions.unsubscribe(all);
+/// # drop(rx);
+/// # subscriptions.unsubscribe(removals);
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Handlers are called in the order they were added, on the watcher's thread. Errors are passed to
+/// every handler, filters only apply to events. The handlers are called without holding the set of
+/// subscriptions locked, so they may subscribe and unsubscribe handlers themselves.
+#[derive(Clone, Default)]
+pub struct Subscriptions {
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Default)]
+struct Inner {
+    next_id: u64,
+    subscribers: Vec<Arc<Subscriber>>,
+}
+
+type Filter = Box<dyn Fn(&Event) -> bool + Send>;
+
+struct Subscriber {
+    id: SubscriptionId,
+    removed: AtomicBool,
+    handler: Mutex<Handler>,
+}
+
+struct Handler {
+    handler: Box<dyn EventHandler>,
+    filter: Option<Filter>,
+}
+
+impl Subscriber {
+    fn lock(&self) -> MutexGuard<'_, Handler> {
+        self.handler
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Subscriptions {
+    /// Create an empty set of subscriptions.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Pass all events on to `handler`.
+    pub fn subscribe<F: EventHandler>(&self, handler: F) -> SubscriptionId {
+        self.add(Box::new(handler), None)
+    }
+
+    /// Pass events on to `handler` for which `filter` returns `true`.
+    pub fn subscribe_filtered<F, P>(&self, handler: F, filter: P) -> SubscriptionId
+    where
+        F: EventHandler,
+        P: Fn(&Event) -> bool + Send + 'static,
+    {
+        self.add(Box::new(handler), Some(Box::new(filter)))
+    }
+
+    /// Remove a handler, returning whether it was still subscribed.
+    ///
+    /// Once this returns, the handler won't receive further events. It's dropped right away, or
+    /// when it returns if it's handling an event at the moment.
+    pub fn unsubscribe(&self, id: SubscriptionId) -> bool {
+        let mut inner = self.lock();
+        let index = inner.subscribers.iter().position(|s| s.id == id);
+        match index.map(|index| inner.subscribers.remove(index)) {
+            Some(subscriber) => {
+                subscriber.removed.store(true, Ordering::SeqCst);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The number of subscribed handlers.
+    pub fn len(&self) -> usize {
+        self.lock().subscribers.len()
+    }
+
+    /// Whether no handlers are subscribed.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn add(&self, handler: Box<dyn EventHandler>, filter: Option<Filter>) -> SubscriptionId {
+        let mut inner = self.lock();
+        let id = SubscriptionId(inner.next_id);
+        inner.next_id += 1;
+        inner.subscribers.push(Arc::new(Subscriber {
+            id,
+            removed: AtomicBool::new(false),
+            handler: Mutex::new(Handler { handler, filter }),
+        }));
+        id
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        // a panicking handler leaves the list itself intact
+        self.inner
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl EventHandler for Subscriptions {
+    fn handle_event(&mut self, event: Result<Event>) {
+        let subscribers = self.lock().subscribers.clone();
+
+        // the last recipient gets the original, everyone else a copy
+        let mut previous: Option<MutexGuard<'_, Handler>> = None;
+        for subscriber in &subscribers {
+            let handler = subscriber.lock();
+            // unsubscribed by another handler since the list was copied
+            if subscriber.removed.load(Ordering::SeqCst) {
+                continue;
+            }
+            let matches = match (&handler.filter, &event) {
+                (Some(filter), Ok(event)) => filter(event),
+                _ => true,
+            };
+            if matches {
+                if let Some(mut previous) = previous.replace(handler) {
+                    previous.handler.handle_event(duplicate(&event));
+                }
+            }
+        }
+        if let Some(mut last) = previous {
+            last.handler.handle_event(event);
+        }
+    }
+}
+
+impl fmt::Debug for Subscriptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let inner = self.lock();
+        f.debug_struct("Subscriptions")
+            .field(
+                "subscribers",
+                &inner
+                    .subscribers
+                    .iter()
+                    .map(|subscriber| subscriber.id)
+                    .collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+/// Copy an event or error, for delivery to more than one handler.
+///
+/// I/O errors can't be cloned, so copies keep only their kind and message.
+fn duplicate(event: &Result<Event>) -> Result<Event> {
+    let err = match event {
+        Ok(event) => return Ok(event.clone()),
+        Err(err) => err,
+    };
+
+    let kind = match err.kind {
+        ErrorKind::Generic(ref msg) => ErrorKind::Generic(msg.clone()),
+        ErrorKind::Io(ref err) => ErrorKind::Io(io::Error::new(err.kind(), err.to_string())),
+        ErrorKind::PathNotFound => ErrorKind::PathNotFound,
+        ErrorKind::WatchNotFound => ErrorKind::WatchNotFound,
+        ErrorKind::InvalidConfig(ref config) => ErrorKind::InvalidConfig(config.clone()),
+        ErrorKind::MaxFilesWatch => ErrorKind::MaxFilesWatch,
+        ErrorKind::WatchOverlap(ref path) => ErrorKind::WatchOverlap(path.clone()),
+    };
+    Err(Error::new(kind).set_paths(err.paths.clone()))
+}
+
+#[test]
+fn subscriptions_fan_out_to_matching_handlers() {
+    use crate::event::{CreateKind, EventKind, RemoveKind};
+    use std::{path::PathBuf, sync::mpsc};
+
+    let mut subscriptions = Subscriptions::new();
+    let (all_tx, all_rx) = mpsc::channel();
+    let (removals_tx, removals_rx) = mpsc::channel();
+    let all = subscriptions.subscribe(all_tx);
+    subscriptions.subscribe_filtered(removals_tx, |event: &Event| event.kind.is_remove());
+    assert_eq!(subscriptions.len(), 2);
+
+    let create = Event::new(EventKind::Create(CreateKind::File)).add_path(PathBuf::from("a"));
+    let remove = Event::new(EventKind::Remove(RemoveKind::File)).add_path(PathBuf::from("a"));
+    subscriptions.handle_event(Ok(create.clone()));
+    subscriptions.handle_event(Ok(remove.clone()));
+    subscriptions.handle_event(Err(Error::io(io::ErrorKind::Other.into())));
+
+    let all_events: Vec<_> = all_rx.try_iter().collect();
+    assert_eq!(all_events.len(), 3);
+    assert_eq!(all_events[0].as_ref().unwrap(), &create);
+    assert_eq!(all_events[1].as_ref().unwrap(), &remove);
+    assert!(all_events[2].is_err());
+    let removals: Vec<_> = removals_rx.try_iter().collect();
+    assert_eq!(removals.len(), 2);
+    assert_eq!(removals[0].as_ref().unwrap(), &remove);
+    assert!(removals[1].is_err());
+
+    assert!(subscriptions.unsubscribe(all));
+    assert!(!subscriptions.unsubscribe(all));
+    subscriptions.handle_event(Ok(create));
+    assert!(all_rx.try_recv().is_err());
+}
+
+#[test]
+fn handlers_can_change_the_subscriptions() {
+    use crate::event::EventKind;
+    use std::sync::mpsc;
+
+    let subscriptions = Subscriptions::new();
+    let (tx, rx) = mpsc::channel();
+    let added = Arc::new(Mutex::new(None));
+    let (inner, added_c) = (subscriptions.clone(), added.clone());
+    subscriptions.subscribe(move |_: Result<Event>| {
+        let mut added = added_c.lock().unwrap();
+        match added.take() {
+            None => *added = Some(inner.subscribe(tx.clone())),
+            Some(id) => assert!(inner.unsubscribe(id)),
+        }
+    });
+
+    let mut handler = subscriptions.clone();
+    handler.handle_event(Ok(Event::new(EventKind::Other)));
+    assert_eq!(subscriptions.len(), 2);
+    handler.handle_event(Ok(Event::new(EventKind::Other)));
+    assert_eq!(subscriptions.len(), 1);
+    assert!(rx.try_recv().is_ok());
+    assert!(rx.try_recv().is_err());
+}
@@ -0,0 +1,207 @@
+//! Suppressing the events of the application's own changes
+
+use crate::{event::Flag, registry, Event};
+use instant::Instant;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, Weak},
+    time::Duration,
+};
+
+/// How long events are still suppressed after a [SuppressGuard] is dropped, by default.
+const DEFAULT_GRACE: Duration = Duration::from_millis(250);
+
+/// Suppresses the events below a path while it is alive and for a grace period after it is
+/// dropped, see [`Watcher::suppress`](crate::Watcher::suppress).
+///
+/// ```no_run
+/// # use std::path::Path;
+/// # use std::time::Duration;
+/// use notify::{RecursiveMode, Watcher};
+///
+/// # fn main() -> notify::Result<()> {
+/// let mut watcher = notify::recommended_watcher(|event| println!("{:?}", event))?;
+/// watcher.watch(Path::new("."), RecursiveMode::Recursive)?;
+///
+/// let guard = watcher
+///     .suppress(Path::new("target"))?
+///     .with_grace(Duration::from_secs(1));
+/// std::fs::write("target/output", b"built")?;
+/// drop(guard);
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Events carrying a [Flag], like rescan notices, are never suppressed.
+#[derive(Debug)]
+pub struct SuppressGuard {
+    entries: Vec<(Weak<Mutex<Vec<Entry>>>, u64)>,
+}
+
+impl SuppressGuard {
+    /// A guard which doesn't suppress anything, for watchers which never deliver events.
+    pub(crate) fn inert() -> Self {
+        Self {
+            entries: Vec::new(),
+        }
+    }
+
+    /// Suppress the events for as long as this after the guard is dropped, instead of 250ms.
+    ///
+    /// Watchers noticing changes late, like the [`PollWatcher`](crate::PollWatcher), need a grace
+    /// period of at least their poll interval.
+    pub fn with_grace(self, grace: Duration) -> Self {
+        self.update(|entry| entry.grace = grace);
+        self
+    }
+
+    /// Deliver the events with [Flag::Suppressed] instead of dropping them.
+    pub fn tagging(self) -> Self {
+        self.update(|entry| entry.tag = true);
+        self
+    }
+
+    /// Suppress the events of another guard's watcher as well, releasing both together.
+    #[cfg_attr(
+        not(all(any(target_os = "linux", target_os = "android"), feature = "linux_inotify")),
+        allow(dead_code)
+    )]
+    pub(crate) fn join(mut self, mut other: SuppressGuard) -> Self {
+        self.entries.append(&mut other.entries);
+        self
+    }
+
+    fn update(&self, f: impl Fn(&mut Entry)) {
+        for (entries, id) in &self.entries {
+            if let Some(entries) = entries.upgrade() {
+                if let Ok(mut entries) = entries.lock() {
+                    entries
+                        .iter_mut()
+                        .filter(|entry| entry.id == *id)
+                        .for_each(&f);
+                }
+            }
+        }
+    }
+}
+
+impl Drop for SuppressGuard {
+    fn drop(&mut self) {
+        let now = Instant::now();
+        self.update(|entry| entry.released = Some(now));
+    }
+}
+
+#[derive(Debug)]
+struct Entry {
+    id: u64,
+    prefixes: Vec<PathBuf>,
+    grace: Duration,
+    tag: bool,
+    released: Option<Instant>,
+}
+
+impl Entry {
+    fn is_active(&self, now: Instant) -> bool {
+        self.released.map_or(true, |released| {
+            now.saturating_duration_since(released) < self.grace
+        })
+    }
+
+    fn matches(&self, event: &Event) -> bool {
+        event.paths.iter().any(|path| {
+            let path = registry::absolute(path);
+            self.prefixes.iter().any(|prefix| path.starts_with(prefix))
+        })
+    }
+}
+
+/// The suppressed paths of a watcher, shared between the watcher and its
+/// [Delivery](crate::delivery::Delivery).
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Suppressions {
+    entries: Arc<Mutex<Vec<Entry>>>,
+    next_id: Arc<Mutex<u64>>,
+}
+
+impl Suppressions {
+    /// Suppress the events below `path` until the returned guard is dropped.
+    pub(crate) fn guard(&self, path: &Path) -> SuppressGuard {
+        let id = match self.next_id.lock() {
+            Ok(mut next_id) => {
+                *next_id += 1;
+                *next_id
+            }
+            Err(_) => return SuppressGuard::inert(),
+        };
+        // some backends report the canonical paths of their watches.
+        let mut prefixes = vec![registry::absolute(path)];
+        if let Ok(canonical) = fs::canonicalize(path) {
+            prefixes.push(canonical);
+        }
+        if let Ok(mut entries) = self.entries.lock() {
+            entries.push(Entry {
+                id,
+                prefixes,
+                grace: DEFAULT_GRACE,
+                tag: false,
+                released: None,
+            });
+        }
+        SuppressGuard {
+            entries: vec![(Arc::downgrade(&self.entries), id)],
+        }
+    }
+
+    /// Whether `event` is to be delivered, tagging it if a guard asks for that.
+    pub(crate) fn should_deliver(&self, event: &mut Event) -> bool {
+        if event.flag().is_some() {
+            return true;
+        }
+        let mut entries = match self.entries.lock() {
+            Ok(entries) => entries,
+            Err(_) => return true,
+        };
+        if entries.is_empty() {
+            return true;
+        }
+
+        let now = Instant::now();
+        entries.retain(|entry| entry.is_active(now));
+        match entries.iter().find(|entry| entry.matches(event)) {
+            Some(entry) if entry.tag => {
+                event.attrs.set_flag(Flag::Suppressed);
+                true
+            }
+            Some(_) => false,
+            None => true,
+        }
+    }
+}
+
+#[test]
+fn guards_suppress_events_until_their_grace_period_ends() {
+    use crate::event::{EventKind, ModifyKind};
+
+    let suppressions = Suppressions::default();
+    let root = std::env::temp_dir().join("notify-suppress");
+    let event = |path: &str| {
+        let mut event = Event::new(EventKind::Modify(ModifyKind::Any)).add_path(root.join(path));
+        let delivered = suppressions.should_deliver(&mut event);
+        (delivered, event.flag())
+    };
+
+    let guard = suppressions
+        .guard(&root.join("out"))
+        .with_grace(Duration::from_millis(50));
+    assert_eq!(event("out/a"), (false, None));
+    assert_eq!(event("src/a"), (true, None));
+    drop(guard);
+    assert_eq!(event("out/a"), (false, None));
+    std::thread::sleep(Duration::from_millis(60));
+    assert_eq!(event("out/a"), (true, None));
+
+    let _guard = suppressions.guard(&root.join("out")).tagging();
+    assert_eq!(event("out/a"), (true, Some(Flag::Suppressed)));
+}
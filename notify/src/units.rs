@@ -0,0 +1,121 @@
+//! Parsing of durations and sizes in config files and environment variables
+//!
+//! Durations are written as a number and a unit, like `500ms`, `2s`, `1.5m` or `1h`. Sizes are a
+//! number of bytes, optionally with a unit: `4096`, `64KiB`, `10MB` or `1GiB`, where `KB` is 1000
+//! bytes and `KiB` is 1024 bytes. Units are case-insensitive.
+//!
+//! With the `serde` feature, the `deserialize_*` functions can be used with
+//! `#[serde(deserialize_with = "...")]`. They also accept plain integers, as milliseconds for
+//! durations and as bytes for sizes.
+
+use std::time::Duration;
+
+/// Parses a duration like `500ms`, `2s`, `1.5m` or `1h`
+pub fn parse_duration(s: &str) -> Option<Duration> {
+    let (number, unit) = split_unit(s);
+    let secs = match unit.to_ascii_lowercase().as_str() {
+        "ms" => 0.001,
+        "s" => 1.0,
+        "m" | "min" => 60.0,
+        "h" => 3600.0,
+        _ => return None,
+    };
+    let number: f64 = number.parse().ok()?;
+    if !number.is_finite() || number < 0.0 {
+        return None;
+    }
+    Some(Duration::from_secs_f64(number * secs))
+}
+
+/// Parses a size like `4096`, `64KiB` or `10MB`
+pub fn parse_size(s: &str) -> Option<u64> {
+    let (number, unit) = split_unit(s);
+    let factor: u64 = match unit.to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "kb" => 1000,
+        "kib" => 1 << 10,
+        "mb" => 1000 * 1000,
+        "mib" => 1 << 20,
+        "gb" => 1000 * 1000 * 1000,
+        "gib" => 1 << 30,
+        _ => return None,
+    };
+    number.parse::<u64>().ok()?.checked_mul(factor)
+}
+
+fn split_unit(s: &str) -> (&str, &str) {
+    let s = s.trim();
+    let at = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(s.len());
+    (&s[..at], s[at..].trim_start())
+}
+
+#[cfg(feature = "serde")]
+mod de {
+    use serde::de::{Deserialize, Deserializer, Error};
+    use std::time::Duration;
+
+    #[derive(serde::Deserialize)]
+    #[serde(untagged)]
+    enum Value {
+        Number(u64),
+        Text(String),
+    }
+
+    fn duration<E: Error>(value: Value) -> Result<Duration, E> {
+        match value {
+            Value::Number(millis) => Ok(Duration::from_millis(millis)),
+            Value::Text(s) => super::parse_duration(&s)
+                .ok_or_else(|| E::custom(format!("invalid duration {:?}", s))),
+        }
+    }
+
+    fn size<E: Error>(value: Value) -> Result<u64, E> {
+        match value {
+            Value::Number(bytes) => Ok(bytes),
+            Value::Text(s) => {
+                super::parse_size(&s).ok_or_else(|| E::custom(format!("invalid size {:?}", s)))
+            }
+        }
+    }
+
+    /// Deserializes a duration string, or an integer as milliseconds
+    pub fn deserialize_duration<'de, D: Deserializer<'de>>(d: D) -> Result<Duration, D::Error> {
+        duration(Value::deserialize(d)?)
+    }
+
+    /// Deserializes an optional duration string, or an integer as milliseconds
+    pub fn deserialize_option_duration<'de, D: Deserializer<'de>>(
+        d: D,
+    ) -> Result<Option<Duration>, D::Error> {
+        Option::<Value>::deserialize(d)?.map(duration).transpose()
+    }
+
+    /// Deserializes a size string, or an integer as bytes
+    pub fn deserialize_size<'de, D: Deserializer<'de>>(d: D) -> Result<u64, D::Error> {
+        size(Value::deserialize(d)?)
+    }
+
+    /// Deserializes an optional size string, or an integer as bytes
+    pub fn deserialize_option_size<'de, D: Deserializer<'de>>(
+        d: D,
+    ) -> Result<Option<u64>, D::Error> {
+        Option::<Value>::deserialize(d)?.map(size).transpose()
+    }
+}
+
+#[cfg(feature = "serde")]
+pub use de::*;
+
+#[test]
+fn durations_and_sizes_are_parsed_with_units() {
+    assert_eq!(parse_duration("500ms"), Some(Duration::from_millis(500)));
+    assert_eq!(parse_duration("1.5 m"), Some(Duration::from_secs(90)));
+    assert_eq!(parse_duration("2H"), Some(Duration::from_secs(7200)));
+    assert_eq!(parse_duration("2"), None);
+    assert_eq!(parse_size("4096"), Some(4096));
+    assert_eq!(parse_size("64KiB"), Some(64 * 1024));
+    assert_eq!(parse_size("10mb"), Some(10_000_000));
+    assert_eq!(parse_size("1.5GB"), None);
+}
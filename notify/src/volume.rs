@@ -0,0 +1,563 @@
+//! Changes between snapshots of BTRFS subvolumes and ZFS datasets
+//!
+//! On huge volumes, watching every file is infeasible, and even scanning them for a
+//! [Snapshot](crate::snapshot::Snapshot) takes long. Copy-on-write filesystems know which parts
+//! changed between two of their own snapshots though. [VolumeChanges] takes a snapshot whenever it
+//! is asked for the changes, and reports the difference to the previous one as events, computed
+//! by `btrfs send --no-data` or `zfs diff`. These need the privileges for taking snapshots, root
+//! usually.
+//!
+//! ```no_run
+//! use notify::volume::{Volume, VolumeChanges};
+//! use std::time::Duration;
+//!
+//! # fn main() -> notify::Result<()> {
+//! let mut changes = VolumeChanges::new(Volume::Zfs {
+//!     dataset: "tank/home".to_string(),
+//! })?;
+//! loop {
+//!     std::thread::sleep(Duration::from_secs(600));
+//!     for event in changes.changes()? {
+//!         println!("{:?}", event);
+//!     }
+//! }
+//! # }
+//! ```
+//!
+//! Changes within an interval are summarized: a file written twice is modified once, a file
+//! created and removed again isn't reported. Directories are reported as modified only if they
+//! weren't changed by creating, removing or renaming their entries.
+
+use crate::event::{
+    CreateKind, DataChange, Event, EventKind, MetadataKind, ModifyKind, RemoveKind, RenameMode,
+};
+use crate::{Error, Result};
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Component, Path, PathBuf};
+use std::process::{self, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Numbers the snapshots of this process, whose names have to be unique on a volume.
+static SNAPSHOTS: AtomicUsize = AtomicUsize::new(0);
+
+/// A volume supporting snapshots
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Volume {
+    /// A BTRFS subvolume, with the directory on the same filesystem to keep its read-only
+    /// snapshots in
+    Btrfs {
+        /// The path of the subvolume, which the events are reported for
+        subvolume: PathBuf,
+        /// The directory of the snapshots
+        snapshots: PathBuf,
+    },
+    /// A mounted ZFS dataset, like `tank/home`, whose events are reported for its mountpoint
+    Zfs {
+        /// The name of the dataset
+        dataset: String,
+    },
+}
+
+impl Volume {
+    /// Take a snapshot named `name`.
+    fn snapshot(&self, name: &str) -> Result<()> {
+        match self {
+            Volume::Btrfs {
+                subvolume,
+                snapshots,
+            } => run(Command::new("btrfs")
+                .args(["subvolume", "snapshot", "-r"])
+                .arg(subvolume)
+                .arg(snapshots.join(name))),
+            Volume::Zfs { dataset } => run(Command::new("zfs")
+                .arg("snapshot")
+                .arg(zfs_snapshot(dataset, name))),
+        }
+        .map(drop)
+    }
+
+    /// Delete the snapshot named `name`.
+    fn delete(&self, name: &str) -> Result<()> {
+        match self {
+            Volume::Btrfs { snapshots, .. } => run(Command::new("btrfs")
+                .args(["subvolume", "delete"])
+                .arg(snapshots.join(name))),
+            Volume::Zfs { dataset } => run(Command::new("zfs")
+                .arg("destroy")
+                .arg(zfs_snapshot(dataset, name))),
+        }
+        .map(drop)
+    }
+}
+
+fn zfs_snapshot(dataset: &str, name: &str) -> String {
+    format!("{}@{}", dataset, name)
+}
+
+/// Run `command`, returning its output, or its error message if it fails.
+fn run(command: &mut Command) -> Result<Vec<u8>> {
+    let output = command.stdin(Stdio::null()).output().map_err(Error::io)?;
+    if !output.status.success() {
+        return Err(Error::generic(&format!(
+            "{:?} failed: {}",
+            command,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(output.stdout)
+}
+
+/// The changes of `volume` between its existing snapshots `from` and `to`, by their names.
+///
+/// For BTRFS, these are the names of the snapshots in the snapshot directory, for ZFS the part
+/// after the `@`.
+pub fn diff(volume: &Volume, from: &str, to: &str) -> Result<Vec<Event>> {
+    match volume {
+        Volume::Btrfs {
+            subvolume,
+            snapshots,
+        } => {
+            let mut send = Command::new("btrfs")
+                .args(["send", "--no-data", "-q", "-p"])
+                .arg(snapshots.join(from))
+                .arg(snapshots.join(to))
+                .stdin(Stdio::null())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()
+                .map_err(Error::io)?;
+            let stream = send.stdout.take().expect("the stdout of send is piped");
+            let dump = run(Command::new("btrfs")
+                .args(["receive", "--dump"])
+                .stdin(Stdio::from(stream)));
+            let sent = send.wait_with_output().map_err(Error::io)?;
+            if !sent.status.success() {
+                return Err(Error::generic(&format!(
+                    "btrfs send failed: {}",
+                    String::from_utf8_lossy(&sent.stderr).trim()
+                )));
+            }
+            Ok(btrfs_events(&dump?, subvolume))
+        }
+        Volume::Zfs { dataset } => {
+            let output = run(Command::new("zfs")
+                .args(["diff", "-FH"])
+                .arg(zfs_snapshot(dataset, from))
+                .arg(zfs_snapshot(dataset, to)))?;
+            Ok(zfs_events(&output))
+        }
+    }
+}
+
+/// Reports the changes of a volume since the last call, by taking snapshots.
+///
+/// Only the latest snapshot is kept, and deleted when this is dropped, unless it is kept with
+/// [VolumeChanges::into_snapshot] to [resume](VolumeChanges::resume) from after a restart.
+#[derive(Debug)]
+pub struct VolumeChanges {
+    volume: Volume,
+    snapshot: Option<String>,
+}
+
+impl VolumeChanges {
+    /// Take the first snapshot of `volume`, to report the changes since.
+    pub fn new(volume: Volume) -> Result<Self> {
+        let snapshot = snapshot_name();
+        volume.snapshot(&snapshot)?;
+        Ok(VolumeChanges {
+            volume,
+            snapshot: Some(snapshot),
+        })
+    }
+
+    /// Report the changes of `volume` since its existing snapshot named `snapshot`, which is
+    /// deleted with the next call of [VolumeChanges::changes] like the ones taken here.
+    pub fn resume(volume: Volume, snapshot: String) -> Self {
+        VolumeChanges {
+            volume,
+            snapshot: Some(snapshot),
+        }
+    }
+
+    /// The volume watched
+    pub fn volume(&self) -> &Volume {
+        &self.volume
+    }
+
+    /// Take a new snapshot and return the changes since the previous one, which is deleted.
+    ///
+    /// If the changes can't be computed, the new snapshot is deleted instead, so that the next
+    /// call reports them. A previous snapshot which can't be deleted is left behind.
+    pub fn changes(&mut self) -> Result<Vec<Event>> {
+        let snapshot = snapshot_name();
+        self.volume.snapshot(&snapshot)?;
+        let previous = self
+            .snapshot
+            .as_deref()
+            .expect("there always is a snapshot");
+        match diff(&self.volume, previous, &snapshot) {
+            Ok(events) => {
+                let previous = self.snapshot.replace(snapshot);
+                let _ = self
+                    .volume
+                    .delete(&previous.expect("there always is a snapshot"));
+                Ok(events)
+            }
+            Err(e) => {
+                let _ = self.volume.delete(&snapshot);
+                Err(e)
+            }
+        }
+    }
+
+    /// Keep the latest snapshot instead of deleting it, returning its name.
+    pub fn into_snapshot(mut self) -> String {
+        self.snapshot.take().expect("there always is a snapshot")
+    }
+}
+
+impl Drop for VolumeChanges {
+    fn drop(&mut self) {
+        if let Some(snapshot) = self.snapshot.take() {
+            let _ = self.volume.delete(&snapshot);
+        }
+    }
+}
+
+fn snapshot_name() -> String {
+    format!(
+        "notify-rs-{}-{}",
+        process::id(),
+        SNAPSHOTS.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
+/// Decode a path escaped with backslashes, and octal codes of up to `digits` digits.
+fn unescape(escaped: &[u8], digits: usize) -> PathBuf {
+    let mut path = Vec::with_capacity(escaped.len());
+    let mut bytes = escaped.iter().copied().peekable();
+    while let Some(byte) = bytes.next() {
+        if byte != b'\\' {
+            path.push(byte);
+            continue;
+        }
+        let mut code = None;
+        for _ in 0..digits {
+            match bytes.peek() {
+                Some(digit @ b'0'..=b'7') => {
+                    code = Some(code.unwrap_or(0u32) * 8 + u32::from(digit - b'0'));
+                    bytes.next();
+                }
+                _ => break,
+            }
+        }
+        if let Some(code) = code {
+            path.push(code as u8);
+            continue;
+        }
+        match bytes.next() {
+            Some(b'a') => path.push(0x07),
+            Some(b'b') => path.push(0x08),
+            Some(b'e') => path.push(0x1b),
+            Some(b'f') => path.push(0x0c),
+            Some(b'n') => path.push(b'\n'),
+            Some(b'r') => path.push(b'\r'),
+            Some(b't') => path.push(b'\t'),
+            Some(b'v') => path.push(0x0b),
+            Some(other) => path.push(other),
+            None => path.push(b'\\'),
+        }
+    }
+    PathBuf::from(OsStr::from_bytes(&path))
+}
+
+/// The events of the output of `zfs diff -FH`.
+fn zfs_events(output: &[u8]) -> Vec<Event> {
+    let mut events = Vec::new();
+    for line in output.split(|&b| b == b'\n') {
+        let columns: Vec<&[u8]> = line.split(|&b| b == b'\t').collect();
+        let (change, file_type, path) = match columns[..] {
+            [change, file_type, path, ..] => (change, file_type, unescape(path, 4)),
+            _ => continue,
+        };
+        let directory = file_type == b"/";
+        let kind = match change {
+            b"+" if directory => EventKind::Create(CreateKind::Folder),
+            b"+" if file_type == b"F" => EventKind::Create(CreateKind::File),
+            b"+" => EventKind::Create(CreateKind::Other),
+            b"-" if directory => EventKind::Remove(RemoveKind::Folder),
+            b"-" if file_type == b"F" => EventKind::Remove(RemoveKind::File),
+            b"-" => EventKind::Remove(RemoveKind::Other),
+            b"M" => EventKind::Modify(ModifyKind::Any),
+            b"R" => match columns.get(3) {
+                Some(to) => {
+                    events.push(
+                        Event::new(EventKind::Modify(ModifyKind::Name(RenameMode::Both)))
+                            .add_path(path)
+                            .add_path(unescape(to, 4)),
+                    );
+                    continue;
+                }
+                None => continue,
+            },
+            _ => continue,
+        };
+        events.push(Event::new(kind).add_path(path));
+    }
+    summarize(events)
+}
+
+/// Where an entry with a temporary name of `btrfs send` came from
+enum Origin {
+    Created(CreateKind),
+    Existing(PathBuf),
+}
+
+/// Whether the file name is one of the temporary `o<inode>-<generation>-<n>` names of entries
+/// `btrfs send` creates or moves out of the way.
+fn is_orphan(path: &Path) -> bool {
+    let name = match path.file_name().and_then(OsStr::to_str) {
+        Some(name) => name,
+        None => return false,
+    };
+    let numbers = match name.strip_prefix('o') {
+        Some(numbers) => numbers,
+        None => return false,
+    };
+    let parts: Vec<&str> = numbers.split('-').collect();
+    parts.len() == 3
+        && parts
+            .iter()
+            .all(|part| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit()))
+}
+
+/// Split a line of `btrfs receive --dump` into its command and fields, at unescaped spaces.
+fn btrfs_tokens(line: &[u8]) -> Vec<&[u8]> {
+    let mut tokens = Vec::new();
+    let mut start = None;
+    let mut escaped = false;
+    for (i, &byte) in line.iter().enumerate() {
+        if escaped {
+            escaped = false;
+        } else if byte == b'\\' {
+            escaped = true;
+            start.get_or_insert(i);
+        } else if byte == b' ' || byte == b'\t' {
+            if let Some(start) = start.take() {
+                tokens.push(&line[start..i]);
+            }
+        } else {
+            start.get_or_insert(i);
+        }
+    }
+    if let Some(start) = start {
+        tokens.push(&line[start..]);
+    }
+    tokens
+}
+
+/// The events of the output of `btrfs receive --dump`, for `subvolume`.
+fn btrfs_events(output: &[u8], subvolume: &Path) -> Vec<Event> {
+    // the paths are relative to the snapshot received, like `./snapshot/dir/file`.
+    let resolve = |path: &[u8]| -> PathBuf {
+        let path = unescape(path, 3);
+        let relative: PathBuf = path
+            .components()
+            .skip_while(|c| *c == Component::CurDir)
+            .skip(1)
+            .collect();
+        subvolume.join(relative)
+    };
+
+    let mut events: Vec<Event> = Vec::new();
+    let mut orphans: HashMap<PathBuf, Origin> = HashMap::new();
+    for line in output.split(|&b| b == b'\n') {
+        let tokens = btrfs_tokens(line);
+        let (command, path) = match tokens[..] {
+            [command, path, ..] => (command, resolve(path)),
+            _ => continue,
+        };
+        let dest = tokens
+            .iter()
+            .find_map(|token| token.strip_prefix(b"dest="))
+            .map(&resolve);
+
+        let kind = match command {
+            b"mkfile" => EventKind::Create(CreateKind::File),
+            b"mkdir" => EventKind::Create(CreateKind::Folder),
+            b"mknod" | b"mkfifo" | b"mksock" | b"symlink" => EventKind::Create(CreateKind::Other),
+            b"link" => EventKind::Create(CreateKind::File),
+            b"unlink" => EventKind::Remove(RemoveKind::File),
+            b"rmdir" => EventKind::Remove(RemoveKind::Folder),
+            b"write" | b"clone" | b"update_extent" => {
+                EventKind::Modify(ModifyKind::Data(DataChange::Content))
+            }
+            b"truncate" => EventKind::Modify(ModifyKind::Data(DataChange::Size)),
+            b"chmod" => EventKind::Modify(ModifyKind::Metadata(MetadataKind::Permissions)),
+            b"chown" => EventKind::Modify(ModifyKind::Metadata(MetadataKind::Ownership)),
+            b"utimes" => EventKind::Modify(ModifyKind::Metadata(MetadataKind::WriteTime)),
+            b"set_xattr" | b"remove_xattr" => {
+                EventKind::Modify(ModifyKind::Metadata(MetadataKind::Extended))
+            }
+            b"rename" => {
+                let dest = match dest {
+                    Some(dest) => dest,
+                    None => continue,
+                };
+                let origin = match orphans.remove(&path) {
+                    Some(origin) => origin,
+                    None => Origin::Existing(path.clone()),
+                };
+                if is_orphan(&dest) {
+                    orphans.insert(dest, origin);
+                    continue;
+                }
+                if is_orphan(&path) {
+                    // the entries created within it so far get their final path too.
+                    for event in &mut events {
+                        for event_path in &mut event.paths {
+                            if let Ok(relative) = event_path.strip_prefix(&path) {
+                                *event_path = dest.join(relative);
+                            }
+                        }
+                    }
+                }
+                events.push(match origin {
+                    Origin::Created(kind) => Event::new(EventKind::Create(kind)).add_path(dest),
+                    Origin::Existing(from) => {
+                        Event::new(EventKind::Modify(ModifyKind::Name(RenameMode::Both)))
+                            .add_path(from)
+                            .add_path(dest)
+                    }
+                });
+                continue;
+            }
+            _ => continue,
+        };
+
+        match kind {
+            EventKind::Create(kind) if is_orphan(&path) => {
+                orphans.insert(path, Origin::Created(kind));
+            }
+            EventKind::Remove(_) if is_orphan(&path) => {
+                if let Some(Origin::Existing(from)) = orphans.remove(&path) {
+                    events.push(Event::new(kind).add_path(from));
+                }
+            }
+            kind => events.push(Event::new(kind).add_path(path)),
+        }
+    }
+    // entries still below a temporary name weren't created under their final one.
+    events.retain(|event| {
+        !event
+            .paths
+            .iter()
+            .any(|path| path.ancestors().any(is_orphan))
+    });
+    summarize(events)
+}
+
+/// Sort `events` by path, dropping duplicates, and the updates of directories and times implied by
+/// other events.
+fn summarize(mut events: Vec<Event>) -> Vec<Event> {
+    let implied = |kind: &EventKind| {
+        matches!(
+            kind,
+            EventKind::Modify(ModifyKind::Any)
+                | EventKind::Modify(ModifyKind::Metadata(MetadataKind::WriteTime))
+        )
+    };
+    let mut changed: HashSet<PathBuf> = HashSet::new();
+    for event in events.iter().filter(|event| !implied(&event.kind)) {
+        for path in &event.paths {
+            changed.insert(path.clone());
+            changed.extend(path.parent().map(Path::to_path_buf));
+        }
+    }
+    let mut seen = HashSet::new();
+    events.retain(|event| {
+        if implied(&event.kind) && event.paths.iter().all(|path| changed.contains(path)) {
+            return false;
+        }
+        seen.insert((event.kind.clone(), event.paths.clone()))
+    });
+    events.sort_by(|a, b| a.paths.cmp(&b.paths));
+    events
+}
+
+#[test]
+fn snapshot_diffs_are_converted_into_events() {
+    fn kinds(events: &[Event]) -> Vec<(EventKind, Vec<&str>)> {
+        events
+            .iter()
+            .map(|event| {
+                let paths = event.paths.iter().map(|p| p.to_str().unwrap()).collect();
+                (event.kind.clone(), paths)
+            })
+            .collect()
+    }
+
+    let zfs = b"M\t/\t/tank/home/\n\
+                +\tF\t/tank/home/new\\0040file\n\
+                M\tF\t/tank/home/log\n\
+                R\tF\t/tank/home/a\t/tank/home/b\n\
+                -\t/\t/tank/home/old\n";
+    assert_eq!(
+        kinds(&zfs_events(zfs)),
+        [
+            (
+                EventKind::Modify(ModifyKind::Name(RenameMode::Both)),
+                vec!["/tank/home/a", "/tank/home/b"]
+            ),
+            (EventKind::Modify(ModifyKind::Any), vec!["/tank/home/log"]),
+            (
+                EventKind::Create(CreateKind::File),
+                vec!["/tank/home/new file"]
+            ),
+            (
+                EventKind::Remove(RemoveKind::Folder),
+                vec!["/tank/home/old"]
+            ),
+        ]
+    );
+
+    // a new directory with a file, a renamed, a written and a removed file
+    let btrfs = b"snapshot        ./s2                            uuid=1 transid=9 parent_uuid=2 parent_transid=8\n\
+                  utimes          ./s2/                           atime=a mtime=b ctime=c\n\
+                  mkdir           ./s2/o257-9-0\n\
+                  mkfile          ./s2/o257-9-0/o258-9-0\n\
+                  rename          ./s2/o257-9-0/o258-9-0          dest=./s2/o257-9-0/in\\ dir\n\
+                  rename          ./s2/o257-9-0                   dest=./s2/dir\n\
+                  rename          ./s2/a                          dest=./s2/b\n\
+                  update_extent   ./s2/log                        offset=0 len=4096\n\
+                  update_extent   ./s2/log                        offset=8192 len=4096\n\
+                  utimes          ./s2/log                        atime=a mtime=b ctime=c\n\
+                  chmod           ./s2/bin                        mode=755\n\
+                  rename          ./s2/old                        dest=./s2/o259-9-0\n\
+                  unlink          ./s2/o259-9-0\n";
+    assert_eq!(
+        kinds(&btrfs_events(btrfs, Path::new("/home"))),
+        [
+            (
+                EventKind::Modify(ModifyKind::Name(RenameMode::Both)),
+                vec!["/home/a", "/home/b"]
+            ),
+            (
+                EventKind::Modify(ModifyKind::Metadata(MetadataKind::Permissions)),
+                vec!["/home/bin"]
+            ),
+            (EventKind::Create(CreateKind::Folder), vec!["/home/dir"]),
+            (
+                EventKind::Create(CreateKind::File),
+                vec!["/home/dir/in dir"]
+            ),
+            (
+                EventKind::Modify(ModifyKind::Data(DataChange::Content)),
+                vec!["/home/log"]
+            ),
+            (EventKind::Remove(RemoveKind::File), vec!["/home/old"]),
+        ]
+    );
+}
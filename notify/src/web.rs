@@ -0,0 +1,526 @@
+//! Watcher implementation for the File System Access API of web browsers
+//!
+//! Browsers don't report changes to the files behind a `FileSystemDirectoryHandle`, so the
+//! [FileSystemAccessWatcher] polls the watched trees like the [crate::PollWatcher], comparing
+//! the size and modification time of their files.
+//!
+//! Handles can't be opened by path, so the watcher works with virtual paths: a handle is added
+//! under a path of the application's choice with
+//! [FileSystemAccessWatcher::add_directory], and the paths below it can then be watched.
+
+use crate::delivery::Delivery;
+use crate::event::{
+    CreateKind, DataChange, Event, EventKind, Flag, ModifyKind, Precision, RemoveKind,
+};
+use crate::registry::{InstallWatches, WatchRegistry};
+use crate::{
+    Config, Error, EventHandler, RecursiveMode, Result, SuppressGuard, Watcher, WatcherKind,
+};
+use js_sys::{Function, IteratorNext, Object, Promise, Reflect};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt,
+    path::{Path, PathBuf},
+    rc::{Rc, Weak},
+    time::Duration,
+};
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::{spawn_local, JsFuture};
+use web_sys::FileSystemHandleKind;
+use web_sys::{File, FileSystemDirectoryHandle, FileSystemFileHandle, FileSystemHandle};
+
+/// Watcher implementation polling `FileSystemDirectoryHandle`s, for `wasm32-unknown-unknown`
+///
+/// ```no_run
+/// # use std::path::Path;
+/// use notify::web::{self, FileSystemAccessWatcher};
+/// use notify::{Config, RecursiveMode, Watcher};
+///
+/// # async fn example(handle: web_sys::FileSystemDirectoryHandle) -> notify::Result<()> {
+/// // for example from `window.showDirectoryPicker()`
+/// web::request_permission(&handle).await;
+///
+/// let mut watcher = FileSystemAccessWatcher::new(|event| log(event), Config::default())?;
+/// watcher.add_directory(Path::new("/project"), handle);
+/// watcher.watch(Path::new("/project/src"), RecursiveMode::Recursive)?;
+/// # Ok(())
+/// # }
+/// # fn log(_: notify::Result<notify::Event>) {}
+/// ```
+///
+/// The watched trees are scanned on the JavaScript event loop, once per
+/// [poll interval](Config::with_poll_interval). A watch whose directory can't be read, because
+/// it doesn't exist or read permission wasn't granted, reports an error once and is retried on
+/// every scan. Only directories can be watched.
+pub struct FileSystemAccessWatcher {
+    state: Rc<RefCell<State>>,
+}
+
+impl fmt::Debug for FileSystemAccessWatcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.state.borrow();
+        f.debug_struct("FileSystemAccessWatcher")
+            .field("directories", &state.directories.keys().collect::<Vec<_>>())
+            .field("watches", &state.watches.keys().collect::<Vec<_>>())
+            .finish_non_exhaustive()
+    }
+}
+
+struct State {
+    delivery: Delivery,
+    interval: Duration,
+    initial_scan: bool,
+    /// Directory handles by the virtual path they were added under
+    directories: HashMap<PathBuf, FileSystemDirectoryHandle>,
+    watches: HashMap<PathBuf, Watch>,
+    registry: WatchRegistry,
+}
+
+struct Watch {
+    recursive_mode: RecursiveMode,
+    /// The entries found by the last scan, none before the first one
+    entries: Option<HashMap<PathBuf, Entry>>,
+    /// Whether the failure to scan the watch was reported already
+    failed: bool,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+struct Entry {
+    is_dir: bool,
+    size: f64,
+    modified: f64,
+}
+
+impl State {
+    /// The handle added for `path` or one of its ancestors, and the names leading from it to
+    /// `path`.
+    fn resolve(&self, path: &Path) -> Option<(FileSystemDirectoryHandle, Vec<String>)> {
+        let (root, handle) = self
+            .directories
+            .iter()
+            .filter(|(root, _)| path.starts_with(root))
+            .max_by_key(|(root, _)| root.components().count())?;
+        let names = path
+            .strip_prefix(root)
+            .ok()?
+            .iter()
+            .map(|name| name.to_string_lossy().into_owned())
+            .collect();
+        Some((handle.clone(), names))
+    }
+
+    /// Report the changes between the last scan of the watch of `path` and `entries`.
+    fn update(&mut self, path: &Path, entries: HashMap<PathBuf, Entry>) {
+        let watch = match self.watches.get_mut(path) {
+            Some(watch) => watch,
+            None => return,
+        };
+        watch.failed = false;
+        // the entries found by the first scan are only reported for the initial scan
+        let (old, initial) = match watch.entries.replace(entries) {
+            Some(old) => (old, false),
+            None if self.initial_scan => (HashMap::new(), true),
+            None => return,
+        };
+        let new = watch.entries.as_ref().expect("set above");
+
+        for (path, kind) in changes(&old, new) {
+            let mut event = Event::new(kind)
+                .add_path(path)
+                .set_precision(Precision::Inferred);
+            if initial {
+                event = event.set_flag(Flag::InitialScan);
+            }
+            self.delivery.handle_event(Ok(event));
+        }
+    }
+
+    /// Report that the watch of `path` couldn't be scanned, once until it can be again.
+    fn fail(&mut self, path: &Path, error: Error) {
+        if let Some(watch) = self.watches.get_mut(path) {
+            if !watch.failed {
+                watch.failed = true;
+                self.delivery
+                    .handle_event(Err(error.add_path(path.to_owned())));
+            }
+        }
+    }
+}
+
+/// The events telling the changes from the `old` to the `new` entries of a watch, by path.
+fn changes(
+    old: &HashMap<PathBuf, Entry>,
+    new: &HashMap<PathBuf, Entry>,
+) -> Vec<(PathBuf, EventKind)> {
+    let mut events = Vec::new();
+    for (path, entry) in new {
+        let create = match entry.is_dir {
+            true => EventKind::Create(CreateKind::Folder),
+            false => EventKind::Create(CreateKind::File),
+        };
+        match old.get(path) {
+            None => events.push((path.clone(), create)),
+            Some(before) if before.is_dir != entry.is_dir => {
+                events.push((path.clone(), EventKind::Remove(RemoveKind::Any)));
+                events.push((path.clone(), create));
+            }
+            Some(before) if before != entry => events.push((
+                path.clone(),
+                EventKind::Modify(ModifyKind::Data(DataChange::Any)),
+            )),
+            Some(_) => {}
+        }
+    }
+    for (path, entry) in old {
+        if !new.contains_key(path) {
+            let kind = match entry.is_dir {
+                true => RemoveKind::Folder,
+                false => RemoveKind::File,
+            };
+            events.push((path.clone(), EventKind::Remove(kind)));
+        }
+    }
+    events.sort_by(|a, b| a.0.cmp(&b.0));
+    events
+}
+
+/// Scan all watches once.
+async fn poll(state: &Rc<RefCell<State>>) {
+    let watches: Vec<(PathBuf, RecursiveMode)> = state
+        .borrow()
+        .watches
+        .iter()
+        .map(|(path, watch)| (path.clone(), watch.recursive_mode))
+        .collect();
+
+    for (path, recursive_mode) in watches {
+        let resolved = state.borrow().resolve(&path);
+        let (handle, names) = match resolved {
+            Some(resolved) => resolved,
+            None => {
+                state.borrow_mut().fail(&path, Error::path_not_found());
+                continue;
+            }
+        };
+        if !permission(&handle, "queryPermission").await {
+            let error = Error::generic("read permission wasn't granted");
+            state.borrow_mut().fail(&path, error);
+            continue;
+        }
+        match scan(handle, names, &path, recursive_mode).await {
+            Ok(entries) => state.borrow_mut().update(&path, entries),
+            Err(e) => state.borrow_mut().fail(&path, js_error(e)),
+        }
+    }
+}
+
+/// The entries below the directory reached from `handle` through `names`, by their paths below
+/// `path`.
+async fn scan(
+    handle: FileSystemDirectoryHandle,
+    names: Vec<String>,
+    path: &Path,
+    recursive_mode: RecursiveMode,
+) -> std::result::Result<HashMap<PathBuf, Entry>, JsValue> {
+    let mut dir = handle;
+    for name in names {
+        dir = JsFuture::from(dir.get_directory_handle(&name))
+            .await?
+            .unchecked_into();
+    }
+
+    let mut entries = HashMap::new();
+    let mut pending = vec![(dir, path.to_owned())];
+    while let Some((dir, dir_path)) = pending.pop() {
+        let children = dir.values();
+        loop {
+            let next: IteratorNext = JsFuture::from(children.next()?).await?.unchecked_into();
+            if next.done() {
+                break;
+            }
+            let child: FileSystemHandle = next.value().unchecked_into();
+            let child_path = dir_path.join(child.name());
+            match child.kind() {
+                FileSystemHandleKind::Directory => {
+                    let entry = Entry {
+                        is_dir: true,
+                        size: 0.0,
+                        modified: 0.0,
+                    };
+                    entries.insert(child_path.clone(), entry);
+                    if recursive_mode.is_recursive() {
+                        pending.push((child.unchecked_into(), child_path));
+                    }
+                }
+                FileSystemHandleKind::File => {
+                    let file = child.unchecked_ref::<FileSystemFileHandle>().get_file();
+                    let file: File = JsFuture::from(file).await?.unchecked_into();
+                    let entry = Entry {
+                        is_dir: false,
+                        size: file.size(),
+                        modified: file.last_modified(),
+                    };
+                    entries.insert(child_path, entry);
+                }
+                _ => {}
+            }
+        }
+    }
+    Ok(entries)
+}
+
+/// Ask for read permission on `handle`, prompting the user if necessary.
+///
+/// Browsers only prompt in response to a user action like a click. Returns whether permission
+/// is granted, which it always is for handles of the origin private file system.
+pub async fn request_permission(handle: &FileSystemHandle) -> bool {
+    permission(handle, "requestPermission").await
+}
+
+/// Call the permission `method` of `handle` for read access.
+async fn permission(handle: &FileSystemHandle, method: &str) -> bool {
+    // the permission methods aren't available everywhere, like for the origin private file
+    // system, whose handles are always readable.
+    let function = match Reflect::get(handle, &JsValue::from_str(method)) {
+        Ok(function) if function.is_function() => function.unchecked_into::<Function>(),
+        _ => return true,
+    };
+    let descriptor = Object::new();
+    let _ = Reflect::set(&descriptor, &"mode".into(), &"read".into());
+    let promise = match function.call1(handle, &descriptor) {
+        Ok(promise) => Promise::resolve(&promise),
+        Err(_) => return false,
+    };
+    match JsFuture::from(promise).await {
+        Ok(state) => state.as_string().as_deref() == Some("granted"),
+        Err(_) => false,
+    }
+}
+
+async fn sleep(duration: Duration) {
+    let promise = Promise::new(&mut |resolve, _reject| {
+        // both windows and workers have a global setTimeout
+        let global = js_sys::global();
+        if let Ok(set_timeout) = Reflect::get(&global, &"setTimeout".into()) {
+            if let Some(set_timeout) = set_timeout.dyn_ref::<Function>() {
+                let millis = JsValue::from_f64(duration.as_millis() as f64);
+                if set_timeout.call2(&global, &resolve, &millis).is_ok() {
+                    return;
+                }
+            }
+        }
+        let _ = resolve.call0(&JsValue::UNDEFINED);
+    });
+    let _ = JsFuture::from(promise).await;
+}
+
+fn js_error(value: JsValue) -> Error {
+    let message = match value.dyn_ref::<js_sys::Error>() {
+        Some(error) => String::from(error.message()),
+        None => value.as_string().unwrap_or_else(|| format!("{:?}", value)),
+    };
+    Error::generic(&message)
+}
+
+impl FileSystemAccessWatcher {
+    /// Make the tree of `handle` available under `path`, replacing the handle added there
+    /// before.
+    ///
+    /// Watches of `path` and the paths below it read from `handle` from the next scan on.
+    pub fn add_directory(&mut self, path: &Path, handle: FileSystemDirectoryHandle) {
+        self.state
+            .borrow_mut()
+            .directories
+            .insert(path.to_owned(), handle);
+    }
+
+    /// Remove the handle added under `path`.
+    ///
+    /// Watches below it report an error from the next scan on, unless another handle covers
+    /// them.
+    pub fn remove_directory(&mut self, path: &Path) {
+        self.state.borrow_mut().directories.remove(path);
+    }
+}
+
+impl InstallWatches for State {
+    fn install(&mut self, path: &Path, recursive_mode: RecursiveMode) -> Result<()> {
+        if self.resolve(path).is_none() {
+            return Err(Error::path_not_found().add_path(path.to_owned()));
+        }
+        self.watches.insert(
+            path.to_owned(),
+            Watch {
+                recursive_mode,
+                entries: None,
+                failed: false,
+            },
+        );
+        Ok(())
+    }
+
+    fn uninstall(&mut self, path: &Path) -> Result<()> {
+        match self.watches.remove(path) {
+            Some(_) => Ok(()),
+            None => Err(Error::watch_not_found().add_path(path.to_owned())),
+        }
+    }
+}
+
+impl Watcher for FileSystemAccessWatcher {
+    fn new<F: EventHandler>(event_handler: F, config: Config) -> Result<Self> {
+        let state = Rc::new(RefCell::new(State {
+            delivery: Delivery::new(event_handler, &config),
+            interval: config.poll_interval(),
+            initial_scan: config.initial_scan(),
+            directories: HashMap::new(),
+            watches: HashMap::new(),
+            registry: WatchRegistry::new(config.watch_overlap()),
+        }));
+
+        // the loop only holds on to the state while scanning, so dropping the watcher stops it
+        let weak: Weak<RefCell<State>> = Rc::downgrade(&state);
+        spawn_local(async move {
+            while let Some(state) = weak.upgrade() {
+                poll(&state).await;
+                let interval = state.borrow().interval;
+                drop(state);
+                sleep(interval).await;
+            }
+        });
+
+        Ok(Self { state })
+    }
+
+    fn watch(&mut self, path: &Path, recursive_mode: RecursiveMode) -> Result<()> {
+        let mut state = self.state.borrow_mut();
+        let plan = state.registry.plan_watch(path, recursive_mode)?;
+        plan.apply(&mut *state, |state| state.install(path, recursive_mode))?;
+        state.registry.insert(path, recursive_mode);
+        Ok(())
+    }
+
+    fn watch_with_token(
+        &mut self,
+        path: &Path,
+        recursive_mode: RecursiveMode,
+        token: u64,
+    ) -> Result<()> {
+        self.watch(path, recursive_mode)?;
+        // the watch is scanned on the event loop only, after the token is in place.
+        self.state.borrow().delivery.tokens().insert(path, token);
+        Ok(())
+    }
+
+    fn unwatch(&mut self, path: &Path) -> Result<()> {
+        let mut state = self.state.borrow_mut();
+        let plan = state.registry.plan_unwatch(path);
+        plan.apply(&mut *state, |state| state.uninstall(path))?;
+        state.delivery.tokens().remove(path);
+        state.registry.remove(path);
+        Ok(())
+    }
+
+    fn configure(&mut self, config: Config) -> Result<bool> {
+        let mut state = self.state.borrow_mut();
+        state.delivery.options().configure(&config);
+        state.interval = config.poll_interval();
+        state.initial_scan = config.initial_scan();
+        Ok(true)
+    }
+
+    fn suppress(&self, path: &Path) -> Result<SuppressGuard> {
+        Ok(self.state.borrow().delivery.suppressions().guard(path))
+    }
+
+    fn is_watched(&self, path: &Path) -> bool {
+        self.state.borrow().registry.is_watched(path)
+    }
+
+    fn kind() -> WatcherKind {
+        WatcherKind::FileSystemAccessWatcher
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    fn file(size: f64, modified: f64) -> Entry {
+        Entry {
+            is_dir: false,
+            size,
+            modified,
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn changes_map_entries_to_events() {
+        let dir = Entry {
+            is_dir: true,
+            size: 0.0,
+            modified: 0.0,
+        };
+        let root = Path::new("/project");
+        let old: HashMap<_, _> = [
+            (root.join("kept"), file(1.0, 1.0)),
+            (root.join("modified"), file(1.0, 1.0)),
+            (root.join("removed"), dir),
+            (root.join("replaced"), file(1.0, 1.0)),
+        ]
+        .into_iter()
+        .collect();
+        let new: HashMap<_, _> = [
+            (root.join("created"), file(1.0, 1.0)),
+            (root.join("kept"), file(1.0, 1.0)),
+            (root.join("modified"), file(1.0, 2.0)),
+            (root.join("replaced"), dir),
+        ]
+        .into_iter()
+        .collect();
+
+        assert_eq!(
+            changes(&old, &new),
+            vec![
+                (root.join("created"), EventKind::Create(CreateKind::File)),
+                (
+                    root.join("modified"),
+                    EventKind::Modify(ModifyKind::Data(DataChange::Any))
+                ),
+                (root.join("removed"), EventKind::Remove(RemoveKind::Folder)),
+                (root.join("replaced"), EventKind::Remove(RemoveKind::Any)),
+                (root.join("replaced"), EventKind::Create(CreateKind::Folder)),
+            ]
+        );
+        assert!(changes(&new, &new).is_empty());
+    }
+
+    #[wasm_bindgen_test]
+    fn failed_watches_leave_no_token_behind() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let mut watcher = FileSystemAccessWatcher::new(tx, Config::default()).unwrap();
+        let root = Path::new("/project");
+        // no directory handle was added for it.
+        assert!(watcher
+            .watch_with_token(root, RecursiveMode::Recursive, 7)
+            .is_err());
+        assert!(!watcher.is_watched(root));
+
+        // as if watched without a token, with one scan done.
+        let mut state = watcher.state.borrow_mut();
+        let watch = Watch {
+            recursive_mode: RecursiveMode::Recursive,
+            entries: Some(HashMap::new()),
+            failed: false,
+        };
+        state.watches.insert(root.to_owned(), watch);
+        let entries = [(root.join("file"), file(1.0, 1.0))].into_iter().collect();
+        state.update(root, entries);
+
+        let event = rx.try_recv().unwrap().unwrap();
+        assert_eq!(event.paths, [root.join("file")]);
+        assert_eq!(event.watch_token(), None);
+    }
+}
@@ -3,11 +3,19 @@
 //!
 //! For more information see the [ReadDirectoryChangesW reference][ref].
 //!
+//! The directory handles of all watches of a watcher are associated with one I/O completion
+//! port, which is serviced by a single thread no matter how many directories are watched.
+//!
 //! [ref]: https://msdn.microsoft.com/en-us/library/windows/desktop/aa363950(v=vs.85).aspx
 
-use crate::{bounded, unbounded, BoundSender, Config, Receiver, Sender};
+use crate::audit::{Audit, Resource};
+use crate::delivery::{Delivery, DeliveryOptions, WatchTokens};
+use crate::registry::{InstallWatches, WatchRegistry};
+use crate::scheduling::Scheduling;
+use crate::suppress::{SuppressGuard, Suppressions};
+use crate::{bounded, snapshot, unbounded, BoundSender, Config, Receiver, Sender};
 use crate::{event::*, WatcherKind};
-use crate::{Error, EventHandler, RecursiveMode, Result, Watcher};
+use crate::{Error, EventHandler, RecursiveMode, Result, SymlinkMode, Watcher};
 use std::collections::HashMap;
 use std::env;
 use std::ffi::OsString;
@@ -20,7 +28,8 @@ use std::slice;
 use std::sync::{Arc, Mutex};
 use std::thread;
 use windows_sys::Win32::Foundation::{
-    CloseHandle, ERROR_OPERATION_ABORTED, HANDLE, INVALID_HANDLE_VALUE, WAIT_OBJECT_0,
+    CloseHandle, GetLastError, ERROR_NOTIFY_ENUM_DIR, ERROR_OPERATION_ABORTED, HANDLE,
+    INVALID_HANDLE_VALUE,
 };
 use windows_sys::Win32::Storage::FileSystem::{
     CreateFileW, ReadDirectoryChangesW, FILE_ACTION_ADDED, FILE_ACTION_MODIFIED,
@@ -31,34 +40,93 @@ use windows_sys::Win32::Storage::FileSystem::{
     FILE_NOTIFY_CHANGE_SIZE, FILE_NOTIFY_INFORMATION, FILE_SHARE_DELETE, FILE_SHARE_READ,
     FILE_SHARE_WRITE, OPEN_EXISTING,
 };
-use windows_sys::Win32::System::Threading::{
-    CreateSemaphoreW, ReleaseSemaphore, WaitForSingleObjectEx,
-};
 use windows_sys::Win32::System::WindowsProgramming::INFINITE;
-use windows_sys::Win32::System::IO::{CancelIo, OVERLAPPED};
+use windows_sys::Win32::System::IO::{
+    CancelIo, CreateIoCompletionPort, GetQueuedCompletionStatus, PostQueuedCompletionStatus,
+    OVERLAPPED,
+};
 
 const BUF_SIZE: u32 = 16384;
 
+/// Completion key of the packets the watcher posts to wake up the server, reads use larger keys.
+const WAKEUP_KEY: usize = 0;
+
+const CHANGE_FLAGS: u32 = FILE_NOTIFY_CHANGE_FILE_NAME
+    | FILE_NOTIFY_CHANGE_DIR_NAME
+    | FILE_NOTIFY_CHANGE_ATTRIBUTES
+    | FILE_NOTIFY_CHANGE_SIZE
+    | FILE_NOTIFY_CHANGE_LAST_WRITE
+    | FILE_NOTIFY_CHANGE_CREATION;
+
 #[derive(Clone)]
 struct ReadData {
     dir: PathBuf,          // directory that is being watched
     file: Option<PathBuf>, // if a file is being watched, this is its full path
-    complete_sem: HANDLE,
+    link: Option<PathBuf>, // if `file` is the target of a watched link, its events are reported for the link
     is_recursive: bool,
+    flags: u32, // the changes reported, FILE_NOTIFY_CHANGE_SECURITY alone for a security read
 }
 
+/// A read of a directory handle, kept by the server until its last completion was dequeued.
+///
+/// The overlapped structure comes first, which keeps the buffer DWORD aligned as
+/// `FILE_NOTIFY_INFORMATION` requires.
+#[repr(C)]
 struct ReadDirectoryRequest {
-    event_handler: Arc<Mutex<dyn EventHandler>>,
+    overlapped: OVERLAPPED,
     buffer: [u8; BUF_SIZE as usize],
     handle: HANDLE,
     data: ReadData,
+    /// The handle was closed, the request is freed when its cancellation completes
+    closing: bool,
+}
+
+/// The I/O completion port of a watcher, shared by the watcher and its thread.
+///
+/// It is closed once both dropped it, so the watcher may still wake up a thread which stopped.
+#[derive(Debug)]
+struct CompletionPort {
+    handle: HANDLE,
+    audit: Audit,
+}
+
+impl CompletionPort {
+    fn new(audit: Audit) -> Option<Self> {
+        let handle = unsafe { CreateIoCompletionPort(INVALID_HANDLE_VALUE, 0, 0, 1) };
+        if handle == 0 {
+            return None;
+        }
+        audit.acquire(Resource::Handle, handle);
+        Some(Self { handle, audit })
+    }
+
+    /// Report the completions of `handle` with `key`.
+    fn associate(&self, handle: HANDLE, key: usize) -> bool {
+        unsafe { CreateIoCompletionPort(handle, self.handle, key, 0) != 0 }
+    }
+
+    /// Break the server out of its wait for completions.
+    fn wake(&self) {
+        unsafe {
+            PostQueuedCompletionStatus(self.handle, 0, WAKEUP_KEY, ptr::null());
+        }
+    }
+}
+
+impl Drop for CompletionPort {
+    fn drop(&mut self) {
+        unsafe {
+            CloseHandle(self.handle);
+        }
+        self.audit.release(Resource::Handle, self.handle);
+    }
 }
 
 enum Action {
-    Watch(PathBuf, RecursiveMode),
+    Watch(PathBuf, RecursiveMode, SymlinkMode),
     Unwatch(PathBuf),
     Stop,
-    Configure(Config, BoundSender<Result<bool>>),
+    Configure(Box<Config>, BoundSender<Result<bool>>),
 }
 
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
@@ -68,8 +136,9 @@ pub enum MetaEvent {
 }
 
 struct WatchState {
-    dir_handle: HANDLE,
-    complete_sem: HANDLE,
+    /// The completion key and directory handle of each read, the second one reporting security
+    /// changes if they are read separately
+    reads: Vec<(usize, HANDLE)>,
 }
 
 struct ReadDirectoryChangesServer {
@@ -78,7 +147,12 @@ struct ReadDirectoryChangesServer {
     meta_tx: Sender<MetaEvent>,
     cmd_tx: Sender<Result<PathBuf>>,
     watches: HashMap<PathBuf, WatchState>,
-    wakeup_sem: HANDLE,
+    requests: HashMap<usize, Box<ReadDirectoryRequest>>,
+    next_key: usize,
+    port: Arc<CompletionPort>,
+    initial_scan: bool,
+    security_changes: bool,
+    audit: Audit,
 }
 
 impl ReadDirectoryChangesServer {
@@ -86,26 +160,38 @@ impl ReadDirectoryChangesServer {
         event_handler: Arc<Mutex<dyn EventHandler>>,
         meta_tx: Sender<MetaEvent>,
         cmd_tx: Sender<Result<PathBuf>>,
-        wakeup_sem: HANDLE,
-    ) -> Sender<Action> {
+        port: Arc<CompletionPort>,
+        config: &Config,
+        audit: Audit,
+    ) -> Result<Sender<Action>> {
+        let initial_scan = config.initial_scan();
+        let security_changes = config.security_changes();
+        let scheduling = Scheduling::new(config);
         let (action_tx, action_rx) = unbounded();
-        // it is, in fact, ok to send the semaphore across threads
-        let sem_temp = wakeup_sem as u64;
-        let _ = thread::Builder::new()
+        thread::Builder::new()
             .name("notify-rs windows loop".to_string())
             .spawn(move || {
-                let wakeup_sem = sem_temp as HANDLE;
+                let thread = thread::current().id();
+                audit.acquire(Resource::Thread, thread);
+                let _ = scheduling.apply();
                 let server = ReadDirectoryChangesServer {
                     rx: action_rx,
                     event_handler,
                     meta_tx,
                     cmd_tx,
                     watches: HashMap::new(),
-                    wakeup_sem,
+                    requests: HashMap::new(),
+                    next_key: WAKEUP_KEY + 1,
+                    port,
+                    initial_scan,
+                    security_changes,
+                    audit: audit.clone(),
                 };
                 server.run();
-            });
-        action_tx
+                audit.release(Resource::Thread, thread);
+            })
+            .map_err(Error::io)?;
+        Ok(action_tx)
     }
 
     fn run(mut self) {
@@ -115,16 +201,25 @@ impl ReadDirectoryChangesServer {
 
             while let Ok(action) = self.rx.try_recv() {
                 match action {
-                    Action::Watch(path, recursive_mode) => {
-                        let res = self.add_watch(path, recursive_mode.is_recursive());
+                    Action::Watch(path, recursive_mode, symlink_mode) => {
+                        let is_link = symlink_mode == SymlinkMode::Link && is_symlink(&path);
+                        let res = self.add_watch(
+                            path.clone(),
+                            recursive_mode.is_recursive(),
+                            symlink_mode,
+                        );
+                        if res.is_ok() && self.initial_scan {
+                            if is_link {
+                                self.emit_event(snapshot::initial_scan_link_event(&path));
+                            } else {
+                                self.emit_initial_scan(&path, recursive_mode);
+                            }
+                        }
                         let _ = self.cmd_tx.send(res);
                     }
                     Action::Unwatch(path) => self.remove_watch(path),
                     Action::Stop => {
                         stopped = true;
-                        for ws in self.watches.values() {
-                            stop_watch(ws, &self.meta_tx);
-                        }
                         break;
                     }
                     Action::Configure(config, tx) => {
@@ -133,212 +228,324 @@ impl ReadDirectoryChangesServer {
                 }
             }
 
-            if stopped {
+            if stopped || !self.wait() {
                 break;
             }
+        }
 
-            unsafe {
-                // wait with alertable flag so that the completion routine fires
-                let waitres = WaitForSingleObjectEx(self.wakeup_sem, 100, 1);
-                if waitres == WAIT_OBJECT_0 {
-                    let _ = self.meta_tx.send(MetaEvent::WatcherAwakened);
-                }
+        for ws in mem::take(&mut self.watches).into_values() {
+            self.stop_watch(ws);
+        }
+        // the buffers of cancelled reads are in use until their completion is dequeued
+        while !self.requests.is_empty() && self.wait() {}
+        // the port failed, better leak the buffers than free them while the kernel writes to them
+        for (_, request) in self.requests.drain() {
+            mem::forget(request);
+        }
+    }
+
+    /// Wait for the next completion and handle it, returns false if the port failed.
+    fn wait(&mut self) -> bool {
+        let mut bytes_written = 0;
+        let mut key = 0;
+        let mut overlapped = ptr::null_mut();
+        let ok = unsafe {
+            GetQueuedCompletionStatus(
+                self.port.handle,
+                &mut bytes_written,
+                &mut key,
+                &mut overlapped,
+                INFINITE,
+            )
+        } != 0;
+        if overlapped.is_null() {
+            // a wakeup posted by the watcher, unless the wait itself failed
+            if ok {
+                let _ = self.meta_tx.send(MetaEvent::WatcherAwakened);
             }
+            return ok;
         }
+        let error_code = if ok { 0 } else { unsafe { GetLastError() } };
+        self.handle_completion(key, error_code, bytes_written);
+        true
+    }
 
-        // we have to clean this up, since the watcher may be long gone
-        unsafe {
-            CloseHandle(self.wakeup_sem);
+    fn handle_completion(&mut self, key: usize, error_code: u32, bytes_written: u32) {
+        let request = match self.requests.get_mut(&key) {
+            Some(request) => request,
+            None => return,
+        };
+        if request.closing || error_code == ERROR_OPERATION_ABORTED {
+            // received when dir is unwatched or watcher is shutdown, the buffer is ours again
+            self.requests.remove(&key);
+            return;
+        }
+
+        let events = read_events(request, error_code, bytes_written);
+        // Get the next request queued up as soon as possible
+        if !start_read(request) {
+            self.requests.remove(&key);
+        }
+
+        if let Ok(mut guard) = self.event_handler.lock() {
+            for event in events {
+                guard.handle_event(Ok(event));
+            }
         }
     }
 
-    fn add_watch(&mut self, path: PathBuf, is_recursive: bool) -> Result<PathBuf> {
-        // path must exist and be either a file or directory
-        if !path.is_dir() && !path.is_file() {
+    fn emit_initial_scan(&self, path: &Path, recursive_mode: RecursiveMode) {
+        if let Ok(mut guard) = self.event_handler.lock() {
+            for event in snapshot::initial_scan_events(path, recursive_mode) {
+                guard.handle_event(Ok(event));
+            }
+        }
+    }
+
+    fn emit_event(&self, event: Event) {
+        if let Ok(mut guard) = self.event_handler.lock() {
+            guard.handle_event(Ok(event));
+        }
+    }
+
+    fn add_watch(
+        &mut self,
+        path: PathBuf,
+        is_recursive: bool,
+        symlink_mode: SymlinkMode,
+    ) -> Result<PathBuf> {
+        let is_link = is_symlink(&path);
+        // path must exist and be either a file or directory, or a link watched itself
+        let watch_link = is_link && symlink_mode == SymlinkMode::Link;
+        if !path.is_dir() && !path.is_file() && !watch_link {
             return Err(
                 Error::generic("Input watch path is neither a file nor a directory.")
                     .add_path(path),
             );
         }
 
+        // the directory handle follows links to directories, the parent directory of a link to a
+        // file reports the link itself. Links to files are followed here.
+        let target = if is_link && !watch_link && path.is_file() {
+            Some(std::fs::canonicalize(&path).map_err(|e| Error::io(e).add_path(path.clone()))?)
+        } else {
+            None
+        };
         let (watching_file, dir_target) = {
-            if path.is_dir() {
+            if path.is_dir() && !watch_link {
                 (false, path.clone())
             } else {
                 // emulate file watching by watching the parent directory
-                (true, path.parent().unwrap().to_path_buf())
+                let file = target.as_ref().unwrap_or(&path);
+                (true, file.parent().unwrap().to_path_buf())
             }
         };
 
-        let encoded_path: Vec<u16> = dir_target
-            .as_os_str()
-            .encode_wide()
-            .chain(Some(0))
-            .collect();
-        let handle;
-        unsafe {
-            handle = CreateFileW(
-                encoded_path.as_ptr(),
-                FILE_LIST_DIRECTORY,
-                FILE_SHARE_READ | FILE_SHARE_DELETE | FILE_SHARE_WRITE,
-                ptr::null_mut(),
-                OPEN_EXISTING,
-                FILE_FLAG_BACKUP_SEMANTICS | FILE_FLAG_OVERLAPPED,
-                0,
-            );
-
-            if handle == INVALID_HANDLE_VALUE {
-                return Err(if watching_file {
-                    Error::generic(
-                        "You attempted to watch a single file, but parent \
-                         directory could not be opened.",
-                    )
-                    .add_path(path)
-                } else {
-                    // TODO: Call GetLastError for better error info?
-                    Error::path_not_found().add_path(path)
-                });
+        let (key, handle) = match self.open_dir(&dir_target) {
+            Some(Ok(opened)) => opened,
+            Some(Err(())) => {
+                return Err(
+                    Error::generic("Failed to associate watch with completion port.")
+                        .add_path(path),
+                )
             }
-        }
+            None if watching_file => {
+                return Err(Error::generic(
+                    "You attempted to watch a single file, but parent \
+                     directory could not be opened.",
+                )
+                .add_path(path))
+            }
+            // TODO: Call GetLastError for better error info?
+            None => return Err(Error::path_not_found().add_path(path)),
+        };
+        // security changes are read with a handle of their own, to tell them apart
+        let security = if self.security_changes {
+            match self.open_dir(&dir_target) {
+                Some(Ok(opened)) => Some(opened),
+                _ => {
+                    self.close_dir(handle);
+                    return Err(
+                        Error::generic("Failed to open directory for security changes.")
+                            .add_path(path),
+                    );
+                }
+            }
+        } else {
+            None
+        };
         let wf = if watching_file {
-            Some(path.clone())
+            Some(target.clone().unwrap_or_else(|| path.clone()))
         } else {
             None
         };
-        // every watcher gets its own semaphore to signal completion
-        let semaphore = unsafe { CreateSemaphoreW(ptr::null_mut(), 0, 1, ptr::null_mut()) };
-        if semaphore == 0 || semaphore == INVALID_HANDLE_VALUE {
-            unsafe {
-                CloseHandle(handle);
-            }
-            return Err(Error::generic("Failed to create semaphore for watch.").add_path(path));
-        }
         let rd = ReadData {
             dir: dir_target,
             file: wf,
-            complete_sem: semaphore,
+            link: target.map(|_| path.clone()),
             is_recursive,
+            flags: if self.security_changes {
+                CHANGE_FLAGS
+            } else {
+                CHANGE_FLAGS | FILE_NOTIFY_CHANGE_SECURITY
+            },
         };
-        let ws = WatchState {
-            dir_handle: handle,
-            complete_sem: semaphore,
+        let mut ws = WatchState {
+            reads: vec![(key, handle)],
         };
+        if let Some((security_key, security_handle)) = security {
+            let rd = ReadData {
+                flags: FILE_NOTIFY_CHANGE_SECURITY,
+                ..rd.clone()
+            };
+            self.start_request(security_key, security_handle, rd);
+            ws.reads.push((security_key, security_handle));
+        }
+        self.start_request(key, handle, rd);
         self.watches.insert(path.clone(), ws);
-        start_read(&rd, self.event_handler.clone(), handle);
         Ok(path)
     }
 
-    fn remove_watch(&mut self, path: PathBuf) {
-        if let Some(ws) = self.watches.remove(&path) {
-            stop_watch(&ws, &self.meta_tx);
+    /// Opens `dir` for reading changes, associated with the completion port under a new key.
+    ///
+    /// Returns `None` if the directory couldn't be opened, and `Some(Err(()))` if it couldn't be
+    /// associated with the port.
+    fn open_dir(&mut self, dir: &Path) -> Option<std::result::Result<(usize, HANDLE), ()>> {
+        let encoded_path: Vec<u16> = dir.as_os_str().encode_wide().chain(Some(0)).collect();
+        let handle = unsafe {
+            CreateFileW(
+                encoded_path.as_ptr(),
+                FILE_LIST_DIRECTORY,
+                FILE_SHARE_READ | FILE_SHARE_DELETE | FILE_SHARE_WRITE,
+                ptr::null_mut(),
+                OPEN_EXISTING,
+                FILE_FLAG_BACKUP_SEMANTICS | FILE_FLAG_OVERLAPPED,
+                0,
+            )
+        };
+        if handle == INVALID_HANDLE_VALUE {
+            return None;
         }
+        self.audit.acquire(Resource::Handle, handle);
+        let key = self.next_key;
+        self.next_key += 1;
+        if !self.port.associate(handle, key) {
+            self.close_dir(handle);
+            return Some(Err(()));
+        }
+        Some(Ok((key, handle)))
     }
 
-    fn configure_raw_mode(&mut self, _config: Config, tx: BoundSender<Result<bool>>) {
-        tx.send(Ok(false))
-            .expect("configuration channel disconnect");
+    /// Closes a directory opened by [Self::open_dir] before any read was started.
+    fn close_dir(&self, handle: HANDLE) {
+        unsafe {
+            CloseHandle(handle);
+        }
+        self.audit.release(Resource::Handle, handle);
     }
-}
 
-fn stop_watch(ws: &WatchState, meta_tx: &Sender<MetaEvent>) {
-    unsafe {
-        let cio = CancelIo(ws.dir_handle);
-        let ch = CloseHandle(ws.dir_handle);
-        // have to wait for it, otherwise we leak the memory allocated for there read request
-        if cio != 0 && ch != 0 {
-            while WaitForSingleObjectEx(ws.complete_sem, INFINITE, 1) != WAIT_OBJECT_0 {
-                // drain the apc queue, fix for https://github.com/notify-rs/notify/issues/287#issuecomment-801465550
+    /// Start reading the changes of `handle`, which completes with `key`.
+    ///
+    /// A read which couldn't be started is dropped, the handle is closed with its watch.
+    fn start_request(&mut self, key: usize, handle: HANDLE, data: ReadData) {
+        let mut request = Box::new(ReadDirectoryRequest {
+            overlapped: unsafe { mem::zeroed() },
+            buffer: [0u8; BUF_SIZE as usize],
+            handle,
+            data,
+            closing: false,
+        });
+        if start_read(&mut request) {
+            self.requests.insert(key, request);
+        }
+    }
+
+    fn remove_watch(&mut self, path: PathBuf) {
+        if let Some(ws) = self.watches.remove(&path) {
+            self.stop_watch(ws);
+        }
+    }
+
+    /// Cancel the reads of a watch and close its handles.
+    ///
+    /// This doesn't wait for the reads, their requests are freed when the completion port reports
+    /// the cancellation.
+    fn stop_watch(&mut self, ws: WatchState) {
+        for (key, handle) in ws.reads {
+            if let Some(request) = self.requests.get_mut(&key) {
+                request.closing = true;
+            }
+            unsafe {
+                CancelIo(handle);
+                CloseHandle(handle);
             }
+            self.audit.release(Resource::Handle, handle);
         }
-        CloseHandle(ws.complete_sem);
+        let _ = self.meta_tx.send(MetaEvent::SingleWatchComplete);
+    }
+
+    fn configure_raw_mode(&mut self, config: Box<Config>, tx: BoundSender<Result<bool>>) {
+        self.initial_scan = config.initial_scan();
+        tx.send(Ok(true)).expect("configuration channel disconnect");
     }
-    let _ = meta_tx.send(MetaEvent::SingleWatchComplete);
 }
 
-fn start_read(rd: &ReadData, event_handler: Arc<Mutex<dyn EventHandler>>, handle: HANDLE) {
-    let mut request = Box::new(ReadDirectoryRequest {
-        event_handler,
-        handle,
-        buffer: [0u8; BUF_SIZE as usize],
-        data: rd.clone(),
-    });
-
-    let flags = FILE_NOTIFY_CHANGE_FILE_NAME
-        | FILE_NOTIFY_CHANGE_DIR_NAME
-        | FILE_NOTIFY_CHANGE_ATTRIBUTES
-        | FILE_NOTIFY_CHANGE_SIZE
-        | FILE_NOTIFY_CHANGE_LAST_WRITE
-        | FILE_NOTIFY_CHANGE_CREATION
-        | FILE_NOTIFY_CHANGE_SECURITY;
-
-    let monitor_subdir = if (&request.data.file).is_none() && request.data.is_recursive {
+fn is_symlink(path: &Path) -> bool {
+    std::fs::symlink_metadata(path).map_or(false, |m| m.file_type().is_symlink())
+}
+
+/// Issue the read of `request`, whose completion is queued to the port of its handle.
+fn start_read(request: &mut ReadDirectoryRequest) -> bool {
+    let monitor_subdir = if request.data.file.is_none() && request.data.is_recursive {
         1
     } else {
         0
     };
 
     unsafe {
-        let mut overlapped: Box<OVERLAPPED> = Box::new(mem::zeroed());
-        // When using callback based async requests, we are allowed to use the hEvent member
-        // for our own purposes
-
-        let req_buf = request.buffer.as_mut_ptr() as *mut c_void;
-        let request_p = Box::into_raw(request) as isize;
-        overlapped.hEvent = request_p;
-
-        // This is using an asynchronous call with a completion routine for receiving notifications
-        // An I/O completion port would probably be more performant
-        let ret = ReadDirectoryChangesW(
-            handle,
-            req_buf,
+        request.overlapped = mem::zeroed();
+        ReadDirectoryChangesW(
+            request.handle,
+            request.buffer.as_mut_ptr() as *mut c_void,
             BUF_SIZE,
             monitor_subdir,
-            flags,
+            request.data.flags,
             &mut 0u32 as *mut u32, // not used for async reqs
-            &mut *overlapped as *mut OVERLAPPED,
-            Some(handle_event),
-        );
-
-        if ret == 0 {
-            // error reading. retransmute request memory to allow drop.
-            // allow overlapped to drop by omitting forget()
-            let request: Box<ReadDirectoryRequest> = mem::transmute(request_p);
-
-            ReleaseSemaphore(request.data.complete_sem, 1, ptr::null_mut());
-        } else {
-            // read ok. forget overlapped to let the completion routine handle memory
-            mem::forget(overlapped);
-        }
+            &mut request.overlapped,
+            None,
+        ) != 0
     }
 }
 
-unsafe extern "system" fn handle_event(
-    error_code: u32,
-    _bytes_written: u32,
-    overlapped: *mut OVERLAPPED,
-) {
-    let overlapped: Box<OVERLAPPED> = Box::from_raw(overlapped);
-    let request: Box<ReadDirectoryRequest> = Box::from_raw(overlapped.hEvent as *mut _);
-
-    if error_code == ERROR_OPERATION_ABORTED {
-        // received when dir is unwatched or watcher is shutdown; return and let overlapped/request
-        // get drop-cleaned
-        ReleaseSemaphore(request.data.complete_sem, 1, ptr::null_mut());
-        return;
+/// The events of a completed read, before its buffer is reused for the next one
+fn read_events(request: &ReadDirectoryRequest, error_code: u32, bytes_written: u32) -> Vec<Event> {
+    // the buffer overflowed and its changes were discarded
+    if error_code == ERROR_NOTIFY_ENUM_DIR || bytes_written == 0 {
+        let path = match request.data.link {
+            Some(ref link) => link.clone(),
+            None => request
+                .data
+                .file
+                .clone()
+                .unwrap_or_else(|| request.data.dir.clone()),
+        };
+        let ev = Event::new(EventKind::Other)
+            .add_path(path)
+            .set_flag(Flag::Rescan);
+        return vec![ev];
     }
 
-    // Get the next request queued up as soon as possible
-    start_read(&request.data, request.event_handler.clone(), request.handle);
-
+    let mut events = Vec::new();
     // The FILE_NOTIFY_INFORMATION struct has a variable length due to the variable length
     // string as its last member. Each struct contains an offset for getting the next entry in
     // the buffer.
     let mut cur_offset: *const u8 = request.buffer.as_ptr();
     let mut cur_entry = cur_offset as *const FILE_NOTIFY_INFORMATION;
     loop {
+        let entry = unsafe { &*cur_entry };
         // filename length is size in bytes, so / 2
-        let len = (*cur_entry).FileNameLength as usize / 2;
-        let encoded_path: &[u16] = slice::from_raw_parts((*cur_entry).FileName.as_ptr(), len);
+        let len = entry.FileNameLength as usize / 2;
+        let encoded_path: &[u16] = unsafe { slice::from_raw_parts(entry.FileName.as_ptr(), len) };
         // prepend root to get a full path
         let path = request
             .data
@@ -351,58 +558,42 @@ unsafe extern "system" fn handle_event(
             None => false,
             Some(ref watch_path) => *watch_path != path,
         };
+        let path = match request.data.link {
+            Some(ref link) if !skip => link.clone(),
+            _ => path,
+        };
 
         if !skip {
             let newe = Event::new(EventKind::Any).add_path(path);
 
-            fn emit_event(event_handler: &Mutex<dyn EventHandler>, res: Result<Event>) {
-                if let Ok(mut guard) = event_handler.lock() {
-                    let f: &mut dyn EventHandler = &mut *guard;
-                    f.handle_event(res);
+            let kind = match entry.Action {
+                FILE_ACTION_RENAMED_OLD_NAME => {
+                    Some(EventKind::Modify(ModifyKind::Name(RenameMode::From)))
                 }
-            }
-
-            let event_handler = |res| emit_event(&request.event_handler, res);
-
-            if (*cur_entry).Action == FILE_ACTION_RENAMED_OLD_NAME {
-                let mode = RenameMode::From;
-                let kind = ModifyKind::Name(mode);
-                let kind = EventKind::Modify(kind);
-                let ev = newe.set_kind(kind);
-                event_handler(Ok(ev))
-            } else {
-                match (*cur_entry).Action {
-                    FILE_ACTION_RENAMED_NEW_NAME => {
-                        let kind = EventKind::Modify(ModifyKind::Name(RenameMode::To));
-                        let ev = newe.set_kind(kind);
-                        event_handler(Ok(ev));
-                    }
-                    FILE_ACTION_ADDED => {
-                        let kind = EventKind::Create(CreateKind::Any);
-                        let ev = newe.set_kind(kind);
-                        event_handler(Ok(ev));
-                    }
-                    FILE_ACTION_REMOVED => {
-                        let kind = EventKind::Remove(RemoveKind::Any);
-                        let ev = newe.set_kind(kind);
-                        event_handler(Ok(ev));
-                    }
-                    FILE_ACTION_MODIFIED => {
-                        let kind = EventKind::Modify(ModifyKind::Any);
-                        let ev = newe.set_kind(kind);
-                        event_handler(Ok(ev));
-                    }
-                    _ => (),
-                };
+                FILE_ACTION_RENAMED_NEW_NAME => {
+                    Some(EventKind::Modify(ModifyKind::Name(RenameMode::To)))
+                }
+                FILE_ACTION_ADDED => Some(EventKind::Create(CreateKind::Any)),
+                FILE_ACTION_REMOVED => Some(EventKind::Remove(RemoveKind::Any)),
+                // Windows doesn't tell whether the owner or the ACLs changed
+                FILE_ACTION_MODIFIED if request.data.flags == FILE_NOTIFY_CHANGE_SECURITY => Some(
+                    EventKind::Modify(ModifyKind::Metadata(MetadataKind::Permissions)),
+                ),
+                FILE_ACTION_MODIFIED => Some(EventKind::Modify(ModifyKind::Any)),
+                _ => None,
+            };
+            if let Some(kind) = kind {
+                events.push(newe.set_kind(kind));
             }
         }
 
-        if (*cur_entry).NextEntryOffset == 0 {
+        if entry.NextEntryOffset == 0 {
             break;
         }
-        cur_offset = cur_offset.offset((*cur_entry).NextEntryOffset as isize);
+        cur_offset = unsafe { cur_offset.offset(entry.NextEntryOffset as isize) };
         cur_entry = cur_offset as *const FILE_NOTIFY_INFORMATION;
     }
+    events
 }
 
 /// Watcher implementation based on ReadDirectoryChanges
@@ -410,38 +601,66 @@ unsafe extern "system" fn handle_event(
 pub struct ReadDirectoryChangesWatcher {
     tx: Sender<Action>,
     cmd_rx: Receiver<Result<PathBuf>>,
-    wakeup_sem: HANDLE,
+    port: Arc<CompletionPort>,
+    registry: WatchRegistry,
+    tokens: WatchTokens,
+    options: DeliveryOptions,
+    suppressions: Suppressions,
 }
 
 impl ReadDirectoryChangesWatcher {
     pub fn create(
         event_handler: Arc<Mutex<dyn EventHandler>>,
         meta_tx: Sender<MetaEvent>,
+    ) -> Result<ReadDirectoryChangesWatcher> {
+        Self::create_with_config(
+            event_handler,
+            meta_tx,
+            &Config::default(),
+            WatchTokens::default(),
+            DeliveryOptions::default(),
+            Suppressions::default(),
+        )
+    }
+
+    fn create_with_config(
+        event_handler: Arc<Mutex<dyn EventHandler>>,
+        meta_tx: Sender<MetaEvent>,
+        config: &Config,
+        tokens: WatchTokens,
+        options: DeliveryOptions,
+        suppressions: Suppressions,
     ) -> Result<ReadDirectoryChangesWatcher> {
         let (cmd_tx, cmd_rx) = unbounded();
 
-        let wakeup_sem = unsafe { CreateSemaphoreW(ptr::null_mut(), 0, 1, ptr::null_mut()) };
-        if wakeup_sem == 0 || wakeup_sem == INVALID_HANDLE_VALUE {
-            return Err(Error::generic("Failed to create wakeup semaphore."));
-        }
+        let audit = Audit::new("windows", config);
+        let port = CompletionPort::new(audit.clone())
+            .map(Arc::new)
+            .ok_or_else(|| Error::generic("Failed to create completion port."))?;
 
-        let action_tx =
-            ReadDirectoryChangesServer::start(event_handler, meta_tx, cmd_tx, wakeup_sem);
+        let action_tx = ReadDirectoryChangesServer::start(
+            event_handler,
+            meta_tx,
+            cmd_tx,
+            port.clone(),
+            config,
+            audit,
+        )?;
 
         Ok(ReadDirectoryChangesWatcher {
             tx: action_tx,
             cmd_rx,
-            wakeup_sem,
+            port,
+            registry: WatchRegistry::new(config.watch_overlap()),
+            tokens,
+            options,
+            suppressions,
         })
     }
 
     fn wakeup_server(&mut self) {
-        // breaks the server out of its wait state.  right now this is really just an optimization,
-        // so that if you add a watch you don't block for 100ms in watch() while the
-        // server sleeps.
-        unsafe {
-            ReleaseSemaphore(self.wakeup_sem, 1, ptr::null_mut());
-        }
+        // breaks the server out of its wait for completions, it doesn't see actions otherwise
+        self.port.wake();
     }
 
     fn send_action_require_ack(&mut self, action: Action, pb: &PathBuf) -> Result<()> {
@@ -469,20 +688,27 @@ impl ReadDirectoryChangesWatcher {
         }
     }
 
-    fn watch_inner(&mut self, path: &Path, recursive_mode: RecursiveMode) -> Result<()> {
+    fn watch_inner(
+        &mut self,
+        path: &Path,
+        recursive_mode: RecursiveMode,
+        symlink_mode: SymlinkMode,
+    ) -> Result<()> {
         let pb = if path.is_absolute() {
             path.to_owned()
         } else {
             let p = env::current_dir().map_err(Error::io)?;
             p.join(path)
         };
-        // path must exist and be either a file or directory
-        if !pb.is_dir() && !pb.is_file() {
+        // path must exist and be either a file or directory, or a link watched itself
+        let watch_link = symlink_mode == SymlinkMode::Link && is_symlink(&pb);
+        if !pb.is_dir() && !pb.is_file() && !watch_link {
             return Err(Error::generic(
                 "Input watch path is neither a file nor a directory.",
             ));
         }
-        self.send_action_require_ack(Action::Watch(pb.clone(), recursive_mode), &pb)
+        let action = Action::Watch(pb.clone(), recursive_mode, symlink_mode);
+        self.send_action_require_ack(action, &pb)
     }
 
     fn unwatch_inner(&mut self, path: &Path) -> Result<()> {
@@ -501,26 +727,104 @@ impl ReadDirectoryChangesWatcher {
     }
 }
 
+impl InstallWatches for ReadDirectoryChangesWatcher {
+    fn install(&mut self, path: &Path, recursive_mode: RecursiveMode) -> Result<()> {
+        self.watch_inner(path, recursive_mode, SymlinkMode::Target)
+    }
+
+    fn uninstall(&mut self, path: &Path) -> Result<()> {
+        self.unwatch_inner(path)
+    }
+}
+
 impl Watcher for ReadDirectoryChangesWatcher {
     fn new<F: EventHandler>(event_handler: F, config: Config) -> Result<Self> {
         // create dummy channel for meta event
         // TODO: determine the original purpose of this - can we remove it?
         let (meta_tx, _) = unbounded();
-        let event_handler = Arc::new(Mutex::new(event_handler));
-        Self::create(event_handler, meta_tx)
+        let delivery = Delivery::new(event_handler, &config);
+        let tokens = delivery.tokens();
+        let options = delivery.options();
+        let suppressions = delivery.suppressions();
+        Self::create_with_config(
+            Arc::new(Mutex::new(delivery)),
+            meta_tx,
+            &config,
+            tokens,
+            options,
+            suppressions,
+        )
     }
 
     fn watch(&mut self, path: &Path, recursive_mode: RecursiveMode) -> Result<()> {
-        self.watch_inner(path, recursive_mode)
+        self.watch_with_symlink_mode(path, recursive_mode, SymlinkMode::Target)
+    }
+
+    fn watch_with_symlink_mode(
+        &mut self,
+        path: &Path,
+        recursive_mode: RecursiveMode,
+        symlink_mode: SymlinkMode,
+    ) -> Result<()> {
+        let plan = self.registry.plan_watch(path, recursive_mode)?;
+        plan.apply(self, |watcher| {
+            watcher.watch_inner(path, recursive_mode, symlink_mode)
+        })?;
+        self.registry.insert(path, recursive_mode);
+        Ok(())
+    }
+
+    fn watch_with_token(
+        &mut self,
+        path: &Path,
+        recursive_mode: RecursiveMode,
+        token: u64,
+    ) -> Result<()> {
+        let plan = self.registry.plan_watch(path, recursive_mode)?;
+        // registered first, so that the initial scan carries the token already.
+        self.tokens.insert(path, token);
+        if let Err(e) = plan.apply(self, |watcher| watcher.install(path, recursive_mode)) {
+            self.tokens.remove(path);
+            return Err(e);
+        }
+        self.registry.insert(path, recursive_mode);
+        Ok(())
     }
 
     fn unwatch(&mut self, path: &Path) -> Result<()> {
-        self.unwatch_inner(path)
+        let plan = self.registry.plan_unwatch(path);
+        plan.apply(self, |watcher| watcher.unwatch_inner(path))?;
+        self.registry.remove(path);
+        self.tokens.remove(path);
+        Ok(())
+    }
+
+    fn set_recursive_mode(&mut self, path: &Path, recursive_mode: RecursiveMode) -> Result<()> {
+        // like the default, but keeping the token of the watch.
+        let plan = self
+            .registry
+            .plan_set_recursive_mode(path, recursive_mode)?;
+        plan.apply(self, |watcher| {
+            watcher.unwatch_inner(path)?;
+            watcher.install(path, recursive_mode)
+        })?;
+        self.registry.insert(path, recursive_mode);
+        Ok(())
+    }
+
+    fn suppress(&self, path: &Path) -> Result<SuppressGuard> {
+        Ok(self.suppressions.guard(path))
+    }
+
+    fn is_watched(&self, path: &Path) -> bool {
+        self.registry.is_watched(path)
     }
 
     fn configure(&mut self, config: Config) -> Result<bool> {
+        self.options.configure(&config);
         let (tx, rx) = bounded(1);
-        self.tx.send(Action::Configure(config, tx))?;
+        self.tx.send(Action::Configure(Box::new(config), tx))?;
+        self.wakeup_server();
         rx.recv()?
     }
 
@@ -537,8 +841,76 @@ impl Drop for ReadDirectoryChangesWatcher {
     }
 }
 
-// `ReadDirectoryChangesWatcher` is not Send/Sync because of the semaphore Handle.
+// `ReadDirectoryChangesWatcher` is not Send/Sync because of the completion port Handle.
 // As said elsewhere it's perfectly safe to send it across threads.
 unsafe impl Send for ReadDirectoryChangesWatcher {}
 // Because all public methods are `&mut self` it's also perfectly safe to share references.
 unsafe impl Sync for ReadDirectoryChangesWatcher {}
+
+#[test]
+fn watches_share_the_completion_port() {
+    use std::sync::mpsc;
+    use std::time::{Duration, Instant};
+
+    let dir = tempfile::tempdir().unwrap();
+    let dirs: Vec<PathBuf> = ["a", "b", "c"]
+        .iter()
+        .map(|name| dir.path().join(name))
+        .collect();
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = ReadDirectoryChangesWatcher::new(tx, Config::default()).unwrap();
+    for dir in &dirs {
+        std::fs::create_dir(dir).unwrap();
+        watcher.watch(dir, RecursiveMode::NonRecursive).unwrap();
+    }
+    for dir in &dirs {
+        std::fs::write(dir.join("file"), "").unwrap();
+    }
+
+    let mut missing: Vec<PathBuf> = dirs.iter().map(|dir| dir.join("file")).collect();
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !missing.is_empty() {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        let event: Event = rx.recv_timeout(timeout).unwrap().unwrap();
+        missing.retain(|path| !event.paths.contains(path));
+    }
+}
+
+#[test]
+fn watches_are_unwatched_with_reads_pending() {
+    use std::sync::mpsc;
+    use std::time::{Duration, Instant};
+
+    let dir = tempfile::tempdir().unwrap();
+    let (tx, rx) = mpsc::channel();
+    let (meta_tx, meta_rx) = unbounded();
+    let mut watcher =
+        ReadDirectoryChangesWatcher::create(Arc::new(Mutex::new(tx)), meta_tx).unwrap();
+
+    // the changes complete reads which are dequeued after the unwatch cancelled them
+    watcher.watch(dir.path(), RecursiveMode::Recursive).unwrap();
+    for i in 0..100 {
+        std::fs::write(dir.path().join(i.to_string()), "").unwrap();
+    }
+    watcher.unwatch(dir.path()).unwrap();
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        if meta_rx.recv_timeout(timeout).unwrap() == MetaEvent::SingleWatchComplete {
+            break;
+        }
+    }
+
+    // the port still serves the next watch of the directory
+    watcher.watch(dir.path(), RecursiveMode::Recursive).unwrap();
+    while rx.try_recv().is_ok() {}
+    let file = dir.path().join("after");
+    std::fs::write(&file, "").unwrap();
+    loop {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        let event: Event = rx.recv_timeout(timeout).unwrap().unwrap();
+        if event.paths.contains(&file) {
+            break;
+        }
+    }
+}